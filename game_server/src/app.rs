@@ -2,15 +2,26 @@ use std::{cmp::max, env::consts, time::Duration};
 
 use crossterm::{event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers}, terminal};
 use ratatui::{
-    layout::{Constraint, Direction, Layout}, style::{Color, Style, Stylize}, symbols::{block, border}, text::Line, widgets::{Bar, BarChart, BarGroup, Block, Borders, Paragraph, Sparkline, Widget}, DefaultTerminal, Frame
+    layout::{Constraint, Direction, Layout}, style::{Color, Style, Stylize}, symbols::{block, border}, text::Line, widgets::{Bar, BarChart, BarGroup, Block, Borders, Cell, Paragraph, Row, Sparkline, Table, Widget}, DefaultTerminal, Frame
 };
 use strum::IntoEnumIterator;
 use strum_macros::{EnumString, Display};
 
 
 
+use crate::clients_service::connection::{ConnectionRole, TransportKind};
+use crate::clients_service::connection_stats::{self, ConnectionStatsSnapshot, StatsColumn};
+use crate::gameplay_service::tick_metrics::HistogramSnapshot;
 use crate::{AppData, ServerChannels};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View
+{
+    Dashboard,
+    Connections,
+    Ticks,
+}
+
 pub struct App {
     pub running: bool,
     pub sleeping: bool,
@@ -37,6 +48,12 @@ pub struct App {
     sent_bytes_graph_max: u64,
 
     online_players:u32,
+
+    // connections table
+    view: View,
+    sort_column: StatsColumn,
+    sort_descending: bool,
+    connection_rows: Vec<ConnectionStatsSnapshot>,
     app_data: AppData,
 }
 
@@ -70,6 +87,10 @@ impl App {
             sent_bytes_per_second: 0f32,
             sent_bytes_graph_data: [0;64],
             sent_bytes_graph_max: 0,
+            view: View::Dashboard,
+            sort_column: StatsColumn::Rtt,
+            sort_descending: true,
+            connection_rows: Vec::new(),
         }
         // Self { running: true, counter:0}
     }
@@ -157,6 +178,8 @@ impl App {
                     let max =  self.sent_bytes_graph_data.iter().max().map(|v| *v);
                     self.sent_bytes_graph_max =  max.unwrap_or(0);
 
+                    //----------------------------------- CONNECTIONS
+                    self.refresh_connection_rows();
                 }
 
                 terminal.draw(|frame| self.draw(frame));
@@ -233,6 +256,14 @@ impl App {
             "<S>".blue().bold(),
             " Awake ".into(),
             "<A>".blue().bold(),
+            " Connections ".into(),
+            "<C>".blue().bold(),
+            " Ticks ".into(),
+            "<G>".blue().bold(),
+            " Sort ".into(),
+            "<T>".blue().bold(),
+            " Reverse ".into(),
+            "<R>".blue().bold(),
             " Quit ".into(),
             "<Q> ".blue().bold(),
         ]);
@@ -240,6 +271,18 @@ impl App {
         let instructions_block = Paragraph::new(instructions).centered();
         frame.render_widget(instructions_block, main_layout[2]);
 
+        if self.view == View::Connections
+        {
+            self.draw_connections(frame, main_layout[1]);
+            return;
+        }
+
+        if self.view == View::Ticks
+        {
+            self.draw_ticks(frame, main_layout[1]);
+            return;
+        }
+
         let inner_layout = Layout::default()
             .direction(ratatui::layout::Direction::Horizontal)
             .constraints(vec![
@@ -426,6 +469,115 @@ impl App {
 
     }
 
+    fn refresh_connection_rows(&mut self)
+    {
+        let mut rows : Vec<ConnectionStatsSnapshot> = self.app_data.game_status.connection_stats
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, stats)| stats.get_snapshot(*id))
+            .collect();
+        connection_stats::sort_snapshots(&mut rows, self.sort_column, self.sort_descending);
+        self.connection_rows = rows;
+    }
+
+    fn draw_connections(&mut self, frame: &mut Frame, area: ratatui::layout::Rect)
+    {
+        let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
+        let current_time_in_millis = current_time.as_millis() as u64;
+
+        let header = Row::new(StatsColumn::ALL.iter().map(|column|
+        {
+            if *column == self.sort_column
+            {
+                let arrow = if self.sort_descending { "v" } else { "^" };
+                Cell::from(format!("{} {}", column.get_name(), arrow)).blue().bold()
+            }
+            else
+            {
+                Cell::from(column.get_name()).bold()
+            }
+        }));
+
+        let rows : Vec<Row> = self.connection_rows.iter().map(|row|
+        {
+            let hero = match row.role
+            {
+                ConnectionRole::Hero => format!("{}", row.hero_id),
+                ConnectionRole::Spectator => "spectator".to_string(),
+                ConnectionRole::Admin => "admin".to_string(),
+            };
+            let transport = match row.id.kind
+            {
+                TransportKind::Udp => "udp",
+                TransportKind::WebSocket => "ws",
+            };
+            let idle = current_time_in_millis.saturating_sub(row.last_activity) as f32 / 1000.0;
+
+            Row::new(vec![
+                Cell::from(hero),
+                Cell::from(transport),
+                Cell::from(format!("{} ms", row.rtt_ms)),
+                Cell::from(Self::format_bytes(row.received_bytes)),
+                Cell::from(Self::format_bytes(row.sent_bytes)),
+                Cell::from(format!("{}", row.dropped_packets)),
+                Cell::from(format!("{}", row.rate_limited_packets)),
+                Cell::from(format!("{idle:.1} s")),
+            ])
+        }).collect();
+
+        let widths = [Constraint::Ratio(1, StatsColumn::ALL.len() as u32); StatsColumn::ALL.len()];
+        let title = Line::from(format!("connections ({})", self.connection_rows.len())).centered();
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title).border_set(border::PLAIN));
+
+        frame.render_widget(table, area);
+    }
+
+    fn draw_ticks(&mut self, frame: &mut Frame, area: ratatui::layout::Rect)
+    {
+        let game_status = &self.app_data.game_status;
+        let metrics = game_status.tick_metrics.get_snapshot();
+        let scheduled = game_status.pending_delayed_commands.load(std::sync::atomic::Ordering::Relaxed);
+
+        let header = Row::new(["stage", "runs", "commands", "avg", "p50", "p99", "max"].map(|name| Cell::from(name).bold()));
+
+        let duration_cells = |durations: &HistogramSnapshot| vec![
+            Cell::from(format!("{}", durations.count)),
+            Cell::from(Self::format_micros(durations.average_micros)),
+            Cell::from(Self::format_micros(durations.p50_micros)),
+            Cell::from(Self::format_micros(durations.p99_micros)),
+            Cell::from(Self::format_micros(durations.max_micros)),
+        ];
+
+        let mut rows : Vec<Row> = metrics.stages.iter().map(|stage|
+        {
+            let mut cells = duration_cells(&stage.durations);
+            cells.insert(0, Cell::from(stage.name));
+            cells.insert(2, Cell::from(format!("{}", stage.commands)));
+            Row::new(cells)
+        }).collect();
+
+        let mut cells = duration_cells(&metrics.ticks);
+        cells.insert(0, Cell::from("whole tick"));
+        cells.insert(2, Cell::from(""));
+        rows.push(Row::new(cells).blue().bold());
+
+        let widths = [Constraint::Ratio(1, 7); 7];
+        let overruns = format!("{} overruns", metrics.overruns);
+        let title = Line::from(vec![
+            format!("ticks of {} ms, ", metrics.tick_micros / 1000).into(),
+            if metrics.overruns > 0 { overruns.red().bold() } else { overruns.into() },
+            format!(", {} delayed commands waiting", scheduled).into(),
+        ]).centered();
+        let table = Table::new(rows, widths)
+            .header(header)
+            .block(Block::bordered().title(title).border_set(border::PLAIN));
+
+        frame.render_widget(table, area);
+    }
+
     /// Reads the crossterm events and updates the state of [`App`].
     ///
     /// If your application needs to perform work in between handling events, you can use the
@@ -451,6 +603,10 @@ impl App {
             (_, KeyCode::Esc | KeyCode::Char('q')) => self.quit(),
             (_, KeyCode::Char('s')) => self.sleep_tui(),
             (_, KeyCode::Char('a')) => self.awake_tui(),
+            (_, KeyCode::Char('c')) => self.toggle_view(View::Connections),
+            (_, KeyCode::Char('g')) => self.toggle_view(View::Ticks),
+            (_, KeyCode::Char('t')) => self.sort_by(self.sort_column.next(), self.sort_descending),
+            (_, KeyCode::Char('r')) => self.sort_by(self.sort_column, !self.sort_descending),
             _ => {}
         }
     }
@@ -466,6 +622,20 @@ impl App {
         self.sleeping = true;
     }
 
+    // the same key goes back to the dashboard.
+    fn toggle_view(&mut self, view: View)
+    {
+        self.view = if self.view == view { View::Dashboard } else { view };
+    }
+
+    // sorts what is on screen now, the next refresh keeps the order.
+    fn sort_by(&mut self, column: StatsColumn, descending: bool)
+    {
+        self.sort_column = column;
+        self.sort_descending = descending;
+        connection_stats::sort_snapshots(&mut self.connection_rows, column, descending);
+    }
+
     fn awake_tui(&mut self)
     {
         self.sleeping = false;
@@ -475,6 +645,18 @@ impl App {
     }
 
 
+    fn format_micros(micros: u64) -> String
+    {
+        if micros >= 1000
+        {
+            format!("{:.1} ms", micros as f32 / 1000.0)
+        }
+        else
+        {
+            format!("{} us", micros)
+        }
+    }
+
     fn format_bytes_per_second(data: f32) -> String
     {
         if data > 1024.0 * 1024.0
@@ -0,0 +1,89 @@
+use game_server::map::tetrahedron_id::TetrahedronId;
+use game_server::protocols::packet_auth::SESSION_SECRET_SIZE;
+use game_server::protocols::protocol_version::PROTOCOL_VERSION;
+use game_server::protocols::schema;
+use game_server::web_service::heroes::{HeroCreationRequest, HeroCreationResponse, JoinWithHeroRequest, PlayerCreationRequest, PlayerCreationResponse};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde::Serialize;
+
+// protocol version (2 bytes), session id (8 bytes) and the session secret, then the hero.
+const JOIN_HEADER_SIZE : usize = 2 + 8 + SESSION_SECRET_SIZE;
+
+// what a hero needs to start sending packets.
+#[derive(Debug, Clone)]
+pub struct Session
+{
+    pub hero_id : u16,
+    pub session_id : u64,
+    pub faction : u8,
+    pub secret : [u8; SESSION_SECRET_SIZE],
+    pub position : TetrahedronId,
+}
+
+// same calls the game client makes, a new player with a new hero each time.
+pub async fn login(client : &Client<HttpConnector>, web_address : &str, player_name : &str, faction : u8) -> Result<Session, String>
+{
+    let player : PlayerCreationResponse = post_json(client, web_address, "player_creation", &PlayerCreationRequest { player_name: player_name.to_string() }).await?;
+
+    let hero_request = HeroCreationRequest { player_token: player.player_token.clone(), faction: faction as u32 };
+    let hero : HeroCreationResponse = post_json(client, web_address, "hero_creation", &hero_request).await?;
+
+    let join_request = JoinWithHeroRequest { player_token: player.player_token, hero_id: hero.hero_id, protocol_version: PROTOCOL_VERSION };
+    let data = post(client, web_address, "join_with_hero", &join_request).await?;
+    read_join_response(&data)
+}
+
+pub fn read_join_response(data : &[u8]) -> Result<Session, String>
+{
+    if data.len() < JOIN_HEADER_SIZE
+    {
+        return Err(format!("join_with_hero: response too short ({} bytes)", data.len()));
+    }
+
+    let version = u16::from_le_bytes([data[0], data[1]]);
+    if version != PROTOCOL_VERSION
+    {
+        return Err(format!("join_with_hero: server speaks version {} and we speak {}", version, PROTOCOL_VERSION));
+    }
+
+    let session_id = u64::from_le_bytes(data[2..10].try_into().unwrap());
+    let secret : [u8; SESSION_SECRET_SIZE] = data[10..JOIN_HEADER_SIZE].try_into().unwrap();
+
+    // the hero is followed by the inventories, we only read the hero.
+    let hero_schema = schema::get_entity("hero").unwrap();
+    let (hero, _size) = schema::decode(hero_schema.fields, &data[JOIN_HEADER_SIZE..]).map_err(|error| format!("join_with_hero: invalid hero {}", error))?;
+    let hero_id = hero["hero_id"].as_u64().unwrap_or_default() as u16;
+    let faction = hero["faction"].as_u64().unwrap_or_default() as u8;
+    let position = TetrahedronId::from_string(hero["position"].as_str().unwrap_or_default());
+
+    Ok(Session { hero_id, session_id, faction, secret, position })
+}
+
+async fn post_json<T : Serialize, R : serde::de::DeserializeOwned>(client : &Client<HttpConnector>, web_address : &str, route : &str, request : &T) -> Result<R, String>
+{
+    let data = post(client, web_address, route, request).await?;
+    serde_json::from_slice(&data).map_err(|error| format!("{}: invalid response {}", route, error))
+}
+
+async fn post<T : Serialize>(client : &Client<HttpConnector>, web_address : &str, route : &str, request : &T) -> Result<Vec<u8>, String>
+{
+    let body = serde_json::to_vec(request).map_err(|error| format!("{}: {}", route, error))?;
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/{}", web_address.trim_end_matches('/'), route))
+        .header("Content-Type", "application/json")
+        .body(Body::from(body))
+        .map_err(|error| format!("{}: {}", route, error))?;
+
+    let response = client.request(request).await.map_err(|error| format!("{}: {}", route, error))?;
+    let status = response.status();
+    let data = hyper::body::to_bytes(response.into_body()).await.map_err(|error| format!("{}: {}", route, error))?;
+
+    // errors come back as a plain string like player_token_not_valid.
+    if status != StatusCode::OK
+    {
+        return Err(format!("{}: {} {}", route, status, String::from_utf8_lossy(&data)));
+    }
+    Ok(data.to_vec())
+}
@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use flate2::read::ZlibDecoder;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use game_server::clients_service::DataType;
+use game_server::clients_service::fragmentation::Reassembler;
+use game_server::map::tetrahedron_id::TetrahedronId;
+use game_server::protocols::packet_auth::{self, SESSION_SECRET_SIZE};
+use game_server::protocols::packet_reader::PacketReader;
+use game_server::protocols::protocol_version::PROTOCOL_VERSION;
+use game_server::protocols::{schema, Protocol};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{Instant, Interval};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::api::Session;
+use crate::report::{self, BotStats};
+use crate::scenario::Scenario;
+
+// a pong that takes longer than this is not coming.
+pub const PING_TIMEOUT : Duration = Duration::from_secs(2);
+// targets the hero remembers from the state packets, the oldest ones go first.
+const MAX_KNOWN_TARGETS : usize = 32;
+// compressed packets start with the zlib header, no protocol goes that high.
+const ZLIB_HEADER : u8 = 0x78;
+
+type WebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+enum Link
+{
+    // every packet is signed with the session secret, see packet_auth.
+    Udp { socket : UdpSocket, secret : [u8; SESSION_SECRET_SIZE], nonce : u64 },
+    WebSocket { sink : SplitSink<WebSocket, Message>, stream : SplitStream<WebSocket> },
+}
+
+impl Link
+{
+    async fn connect(scenario : &Scenario, uses_websocket : bool, session : &Session) -> Result<Link, String>
+    {
+        if uses_websocket
+        {
+            let (websocket, _response) = tokio_tungstenite::connect_async(scenario.websocket_address.as_str()).await.map_err(|error| format!("websocket: {}", error))?;
+            let (sink, stream) = websocket.split();
+            return Ok(Link::WebSocket { sink, stream });
+        }
+
+        let local_address : SocketAddr = if scenario.udp_address.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" }.parse().unwrap();
+        let socket = UdpSocket::bind(local_address).await.map_err(|error| format!("udp: {}", error))?;
+        socket.connect(scenario.udp_address).await.map_err(|error| format!("udp: {}", error))?;
+        Ok(Link::Udp { socket, secret: session.secret, nonce: 1 })
+    }
+
+    // returns the size on the wire.
+    async fn send(&mut self, data : Vec<u8>) -> Result<usize, String>
+    {
+        match self
+        {
+            Link::Udp { socket, secret, nonce } =>
+            {
+                let signed = packet_auth::sign(secret, *nonce, &data);
+                *nonce += 1;
+                socket.send(&signed).await.map_err(|error| error.to_string())
+            },
+            Link::WebSocket { sink, .. } =>
+            {
+                let size = data.len();
+                sink.send(Message::binary(data)).await.map_err(|error| error.to_string())?;
+                Ok(size)
+            },
+        }
+    }
+
+    async fn receive(&mut self, buffer : &mut [u8]) -> Result<Vec<u8>, String>
+    {
+        match self
+        {
+            Link::Udp { socket, .. } =>
+            {
+                let size = socket.recv(buffer).await.map_err(|error| error.to_string())?;
+                Ok(buffer[..size].to_vec())
+            },
+            Link::WebSocket { stream, .. } => loop
+            {
+                match stream.next().await
+                {
+                    Some(Ok(message)) if message.is_binary() => return Ok(message.into_data().to_vec()),
+                    Some(Ok(message)) if message.is_close() => return Err("websocket closed".to_string()),
+                    Some(Ok(_message)) => continue,
+                    Some(Err(error)) => return Err(error.to_string()),
+                    None => return Err("websocket closed".to_string()),
+                }
+            },
+        }
+    }
+}
+
+enum Event
+{
+    Ping,
+    Move,
+    Attack,
+    Harvest,
+    Chat,
+    Received(Result<Vec<u8>, String>),
+    Done,
+}
+
+pub struct Bot
+{
+    session : Session,
+    position : TetrahedronId,
+    random : StdRng,
+    stats : BotStats,
+    next_ping_id : u16,
+    // ping id and when it left, by our clock in ms.
+    pending_pings : HashMap<u16, u64>,
+    // server time of the last pong and when it got here, echoed in the next ping.
+    last_pong : Option<(u64, Instant)>,
+    last_packet_numbers : HashMap<u16, u64>,
+    known_mobs : Vec<(u32, TetrahedronId)>,
+    known_resources : Vec<TetrahedronId>,
+    // messages bigger than a datagram come in fragments.
+    reassembler : Reassembler,
+}
+
+impl Bot
+{
+    pub fn new(index : usize, session : Session) -> Self
+    {
+        Bot
+        {
+            position: session.position.clone(),
+            session,
+            random: StdRng::seed_from_u64(index as u64),
+            stats: BotStats::default(),
+            next_ping_id: 1,
+            pending_pings: HashMap::new(),
+            last_pong: None,
+            last_packet_numbers: HashMap::new(),
+            known_mobs: Vec::new(),
+            known_resources: Vec::new(),
+            reassembler: Reassembler::new(),
+        }
+    }
+
+    // plays until the deadline, the connection only fails if the server goes away.
+    pub async fn run(mut self, scenario : &Scenario, uses_websocket : bool, deadline : Instant) -> Result<BotStats, String>
+    {
+        let mut link = Link::connect(scenario, uses_websocket, &self.session).await?;
+
+        // greet goes first, it is where the server checks we speak its version.
+        let mut greet = self.get_header(Protocol::Greet);
+        greet.extend_from_slice(&u16::to_le_bytes(PROTOCOL_VERSION));
+        self.send(&mut link, greet).await;
+
+        let actions = &scenario.actions;
+        let mut ping = get_interval(actions.ping_ms);
+        let mut movement = get_interval(actions.move_ms);
+        let mut attack = get_interval(actions.attack_ms);
+        let mut harvest = get_interval(actions.harvest_ms);
+        let mut chat = get_interval(actions.chat_ms);
+        let mut buffer = vec![0u8; 65536];
+
+        loop
+        {
+            let event = tokio::select!
+            {
+                _ = tokio::time::sleep_until(deadline) => Event::Done,
+                _ = tick(&mut ping) => Event::Ping,
+                _ = tick(&mut movement) => Event::Move,
+                _ = tick(&mut attack) => Event::Attack,
+                _ = tick(&mut harvest) => Event::Harvest,
+                _ = tick(&mut chat) => Event::Chat,
+                result = link.receive(&mut buffer) => Event::Received(result),
+            };
+
+            let packet = match event
+            {
+                Event::Done => break,
+                Event::Ping => Some(self.get_ping()),
+                Event::Move => Some(self.get_move()),
+                Event::Attack => self.get_attack(actions.attack_card_id),
+                Event::Harvest => self.get_harvest(actions.harvest_damage),
+                Event::Chat => Some(self.get_chat(&actions.chat_messages)),
+                Event::Received(Ok(data)) =>
+                {
+                    self.read_packet(&data);
+                    None
+                },
+                Event::Received(Err(error)) => return Err(format!("connection lost after {} packets: {}", self.stats.received_packets, error)),
+            };
+
+            if let Some(packet) = packet
+            {
+                self.send(&mut link, packet).await;
+            }
+        }
+
+        let now = get_time_in_millis();
+        self.stats.lost_pings = self.pending_pings.values().filter(|sent| now - **sent > PING_TIMEOUT.as_millis() as u64).count() as u64;
+        Ok(self.stats)
+    }
+
+    async fn send(&mut self, link : &mut Link, packet : Vec<u8>)
+    {
+        match link.send(packet).await
+        {
+            Ok(size) =>
+            {
+                self.stats.sent_packets += 1;
+                self.stats.sent_bytes += size as u64;
+            },
+            Err(_error) => self.stats.send_errors += 1,
+        }
+    }
+
+    // protocol (1 byte), session id (8 bytes), hero id (2 bytes) and faction (1 byte), like every client packet.
+    fn get_header(&self, protocol : Protocol) -> Vec<u8>
+    {
+        let mut packet = Vec::with_capacity(64);
+        packet.push(protocol as u8);
+        packet.extend_from_slice(&u64::to_le_bytes(self.session.session_id));
+        packet.extend_from_slice(&u16::to_le_bytes(self.session.hero_id));
+        packet.push(self.session.faction);
+        packet
+    }
+
+    fn get_ping(&mut self) -> Vec<u8>
+    {
+        let id = self.next_ping_id;
+        self.next_ping_id = self.next_ping_id.wrapping_add(1);
+        let client_time = get_time_in_millis();
+        self.pending_pings.insert(id, client_time);
+        self.stats.pings += 1;
+
+        let (echoed_server_time, hold_ms) = match self.last_pong
+        {
+            Some((server_time, received_at)) => (server_time, received_at.elapsed().as_millis().min(u32::MAX as u128) as u32),
+            None => (0, 0),
+        };
+
+        let mut packet = self.get_header(Protocol::Ping);
+        packet.extend_from_slice(&u16::to_le_bytes(id));
+        packet.extend_from_slice(&u64::to_le_bytes(client_time));
+        packet.extend_from_slice(&u64::to_le_bytes(echoed_server_time));
+        packet.extend_from_slice(&u32::to_le_bytes(hold_ms));
+        packet
+    }
+
+    // walks around the tiles next to the current one, they all share the parent.
+    fn get_move(&mut self) -> Vec<u8>
+    {
+        let next_position = self.position.get_parent(1).subdivide(self.random.gen_range(0..4));
+        self.stats.moves += 1;
+
+        let mut packet = self.get_header(Protocol::CharacterMovement);
+        packet.extend_from_slice(&[0u8; 6]);
+        packet.extend_from_slice(&self.position.to_bytes());
+        packet.extend_from_slice(&next_position.to_bytes());
+        packet.extend_from_slice(&i32::to_le_bytes(-1));
+        packet.extend_from_slice(&[0u8; 6]);
+        self.position = next_position;
+        packet
+    }
+
+    fn get_attack(&mut self, card_id : u32) -> Option<Vec<u8>>
+    {
+        if self.known_mobs.is_empty()
+        {
+            self.stats.skipped_actions += 1;
+            return None;
+        }
+
+        let (mob_id, tile_id) = self.known_mobs[self.random.gen_range(0..self.known_mobs.len())].clone();
+        self.stats.attacks += 1;
+
+        let mut packet = self.get_header(Protocol::AttackMob);
+        packet.extend_from_slice(&u32::to_le_bytes(mob_id));
+        packet.extend_from_slice(&tile_id.to_bytes());
+        packet.extend_from_slice(&u32::to_le_bytes(card_id));
+        // no projectile, it lands right away.
+        packet.extend_from_slice(&u32::to_le_bytes(0));
+        packet.push(0);
+        Some(packet)
+    }
+
+    fn get_harvest(&mut self, damage : u16) -> Option<Vec<u8>>
+    {
+        if self.known_resources.is_empty()
+        {
+            self.stats.skipped_actions += 1;
+            return None;
+        }
+
+        let tile_id = self.known_resources[self.random.gen_range(0..self.known_resources.len())].clone();
+        self.stats.harvests += 1;
+
+        let mut packet = self.get_header(Protocol::ResourceExtraction);
+        packet.extend_from_slice(&tile_id.to_bytes());
+        packet.extend_from_slice(&u16::to_le_bytes(damage));
+        Some(packet)
+    }
+
+    fn get_chat(&mut self, messages : &[String]) -> Vec<u8>
+    {
+        let message = &messages[self.random.gen_range(0..messages.len())];
+        let letters : Vec<char> = message.chars().collect();
+        self.stats.chats += 1;
+
+        let mut packet = self.get_header(Protocol::ChatMessage);
+        packet.extend_from_slice(&self.position.to_bytes());
+        packet.push(letters.len() as u8);
+        for letter in letters
+        {
+            packet.extend_from_slice(&u32::to_le_bytes(letter as u32));
+        }
+        packet
+    }
+
+    fn read_packet(&mut self, data : &[u8])
+    {
+        self.stats.received_packets += 1;
+        self.stats.received_bytes += data.len() as u64;
+
+        if data.first() == Some(&(Protocol::Fragment as u8))
+        {
+            self.read_fragment(data);
+            return;
+        }
+        self.read_message(data);
+    }
+
+    // message id (2 bytes), index (1 byte) and count (1 byte), then a piece of the message.
+    fn read_fragment(&mut self, data : &[u8])
+    {
+        let mut reader = PacketReader::new(data);
+        let (Ok(message_id), Ok(index), Ok(count)) = (reader.read_u16(), reader.read_u8(), reader.read_u8()) else
+        {
+            return;
+        };
+
+        let chunk = &data[(data.len() - reader.remaining())..];
+        if let Ok(Some(message)) = self.reassembler.push(message_id, index, count, chunk, std::time::Instant::now())
+        {
+            self.read_message(&message);
+        }
+    }
+
+    // a whole message, compressed or not.
+    fn read_message(&mut self, data : &[u8])
+    {
+        let decompressed;
+        let data = if data.first() == Some(&ZLIB_HEADER)
+        {
+            let mut output = Vec::new();
+            if ZlibDecoder::new(data).read_to_end(&mut output).is_err()
+            {
+                return;
+            }
+            decompressed = output;
+            &decompressed[..]
+        }
+        else
+        {
+            data
+        };
+
+        match data.first().copied()
+        {
+            Some(protocol) if protocol == Protocol::Ping as u8 => self.read_pong(data),
+            Some(protocol) if protocol == Protocol::GlobalState as u8 => self.read_state(data),
+            Some(protocol) if protocol == Protocol::Rejected as u8 => self.stats.rejections += 1,
+            _ => {},
+        }
+    }
+
+    // id (2 bytes), our time (8 bytes), when the server got the ping and when it sent the pong (8 bytes each).
+    fn read_pong(&mut self, data : &[u8])
+    {
+        let mut reader = PacketReader::new(data);
+        let (Ok(id), Ok(_client_time), Ok(_received_time), Ok(sent_time)) = (reader.read_u16(), reader.read_u64(), reader.read_u64(), reader.read_u64()) else
+        {
+            return;
+        };
+
+        if let Some(sent) = self.pending_pings.remove(&id)
+        {
+            self.stats.pongs += 1;
+            self.stats.rtt_samples.push(get_time_in_millis().saturating_sub(sent) as u32);
+            self.last_pong = Some((sent_time, Instant::now()));
+        }
+    }
+
+    // the header, then the entities one after the other, each one after its data type, until NoData.
+    fn read_state(&mut self, data : &[u8])
+    {
+        let mut reader = PacketReader::new(data);
+        let (Ok(packet_number), Ok(_time), Ok(region)) = (reader.read_u64(), reader.read_u64(), reader.read_u16()) else
+        {
+            return;
+        };
+
+        self.stats.state_packets += 1;
+        // region 0 is chat, it is numbered per faction so there are gaps that are not losses.
+        if region != 0
+        {
+            self.stats.missing_state_packets += report::track_packet_number(&mut self.last_packet_numbers, region, packet_number);
+        }
+
+        let mut offset = data.len() - reader.remaining();
+        while let Some(data_type) = data.get(offset).copied()
+        {
+            let Some(entity) = schema::ENTITIES.iter().find(|entity| entity.code == Some(data_type)) else { break };
+            let Some(size) = entity.fixed_size() else { break };
+            let Some(entity_data) = data.get(offset + 1..offset + 1 + size) else { break };
+            self.stats.entities += 1;
+
+            if data_type == DataType::MobStatus as u8
+            {
+                self.remember_mob(entity.fields, entity_data);
+            }
+            else if data_type == DataType::TileState as u8
+            {
+                self.remember_resource(entity.fields, entity_data);
+            }
+            offset += 1 + size;
+        }
+    }
+
+    fn remember_mob(&mut self, fields : &[schema::Field], data : &[u8])
+    {
+        let Ok((mob, _size)) = schema::decode(fields, data) else { return };
+        let mob_id = mob["mob_id"].as_u64().unwrap_or_default() as u32;
+        let tile_id = TetrahedronId::from_string(mob["start_position"].as_str().unwrap_or_default());
+        let is_alive = mob["health"].as_u64().unwrap_or_default() > 0;
+
+        self.known_mobs.retain(|(known_mob_id, _tile_id)| *known_mob_id != mob_id);
+        if is_alive
+        {
+            remember(&mut self.known_mobs, (mob_id, tile_id));
+        }
+    }
+
+    fn remember_resource(&mut self, fields : &[schema::Field], data : &[u8])
+    {
+        let Ok((tile, _size)) = schema::decode(fields, data) else { return };
+        let tile_id = TetrahedronId::from_string(tile["id"].as_str().unwrap_or_default());
+        let has_prop = tile["prop"].as_u64().unwrap_or_default() > 0;
+
+        self.known_resources.retain(|known_tile_id| *known_tile_id != tile_id);
+        if has_prop
+        {
+            remember(&mut self.known_resources, tile_id);
+        }
+    }
+}
+
+fn remember<T>(known : &mut Vec<T>, item : T)
+{
+    if known.len() == MAX_KNOWN_TARGETS
+    {
+        known.remove(0);
+    }
+    known.push(item);
+}
+
+fn get_interval(interval_ms : u64) -> Option<Interval>
+{
+    if interval_ms == 0
+    {
+        return None;
+    }
+
+    // the first tick is right away, heroes that joined together would all act together.
+    let period = Duration::from_millis(interval_ms);
+    let start = Instant::now() + Duration::from_millis(rand::thread_rng().gen_range(0..interval_ms));
+    let mut interval = tokio::time::interval_at(start, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    Some(interval)
+}
+
+// an action that is turned off never happens.
+async fn tick(interval : &mut Option<Interval>)
+{
+    match interval
+    {
+        Some(interval) => { interval.tick().await; },
+        None => std::future::pending().await,
+    }
+}
+
+fn get_time_in_millis() -> u64
+{
+    let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
+    current_time.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game_server::clients_service::fragmentation;
+    use game_server::gameplay_service::data_packer;
+    use game_server::mob::mob_entity::{MobEntity, MOB_ENTITY_SIZE};
+
+    #[test]
+    fn state_packets_give_the_mobs_to_attack()
+    {
+        let session = Session { hero_id: 7, session_id: 1234, faction: 1, secret: [0u8; SESSION_SECRET_SIZE], position: TetrahedronId::from_string("t312222222") };
+        let mut bot = Bot::new(0, session);
+        assert_eq!(bot.get_attack(1), None);
+
+        let mob = MobEntity
+        {
+            mob_id: 300,
+            mob_definition_id: 2,
+            level: 3,
+            version: 4,
+            owner_id: 0,
+            ownership_time: 0,
+            start_position_id: TetrahedronId::from_string("t312222223"),
+            end_position_id: TetrahedronId::from_string("t312222223"),
+            path: [0; 6],
+            time: 0,
+            health: 8,
+            buffs: Vec::new(),
+            buffs_summary: [0; 5],
+        };
+
+        let mut buffer = [0u8; 5000];
+        let mut offset = data_packer::write_packet_header(&mut buffer, Protocol::GlobalState as u8, 10, 1_700_000_000_000, 5);
+        let mut count = 0;
+        data_packer::add_to_data_packet(&mut buffer, &mut offset, &mut count, DataType::MobStatus, MOB_ENTITY_SIZE, &mob.to_bytes());
+        bot.read_packet(&data_packer::encode_packet(&mut buffer, offset));
+
+        assert_eq!(bot.stats.state_packets, 1);
+        assert_eq!(bot.stats.entities, 1);
+        assert_eq!(bot.known_mobs, vec![(300, TetrahedronId::from_string("t312222223"))]);
+
+        let attack = bot.get_attack(1).unwrap();
+        assert_eq!(attack[0], Protocol::AttackMob as u8);
+        let request = schema::get_request(Protocol::AttackMob as u8).unwrap();
+        let (fields, size) = schema::decode(request.fields, &attack[1..]).unwrap();
+        assert_eq!(size, attack.len() - 1);
+        assert_eq!(fields["mob_id"], 300);
+        assert_eq!(fields["hero_id"], 7);
+    }
+
+    #[test]
+    fn fragmented_state_packets_are_put_back_together()
+    {
+        let session = Session { hero_id: 7, session_id: 1234, faction: 1, secret: [0u8; SESSION_SECRET_SIZE], position: TetrahedronId::from_string("t312222222") };
+        let mut bot = Bot::new(0, session);
+
+        let mut buffer = [0u8; 5000];
+        let mut offset = data_packer::write_packet_header(&mut buffer, Protocol::GlobalState as u8, 10, 1_700_000_000_000, 5);
+        let mut count = 0;
+        for mob_id in 0..20
+        {
+            let mob = MobEntity
+            {
+                mob_id,
+                mob_definition_id: 2,
+                level: 3,
+                version: 4,
+                owner_id: 0,
+                ownership_time: 0,
+                start_position_id: TetrahedronId::from_string("t312222223"),
+                end_position_id: TetrahedronId::from_string("t312222223"),
+                path: [0; 6],
+                time: 0,
+                health: 8,
+                buffs: Vec::new(),
+                buffs_summary: [0; 5],
+            };
+            data_packer::add_to_data_packet(&mut buffer, &mut offset, &mut count, DataType::MobStatus, MOB_ENTITY_SIZE, &mob.to_bytes());
+        }
+
+        // the way the server sends it, too big for a datagram.
+        let fragments = fragmentation::split(3, &buffer[..offset]).unwrap();
+        assert!(fragments.len() > 1);
+        for fragment in fragments.iter().rev()
+        {
+            bot.read_packet(fragment);
+        }
+
+        assert_eq!(bot.stats.received_packets, fragments.len() as u64);
+        assert_eq!(bot.stats.state_packets, 1);
+        assert_eq!(bot.stats.entities, 20);
+    }
+}
@@ -0,0 +1,70 @@
+// simulated heroes against a local server, they join through the api like the game client
+// and then move, attack, harvest and chat as the scenario says. Prints a report at the end.
+// run with: cargo run --release --bin load_test -- --scenario load_test.example.toml
+
+mod api;
+mod bot;
+mod report;
+mod scenario;
+
+use std::time::Duration;
+
+use clap::Parser;
+use tokio::time::Instant;
+
+use bot::Bot;
+use report::Report;
+use scenario::{Args, Scenario};
+
+#[tokio::main]
+async fn main()
+{
+    let args = Args::parse();
+    let scenario = match Scenario::load(&args)
+    {
+        Ok(scenario) => scenario,
+        Err(error) =>
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    // player names have to be unique, every run gets new players.
+    let run_id = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap().as_secs();
+    let client = hyper::Client::new();
+    let start = Instant::now();
+    let deadline = start + scenario.duration();
+
+    println!("starting {} heroes against {} for {} seconds", scenario.heroes, scenario.web_address, scenario.duration_secs);
+
+    let mut bots = Vec::with_capacity(scenario.heroes);
+    for index in 0..scenario.heroes
+    {
+        let uses_websocket = scenario.uses_websocket(index);
+        let bot_scenario = scenario.clone();
+        let client = client.clone();
+        let bot = tokio::spawn(async move
+        {
+            let player_name = format!("bot_{}_{}", run_id, index);
+            let session = api::login(&client, &bot_scenario.web_address, &player_name, bot_scenario.get_faction(index)).await?;
+            Bot::new(index, session).run(&bot_scenario, uses_websocket, deadline).await
+        });
+        bots.push((index, uses_websocket, bot));
+
+        tokio::time::sleep(Duration::from_millis(scenario.ramp_up_ms)).await;
+    }
+
+    let mut report = Report::default();
+    for (index, uses_websocket, bot) in bots
+    {
+        match bot.await
+        {
+            Ok(Ok(stats)) => report.add(uses_websocket, stats),
+            Ok(Err(error)) => report.add_failure(index, error),
+            Err(error) => report.add_failure(index, error.to_string()),
+        }
+    }
+
+    report.print(start.elapsed());
+}
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+// a bigger jump in the packet numbers of a region is the hero walking out of it and back,
+// not packets that got lost on the way.
+pub const MAX_PACKET_GAP : u64 = 64;
+
+// what one hero saw, merged into the report when it is done.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BotStats
+{
+    pub sent_packets : u64,
+    pub sent_bytes : u64,
+    pub send_errors : u64,
+    pub received_packets : u64,
+    pub received_bytes : u64,
+    pub state_packets : u64,
+    // estimated from the gaps in the region packet numbers.
+    pub missing_state_packets : u64,
+    pub entities : u64,
+    pub rejections : u64,
+    pub pings : u64,
+    pub pongs : u64,
+    // pings still waiting at the end count as lost only if they are older than the ping timeout.
+    pub lost_pings : u64,
+    pub rtt_samples : Vec<u32>,
+    pub moves : u64,
+    pub attacks : u64,
+    pub harvests : u64,
+    pub chats : u64,
+    // attacks and harvests with nothing known around to use them on.
+    pub skipped_actions : u64,
+}
+
+// returns how many packets are missing before this one, 0 for the first one of a region.
+pub fn track_packet_number(last_numbers : &mut HashMap<u16, u64>, region : u16, packet_number : u64) -> u64
+{
+    let Some(last) = last_numbers.get(&region).copied() else
+    {
+        last_numbers.insert(region, packet_number);
+        return 0;
+    };
+
+    // late or repeated, udp doesn't keep the order.
+    if packet_number <= last
+    {
+        return 0;
+    }
+
+    last_numbers.insert(region, packet_number);
+    let gap = packet_number - last - 1;
+    if gap > MAX_PACKET_GAP { 0 } else { gap }
+}
+
+// nearest rank, the samples have to be sorted.
+pub fn percentile(sorted_samples : &[u32], percent : f64) -> Option<u32>
+{
+    if sorted_samples.is_empty()
+    {
+        return None;
+    }
+
+    let rank = (percent / 100.0 * sorted_samples.len() as f64).ceil() as usize;
+    Some(sorted_samples[rank.clamp(1, sorted_samples.len()) - 1])
+}
+
+#[derive(Debug, Default)]
+pub struct Report
+{
+    pub udp_heroes : usize,
+    pub websocket_heroes : usize,
+    pub failed_heroes : Vec<(usize, String)>,
+    pub totals : BotStats,
+}
+
+impl Report
+{
+    pub fn add(&mut self, uses_websocket : bool, stats : BotStats)
+    {
+        if uses_websocket
+        {
+            self.websocket_heroes += 1;
+        }
+        else
+        {
+            self.udp_heroes += 1;
+        }
+
+        let totals = &mut self.totals;
+        totals.sent_packets += stats.sent_packets;
+        totals.sent_bytes += stats.sent_bytes;
+        totals.send_errors += stats.send_errors;
+        totals.received_packets += stats.received_packets;
+        totals.received_bytes += stats.received_bytes;
+        totals.state_packets += stats.state_packets;
+        totals.missing_state_packets += stats.missing_state_packets;
+        totals.entities += stats.entities;
+        totals.rejections += stats.rejections;
+        totals.pings += stats.pings;
+        totals.pongs += stats.pongs;
+        totals.lost_pings += stats.lost_pings;
+        totals.rtt_samples.extend(stats.rtt_samples);
+        totals.moves += stats.moves;
+        totals.attacks += stats.attacks;
+        totals.harvests += stats.harvests;
+        totals.chats += stats.chats;
+        totals.skipped_actions += stats.skipped_actions;
+    }
+
+    pub fn add_failure(&mut self, index : usize, error : String)
+    {
+        self.failed_heroes.push((index, error));
+    }
+
+    pub fn print(&mut self, elapsed : Duration)
+    {
+        let seconds = elapsed.as_secs_f64().max(0.001);
+        let totals = &mut self.totals;
+        totals.rtt_samples.sort_unstable();
+
+        println!("---------- load test, {:.1} seconds", seconds);
+        println!("heroes: {} udp, {} websocket, {} failed", self.udp_heroes, self.websocket_heroes, self.failed_heroes.len());
+        for (index, error) in self.failed_heroes.iter().take(5)
+        {
+            println!("  hero {}: {}", index, error);
+        }
+
+        println!("actions: {} moves, {} attacks, {} harvests, {} chats, {} skipped", totals.moves, totals.attacks, totals.harvests, totals.chats, totals.skipped_actions);
+
+        let rtt = |percent : f64| percentile(&totals.rtt_samples, percent).map(|rtt| format!("{} ms", rtt)).unwrap_or("-".to_string());
+        println!("rtt: p50 {}  p90 {}  p99 {}  max {}  ({} samples)", rtt(50.0), rtt(90.0), rtt(99.0), rtt(100.0), totals.rtt_samples.len());
+
+        let ping_loss = Self::get_loss(totals.lost_pings, totals.pongs + totals.lost_pings);
+        let state_loss = Self::get_loss(totals.missing_state_packets, totals.state_packets + totals.missing_state_packets);
+        println!("loss: {:.2}% of pings ({} of {}), {:.2}% of state packets ({} of {})",
+            ping_loss, totals.lost_pings, totals.pongs + totals.lost_pings,
+            state_loss, totals.missing_state_packets, totals.state_packets + totals.missing_state_packets);
+
+        println!("sent: {:.1} packets/s  {:.1} KB/s  ({} errors)", totals.sent_packets as f64 / seconds, totals.sent_bytes as f64 / 1024.0 / seconds, totals.send_errors);
+        println!("server: {:.1} packets/s  {:.1} KB/s  {:.1} entities/s  ({} rejections)",
+            totals.received_packets as f64 / seconds,
+            totals.received_bytes as f64 / 1024.0 / seconds,
+            totals.entities as f64 / seconds,
+            totals.rejections);
+    }
+
+    fn get_loss(lost : u64, total : u64) -> f64
+    {
+        if total == 0 { 0.0 } else { lost as f64 * 100.0 / total as f64 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_and_packet_gaps()
+    {
+        let samples : Vec<u32> = (1..=100).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(50));
+        assert_eq!(percentile(&samples, 99.0), Some(99));
+        assert_eq!(percentile(&samples, 100.0), Some(100));
+        assert_eq!(percentile(&[7], 0.0), Some(7));
+        assert_eq!(percentile(&[], 50.0), None);
+
+        let mut last_numbers = HashMap::new();
+        assert_eq!(track_packet_number(&mut last_numbers, 3, 10), 0);
+        assert_eq!(track_packet_number(&mut last_numbers, 3, 11), 0);
+        assert_eq!(track_packet_number(&mut last_numbers, 3, 14), 2);
+        assert_eq!(track_packet_number(&mut last_numbers, 3, 12), 0);
+        assert_eq!(track_packet_number(&mut last_numbers, 4, 500), 0);
+        assert_eq!(track_packet_number(&mut last_numbers, 3, 14 + MAX_PACKET_GAP + 2), 0);
+    }
+}
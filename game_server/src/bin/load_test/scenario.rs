@@ -0,0 +1,203 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use serde::Deserialize;
+
+// one chat message is at most this many characters, see chat_message_protocol.
+pub const MAX_CHAT_MESSAGE_SIZE : usize = 100;
+
+// how often each hero does something, in ms. 0 turns the action off.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ActionsConfig
+{
+    pub ping_ms : u64,
+    pub move_ms : u64,
+    // only mobs the hero got in a state packet, nothing is sent before that.
+    pub attack_ms : u64,
+    // same, tiles with a prop the hero got in a state packet.
+    pub harvest_ms : u64,
+    pub chat_ms : u64,
+    pub attack_card_id : u32,
+    pub harvest_damage : u16,
+    pub chat_messages : Vec<String>,
+}
+
+impl Default for ActionsConfig
+{
+    fn default() -> Self
+    {
+        ActionsConfig
+        {
+            ping_ms: 1000,
+            move_ms: 1000,
+            attack_ms: 3000,
+            harvest_ms: 5000,
+            chat_ms: 15000,
+            attack_card_id: 1,
+            harvest_damage: 1,
+            chat_messages: vec!["hello".to_string(), "anyone around?".to_string(), "gg".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario
+{
+    // the api, where we create the players and join.
+    pub web_address : String,
+    pub udp_address : SocketAddr,
+    pub websocket_address : String,
+    pub heroes : usize,
+    // 0 is every hero on udp, 1 every hero on websockets.
+    pub websocket_share : f32,
+    // heroes take turns, 1, 2 and 3 are the playable ones.
+    pub factions : Vec<u8>,
+    // between two heroes joining, so the api doesn't get everyone at once.
+    pub ramp_up_ms : u64,
+    // from the first hero joining to the report.
+    pub duration_secs : u64,
+    pub actions : ActionsConfig,
+}
+
+impl Default for Scenario
+{
+    fn default() -> Self
+    {
+        Scenario
+        {
+            web_address: "http://127.0.0.1:3031".to_string(),
+            udp_address: SocketAddr::from(([127, 0, 0, 1], 11002)),
+            websocket_address: "ws://127.0.0.1:11001".to_string(),
+            heroes: 10,
+            websocket_share: 0.0,
+            factions: vec![1, 2, 3],
+            ramp_up_ms: 100,
+            duration_secs: 60,
+            actions: ActionsConfig::default(),
+        }
+    }
+}
+
+impl Scenario
+{
+    pub fn from_toml(data : &str) -> Result<Self, String>
+    {
+        toml::from_str(data).map_err(|error| error.to_string())
+    }
+
+    // defaults, then the file, then the command line.
+    pub fn load(args : &Args) -> Result<Self, String>
+    {
+        let mut scenario = match &args.scenario
+        {
+            Some(path) =>
+            {
+                let data = std::fs::read_to_string(path).map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+                Self::from_toml(&data).map_err(|error| format!("invalid scenario {}: {}", path.display(), error))?
+            },
+            None => Scenario::default(),
+        };
+
+        scenario.apply(args);
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn apply(&mut self, args : &Args)
+    {
+        if let Some(heroes) = args.heroes { self.heroes = heroes; }
+        if let Some(websocket_share) = args.websocket_share { self.websocket_share = websocket_share; }
+        if let Some(duration_secs) = args.duration_secs { self.duration_secs = duration_secs; }
+    }
+
+    pub fn validate(&self) -> Result<(), String>
+    {
+        if self.heroes == 0
+        {
+            return Err("heroes has to be at least 1".to_string());
+        }
+
+        if !(0.0..=1.0).contains(&self.websocket_share)
+        {
+            return Err("websocket_share has to be between 0 and 1".to_string());
+        }
+
+        if self.factions.is_empty() || self.factions.iter().any(|faction| !(1..=3).contains(faction))
+        {
+            return Err("factions has to have at least one of 1, 2 or 3".to_string());
+        }
+
+        if self.duration_secs == 0
+        {
+            return Err("duration_secs has to be at least 1".to_string());
+        }
+
+        let actions = &self.actions;
+        if actions.chat_messages.iter().any(|message| message.is_empty() || message.chars().count() > MAX_CHAT_MESSAGE_SIZE)
+        {
+            return Err(format!("chat messages have to be between 1 and {} characters", MAX_CHAT_MESSAGE_SIZE));
+        }
+
+        if actions.chat_ms > 0 && actions.chat_messages.is_empty()
+        {
+            return Err("chat_messages can't be empty when chat_ms is set".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn get_faction(&self, index : usize) -> u8
+    {
+        self.factions[index % self.factions.len()]
+    }
+
+    // spreads the websocket heroes between the udp ones instead of putting them all at the end.
+    pub fn uses_websocket(&self, index : usize) -> bool
+    {
+        let share = self.websocket_share as f64;
+        ((index + 1) as f64 * share).floor() > (index as f64 * share).floor()
+    }
+
+    pub fn duration(&self) -> Duration
+    {
+        Duration::from_secs(self.duration_secs)
+    }
+}
+
+#[derive(Debug, Default, Parser)]
+#[command(name = "load_test", about = "simulated heroes against a local gaia server")]
+pub struct Args
+{
+    // toml, see load_test.example.toml. Missing values use the defaults.
+    #[arg(long)]
+    pub scenario : Option<PathBuf>,
+    #[arg(long)]
+    pub heroes : Option<usize>,
+    #[arg(long)]
+    pub websocket_share : Option<f32>,
+    #[arg(long)]
+    pub duration_secs : Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn example_scenario_is_the_default()
+    {
+        let scenario = Scenario::from_toml(include_str!("../../../load_test.example.toml")).unwrap();
+        assert_eq!(scenario, Scenario::default());
+        assert!(scenario.validate().is_ok());
+
+        let mut scenario = Scenario::from_toml("heroes = 4\nwebsocket_share = 0.5").unwrap();
+        assert_eq!((0..4).filter(|index| scenario.uses_websocket(*index)).count(), 2);
+        assert_eq!(scenario.get_faction(4), 2);
+
+        scenario.apply(&Args { heroes: Some(0), ..Args::default() });
+        assert!(scenario.validate().is_err());
+    }
+}
@@ -0,0 +1,188 @@
+// feeds a packet capture into the gameplay service against a copy of the world, on simulated time and
+// with seeded rolls, then prints the heroes, the changed tiles, the mobs, the towers and the kingdoms.
+// run it against a copy of the db taken before the capture started, the server keeps saving into the original.
+// run with: cargo run --release --features replay --bin replay -- --capture captures/world_088.gcap --world-name world_088_copy
+
+mod world;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use game_server::clients_service::connection::ConnectionRegistry;
+use game_server::clients_service::packet_capture::Capture;
+use game_server::gameplay_service::clock::SimulatedClock;
+use game_server::gameplay_service::generic_command::GenericCommand;
+use game_server::gameplay_service::{self, random};
+use game_server::hero::hero_command::HeroCommand;
+use game_server::kingdom::KingdomCommand;
+use game_server::map::map_entity::MapCommand;
+use game_server::mob::mob_command::MobCommand;
+use game_server::protocols::{self, protocol_version};
+use game_server::server_config::{self, ServerConfig};
+use game_server::tower::TowerCommand;
+use game_server::chat::ChatCommand;
+use game_server::{definitions, gaia_mpsc, ServerChannels, ServerState};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
+
+#[derive(Debug, Parser)]
+#[command(name = "replay", about = "replays a packet capture against a copy of the world")]
+struct Args
+{
+    #[arg(long)]
+    capture : PathBuf,
+    // the server config, for the db, the world name and the gameplay tick.
+    #[arg(long)]
+    config : Option<PathBuf>,
+    #[arg(long)]
+    world_name : Option<String>,
+    #[arg(long)]
+    db_uri : Option<String>,
+    // same capture, same world and same seed give the same states.
+    #[arg(long, default_value_t = 0)]
+    seed : u64,
+    // simulated time after the last packet, so the delayed commands still run.
+    #[arg(long, default_value_t = 5000)]
+    settle_ms : u64,
+    // the states go to stdout without it.
+    #[arg(long)]
+    output : Option<PathBuf>,
+}
+
+// start_service and the drains are polled in a fixed order, and the paused clock only moves when every task waits.
+#[tokio::main(flavor = "current_thread", start_paused = true)]
+async fn main()
+{
+    let args = Args::parse();
+    if let Err(error) = run(args).await
+    {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+async fn run(args : Args) -> Result<(), String>
+{
+    let capture = Capture::load(&args.capture)?;
+    if capture.protocol_version != protocol_version::PROTOCOL_VERSION
+    {
+        eprintln!("the capture was made with protocol {} and this server speaks {}, packets can be read differently",
+            capture.protocol_version,
+            protocol_version::PROTOCOL_VERSION);
+    }
+
+    let config = ServerConfig::load(&server_config::Args
+    {
+        config: args.config.clone(),
+        world_name: args.world_name.clone(),
+        db_uri: args.db_uri.clone(),
+        ..server_config::Args::default()
+    })?;
+
+    let (definitions, _) = definitions::load_definitions().await;
+    let map = Arc::new(world::load_world(&config, definitions).await?);
+    let initial_tiles = world::get_tiles(&map).await;
+    random::seed(args.seed);
+
+    let server_state = Arc::new(ServerState::new(None));
+    let (tx_gc_clients_gameplay, rx_gc_clients_gameplay) = gaia_mpsc::channel::<GenericCommand>(100, ServerChannels::TX_GC_ClIENTS_GAMEPLAY, server_state.clone());
+    let (tx_mc_clients_gameplay, rx_mc_clients_gameplay) = gaia_mpsc::channel::<MapCommand>(100, ServerChannels::TX_MC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_moc_clients_gameplay, rx_moc_clients_gameplay) = gaia_mpsc::channel::<MobCommand>(100, ServerChannels::TX_MOC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_pc_clients_gameplay, rx_pc_clients_gameplay) = gaia_mpsc::channel::<HeroCommand>(100, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_tc_clients_gameplay, rx_tc_clients_gameplay) = gaia_mpsc::channel::<TowerCommand>(100, ServerChannels::TX_TC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_kc_clients_gameplay, rx_kc_clients_gameplay) = gaia_mpsc::channel::<KingdomCommand>(100, ServerChannels::TX_KC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_cc_clients_gameplay, rx_cc_clients_gameplay) = gaia_mpsc::channel::<ChatCommand>(100, ServerChannels::TX_CC_CLIENTS_GAMEPLAY, server_state.clone());
+    let (tx_packets_gameplay_clients, rx_packets_gameplay_clients) = gaia_mpsc::channel(100, ServerChannels::TX_PACKETS_GAMEPLAY_CHAT_CLIENTS, server_state.clone());
+    let (tx_snapshots_gameplay_clients, rx_snapshots_gameplay_clients) = gaia_mpsc::channel(100, ServerChannels::TX_SNAPSHOTS_GAMEPLAY_CLIENTS, server_state.clone());
+
+    // nobody is listening, answers and chat are not part of the states we compare.
+    drain(rx_gc_clients_gameplay);
+    drain(rx_cc_clients_gameplay);
+    drain(rx_packets_gameplay_clients);
+    drain(rx_snapshots_gameplay_clients);
+
+    let (rx_me_gameplay_longterm,
+        rx_me_gameplay_webservice,
+        rx_moe_gameplay_webservice,
+        rx_he_gameplay_longterm,
+        rx_te_gameplay_longterm,
+        rx_te_gameplay_webservice,
+        rx_ke_gameplay_longterm,
+        rx_ke_gameplay_webservice,
+        _tx_mc_webservice_gameplay,
+    ) = gameplay_service::start_service(
+        rx_pc_clients_gameplay,
+        rx_mc_clients_gameplay,
+        rx_moc_clients_gameplay,
+        rx_tc_clients_gameplay,
+        rx_kc_clients_gameplay,
+        map.clone(),
+        server_state.clone(),
+        tx_packets_gameplay_clients,
+        tx_snapshots_gameplay_clients,
+        config.gameplay_tick(),
+        Arc::new(SimulatedClock::new(capture.start_time)));
+
+    // the db and the web service would get these, the replay never saves anything.
+    drain(rx_me_gameplay_longterm);
+    drain(rx_me_gameplay_webservice);
+    drain(rx_moe_gameplay_webservice);
+    drain(rx_he_gameplay_longterm);
+    drain(rx_te_gameplay_longterm);
+    drain(rx_te_gameplay_webservice);
+    drain(rx_ke_gameplay_longterm);
+    drain(rx_ke_gameplay_webservice);
+
+    let connections = Arc::new(ConnectionRegistry::new(map.clone(), server_state.clone()));
+    let started = Instant::now();
+    for record in &capture.records
+    {
+        tokio::time::sleep_until(started + Duration::from_millis(record.time as u64)).await;
+
+        // the join went through the api, the session in the packet is all we need from it.
+        if let Some(logged_in_session_id) = map.logged_in_players.get(record.hero_id as usize)
+        {
+            logged_in_session_id.store(record.session_id, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        protocols::dispatch_packet(
+            record.connection,
+            &record.data,
+            &map,
+            &server_state,
+            &connections,
+            &tx_gc_clients_gameplay,
+            &tx_pc_clients_gameplay,
+            &tx_mc_clients_gameplay,
+            &tx_moc_clients_gameplay,
+            &tx_tc_clients_gameplay,
+            &tx_kc_clients_gameplay,
+            &tx_cc_clients_gameplay).await;
+    }
+
+    tokio::time::sleep(Duration::from_millis(args.settle_ms)).await;
+
+    let states = world::dump_states(&map, &initial_tiles).await;
+    match &args.output
+    {
+        Some(path) => std::fs::write(path, states).map_err(|error| format!("can't write {}: {}", path.display(), error))?,
+        None => print!("{}", states),
+    }
+
+    let dropped_packets : u64 = server_state.dropped_packets.iter().map(|dropped| dropped.load(std::sync::atomic::Ordering::Relaxed)).sum();
+    eprintln!("replayed {} packets over {:.1} simulated seconds, {} dropped as malformed",
+        capture.records.len(),
+        started.elapsed().as_secs_f64(),
+        dropped_packets);
+    Ok(())
+}
+
+fn drain<T : Send + 'static>(mut rx : Receiver<T>)
+{
+    tokio::spawn(async move
+    {
+        while rx.recv().await.is_some() {}
+    });
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use game_server::definitions::definitions_container::Definitions;
+use game_server::long_term_storage_service::{heroes_service, kingdom_service, towers_service, world_service};
+use game_server::map::GameMap;
+use game_server::map::map_entity::MapEntity;
+use game_server::map::tetrahedron_id::TetrahedronId;
+use game_server::server_config::ServerConfig;
+use mongodb::Client;
+use mongodb::options::{ClientOptions, ResolverConfig};
+
+// the world as the server loads it on start, nothing is written back to the db.
+pub async fn load_world(config : &ServerConfig, definitions : Definitions) -> Result<GameMap, String>
+{
+    let options = ClientOptions::parse_with_resolver_config(&config.db_uri, ResolverConfig::cloudflare()).await
+        .map_err(|error| format!("invalid db uri: {}", error))?;
+    let db_client = Client::with_options(options).map_err(|error| format!("can't connect to the db: {}", error))?;
+
+    let world = world_service::check_world_state(&config.world_name, db_client.clone()).await
+        .ok_or(format!("world {} is not in the db", config.world_name))?;
+
+    let heroes = heroes_service::get_heroes_from_db_by_world(world.id, db_client.clone()).await;
+    let regions_db_data = world_service::get_regions_from_db(world.id, db_client.clone()).await;
+    let (regions, _) = world_service::load_regions_data_into_game_map(&regions_db_data);
+    let towers = towers_service::get_towers_from_db_by_world(world.id, db_client.clone()).await;
+    let kingdoms = kingdom_service::get_kingdoms_from_db_by_world(world.id, db_client).await;
+
+    Ok(GameMap::new(world.id, world.world_name, definitions, regions, Vec::new(), heroes, towers, kingdoms))
+}
+
+// the tiles before the replay, only the ones that changed end up in the dump.
+pub async fn get_tiles(map : &GameMap) -> HashMap<TetrahedronId, MapEntity>
+{
+    let mut tiles = HashMap::new();
+    for region in map.regions.values()
+    {
+        let region = region.lock().await;
+        tiles.extend(region.iter().map(|(id, tile)| (id.clone(), tile.clone())));
+    }
+    tiles
+}
+
+// one entity per line sorted by id, so two dumps can go straight into diff.
+pub async fn dump_states(map : &GameMap, initial_tiles : &HashMap<TetrahedronId, MapEntity>) -> String
+{
+    let mut output = String::new();
+
+    // one lock at a time, the gameplay could be waiting on another one while holding this.
+    let mut heroes : Vec<_> = map.character.lock().await.values().map(|hero| (hero.hero_id, format!("{:?}", hero))).collect();
+    heroes.sort();
+    writeln!(output, "# heroes {}", heroes.len()).unwrap();
+    for (_, hero) in heroes
+    {
+        writeln!(output, "{}", hero).unwrap();
+    }
+
+    let mut tiles = Vec::new();
+    for region in map.regions.values()
+    {
+        let region = region.lock().await;
+        tiles.extend(region.values().filter(|tile| initial_tiles.get(&tile.id) != Some(*tile)).map(|tile| (tile.id.to_string(), format!("{:?}", tile))));
+    }
+    tiles.sort();
+    writeln!(output, "# changed tiles {}", tiles.len()).unwrap();
+    for (_, tile) in tiles
+    {
+        writeln!(output, "{}", tile).unwrap();
+    }
+
+    let mut mobs = Vec::new();
+    for (region_id, region) in map.mobs.iter()
+    {
+        let region = region.lock().await;
+        mobs.extend(region.values().map(|mob| ((region_id.to_string(), mob.mob_id), format!("{:?}", mob))));
+    }
+    mobs.sort();
+    writeln!(output, "# mobs {}", mobs.len()).unwrap();
+    for (_, mob) in mobs
+    {
+        writeln!(output, "{}", mob).unwrap();
+    }
+
+    let mut towers : Vec<_> = map.towers.lock().await.values().map(|tower| (tower.tetrahedron_id.to_string(), format!("{:?}", tower))).collect();
+    towers.sort();
+    writeln!(output, "# towers {}", towers.len()).unwrap();
+    for (_, tower) in towers
+    {
+        writeln!(output, "{}", tower).unwrap();
+    }
+
+    let mut kingdoms : Vec<_> = map.kingdomes.lock().await.values().map(|kingdom| (kingdom.tetrahedron_id.to_string(), format!("{:?}", kingdom))).collect();
+    kingdoms.sort();
+    writeln!(output, "# kingdoms {}", kingdoms.len()).unwrap();
+    for (_, kingdom) in kingdoms
+    {
+        writeln!(output, "{}", kingdom).unwrap();
+    }
+
+    output
+}
@@ -7,14 +7,14 @@ use crate::{chat::{chat_entry::ChatEntry, ChatCommand}, gaia_mpsc::GaiaSender, m
 pub async fn process_chat_commands (
     _map : Arc<GameMap>,
     server_state: Arc<ServerState>,
+    current_time : u64,
     chat_commands_processor_lock : Arc<Mutex<Vec<ChatCommand>>>,
     tx_ce_chat_webservice : &GaiaSender<ChatEntry>,
     chat_summary : &mut [Vec<ChatEntry>; 10],
 )
 {
     let mut chat_commands_data = chat_commands_processor_lock.lock().await;
-    let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-    let current_time_in_seconds = current_time.as_secs() as u32;
+    let current_time_in_seconds = (current_time / 1000) as u32;
     if chat_commands_data.len() > 0 
     {
         for chat_command in chat_commands_data.iter()
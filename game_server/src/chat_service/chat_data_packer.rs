@@ -1,34 +1,21 @@
 use crate::chat::chat_entry::{CHAT_ENTRY_SIZE, ChatEntry};
 use crate::clients_service::DataType;
+use crate::gameplay_service::data_packer::write_packet_header;
 
 
 use std::io::prelude::*;
-use std::time::SystemTime;
 use bytes::Bytes;
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
 
-pub fn create_data_packets(faction: u8, data : &Vec<ChatEntry>, packet_number : &mut u64) -> Vec<(u64, u8, u16, u32, Bytes)> 
+pub fn create_data_packets(faction: u8, data : &Vec<ChatEntry>, packet_number : &mut u64, time : u64) -> Vec<(u64, u8, u16, u32, Bytes)> 
 {
     *packet_number = 0u64;
     // cli_log::info!("{packet_number} -A");
 
     let mut buffer = [0u8; 5000];
-    let mut start: usize = 1;
-    buffer[0] = crate::protocols::Protocol::GlobalState as u8;
-
-    let packet_number_bytes = u64::to_le_bytes(*packet_number); // 8 bytes
-    let end: usize = start + 8;
-    buffer[start..end].copy_from_slice(&packet_number_bytes);
-    start = end;
-
-    let result = std::time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
-    let current_time = result.ok().map(|d| d.as_secs() as u32);
-    let current_time_bytes = u32::to_le_bytes(current_time.unwrap()); // 4 bytes
- 
-    let end: usize = start + 4;
-    buffer[start..end].copy_from_slice(&current_time_bytes);
-    start = end;
+    // same header as the game packets, chat is always sent to the global region.
+    let mut start = write_packet_header(&mut buffer, crate::protocols::Protocol::GlobalState as u8, *packet_number, time, 0);
 
     let mut stored_bytes:u32 = 0;
     let mut stored_states:u8 = 0;
@@ -51,24 +38,12 @@ pub fn create_data_packets(faction: u8, data : &Vec<ChatEntry>, packet_number :
             // cli_log::info!("compressed {} vs normal {}", compressed_bytes.len(), buffer.len());
             packets.push((0, faction, 0, stored_states as u32, Bytes::from(compressed_bytes))); // this is a copy!
 
-            start = 1;
             stored_states = 0;
             stored_bytes = 0;
 
             *packet_number += 1u64;
             cli_log::info!("{packet_number} -B");
-            let end: usize = start + 8;
-            let packet_number_bytes = u64::to_le_bytes(*packet_number); // 8 bytes
-            buffer[start..end].copy_from_slice(&packet_number_bytes);
-            start = end;
-
-            let result = std::time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
-            let current_time = result.ok().map(|d| d.as_secs() as u32);
-            let current_time_bytes = u32::to_le_bytes(current_time.unwrap()); // 4 bytes
-        
-            let end: usize = start + 4;
-            buffer[start..end].copy_from_slice(&current_time_bytes);
-            start = end;
+            start = write_packet_header(&mut buffer, crate::protocols::Protocol::GlobalState as u8, *packet_number, time, 0);
         }
 
         buffer[start] = DataType::ChatMessage as u8;
@@ -4,6 +4,7 @@ use crate::{gaia_mpsc, ServerState};
 use crate::chat::ChatCommand;
 use crate::chat::chat_entry::ChatEntry;
 use crate::map::GameMap;
+use crate::gameplay_service::clock::Clock;
 use bytes::Bytes;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::Mutex;
@@ -15,7 +16,9 @@ pub fn start_service(
     mut rx_cc_client_game : tokio::sync::mpsc::Receiver<ChatCommand>,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
-    tx_packets_gameplay_chat_clients: gaia_mpsc::GaiaSender<Vec<(u64, u8, u16, u32, Bytes)>> //faction-data 0 means global
+    tx_packets_gameplay_chat_clients: gaia_mpsc::GaiaSender<Vec<(u64, u8, u16, u32, Bytes)>>, //faction-data 0 means global
+    tick : std::time::Duration,
+    clock : Arc<dyn Clock>
 ) 
 -> Receiver<ChatEntry>
 {
@@ -28,7 +31,7 @@ pub fn start_service(
     let chat_commands_processor_lock = chat_commands_mutex.clone();
     let chat_commands_agregator_from_client_lock = chat_commands_mutex.clone();
 
-    let mut interval = tokio::time::interval(std::time::Duration::from_millis(500));
+    let mut interval = tokio::time::interval(tick);
 
     tokio::spawn(async move 
     {
@@ -63,10 +66,12 @@ pub fn start_service(
         loop 
         {
             interval.tick().await;
+            let current_time_in_millis = clock.now_millis();
 
             chat_commands_processor::process_chat_commands(
                 map.clone(),
                 server_state.clone(),
+                current_time_in_millis,
                 chat_commands_processor_lock.clone(),
                 &tx_ce_chat_webservice,
                 &mut chat_summary,
@@ -80,7 +85,7 @@ pub fn start_service(
             {
                 if faction_chat.len() > 0 
                 {
-                    let packages = chat_data_packer::create_data_packets(faction as u8, faction_chat, &mut packet_number);
+                    let packages = chat_data_packer::create_data_packets(faction as u8, faction_chat, &mut packet_number, current_time_in_millis);
                     // the data that will be sent to each client is not copied.
                     tx_packets_gameplay_chat_clients.send(packages).await.unwrap();
                 }
@@ -1,12 +1,8 @@
 
 // mod create:utils;
 
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::atomic::AtomicU16;
 use tokio::time;
-use tokio::time::Duration;
-use tokio::sync::mpsc;
 
 use crate::ability_user::attack::Attack;
 use crate::ability_user::attack_result::AttackResult;
@@ -22,7 +18,9 @@ use crate::chat::ChatCommand;
 use crate::chat::chat_entry::ChatEntry;
 use crate::map::GameMap;
 use crate::map::map_entity::{MapEntity, MapCommand};
-use crate::protocols::disconnect_protocol;
+use super::connection::{ConnectionId, ConnectionRegistry, Registration, CONNECTION_TIMEOUT, RESUME_WINDOW};
+use super::fragmentation::{Reassembler, MAX_DATAGRAM_SIZE};
+use super::rate_limiter::{self, RateLimitConfig, RateLimitResult, RateLimiter};
 use crate::tower::TowerCommand;
 use crate::tower::tower_entity::TowerEntity;
 use crate::{gaia_mpsc, protocols, ServerState};
@@ -50,16 +48,17 @@ pub async fn spawn_client_process(
     from_address : std::net::SocketAddr, 
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
+    connections : Arc<ConnectionRegistry>,
+    registration : Registration,
     tx_gc_clients_gameplay : gaia_mpsc::GaiaSender<GenericCommand>,
-    diconnected_channel_tx : mpsc::Sender<(std::net::SocketAddr, u64)>,
     tx_mc_clients_gameplay : gaia_mpsc::GaiaSender<MapCommand>,
     tx_moc_clients_gameplay : gaia_mpsc::GaiaSender<MobCommand>,
     tx_pc_clients_gameplay : gaia_mpsc::GaiaSender<HeroCommand>,
     tx_tc_clients_gameplay : gaia_mpsc::GaiaSender<TowerCommand>,
     tx_kc_clients_gameplay : gaia_mpsc::GaiaSender<KingdomCommand>,
     tx_cc_clients_gameplay : gaia_mpsc::GaiaSender<ChatCommand>,
-    regions : Arc<HashMap<u16, [AtomicU16;3]>>,
-    initial_data : [u8; 508],
+    rate_limit_config : Arc<RateLimitConfig>,
+    initial_data : [u8; MAX_DATAGRAM_SIZE],
     packet_size: usize)
 {
     cli_log::info!("------ create reusable socket {} from {}", address, from_address);
@@ -74,31 +73,54 @@ pub async fn spawn_client_process(
     //messages from the client to the server, like an updated position
     tokio::spawn(async move 
     {
-        //handle the first package
-        protocols::route_packet(
-            from_address,
-            true,
-            &initial_data, 
-            packet_size,
-            &map,
-            &server_state,
-            &regions,
-            &tx_gc_clients_gameplay,
-            &tx_pc_clients_gameplay, 
-            &tx_mc_clients_gameplay,
-            &tx_moc_clients_gameplay,
-            &tx_tc_clients_gameplay,
-            &tx_kc_clients_gameplay,
-            &tx_cc_clients_gameplay,
-        ).await;
-
-        let mut child_buff = [0u8; 508];
+        let connection = ConnectionId::udp(from_address);
+        let Registration { generation, mut stopped } = registration;
+        let mut rate_limiter = RateLimiter::new(rate_limit_config);
+        let mut reassembler = Reassembler::new();
+        // the parent registered or resumed the connection before spawning us.
+        let stats = connections.get_stats(connection).await;
+        let stats = stats.as_deref();
+
+        //handle the first package, a resumed session has none, the parent already handled the resume.
+        if packet_size > 0
+        {
+            stats.inspect(|stats| stats.add_received(packet_size));
+            protocols::route_packet(
+                connection,
+                session_id,
+                player_id,
+                &initial_data, 
+                packet_size,
+                &map,
+                &server_state,
+                &connections,
+                &mut reassembler,
+                &mut rate_limiter,
+                stats,
+                &tx_gc_clients_gameplay,
+                &tx_pc_clients_gameplay, 
+                &tx_mc_clients_gameplay,
+                &tx_moc_clients_gameplay,
+                &tx_tc_clients_gameplay,
+                &tx_kc_clients_gameplay,
+                &tx_cc_clients_gameplay,
+            ).await;
+        }
+
+        let mut timed_out = false;
+        let mut child_buff = [0u8; MAX_DATAGRAM_SIZE];
         'main_loop : loop 
         {
             let socket_receive = socket_receiver.recv(&mut child_buff);
-            let time_out = time::sleep(Duration::from_secs_f32(10.0)); 
+            let time_out = time::sleep(CONNECTION_TIMEOUT); 
             tokio::select! 
             {
+                // resumed from another address or gone, the socket has to go before the client comes back here.
+                _ = stopped.changed() =>
+                {
+                    cli_log::info!("{} is not served by this task anymore", connection);
+                    break 'main_loop;
+                }
                 result = socket_receive => 
                 {
                     // read the player id and the session id and drop if session id is different
@@ -108,14 +130,19 @@ pub async fn spawn_client_process(
                         Ok(packet_size) => 
                         {
                             // cli_log::info!("Child: {:?} bytes received on child process for {}", size, from_address);
-                            protocols::route_packet(
-                                from_address,
-                                true,
+                            stats.inspect(|stats| stats.add_received(packet_size));
+                            let rate_limit = protocols::route_packet(
+                                connection,
+                                session_id,
+                                player_id,
                                 &child_buff, 
                                 packet_size,
                                 &map,
                                 &server_state,
-                                &regions,
+                                &connections,
+                                &mut reassembler,
+                                &mut rate_limiter,
+                                stats,
                                 &tx_gc_clients_gameplay,
                                 &tx_pc_clients_gameplay, 
                                 &tx_mc_clients_gameplay,
@@ -124,6 +151,11 @@ pub async fn spawn_client_process(
                                 &tx_kc_clients_gameplay,
                                 &tx_cc_clients_gameplay,
                             ).await;
+
+                            if rate_limit == RateLimitResult::Disconnect
+                            {
+                                break 'main_loop;
+                            }
                         }
                         Err(error) => 
                         {
@@ -135,16 +167,24 @@ pub async fn spawn_client_process(
                 _ = time_out => 
                 {
                     cli_log::info!("we couldn't wait any longer sorry!");
+                    timed_out = true;
                     break 'main_loop;
                 }
             }
         }
 
-        // before disconnecting, we set action to 0, to indicate that the player is not active
-        disconnect_protocol::process(player_id, &tx_pc_clients_gameplay).await;
+        // the client may be switching networks, it has some time to resume from the new address.
+        if timed_out
+        {
+            tokio::select!
+            {
+                _ = time::sleep(RESUME_WINDOW) => {},
+                _ = stopped.changed() => {},
+            }
+        }
 
-        // if we are here, this task expired and we need to remove the key from the hashset
-        diconnected_channel_tx.send((from_address, session_id)).await.unwrap();
+        // if we are here, this task expired and the client has to login again, unless the session moved.
+        connections.expire(connection, generation, &tx_pc_clients_gameplay).await;
 
     });
     // borrowed_socket
@@ -0,0 +1,170 @@
+use std::collections::VecDeque;
+
+// the offset comes from the fastest of the last samples, slow ones are the most asymmetric.
+pub const OFFSET_SAMPLES : usize = 8;
+// anything slower is a lost ping that came back late, not latency.
+pub const MAX_RTT_MS : u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientLatency
+{
+    pub session_id : u64,
+    // smoothed round trip time.
+    pub rtt_ms : u32,
+    // how much the round trip time moves around.
+    pub jitter_ms : u32,
+    // client clock minus server clock.
+    pub clock_offset_ms : i64,
+    pub samples : u32,
+}
+
+// round trip and clock offset of one session, measured from its pings. The smoothing is the
+// same as tcp uses for its retransmit timer (rfc 6298), the jitter is its rtt variation.
+#[derive(Debug, Default)]
+pub struct ClockSync
+{
+    smoothed_rtt : f32,
+    rtt_variation : f32,
+    recent : VecDeque<(u32, i64)>,
+    samples : u32,
+    // server time of the last pong, the next ping echoes it back.
+    last_pong_time : Option<u64>,
+}
+
+impl ClockSync
+{
+    pub fn new() -> Self
+    {
+        Self::default()
+    }
+
+    pub fn add_sample(&mut self, rtt_ms : u32, clock_offset_ms : i64)
+    {
+        let rtt = rtt_ms as f32;
+        if self.samples == 0
+        {
+            self.smoothed_rtt = rtt;
+            self.rtt_variation = rtt / 2.0;
+        }
+        else
+        {
+            self.rtt_variation = 0.75 * self.rtt_variation + 0.25 * (self.smoothed_rtt - rtt).abs();
+            self.smoothed_rtt = 0.875 * self.smoothed_rtt + 0.125 * rtt;
+        }
+        self.samples = self.samples.saturating_add(1);
+
+        if self.recent.len() == OFFSET_SAMPLES
+        {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((rtt_ms, clock_offset_ms));
+    }
+
+    pub fn pong_sent(&mut self, sent_time : u64)
+    {
+        self.last_pong_time = Some(sent_time);
+    }
+
+    // the echo only tells us which pong the ping answers, the time is the one we kept, so a client
+    // can't make up its round trip. Each pong is measured once.
+    pub fn add_ping(&mut self, now : u64, client_time : u64, echoed_server_time : u64, hold_ms : u32) -> Option<(u32, i64)>
+    {
+        let pong_time = self.last_pong_time.filter(|pong_time| *pong_time == echoed_server_time)?;
+        self.last_pong_time = None;
+
+        let (rtt_ms, clock_offset_ms) = measure(now, client_time, pong_time, hold_ms)?;
+        self.add_sample(rtt_ms, clock_offset_ms);
+        Some((rtt_ms, clock_offset_ms))
+    }
+
+    pub fn get_latency(&self, session_id : u64) -> ClientLatency
+    {
+        let clock_offset_ms = self.recent.iter().min_by_key(|(rtt, _offset)| *rtt).map(|(_rtt, offset)| *offset).unwrap_or_default();
+        ClientLatency
+        {
+            session_id,
+            rtt_ms: self.smoothed_rtt.round() as u32,
+            jitter_ms: self.rtt_variation.round() as u32,
+            clock_offset_ms,
+            samples: self.samples,
+        }
+    }
+}
+
+// the ping says how long the client held the last pong, so the time since that pong left,
+// minus the hold, is the round trip. All times in ms.
+// the client sent the ping half a round trip ago by our clock, the offset is how far its clock is from that.
+pub fn measure(now : u64, client_time : u64, pong_time : u64, hold_ms : u32) -> Option<(u32, i64)>
+{
+    if pong_time == 0 || pong_time > now
+    {
+        return None;
+    }
+
+    let rtt = (now - pong_time).checked_sub(hold_ms as u64)?;
+    if rtt > MAX_RTT_MS
+    {
+        return None;
+    }
+
+    let client_time_by_server_clock = now - rtt / 2;
+    let clock_offset = client_time as i64 - client_time_by_server_clock as i64;
+    Some((rtt as u32, clock_offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_and_offset_come_from_the_last_pong()
+    {
+        // pong left at 1000, the client held it 30 ms, the ping came back at 1130.
+        // the client clock is 500 ms ahead, it sent the ping at 1080 by our clock.
+        assert_eq!(measure(1130, 1580, 1000, 30), Some((100, 500)));
+
+        assert_eq!(measure(1130, 1580, 0, 30), None);
+        assert_eq!(measure(1130, 1580, 2000, 30), None);
+        assert_eq!(measure(1130, 1580, 1000, 200), None);
+        assert_eq!(measure(20_000, 0, 1000, 0), None);
+    }
+
+    #[test]
+    fn pings_are_measured_against_the_pong_we_sent()
+    {
+        let mut clock = ClockSync::new();
+        // nothing sent yet, whatever the client echoes.
+        assert_eq!(clock.add_ping(1130, 1580, 1000, 30), None);
+
+        // an echo that is not our pong would make the round trip whatever the client wants.
+        clock.pong_sent(1000);
+        assert_eq!(clock.add_ping(1130, 1580, 900, 30), None);
+        assert_eq!(clock.add_ping(1130, 1580, 1000, 30), Some((100, 500)));
+        assert_eq!(clock.get_latency(3).samples, 1);
+
+        // the same pong again is a replay.
+        assert_eq!(clock.add_ping(1230, 1680, 1000, 30), None);
+        assert_eq!(clock.get_latency(3).samples, 1);
+    }
+
+    #[test]
+    fn latency_is_smoothed()
+    {
+        let mut clock = ClockSync::new();
+        clock.add_sample(100, 40);
+        assert_eq!(clock.get_latency(3), ClientLatency { session_id: 3, rtt_ms: 100, jitter_ms: 50, clock_offset_ms: 40, samples: 1 });
+
+        // one slow sample moves the average a bit and the jitter more, the offset stays with the fast sample.
+        clock.add_sample(180, 90);
+        let latency = clock.get_latency(3);
+        assert_eq!(latency.rtt_ms, 110);
+        assert_eq!(latency.jitter_ms, 58);
+        assert_eq!(latency.clock_offset_ms, 40);
+
+        for _ in 0..OFFSET_SAMPLES
+        {
+            clock.add_sample(120, 60);
+        }
+        assert_eq!(clock.get_latency(3).clock_offset_ms, 60);
+    }
+}
@@ -0,0 +1,855 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU16, AtomicU64};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::{watch, Mutex};
+
+use crate::gaia_mpsc::GaiaSender;
+use crate::hero::hero_command::HeroCommand;
+use crate::map::GameMap;
+use crate::protocols::disconnect_protocol;
+use super::clock_sync::ClockSync;
+use super::connection_stats::ConnectionStats;
+use super::fragmentation;
+use super::reliable_channel::ReliableChannel;
+use crate::ServerState;
+
+// both transports drop a client that doesn't send anything for this long.
+pub const CONNECTION_TIMEOUT : Duration = Duration::from_secs(10);
+// a udp client that went quiet keeps its session this much longer, so it can resume from another address.
+pub const RESUME_WINDOW : Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransportKind
+{
+    Udp,
+    WebSocket,
+}
+
+// the address alone is not enough, a udp and a tcp client can share it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId
+{
+    pub kind : TransportKind,
+    pub address : SocketAddr,
+}
+
+impl ConnectionId
+{
+    pub fn udp(address : SocketAddr) -> Self
+    {
+        ConnectionId { kind: TransportKind::Udp, address }
+    }
+
+    pub fn websocket(address : SocketAddr) -> Self
+    {
+        ConnectionId { kind: TransportKind::WebSocket, address }
+    }
+}
+
+impl std::fmt::Display for ConnectionId
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self.kind
+        {
+            TransportKind::Udp => write!(f, "udp:{}", self.address),
+            TransportKind::WebSocket => write!(f, "ws:{}", self.address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportError
+{
+    // the socket or the queue is full, the packet is lost like any other udp packet.
+    WouldBlock,
+    Closed,
+    // more than fragmentation::MAX_MESSAGE_SIZE.
+    TooLarge,
+}
+
+pub trait Transport : Send + Sync
+{
+    // never waits, broadcasting can't be held back by a slow client.
+    fn send(&self, data : Bytes) -> Result<(), TransportError>;
+}
+
+pub struct UdpTransport
+{
+    pub socket : Arc<tokio::net::UdpSocket>,
+    pub address : SocketAddr,
+    next_message_id : AtomicU16,
+}
+
+impl UdpTransport
+{
+    pub fn new(socket : Arc<tokio::net::UdpSocket>, address : SocketAddr) -> Self
+    {
+        UdpTransport { socket, address, next_message_id: AtomicU16::new(0) }
+    }
+
+    fn send_datagram(&self, data : &[u8]) -> Result<(), TransportError>
+    {
+        match self.socket.try_send_to(data, self.address)
+        {
+            Ok(_) => Ok(()),
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => Err(TransportError::WouldBlock),
+            Err(_) => Err(TransportError::Closed),
+        }
+    }
+}
+
+impl Transport for UdpTransport
+{
+    // anything bigger than a datagram goes in fragments, losing one loses the whole message.
+    fn send(&self, data : Bytes) -> Result<(), TransportError>
+    {
+        if data.len() <= fragmentation::MAX_DATAGRAM_SIZE
+        {
+            return self.send_datagram(&data);
+        }
+
+        let message_id = self.next_message_id.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let fragments = fragmentation::split(message_id, &data).ok_or(TransportError::TooLarge)?;
+        for fragment in fragments
+        {
+            self.send_datagram(&fragment)?;
+        }
+        Ok(())
+    }
+}
+
+// the websocket is written by its own task, we only queue the data.
+pub struct WebSocketTransport
+{
+    pub link : tokio::sync::mpsc::Sender<Bytes>,
+}
+
+impl Transport for WebSocketTransport
+{
+    fn send(&self, data : Bytes) -> Result<(), TransportError>
+    {
+        match self.link.try_send(data)
+        {
+            Ok(_) => Ok(()),
+            Err(tokio::sync::mpsc::error::TrySendError::Full(_)) => Err(TransportError::WouldBlock),
+            Err(tokio::sync::mpsc::error::TrySendError::Closed(_)) => Err(TransportError::Closed),
+        }
+    }
+}
+
+// keeps everything it is asked to send, for the tests that need a client on the other side.
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub struct RecordingTransport
+{
+    pub sent : Arc<std::sync::Mutex<Vec<Bytes>>>,
+}
+
+#[cfg(test)]
+impl Transport for RecordingTransport
+{
+    fn send(&self, data : Bytes) -> Result<(), TransportError>
+    {
+        self.sent.lock().unwrap().push(data);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRole
+{
+    Hero,
+    // no hero, can't send gameplay packets. Spectators only get what every faction gets.
+    Spectator,
+    Admin,
+}
+
+// what the task serving a connection gets when registering it. The connection only belongs to the task
+// while the generation matches, a resume or a new registration of the same address gives a new one.
+pub struct Registration
+{
+    pub generation : u64,
+    // changes or closes once the connection is not this task's anymore, the task should stop right away.
+    pub stopped : watch::Receiver<bool>,
+}
+
+impl Registration
+{
+    pub fn is_stopped(&self) -> bool
+    {
+        self.stopped.has_changed().unwrap_or(true)
+    }
+}
+
+pub struct Connection
+{
+    pub role : ConnectionRole,
+    // 0 for spectators and admins.
+    pub hero_id : u16,
+    pub session_id : u64,
+    pub faction : u8,
+    // what the client said in its greet, None until then.
+    pub protocol_version : Option<u16>,
+    transport : Box<dyn Transport>,
+    generation : u64,
+    stop : watch::Sender<bool>,
+    // moves with the connection on resume, pending messages go to the new address.
+    reliable : ReliableChannel,
+    clock : ClockSync,
+    stats : Arc<ConnectionStats>,
+}
+
+// the first packet of a connection has to carry the session that is logged in for that hero.
+pub fn is_logged_in(map : &GameMap, hero_id : u16, session_id : u64) -> bool
+{
+    map.logged_in_players
+        .get(hero_id as usize)
+        .map(|stored_session_id| stored_session_id.load(std::sync::atomic::Ordering::Relaxed))
+        .is_some_and(|stored_session_id| stored_session_id == session_id && stored_session_id != 0)
+}
+
+// deltas negotiated by an old session don't count, the new one has to ack again.
+pub fn uses_deltas(map : &GameMap, delta_clients : &HashSet<(u16, u64)>, hero_id : u16) -> bool
+{
+    map.logged_in_players
+        .get(hero_id as usize)
+        .map(|session_id| session_id.load(std::sync::atomic::Ordering::Relaxed))
+        .is_some_and(|session_id| session_id != 0 && delta_clients.contains(&(hero_id, session_id)))
+}
+
+#[derive(Default)]
+struct SentStats
+{
+    bytes : u64,
+    udp_packets : u64,
+    game_packets : u64,
+}
+
+// every validated connection, udp or websocket. Registering, disconnecting and sending
+// go through here so both transports behave the same.
+pub struct ConnectionRegistry
+{
+    connections : Mutex<HashMap<ConnectionId, Connection>>,
+    next_generation : AtomicU64,
+    map : Arc<GameMap>,
+    server_state : Arc<ServerState>,
+}
+
+impl ConnectionRegistry
+{
+    pub fn new(map : Arc<GameMap>, server_state : Arc<ServerState>) -> Self
+    {
+        ConnectionRegistry { connections: Mutex::new(HashMap::new()), next_generation: AtomicU64::new(1), map, server_state }
+    }
+
+    fn create_registration(&self) -> (u64, watch::Sender<bool>, Registration)
+    {
+        let generation = self.next_generation.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let (stop, stopped) = watch::channel(false);
+        (generation, stop, Registration { generation, stopped })
+    }
+
+    pub async fn contains(&self, id : ConnectionId) -> bool
+    {
+        self.connections.lock().await.contains_key(&id)
+    }
+
+    // a task still serving an older registration of this address is stopped.
+    pub async fn register(&self, id : ConnectionId, hero_id : u16, session_id : u64, faction : u8, transport : Box<dyn Transport>) -> Registration
+    {
+        cli_log::info!("registering {} for hero {} with session {}", id, hero_id, session_id);
+        let stats = Arc::new(ConnectionStats::new(hero_id, ConnectionRole::Hero));
+        let (generation, stop, registration) = self.create_registration();
+        let previous = self.connections.lock().await.insert(id, Connection { role: ConnectionRole::Hero, hero_id, session_id, faction, protocol_version: None, transport, generation, stop, reliable: ReliableChannel::new(), clock: ClockSync::new(), stats: stats.clone() });
+        self.server_state.connection_stats.lock().unwrap().insert(id, stats);
+        if previous.is_none()
+        {
+            self.server_state.online_players.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+        self.map.add_hero_connection(id, hero_id).await;
+        registration
+    }
+
+    // spectators don't count as online players and don't follow any hero until they ask for it.
+    pub async fn register_spectator(&self, id : ConnectionId, role : ConnectionRole, transport : Box<dyn Transport>) -> Registration
+    {
+        cli_log::info!("registering {} as {:?}", id, role);
+        let stats = Arc::new(ConnectionStats::new(0, role));
+        let (generation, stop, registration) = self.create_registration();
+        self.connections.lock().await.insert(id, Connection { role, hero_id: 0, session_id: 0, faction: 0, protocol_version: None, transport, generation, stop, reliable: ReliableChannel::new(), clock: ClockSync::new(), stats: stats.clone() });
+        self.server_state.connection_stats.lock().unwrap().insert(id, stats);
+        registration
+    }
+
+    pub async fn remove_spectator(&self, id : ConnectionId)
+    {
+        let mut connections = self.connections.lock().await;
+        if connections.get(&id).is_none_or(|connection| connection.role == ConnectionRole::Hero)
+        {
+            return;
+        }
+        connections.remove(&id);
+        drop(connections);
+
+        cli_log::info!("removing spectator {}", id);
+        self.server_state.connection_stats.lock().unwrap().remove(&id);
+        self.map.region_subscriptions.remove_connection(id).await;
+    }
+
+    pub async fn set_protocol_version(&self, id : ConnectionId, version : u16)
+    {
+        if let Some(connection) = self.connections.lock().await.get_mut(&id)
+        {
+            connection.protocol_version = Some(version);
+        }
+    }
+
+    pub async fn get_protocol_version(&self, id : ConnectionId) -> Option<u16>
+    {
+        self.connections.lock().await.get(&id).and_then(|connection| connection.protocol_version)
+    }
+
+    // the counters the connection task has to update, None if it is not registered.
+    pub async fn get_stats(&self, id : ConnectionId) -> Option<Arc<ConnectionStats>>
+    {
+        self.connections.lock().await.get(&id).map(|connection| connection.stats.clone())
+    }
+
+    // the live udp connection of this session, if the hero still has one.
+    pub async fn find_session(&self, hero_id : u16, session_id : u64) -> Option<ConnectionId>
+    {
+        let connections = self.connections.lock().await;
+        connections.iter()
+            .find(|(id, connection)| id.kind == TransportKind::Udp && connection.hero_id == hero_id && connection.session_id == session_id)
+            .map(|(id, _connection)| *id)
+    }
+
+    // same session, hero and subscriptions, only the address changes. The old task is stopped and
+    // its generation is gone, so it can't disconnect the session even if it comes back to the old address.
+    pub async fn resume(&self, old_id : ConnectionId, new_id : ConnectionId, transport : Box<dyn Transport>) -> Option<Registration>
+    {
+        let mut connections = self.connections.lock().await;
+        let mut connection = connections.remove(&old_id)?;
+        cli_log::info!("resuming session of hero {} from {} on {}", connection.hero_id, old_id, new_id);
+        let (generation, stop, registration) = self.create_registration();
+        connection.transport = transport;
+        connection.generation = generation;
+        let _ = std::mem::replace(&mut connection.stop, stop).send(true);
+        let stats = connection.stats.clone();
+        connections.insert(new_id, connection);
+        drop(connections);
+
+        self.move_stats(old_id, new_id, stats);
+
+        self.map.region_subscriptions.move_connection(old_id, new_id).await;
+        Some(registration)
+    }
+
+    // the client has to login again after this, whatever the reason was.
+    pub async fn disconnect(&self, id : ConnectionId, session_id : u64, tx_pc_clients_gameplay : &GaiaSender<HeroCommand>)
+    {
+        self.remove_hero_connection(id, |connection| connection.session_id == session_id, tx_pc_clients_gameplay).await;
+    }
+
+    // the task of that registration is done, a connection that moved or was registered again is not its own anymore.
+    pub async fn expire(&self, id : ConnectionId, generation : u64, tx_pc_clients_gameplay : &GaiaSender<HeroCommand>)
+    {
+        self.remove_hero_connection(id, |connection| connection.generation == generation, tx_pc_clients_gameplay).await;
+    }
+
+    async fn remove_hero_connection(&self, id : ConnectionId, is_current : impl Fn(&Connection) -> bool, tx_pc_clients_gameplay : &GaiaSender<HeroCommand>)
+    {
+        let mut connections = self.connections.lock().await;
+        if connections.get(&id).is_none_or(|connection| connection.role != ConnectionRole::Hero || !is_current(connection))
+        {
+            cli_log::info!("probably a reconnection {}", id);
+            return;
+        }
+        let connection = connections.remove(&id).unwrap();
+        drop(connections);
+
+        let session_id = connection.session_id;
+        cli_log::info!("disconnecting {} for hero {}", id, connection.hero_id);
+        self.server_state.online_players.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+        self.map.region_subscriptions.remove_connection(id).await;
+        self.map.delta_tracker.remove(connection.hero_id, session_id).await;
+        self.remove_latency(connection.hero_id, session_id);
+        self.server_state.connection_stats.lock().unwrap().remove(&id);
+        if let Some(logged_in_session_id) = self.map.logged_in_players.get(connection.hero_id as usize)
+        {
+            let _ = logged_in_session_id.compare_exchange(session_id, 0, std::sync::atomic::Ordering::Relaxed, std::sync::atomic::Ordering::Relaxed);
+        }
+
+        // before disconnecting, we set action to 0, to indicate that the player is not active
+        disconnect_protocol::process(connection.hero_id, tx_pc_clients_gameplay).await;
+    }
+
+    pub async fn send_to(&self, id : ConnectionId, data : Bytes, reliable : bool)
+    {
+        let mut connections = self.connections.lock().await;
+        let Some(connection) = connections.get_mut(&id) else
+        {
+            cli_log::error!("client not found for direct message {}", id);
+            return;
+        };
+
+        // tcp already delivers in order, only udp clients that acked before get the reliable lane.
+        let data = if reliable && id.kind == TransportKind::Udp && connection.reliable.is_enabled()
+        {
+            match connection.reliable.push(&data, Instant::now())
+            {
+                Ok(packet) => packet,
+                Err(error) =>
+                {
+                    // the client stopped acking long ago, it is gone. Its task ends and disconnects it.
+                    cli_log::error!("reliable lane of {} is {:?}, dropping the client", id, error);
+                    let _ = connection.stop.send(true);
+                    return;
+                }
+            }
+        }
+        else
+        {
+            data
+        };
+        let data_size = data.len() as u64;
+
+        if let Err(error) = connection.transport.send(data)
+        {
+            connection.stats.add_failed(error);
+            cli_log::info!("error sending specific data to {} {:?}", id, error);
+            return;
+        }
+        connection.stats.add_sent(data_size as usize);
+        drop(connections);
+
+        self.add_sent_stats(&SentStats { bytes: data_size, udp_packets: 1, game_packets: 1 });
+    }
+
+    pub async fn ack_reliable(&self, id : ConnectionId, sequence : u32)
+    {
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get_mut(&id)
+        {
+            connection.reliable.ack(sequence);
+        }
+    }
+
+    // when the pong left, the next ping is measured against it.
+    pub async fn pong_sent(&self, id : ConnectionId, sent_time : u64)
+    {
+        if let Some(connection) = self.connections.lock().await.get_mut(&id)
+        {
+            connection.clock.pong_sent(sent_time);
+        }
+    }
+
+    // a ping that answers our last pong is a new measurement, the result is also published in the server state.
+    pub async fn add_ping(&self, id : ConnectionId, now : u64, client_time : u64, echoed_server_time : u64, hold_ms : u32)
+    {
+        let mut connections = self.connections.lock().await;
+        let Some(connection) = connections.get_mut(&id) else { return };
+        if connection.clock.add_ping(now, client_time, echoed_server_time, hold_ms).is_none()
+        {
+            return;
+        }
+        let hero_id = connection.hero_id;
+        let latency = connection.clock.get_latency(connection.session_id);
+        connection.stats.set_rtt(latency.rtt_ms);
+        drop(connections);
+
+        self.server_state.client_latencies.lock().unwrap().insert(hero_id, latency);
+    }
+
+    fn move_stats(&self, old_id : ConnectionId, new_id : ConnectionId, stats : Arc<ConnectionStats>)
+    {
+        let mut connection_stats = self.server_state.connection_stats.lock().unwrap();
+        connection_stats.remove(&old_id);
+        connection_stats.insert(new_id, stats);
+    }
+
+    // a reconnection may have published the new session already.
+    fn remove_latency(&self, hero_id : u16, session_id : u64)
+    {
+        let mut latencies = self.server_state.client_latencies.lock().unwrap();
+        if latencies.get(&hero_id).is_some_and(|latency| latency.session_id == session_id)
+        {
+            latencies.remove(&hero_id);
+        }
+    }
+
+    // reliable messages that are still waiting for their ack.
+    pub async fn resend_reliable(&self)
+    {
+        let mut stats = SentStats::default();
+        let now = Instant::now();
+        let mut connections = self.connections.lock().await;
+        for (id, connection) in connections.iter_mut()
+        {
+            for packet in connection.reliable.get_due(now)
+            {
+                let packet_size = packet.len() as u64;
+                match connection.transport.send(packet)
+                {
+                    Ok(_) =>
+                    {
+                        connection.stats.add_sent(packet_size as usize);
+                        stats.bytes += packet_size;
+                        stats.udp_packets += 1;
+                    },
+                    Err(error) =>
+                    {
+                        connection.stats.add_failed(error);
+                        cli_log::info!("error resending reliable data to {} {:?}", id, error);
+                    },
+                }
+            }
+        }
+        drop(connections);
+
+        self.add_sent_stats(&stats);
+    }
+
+    // region 0 is for everyone, the rest only goes to the region subscribers.
+    pub async fn broadcast(&self, packet_list : &[(u64, u8, u16, u32, Bytes)])
+    {
+        let mut stats = SentStats::default();
+        let delta_clients = self.map.delta_tracker.get_clients().await;
+        let subscribers = self.map.region_subscriptions.get_subscribers(packet_list.iter().map(|packet| packet.2)).await;
+        let connections = self.connections.lock().await;
+        let everyone : Vec<ConnectionId> = connections.keys().copied().collect();
+        let no_subscribers = Vec::new();
+
+        for (_packet_id, faction, region, game_packets, data) in packet_list.iter()
+        {
+            let ids = if *region == 0 { &everyone } else { subscribers.get(region).unwrap_or(&no_subscribers) };
+            for id in ids
+            {
+                let Some(connection) = connections.get(id) else { continue };
+
+                // these clients get their entities from the delta packets.
+                if *region != 0 && connection.role == ConnectionRole::Hero && uses_deltas(&self.map, &delta_clients, connection.hero_id)
+                {
+                    continue;
+                }
+
+                if connection.faction != *faction && *faction != 0 && connection.role != ConnectionRole::Admin
+                {
+                    continue;
+                }
+
+                match connection.transport.send(data.clone())
+                {
+                    Ok(_) =>
+                    {
+                        connection.stats.add_sent(data.len());
+                        stats.bytes += data.len() as u64;
+                        stats.udp_packets += 1;
+                        stats.game_packets += *game_packets as u64;
+                    },
+                    Err(error) =>
+                    {
+                        connection.stats.add_failed(error);
+                        cli_log::info!("error sending data to {} {:?}", id, error);
+                    },
+                }
+            }
+        }
+        drop(connections);
+
+        self.add_sent_stats(&stats);
+    }
+
+    // entity snapshots for the clients that negotiated deltas, encoded for each of them.
+    pub async fn broadcast_snapshots(&self, time : u64, snapshots : &[(u16, Vec<(u8, Bytes)>)])
+    {
+        let mut stats = SentStats::default();
+        let delta_clients = self.map.delta_tracker.get_clients().await;
+        let subscribers = self.map.region_subscriptions.get_subscribers(snapshots.iter().map(|(region, _)| *region)).await;
+        // who gets what first, the packets are built without holding the registry lock.
+        let mut receivers = Vec::new();
+        let connections = self.connections.lock().await;
+        for (region, region_snapshots) in snapshots.iter()
+        {
+            for id in subscribers.get(region).into_iter().flatten()
+            {
+                let Some(connection) = connections.get(id) else { continue };
+                if connection.role == ConnectionRole::Hero && uses_deltas(&self.map, &delta_clients, connection.hero_id)
+                {
+                    receivers.push((*id, connection.hero_id, *region, region_snapshots));
+                }
+            }
+        }
+        drop(connections);
+
+        let mut outgoing = Vec::with_capacity(receivers.len());
+        for (id, hero_id, region, region_snapshots) in receivers
+        {
+            let packets = self.map.delta_tracker.build_packets(hero_id, region, time, region_snapshots).await;
+            outgoing.push((id, packets.unwrap_or_default(), region_snapshots.len()));
+        }
+
+        let connections = self.connections.lock().await;
+        for (id, packets, game_packets) in outgoing
+        {
+            // it can be gone while we were building.
+            let Some(connection) = connections.get(&id) else { continue };
+            for packet in packets
+            {
+                let packet_size = packet.len() as u64;
+                match connection.transport.send(packet)
+                {
+                    Ok(_) =>
+                    {
+                        connection.stats.add_sent(packet_size as usize);
+                        stats.bytes += packet_size;
+                        stats.udp_packets += 1;
+                    },
+                    Err(error) =>
+                    {
+                        connection.stats.add_failed(error);
+                        cli_log::info!("error sending delta data to {} {:?}", id, error);
+                    },
+                }
+            }
+            stats.game_packets += game_packets as u64;
+        }
+        drop(connections);
+
+        self.add_sent_stats(&stats);
+    }
+
+    fn add_sent_stats(&self, stats : &SentStats)
+    {
+        self.server_state.sent_bytes.fetch_add(stats.bytes, std::sync::atomic::Ordering::Relaxed);
+        self.server_state.sent_udp_packets.fetch_add(stats.udp_packets, std::sync::atomic::Ordering::Relaxed);
+        self.server_state.sent_game_packets.fetch_add(stats.game_packets, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    use crate::clients_service::DataType;
+    use crate::clients_service::reliable_channel::MAX_PENDING;
+    use crate::hero::hero_command::HeroCommandInfo;
+    use crate::map::map_entity::MapEntity;
+    use crate::protocols::Protocol;
+    use crate::{gaia_mpsc, ServerChannels};
+
+    const REGION : u16 = 5;
+
+    async fn create_registry() -> (Arc<GameMap>, Arc<ServerState>, ConnectionRegistry)
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        let server_state = Arc::new(ServerState::new(None));
+        let connections = ConnectionRegistry::new(map.clone(), server_state.clone());
+        (map, server_state, connections)
+    }
+
+    fn udp(port : u16) -> ConnectionId
+    {
+        ConnectionId::udp(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    fn websocket(port : u16) -> ConnectionId
+    {
+        ConnectionId::websocket(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    async fn register_hero(map : &GameMap, connections : &ConnectionRegistry, id : ConnectionId, hero_id : u16, faction : u8) -> RecordingTransport
+    {
+        let session_id = 100 + hero_id as u64;
+        map.logged_in_players[hero_id as usize].store(session_id, Ordering::Relaxed);
+        let transport = RecordingTransport::default();
+        connections.register(id, hero_id, session_id, faction, Box::new(transport.clone())).await;
+        map.region_subscriptions.subscribe(id, &[REGION]).await;
+        transport
+    }
+
+    fn take_sent(transport : &RecordingTransport) -> Vec<Bytes>
+    {
+        std::mem::take(&mut *transport.sent.lock().unwrap())
+    }
+
+    #[tokio::test]
+    async fn disconnect_needs_the_registered_session()
+    {
+        let (map, server_state, connections) = create_registry().await;
+        let (tx_hc, mut rx_hc) = gaia_mpsc::channel::<HeroCommand>(10, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let id = udp(5000);
+        register_hero(&map, &connections, id, 3, 1).await;
+        assert_eq!(server_state.online_players.load(Ordering::Relaxed), 1);
+        assert!(connections.get_stats(id).await.is_some());
+
+        // an old task of the same address, the session moved on.
+        connections.disconnect(id, 102, &tx_hc).await;
+        assert!(connections.contains(id).await);
+        assert!(rx_hc.try_recv().is_err());
+
+        connections.disconnect(id, 103, &tx_hc).await;
+        assert!(!connections.contains(id).await);
+        assert_eq!(server_state.online_players.load(Ordering::Relaxed), 0);
+        assert_eq!(map.logged_in_players[3].load(Ordering::Relaxed), 0);
+        assert!(map.region_subscriptions.get_regions(id).await.is_empty());
+        assert!(!server_state.connection_stats.lock().unwrap().contains_key(&id));
+        let command = rx_hc.try_recv().unwrap();
+        assert_eq!(command.player_id, 3);
+        assert!(matches!(command.info, HeroCommandInfo::Disconnect()));
+    }
+
+    #[tokio::test]
+    async fn broadcast_filters_factions_and_delta_clients()
+    {
+        let (map, _server_state, connections) = create_registry().await;
+        let red = register_hero(&map, &connections, udp(5000), 1, 1).await;
+        let green = register_hero(&map, &connections, websocket(5001), 2, 2).await;
+        let red_with_deltas = register_hero(&map, &connections, udp(5002), 4, 1).await;
+        map.delta_tracker.ack(4, 104, &[]).await;
+        let admin = RecordingTransport::default();
+        connections.register_spectator(websocket(5003), ConnectionRole::Admin, Box::new(admin.clone())).await;
+        map.region_subscriptions.subscribe(websocket(5003), &[REGION]).await;
+        let spectator = RecordingTransport::default();
+        connections.register_spectator(websocket(5004), ConnectionRole::Spectator, Box::new(spectator.clone())).await;
+        map.region_subscriptions.subscribe(websocket(5004), &[REGION]).await;
+
+        connections.broadcast(&[
+            (1, 1, REGION, 1, Bytes::from_static(b"red")),
+            (2, 0, REGION, 1, Bytes::from_static(b"everyone in the region")),
+            (3, 2, 0, 1, Bytes::from_static(b"green everywhere")),
+            (4, 0, 0, 1, Bytes::from_static(b"everyone")),
+            (5, 0, REGION + 1, 1, Bytes::from_static(b"nobody")),
+        ]).await;
+
+        assert_eq!(take_sent(&red), vec![Bytes::from_static(b"red"), Bytes::from_static(b"everyone in the region"), Bytes::from_static(b"everyone")]);
+        assert_eq!(take_sent(&green), vec![Bytes::from_static(b"everyone in the region"), Bytes::from_static(b"green everywhere"), Bytes::from_static(b"everyone")]);
+        // regions come as deltas, the global packets don't.
+        assert_eq!(take_sent(&red_with_deltas), vec![Bytes::from_static(b"everyone")]);
+        assert_eq!(take_sent(&admin).len(), 4);
+        assert_eq!(take_sent(&spectator), vec![Bytes::from_static(b"everyone in the region"), Bytes::from_static(b"everyone")]);
+
+        let tile = Bytes::copy_from_slice(&MapEntity::new("a0123", 100).to_bytes());
+        connections.broadcast_snapshots(1_700_000_000_000, &[(REGION, vec![(DataType::TileState as u8, tile)])]).await;
+        assert!(!take_sent(&red_with_deltas).is_empty());
+        assert!(take_sent(&red).is_empty());
+        assert!(take_sent(&admin).is_empty());
+        assert!(take_sent(&spectator).is_empty());
+
+        // a new login doesn't keep the deltas of the old session.
+        map.logged_in_players[4].store(204, Ordering::Relaxed);
+        connections.broadcast(&[(6, 1, REGION, 1, Bytes::from_static(b"red"))]).await;
+        assert_eq!(take_sent(&red_with_deltas), vec![Bytes::from_static(b"red")]);
+    }
+
+    #[tokio::test]
+    async fn resume_keeps_the_session_on_the_new_address()
+    {
+        let (map, server_state, connections) = create_registry().await;
+        let (tx_hc, _rx_hc) = gaia_mpsc::channel::<HeroCommand>(10, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let old_id = udp(5000);
+        let new_id = udp(5001);
+        let old_transport = register_hero(&map, &connections, old_id, 3, 1).await;
+        assert_eq!(connections.find_session(3, 103).await, Some(old_id));
+
+        let new_transport = RecordingTransport::default();
+        assert!(connections.resume(old_id, new_id, Box::new(new_transport.clone())).await.is_some());
+        assert!(connections.resume(old_id, new_id, Box::new(RecordingTransport::default())).await.is_none());
+        assert!(!connections.contains(old_id).await);
+        assert_eq!(connections.find_session(3, 103).await, Some(new_id));
+        assert_eq!(map.region_subscriptions.get_regions(new_id).await, HashSet::from([REGION]));
+        assert!(server_state.connection_stats.lock().unwrap().contains_key(&new_id));
+        assert!(!server_state.connection_stats.lock().unwrap().contains_key(&old_id));
+
+        connections.send_to(new_id, Bytes::from_static(b"hello"), false).await;
+        assert!(take_sent(&old_transport).is_empty());
+        assert_eq!(take_sent(&new_transport), vec![Bytes::from_static(b"hello")]);
+
+        // the old task expiring doesn't end the session.
+        connections.disconnect(old_id, 103, &tx_hc).await;
+        assert!(connections.contains(new_id).await);
+        assert_eq!(map.logged_in_players[3].load(Ordering::Relaxed), 103);
+        assert_eq!(server_state.online_players.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn old_tasks_are_stopped_and_cant_expire_a_resumed_session()
+    {
+        let (map, server_state, connections) = create_registry().await;
+        let (tx_hc, _rx_hc) = gaia_mpsc::channel::<HeroCommand>(10, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let first_id = udp(5000);
+        let second_id = udp(5001);
+        map.logged_in_players[3].store(103, Ordering::Relaxed);
+        let first = connections.register(first_id, 3, 103, 1, Box::new(RecordingTransport::default())).await;
+
+        // the client goes to another network and comes back before the first task expired.
+        let second = connections.resume(first_id, second_id, Box::new(RecordingTransport::default())).await.unwrap();
+        assert!(first.is_stopped());
+        let back = connections.resume(second_id, first_id, Box::new(RecordingTransport::default())).await.unwrap();
+        assert!(second.is_stopped());
+        assert!(!back.is_stopped());
+        assert!(back.generation != first.generation && back.generation != second.generation);
+
+        connections.expire(first_id, first.generation, &tx_hc).await;
+        connections.expire(second_id, second.generation, &tx_hc).await;
+        assert!(connections.contains(first_id).await);
+        assert_eq!(map.logged_in_players[3].load(Ordering::Relaxed), 103);
+
+        // the task serving it now is the one that can end it.
+        connections.expire(first_id, back.generation, &tx_hc).await;
+        assert!(!connections.contains(first_id).await);
+        assert_eq!(map.logged_in_players[3].load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn clients_that_stop_acking_are_dropped()
+    {
+        let (map, _server_state, connections) = create_registry().await;
+        let id = udp(5000);
+        let transport = RecordingTransport::default();
+        map.logged_in_players[3].store(103, Ordering::Relaxed);
+        let registration = connections.register(id, 3, 103, 1, Box::new(transport.clone())).await;
+        connections.ack_reliable(id, 0).await;
+
+        for _ in 0..MAX_PENDING
+        {
+            connections.send_to(id, Bytes::from_static(b"inventory"), true).await;
+        }
+        assert!(!registration.is_stopped());
+
+        connections.send_to(id, Bytes::from_static(b"inventory"), true).await;
+        assert!(registration.is_stopped());
+        let sent = take_sent(&transport);
+        assert_eq!(sent.len(), MAX_PENDING);
+        assert!(sent.iter().all(|packet| packet[0] == Protocol::Reliable as u8));
+    }
+
+    #[tokio::test]
+    async fn only_spectators_are_removed_as_spectators()
+    {
+        let (map, server_state, connections) = create_registry().await;
+        let hero_id = websocket(5000);
+        register_hero(&map, &connections, hero_id, 3, 1).await;
+        let spectator_id = websocket(5001);
+        connections.register_spectator(spectator_id, ConnectionRole::Spectator, Box::new(RecordingTransport::default())).await;
+        map.region_subscriptions.subscribe(spectator_id, &[REGION]).await;
+        assert_eq!(server_state.online_players.load(Ordering::Relaxed), 1);
+
+        connections.remove_spectator(hero_id).await;
+        assert!(connections.contains(hero_id).await);
+
+        connections.remove_spectator(spectator_id).await;
+        assert!(!connections.contains(spectator_id).await);
+        assert!(map.region_subscriptions.get_regions(spectator_id).await.is_empty());
+        assert!(!server_state.connection_stats.lock().unwrap().contains_key(&spectator_id));
+        assert_eq!(server_state.online_players.load(Ordering::Relaxed), 1);
+        assert_eq!(map.region_subscriptions.get_subscribers([REGION].into_iter()).await[&REGION], vec![hero_id]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU32, AtomicU64};
+
+use super::connection::{ConnectionId, ConnectionRole, TransportError, TransportKind};
+
+// counters of one connection, shared by its task, the registry and the tui.
+// they move with the session on a resume, so they cover every address it had.
+#[derive(Debug)]
+pub struct ConnectionStats
+{
+    pub hero_id : u16,
+    pub role : ConnectionRole,
+    received_bytes : AtomicU64,
+    received_packets : AtomicU64,
+    sent_bytes : AtomicU64,
+    sent_packets : AtomicU64,
+    // the socket or the websocket queue was full.
+    dropped_packets : AtomicU64,
+    rate_limited_packets : AtomicU64,
+    // ms, last packet from the client.
+    last_activity : AtomicU64,
+    rtt_ms : AtomicU32,
+}
+
+impl ConnectionStats
+{
+    pub fn new(hero_id : u16, role : ConnectionRole) -> Self
+    {
+        ConnectionStats
+        {
+            hero_id,
+            role,
+            received_bytes: AtomicU64::new(0),
+            received_packets: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            sent_packets: AtomicU64::new(0),
+            dropped_packets: AtomicU64::new(0),
+            rate_limited_packets: AtomicU64::new(0),
+            last_activity: AtomicU64::new(get_time_in_millis()),
+            rtt_ms: AtomicU32::new(0),
+        }
+    }
+
+    pub fn add_received(&self, bytes : usize)
+    {
+        self.received_bytes.fetch_add(bytes as u64, std::sync::atomic::Ordering::Relaxed);
+        self.received_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.last_activity.store(get_time_in_millis(), std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn add_sent(&self, bytes : usize)
+    {
+        self.sent_bytes.fetch_add(bytes as u64, std::sync::atomic::Ordering::Relaxed);
+        self.sent_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn add_dropped(&self)
+    {
+        self.dropped_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    // only a full queue is a dropped packet, a closed one means the connection is going away.
+    pub fn add_failed(&self, error : TransportError)
+    {
+        if error == TransportError::WouldBlock
+        {
+            self.add_dropped();
+        }
+    }
+
+    pub fn add_rate_limited(&self)
+    {
+        self.rate_limited_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn set_rtt(&self, rtt_ms : u32)
+    {
+        self.rtt_ms.store(rtt_ms, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_snapshot(&self, id : ConnectionId) -> ConnectionStatsSnapshot
+    {
+        let order = std::sync::atomic::Ordering::Relaxed;
+        ConnectionStatsSnapshot
+        {
+            id,
+            hero_id: self.hero_id,
+            role: self.role,
+            rtt_ms: self.rtt_ms.load(order),
+            received_bytes: self.received_bytes.load(order),
+            received_packets: self.received_packets.load(order),
+            sent_bytes: self.sent_bytes.load(order),
+            sent_packets: self.sent_packets.load(order),
+            dropped_packets: self.dropped_packets.load(order),
+            rate_limited_packets: self.rate_limited_packets.load(order),
+            last_activity: self.last_activity.load(order),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionStatsSnapshot
+{
+    pub id : ConnectionId,
+    pub hero_id : u16,
+    pub role : ConnectionRole,
+    pub rtt_ms : u32,
+    pub received_bytes : u64,
+    pub received_packets : u64,
+    pub sent_bytes : u64,
+    pub sent_packets : u64,
+    pub dropped_packets : u64,
+    pub rate_limited_packets : u64,
+    pub last_activity : u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsColumn
+{
+    Hero,
+    Transport,
+    Rtt,
+    BytesIn,
+    BytesOut,
+    Dropped,
+    RateLimited,
+    LastActivity,
+}
+
+impl StatsColumn
+{
+    pub const ALL : [StatsColumn; 8] = [
+        StatsColumn::Hero,
+        StatsColumn::Transport,
+        StatsColumn::Rtt,
+        StatsColumn::BytesIn,
+        StatsColumn::BytesOut,
+        StatsColumn::Dropped,
+        StatsColumn::RateLimited,
+        StatsColumn::LastActivity,
+    ];
+
+    pub fn get_name(&self) -> &'static str
+    {
+        match self
+        {
+            StatsColumn::Hero => "hero",
+            StatsColumn::Transport => "transport",
+            StatsColumn::Rtt => "rtt",
+            StatsColumn::BytesIn => "in",
+            StatsColumn::BytesOut => "out",
+            StatsColumn::Dropped => "dropped",
+            StatsColumn::RateLimited => "rate limited",
+            StatsColumn::LastActivity => "idle",
+        }
+    }
+
+    pub fn next(&self) -> StatsColumn
+    {
+        let index = StatsColumn::ALL.iter().position(|column| column == self).unwrap_or_default();
+        StatsColumn::ALL[(index + 1) % StatsColumn::ALL.len()]
+    }
+
+    fn compare(&self, a : &ConnectionStatsSnapshot, b : &ConnectionStatsSnapshot) -> Ordering
+    {
+        let transport = |snapshot : &ConnectionStatsSnapshot| snapshot.id.kind == TransportKind::WebSocket;
+        match self
+        {
+            StatsColumn::Hero => a.hero_id.cmp(&b.hero_id),
+            StatsColumn::Transport => transport(a).cmp(&transport(b)),
+            StatsColumn::Rtt => a.rtt_ms.cmp(&b.rtt_ms),
+            StatsColumn::BytesIn => a.received_bytes.cmp(&b.received_bytes),
+            StatsColumn::BytesOut => a.sent_bytes.cmp(&b.sent_bytes),
+            StatsColumn::Dropped => a.dropped_packets.cmp(&b.dropped_packets),
+            StatsColumn::RateLimited => a.rate_limited_packets.cmp(&b.rate_limited_packets),
+            // idle time, the oldest activity is the longest idle.
+            StatsColumn::LastActivity => b.last_activity.cmp(&a.last_activity),
+        }
+    }
+}
+
+// ties keep the hero order, so rows don't jump around between refreshes.
+pub fn sort_snapshots(snapshots : &mut [ConnectionStatsSnapshot], column : StatsColumn, descending : bool)
+{
+    snapshots.sort_by(|a, b|
+    {
+        let ordering = column.compare(a, b);
+        let ordering = if descending { ordering.reverse() } else { ordering };
+        ordering.then_with(|| a.hero_id.cmp(&b.hero_id)).then_with(|| a.id.address.cmp(&b.id.address))
+    });
+}
+
+fn get_time_in_millis() -> u64
+{
+    let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
+    current_time.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(port : u16, hero_id : u16, stats : &ConnectionStats) -> ConnectionStatsSnapshot
+    {
+        let mut snapshot = stats.get_snapshot(ConnectionId::udp(std::net::SocketAddr::from(([127, 0, 0, 1], port))));
+        snapshot.hero_id = hero_id;
+        snapshot
+    }
+
+    #[test]
+    fn snapshots_sort_by_any_column()
+    {
+        let quiet = ConnectionStats::new(1, ConnectionRole::Hero);
+        quiet.add_received(10);
+        quiet.set_rtt(300);
+
+        let busy = ConnectionStats::new(2, ConnectionRole::Hero);
+        busy.add_received(500);
+        busy.add_received(500);
+        busy.add_sent(40);
+        busy.add_dropped();
+        busy.add_rate_limited();
+        busy.set_rtt(30);
+
+        let busy_snapshot = snapshot(2, 2, &busy);
+        assert_eq!(busy_snapshot.received_bytes, 1000);
+        assert_eq!(busy_snapshot.received_packets, 2);
+        assert_eq!(busy_snapshot.sent_packets, 1);
+        assert_eq!(busy_snapshot.dropped_packets, 1);
+
+        let mut snapshots = vec![snapshot(1, 1, &quiet), busy_snapshot, snapshot(3, 3, &quiet)];
+        sort_snapshots(&mut snapshots, StatsColumn::BytesIn, true);
+        assert_eq!(snapshots.iter().map(|snapshot| snapshot.hero_id).collect::<Vec<u16>>(), vec![2, 1, 3]);
+
+        sort_snapshots(&mut snapshots, StatsColumn::Rtt, false);
+        assert_eq!(snapshots.iter().map(|snapshot| snapshot.hero_id).collect::<Vec<u16>>(), vec![2, 1, 3]);
+
+        sort_snapshots(&mut snapshots, StatsColumn::Hero, true);
+        assert_eq!(snapshots.iter().map(|snapshot| snapshot.hero_id).collect::<Vec<u16>>(), vec![3, 2, 1]);
+
+        assert_eq!(StatsColumn::LastActivity.next(), StatsColumn::Hero);
+    }
+}
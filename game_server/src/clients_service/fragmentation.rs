@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::protocols::Protocol;
+use crate::protocols::packet_auth::AUTH_TRAILER_SIZE;
+use crate::protocols::packet_reader::ProtocolError;
+
+// the biggest udp payload that is safe everywhere, and the size of our receive buffers.
+pub const MAX_DATAGRAM_SIZE : usize = 508;
+// protocol (1 byte), message id (2 bytes), index (1 byte) and count (1 byte).
+pub const FRAGMENT_HEADER_SIZE : usize = 5;
+pub const MAX_FRAGMENT_PAYLOAD : usize = MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE;
+pub const MAX_FRAGMENTS : usize = 32;
+pub const MAX_MESSAGE_SIZE : usize = MAX_FRAGMENTS * MAX_FRAGMENT_PAYLOAD;
+// client fragments also carry the session id, hero id and faction, and over udp the auth trailer.
+// the whole signed fragment has to fit in a datagram, websocket clients use the same limit.
+pub const CLIENT_FRAGMENT_HEADER_SIZE : usize = FRAGMENT_HEADER_SIZE + 8 + 2 + 1;
+pub const MAX_CLIENT_FRAGMENT_PAYLOAD : usize = MAX_DATAGRAM_SIZE - CLIENT_FRAGMENT_HEADER_SIZE - AUTH_TRAILER_SIZE;
+
+// a message that doesn't complete in this time is not going to.
+pub const REASSEMBLY_TIMEOUT : Duration = Duration::from_secs(2);
+// messages being put together at the same time for one client.
+pub const MAX_PARTIAL_MESSAGES : usize = 4;
+
+// splits a message that doesn't fit in one datagram, each fragment is sent as its own packet.
+// the last fragment can be shorter, the receiver only needs all of them to put the message back.
+pub fn split(message_id : u16, data : &[u8]) -> Option<Vec<Bytes>>
+{
+    if data.len() > MAX_MESSAGE_SIZE
+    {
+        return None;
+    }
+
+    let chunks : Vec<&[u8]> = data.chunks(MAX_FRAGMENT_PAYLOAD).collect();
+    let count = chunks.len() as u8;
+    let fragments = chunks.iter()
+        .enumerate()
+        .map(|(index, chunk)|
+        {
+            let mut buffer = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            buffer.push(Protocol::Fragment as u8);
+            buffer.extend_from_slice(&u16::to_le_bytes(message_id));
+            buffer.push(index as u8);
+            buffer.push(count);
+            buffer.extend_from_slice(chunk);
+            Bytes::from(buffer)
+        })
+        .collect();
+    Some(fragments)
+}
+
+struct PartialMessage
+{
+    chunks : Vec<Option<Vec<u8>>>,
+    received : usize,
+    size : usize,
+    started : Instant,
+}
+
+// puts client messages back together, one per connection.
+pub struct Reassembler
+{
+    partial_messages : HashMap<u16, PartialMessage>,
+}
+
+impl Reassembler
+{
+    pub fn new() -> Self
+    {
+        Reassembler { partial_messages: HashMap::new() }
+    }
+
+    // the whole message once its last missing fragment arrives.
+    pub fn push(&mut self, message_id : u16, index : u8, count : u8, chunk : &[u8], now : Instant) -> Result<Option<Vec<u8>>, ProtocolError>
+    {
+        if count == 0 || count as usize > MAX_FRAGMENTS
+        {
+            return Err(ProtocolError::InvalidField("count"));
+        }
+
+        if index >= count
+        {
+            return Err(ProtocolError::InvalidField("index"));
+        }
+
+        if chunk.is_empty() || chunk.len() > MAX_FRAGMENT_PAYLOAD
+        {
+            return Err(ProtocolError::InvalidField("fragment_size"));
+        }
+
+        self.partial_messages.retain(|_, message| now.duration_since(message.started) < REASSEMBLY_TIMEOUT);
+
+        if !self.partial_messages.contains_key(&message_id) && self.partial_messages.len() >= MAX_PARTIAL_MESSAGES
+        {
+            return Err(ProtocolError::InvalidField("message_id"));
+        }
+
+        let message = self.partial_messages
+            .entry(message_id)
+            .or_insert_with(|| PartialMessage { chunks: vec![None; count as usize], received: 0, size: 0, started: now });
+
+        if message.chunks.len() != count as usize
+        {
+            return Err(ProtocolError::InvalidField("count"));
+        }
+
+        let slot = &mut message.chunks[index as usize];
+        if slot.is_none()
+        {
+            message.received += 1;
+            message.size += chunk.len();
+            *slot = Some(chunk.to_vec());
+        }
+
+        if message.received < message.chunks.len()
+        {
+            return Ok(None);
+        }
+
+        let message = self.partial_messages.remove(&message_id).unwrap();
+        let mut data = Vec::with_capacity(message.size);
+        for chunk in message.chunks.into_iter().flatten()
+        {
+            data.extend_from_slice(&chunk);
+        }
+        Ok(Some(data))
+    }
+
+    pub fn partial_len(&self) -> usize
+    {
+        self.partial_messages.len()
+    }
+}
+
+impl Default for Reassembler
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_fragment(reassembler : &mut Reassembler, fragment : &[u8], now : Instant) -> Result<Option<Vec<u8>>, ProtocolError>
+    {
+        let message_id = u16::from_le_bytes([fragment[1], fragment[2]]);
+        reassembler.push(message_id, fragment[3], fragment[4], &fragment[FRAGMENT_HEADER_SIZE..], now)
+    }
+
+    #[test]
+    fn split_messages_come_back_in_any_order()
+    {
+        let now = Instant::now();
+        let data : Vec<u8> = (0..2000).map(|value| value as u8).collect();
+        let fragments = split(7, &data).unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments.iter().all(|fragment| fragment.len() <= MAX_DATAGRAM_SIZE && fragment[0] == Protocol::Fragment as u8));
+
+        let mut reassembler = Reassembler::new();
+        for fragment in [&fragments[3], &fragments[1], &fragments[1], &fragments[0]]
+        {
+            assert_eq!(push_fragment(&mut reassembler, fragment, now).unwrap(), None);
+        }
+        assert_eq!(push_fragment(&mut reassembler, &fragments[2], now).unwrap(), Some(data));
+        assert_eq!(reassembler.partial_len(), 0);
+
+        assert!(split(8, &vec![0u8; MAX_MESSAGE_SIZE + 1]).is_none());
+    }
+
+    #[test]
+    fn incomplete_messages_expire()
+    {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        for message_id in 0..MAX_PARTIAL_MESSAGES as u16
+        {
+            reassembler.push(message_id, 0, 2, &[1], now).unwrap();
+        }
+        assert!(reassembler.push(100, 0, 2, &[1], now).is_err());
+        assert!(reassembler.push(0, 0, 3, &[1], now).is_err());
+        assert!(reassembler.push(0, 2, 2, &[1], now).is_err());
+
+        let later = now + REASSEMBLY_TIMEOUT;
+        assert_eq!(reassembler.push(100, 0, 2, &[1], later).unwrap(), None);
+        assert_eq!(reassembler.partial_len(), 1);
+        // the first half expired, this one starts a new message.
+        assert_eq!(reassembler.push(0, 1, 2, &[2], later).unwrap(), None);
+    }
+}
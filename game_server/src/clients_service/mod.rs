@@ -1,13 +1,20 @@
 pub mod client_handler;
+pub mod clock_sync;
+pub mod connection;
+pub mod connection_stats;
+pub mod fragmentation;
+pub mod packet_capture;
+pub mod rate_limiter;
+pub mod region_subscriptions;
+pub mod reliable_channel;
 pub mod utils;
 pub mod websocket_client_handler;
 
-use std::net::SocketAddr;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU16, AtomicU64};
-use std::collections::HashMap;
+use std::sync::atomic::AtomicU64;
 use crate::gaia_mpsc::GaiaSender;
 use crate::gameplay_service::generic_command::GenericCommand;
+use crate::gameplay_service::TickSnapshots;
 use crate::kingdom::KingdomCommand;
 use crate::mob::mob_command::MobCommand;
 use crate::{gaia_mpsc, ServerChannels, ServerState};
@@ -16,10 +23,16 @@ use crate::map::GameMap;
 use crate::map::map_entity::MapCommand;
 use crate::hero::hero_command::HeroCommand;
 use crate::tower::TowerCommand;
+use crate::protocols::packet_reader::PacketReader;
+use crate::protocols::protocol_version::{self, RejectReason};
+use crate::protocols::Protocol;
+use crate::server_config::SpectatorConfig;
+use self::rate_limiter::RateLimitConfig;
 use bytes::Bytes;
-use tokio::sync::Mutex;
 use tokio::sync::mpsc::{Receiver, Sender};
+use self::connection::{ConnectionId, ConnectionRegistry, UdpTransport};
 
+#[derive(Clone, Copy)]
 pub enum DataType
 {
     NoData = 25,
@@ -33,11 +46,20 @@ pub enum DataType
     ServerStatus = 34,
     MobStatus = 35,
     AttackDetails = 36,
+    // only sent to clients that negotiated deltas, see delta_encoder.
+    PlayerStateDelta = 37,
+    TileStateDelta = 38,
+    MobStatusDelta = 39,
+    TowerStateDelta = 40,
 }
 
 pub fn start_server(
     map : Arc<GameMap>,
-    server_state: Arc<ServerState>
+    server_state: Arc<ServerState>,
+    udp_address : std::net::SocketAddr,
+    websocket_address : std::net::SocketAddr,
+    spectators : SpectatorConfig,
+    rate_limit_config : RateLimitConfig
 ) -> (
     Receiver<MapCommand>,
     Receiver<MobCommand>,
@@ -45,7 +67,8 @@ pub fn start_server(
     Receiver<TowerCommand>, 
     Receiver<KingdomCommand>, 
     Receiver<ChatCommand>,
-    GaiaSender<Vec<(u64,u8,u16,u32,Bytes)>>
+    GaiaSender<Vec<(u64,u8,u16,u32,Bytes)>>,
+    GaiaSender<TickSnapshots>
 ) // packet number, faction, region, gamepackets,data
 {
     let (tx_gc_clients_gameplay, mut rx_gc_clients_gameplay) = gaia_mpsc::channel::<GenericCommand>(100, ServerChannels::TX_GC_ClIENTS_GAMEPLAY, server_state.clone());
@@ -57,44 +80,21 @@ pub fn start_server(
     let (tx_cc_clients_gameplay, rx_cc_clients_gameplay) = gaia_mpsc::channel::<ChatCommand>(100, ServerChannels::TX_CC_CLIENTS_GAMEPLAY, server_state.clone());
     let (tx_packets_gameplay_chat_clients, mut rx_packets_gameplay_chat_clients) = gaia_mpsc::channel::<Vec<(u64, u8, u16, u32, Bytes)>>(100, ServerChannels::TX_PACKETS_GAMEPLAY_CHAT_CLIENTS, server_state.clone());
 
-    let packet_builder_server_state = server_state.clone();
-    let generic_packet_builder_server_state: Arc<ServerState> = server_state.clone();
+    let (tx_snapshots_gameplay_clients, mut rx_snapshots_gameplay_clients) = gaia_mpsc::channel::<TickSnapshots>(100, ServerChannels::TX_SNAPSHOTS_GAMEPLAY_CLIENTS, server_state.clone());
 
-    // udp connections
-    let udp_client_connections:HashMap<std::net::SocketAddr, (u16, u8)> = HashMap::new();
-    let udp_client_connections_mutex = std::sync::Arc::new(Mutex::new(udp_client_connections));
+    let rate_limit_config = Arc::new(rate_limit_config);
+    let rate_limit_config_for_websocket = rate_limit_config.clone();
+    let spectators = Arc::new(spectators);
 
-    let udp_client_connections_receiver_lock = udp_client_connections_mutex.clone();
-    let udp_client_connections_sender_lock = udp_client_connections_mutex.clone();
+    // udp and websocket clients, once they send a valid first packet.
+    let connections = Arc::new(ConnectionRegistry::new(map.clone(), server_state.clone()));
+    let connections_for_generic = connections.clone();
+    let connections_for_broadcast = connections.clone();
+    let connections_for_deltas = connections.clone();
+    let connections_for_reliable = connections.clone();
+    let connections_for_websocket = connections.clone();
 
-    let udp_address: std::net::SocketAddr = "0.0.0.0:11002".parse().unwrap();
     let udp_socket = Arc::new(utils::create_reusable_udp_socket(udp_address));
-    let send_udp_socket = udp_socket.clone();
-    let send_directly_udp_socket = udp_socket.clone();
-    
-    let (tx_packets_gameplay_chat_websocket_clients, rx_packets_gameplay_chat_websocket_clients) =  gaia_mpsc::channel::<Vec<(u64, u8, u16, u32, Bytes)>>(100, ServerChannels::TX_PACKETS_GAMEPLAY_CHAT_WEBSOCKET_CLIENTS, server_state.clone());
-    let (tx_packets_gameplay_chat_websocket_specific_client, rx_packets_gameplay_chat_websocket_specific_clients) =  gaia_mpsc::channel::<(SocketAddr, Bytes)>(100, ServerChannels::TX_PACKETS_GAMEPLAY_CHAT_WEBSOCKET_SPECIFIC_CLIENT, server_state.clone());
-
-
-    let mut player_regions_record = HashMap::<u16, [AtomicU16;3]>::new();
-
-    let mut i:u16 = 0;
-
-    while i < u16::MAX 
-    {
-        i = i + 1;
-        let data = 
-        [
-            AtomicU16::new(0),
-            AtomicU16::new(0),
-            AtomicU16::new(0),
-        ];
-        player_regions_record.insert(i, data);
-    }
-
-    let shared_player_regions_record= Arc::new(player_regions_record);
-    let reader_shared_player_regions_record= shared_player_regions_record.clone();
-    let updater_shared_player_regions_record = reader_shared_player_regions_record.clone();
 
     let map_for_websocket = map.clone();
     let server_state_for_websocket = server_state.clone();
@@ -105,15 +105,14 @@ pub fn start_server(
     let tx_tc_clients_gameplay_for_websocket = tx_tc_clients_gameplay.clone();
     let tx_kc_clients_gameplay_for_websocket = tx_kc_clients_gameplay.clone();
     let tx_cc_clients_gameplay_for_websocket = tx_cc_clients_gameplay.clone();
-    let updater_shared_player_regions_record_for_websocket = updater_shared_player_regions_record.clone();
 
     tokio::spawn(async move 
     {
         websocket_client_handler::run(
-        rx_packets_gameplay_chat_websocket_clients,
-        rx_packets_gameplay_chat_websocket_specific_clients,
+                websocket_address,
                 map_for_websocket,
                 server_state_for_websocket,
+                connections_for_websocket,
                 tx_gc_clients_gameplay_for_websocket,
                 tx_mc_clients_gameplay_for_websocket,
                 tx_moc_clients_gameplay_for_websocket,
@@ -121,7 +120,8 @@ pub fn start_server(
                 tx_tc_clients_gameplay_for_websocket,
                 tx_kc_clients_gameplay_for_websocket,
                 tx_cc_clients_gameplay_for_websocket,
-                updater_shared_player_regions_record_for_websocket
+                rate_limit_config_for_websocket,
+                spectators
             ).await;
     });
 
@@ -131,40 +131,9 @@ pub fn start_server(
         {
             if let Some(command) = rx_gc_clients_gameplay.recv().await 
             {
-                let data_size = command.data.len() as u64;
-                if command.is_udp
-                {
-                    let result = send_directly_udp_socket.try_send_to(&command.data, command.player_address);
-                    match result 
-                    {
-                        Ok(_) => 
-                        {
-                            // cli_log::info!("ping sent to client ");
-                        },
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock =>
-                        {
-                            cli_log::info!("error sending specific data to {} would block", command.player_address);
-                        },
-                        Err(_) => cli_log::info!("error sending specific data through socket"),
-                    }
-                }
-                else
-                {
-                    let result = tx_packets_gameplay_chat_websocket_specific_client.send((command.player_address, command.data)).await;
-                    if result.is_err()
-                    {
-                        cli_log::error!("client_service:error sending data to specific client channel");
-                    }
-                }
-
-                // cli_log::info!("client_service:send data to specific client {}" , command.player_address);
-
-                generic_packet_builder_server_state.sent_bytes.fetch_add(data_size, std::sync::atomic::Ordering::Relaxed);
-                generic_packet_builder_server_state.sent_udp_packets.fetch_add(1u64, std::sync::atomic::Ordering::Relaxed);
-                generic_packet_builder_server_state.sent_game_packets.fetch_add(1u64, std::sync::atomic::Ordering::Relaxed);
-
+                // cli_log::info!("client_service:send data to specific client {}" , command.connection);
+                connections_for_generic.send_to(command.connection, command.data, command.reliable).await;
             }
-
         }
     });
 
@@ -175,169 +144,147 @@ pub fn start_server(
             // there are two sources of packets, chat and game. Each one has a differente packet id.
             if let Some(packet_list) = rx_packets_gameplay_chat_clients.recv().await 
             {
-                // let mut first_client = true;
-                let mut sent_bytes : u64 = 0;
-                let mut sent_game_packets : u64 = 0;
-                let mut sent_udp_packets : u64 = 0;
-                let mut clients_data = udp_client_connections_sender_lock.lock().await;
-                for client in clients_data.iter_mut()
-                {
-                    for (_packet_id, faction, region, game_packets, data) in packet_list.iter()
-                    {
-                        // cli_log::info!("sending packet with id {packet_id}");
-                        if client.1.0 == 0u16 
-                        {
-                            // let first_byte = packet[0]; // this is the protocol
-                            // the packet is compress, I can't read the sequence number
-                            // let packet_sequence_number = u64::from_le_bytes(packet[1..9].try_into().unwrap());
-                            // cli_log::info!("sending {}", packet_sequence_number);
-                        }
-                        // todo: only send data if client is correctly validated, add state to clients_data
-                        
-                        let client_regions = reader_shared_player_regions_record.get(&client.1.0).unwrap();
-                        let a = client_regions[0].load(std::sync::atomic::Ordering::Relaxed);
-                        let b = client_regions[1].load(std::sync::atomic::Ordering::Relaxed);
-                        let c = client_regions[2].load(std::sync::atomic::Ordering::Relaxed);
-
-                        // a = 0 makes us receive everything
-                        let is_in_region = *region == a || *region == b || *region == c || a == 0 || *region == 0;
-
-                        if is_in_region && (client.1.1 == *faction || *faction == 0)
-                        {
-                            sent_bytes += data.len() as u64;
-                            sent_udp_packets += 1;
-                            sent_game_packets += *game_packets as u64;
-                            let result = send_udp_socket.try_send_to(data, client.0.clone());
-                            match result 
-                            {
-                                Ok(_) => 
-                                {
-                                    // cli_log::info!("data sent to client {}", packet.len());
-                                },
-                                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock =>
-                                {
-                                    cli_log::info!("error sending data would block");
-                                },
-                                Err(_) => cli_log::info!("error sending data through socket"),
-                            }
-                        }
-                    }
-
-                    packet_builder_server_state.sent_bytes.fetch_add(sent_bytes, std::sync::atomic::Ordering::Relaxed);
-                    packet_builder_server_state.sent_udp_packets.fetch_add(sent_udp_packets, std::sync::atomic::Ordering::Relaxed);
-                    packet_builder_server_state.sent_game_packets.fetch_add(sent_game_packets, std::sync::atomic::Ordering::Relaxed);
-                }
-
-                // cli_log::info!("seding data from client service to all websocket clients");
+                connections_for_broadcast.broadcast(&packet_list).await;
+            }
+        }
+    });
 
-                let result = tx_packets_gameplay_chat_websocket_clients.send(packet_list).await;
-                if result.is_err()
-                {
-                    cli_log::info!("Error sending data to websockets");
-                }
+    tokio::spawn(async move 
+    {
+        loop 
+        {
+            if let Some((time, snapshots)) = rx_snapshots_gameplay_clients.recv().await 
+            {
+                connections_for_deltas.broadcast_snapshots(time, &snapshots).await;
             }
         }
     });
 
     tokio::spawn(async move 
     {
-        //use to communicate that the client disconnected
-        let (tx_addr_client_realtime, mut rx_addr_client_realtime ) = tokio::sync::mpsc::channel::<(std::net::SocketAddr, u64)>(100);
+        let mut interval = tokio::time::interval(std::time::Duration::from_millis(50));
+        loop 
+        {
+            interval.tick().await;
+            connections_for_reliable.resend_reliable().await;
+        }
+    });
 
-        let mut buf_udp = [0u8; 508];
+    tokio::spawn(async move 
+    {
+        let mut buf_udp = [0u8; fragmentation::MAX_DATAGRAM_SIZE];
         loop {
-            let socket_receive = udp_socket.recv_from(&mut buf_udp);
-
+            let result = udp_socket.recv_from(&mut buf_udp).await;
             // tokio::time::sleep(tokio::time::Duration::from_millis(30)).await;
 
-            tokio::select! 
+            if let Ok((packet_size, from_address)) = result 
             {
-                result = socket_receive => 
+                cli_log::info!("Parent: {:?} bytes received from {}", packet_size, from_address);
+                let connection = ConnectionId::udp(from_address);
+                if connections.contains(connection).await
+                {
+                    cli_log::info!("rejected: client process should be handling this");
+                    continue;
+                }
+
+                // byte 0 is for the protocol, then the session id, player id and faction.
+                let mut reader = PacketReader::new(&buf_udp[..packet_size]);
+                let header = match reader.read_header()
                 {
-                    if let Ok((packet_size, from_address)) = result 
+                    Ok(header) => header,
+                    Err(error) =>
                     {
-                        cli_log::info!("Parent: {:?} bytes received from {}", packet_size, from_address);
-                        let mut clients_data = udp_client_connections_receiver_lock.lock().await;
-                        if !clients_data.contains_key(&from_address)
-                        {
-                            // byte 0 is for the protocol, and we are sure the next 8 bytes are for the id.
-                            let start = 1;
-                            let end = start + 8;
-                            let player_session_id = u64::from_le_bytes(buf_udp[start..end].try_into().unwrap());
+                        cli_log::info!("rejected: bad header from {} {}", from_address, error);
+                        continue;
+                    }
+                };
+                let player_session_id = header.session_id;
+                let player_id = header.player_id;
+                let faction = header.faction;
 
-                            let start = end;
-                            let end = start + 2;
-                            let player_id = u16::from_le_bytes(buf_udp[start..end].try_into().unwrap());
+                cli_log::info!("--- create child for {} with session id {}", player_id, player_session_id);
 
-                            let start = end;
-                            // let end = start + 1;
-                            let faction = buf_udp[start];
+                let is_resume = buf_udp[0] == Protocol::Resume as u8;
+                // a spoofed packet would steal the broadcast for this hero, it has to be signed.
+                // a resume is never routed, so it uses the nonce here and can't be replayed from somewhere else.
+                let is_signed = if is_resume
+                {
+                    map.session_keys.authenticate(player_id, player_session_id, &buf_udp[..packet_size]).await.is_ok()
+                }
+                else
+                {
+                    map.session_keys.check(player_id, player_session_id, &buf_udp[..packet_size]).await.is_ok()
+                };
+
+                if !is_signed
+                {
+                    cli_log::info!("rejected: invalid signature from {}", from_address);
+                    continue;
+                }
 
-                            cli_log::info!("--- create child for {} with session id {}", player_id, player_session_id);
-                            let stored_session_id = &map.logged_in_players[player_id as usize];
-                            let session_id = stored_session_id.load(std::sync::atomic::Ordering::Relaxed);
-                            cli_log::info!("comparing {} with server {}", player_session_id, session_id);
+                let is_logged_in = connection::is_logged_in(&map, player_id, player_session_id);
+                let previous_connection = if is_logged_in { connections.find_session(player_id, player_session_id).await } else { None };
+                let new_transport = || Box::new(UdpTransport::new(udp_socket.clone(), from_address));
 
-                            if session_id == player_session_id  && session_id != 0
-                            {
-                                clients_data.insert(from_address, (player_id, faction));
+                let resumed = match (is_resume, previous_connection)
+                {
+                    (true, Some(previous_connection)) => connections.resume(previous_connection, connection, new_transport()).await,
+                    _ => None,
+                };
 
-                                server_state.online_players.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                                // each client can send a message to remove itself using tx,
-                                // each client can send actions to be processed using client_action_tx,
-                                // each client can receive data to be sent to the client using client_state_rx because each client has its socket.
-                                // the producer for this channel is saved in the player_entity which is saved on the clients_data
-                                client_handler::spawn_client_process(
-                                    player_id, 
-                                    session_id,
-                                    udp_address, 
-                                    from_address, 
-                                    map.clone(),
-                                    server_state.clone(),
-                                    tx_gc_clients_gameplay.clone(),
-                                    tx_addr_client_realtime.clone(), 
-                                    tx_mc_clients_gameplay.clone(), 
-                                    tx_moc_clients_gameplay.clone(), 
-                                    tx_pc_clients_gameplay.clone(), 
-                                    tx_tc_clients_gameplay.clone(), 
-                                    tx_kc_clients_gameplay.clone(), 
-                                    tx_cc_clients_gameplay.clone(), 
-                                    updater_shared_player_regions_record.clone(),
-                                    buf_udp,
-                                    packet_size
-                                ).await;
-                            } 
-                            else
-                            {
-                                cli_log::info!("rejected: invalid session id");
-                            }
-                        }
-                        else
-                        {
-                            cli_log::info!("rejected: client process should be handling this");
-                        }
-                    }
-                }
-                Some((socket, active_session_id)) = rx_addr_client_realtime.recv() => 
+                let (registration, initial_packet_size) = match (is_resume, previous_connection, resumed)
                 {
-                    cli_log::info!("removing entry from hash set");
-                    server_state.online_players.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
-                    let mut clients_data = udp_client_connections_receiver_lock.lock().await;
-                    let character_id = clients_data.get(&socket);
-                    if let Some(session_id) = character_id.map(|(id, _faction)| &map.logged_in_players[*id as usize])
+                    (true, _, Some(registration)) =>
                     {
-                        let current_session_id = session_id.load(std::sync::atomic::Ordering::Relaxed);
-                        if current_session_id == active_session_id
-                        {
-                            session_id.store(0, std::sync::atomic::Ordering::Relaxed);
-                            let _removed_player_id = clients_data.remove(&socket);
-                        }
-                        else
-                        {
-                            cli_log::info!("probably a reconnection {:?}", character_id);
-                        }
-                    }
-                }
+                        connections.send_to(connection, Bytes::from_static(&[Protocol::Resume as u8]), false).await;
+                        (registration, 0)
+                    },
+                    (true, _, None) =>
+                    {
+                        cli_log::info!("rejected: nothing to resume for hero {} from {}", player_id, from_address);
+                        let rejection = protocol_version::build_rejection(RejectReason::SessionExpired);
+                        let _ = udp_socket.try_send_to(&rejection, from_address);
+                        continue;
+                    },
+                    (false, Some(previous_connection), _) =>
+                    {
+                        // the hero is still playing from the other address, moving needs a resume.
+                        cli_log::info!("rejected: hero {} is connected from {}", player_id, previous_connection);
+                        continue;
+                    },
+                    (false, None, _) if is_logged_in =>
+                    {
+                        let registration = connections.register(connection, player_id, player_session_id, faction, new_transport()).await;
+                        (registration, packet_size)
+                    },
+                    (false, None, _) =>
+                    {
+                        cli_log::info!("rejected: invalid session id");
+                        continue;
+                    },
+                };
+
+                // each client has its own socket connected to the client address, the parent only sees first packets.
+                client_handler::spawn_client_process(
+                    player_id, 
+                    player_session_id,
+                    udp_address, 
+                    from_address, 
+                    map.clone(),
+                    server_state.clone(),
+                    connections.clone(),
+                    registration,
+                    tx_gc_clients_gameplay.clone(),
+                    tx_mc_clients_gameplay.clone(), 
+                    tx_moc_clients_gameplay.clone(), 
+                    tx_pc_clients_gameplay.clone(), 
+                    tx_tc_clients_gameplay.clone(), 
+                    tx_kc_clients_gameplay.clone(), 
+                    tx_cc_clients_gameplay.clone(), 
+                    rate_limit_config.clone(),
+                    buf_udp,
+                    initial_packet_size
+                ).await;
             }
         }   
     });
@@ -349,8 +296,7 @@ pub fn start_server(
         rx_tc_clients_gameplay,
         rx_kc_clients_gameplay,
         rx_cc_clients_gameplay,
-        tx_packets_gameplay_chat_clients
+        tx_packets_gameplay_chat_clients,
+        tx_snapshots_gameplay_clients
     )
 }
-
-
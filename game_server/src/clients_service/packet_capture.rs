@@ -0,0 +1,367 @@
+use std::io::{BufWriter, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+
+use crate::protocols::protocol_version;
+use super::connection::{ConnectionId, TransportKind};
+
+// every capture starts with this, then the format version, the protocol version and the start time.
+pub const CAPTURE_MAGIC : &[u8; 4] = b"GCAP";
+pub const CAPTURE_VERSION : u16 = 1;
+pub const CAPTURE_HEADER_SIZE : usize = 16;
+// records waiting for the writer, past this they are dropped instead of slowing the clients down.
+pub const CAPTURE_QUEUE_SIZE : usize = 10000;
+// the record keeps the data size in 2 bytes.
+pub const MAX_RECORD_DATA_SIZE : usize = u16::MAX as usize;
+
+// one packet as route_packet got it, already authenticated and put back together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRecord
+{
+    // ms since the capture started.
+    pub time : u32,
+    pub connection : ConnectionId,
+    pub session_id : u64,
+    pub hero_id : u16,
+    pub data : Vec<u8>,
+}
+
+impl CaptureRecord
+{
+    // time (4 bytes), transport (1 byte), ip kind (1 byte), ip (4 or 16 bytes), port (2 bytes),
+    // session (8 bytes), hero (2 bytes), data size (2 bytes) and the data.
+    // nothing is written if the data doesn't fit in a record.
+    pub fn write_to(&self, output : &mut Vec<u8>) -> Result<(), String>
+    {
+        if self.data.len() > MAX_RECORD_DATA_SIZE
+        {
+            return Err(format!("{} bytes from {} don't fit in a record", self.data.len(), self.connection));
+        }
+
+        output.extend_from_slice(&u32::to_le_bytes(self.time));
+        output.push(match self.connection.kind
+        {
+            TransportKind::Udp => 0,
+            TransportKind::WebSocket => 1,
+        });
+
+        match self.connection.address.ip()
+        {
+            IpAddr::V4(ip) =>
+            {
+                output.push(4);
+                output.extend_from_slice(&ip.octets());
+            },
+            IpAddr::V6(ip) =>
+            {
+                output.push(6);
+                output.extend_from_slice(&ip.octets());
+            },
+        }
+
+        output.extend_from_slice(&u16::to_le_bytes(self.connection.address.port()));
+        output.extend_from_slice(&u64::to_le_bytes(self.session_id));
+        output.extend_from_slice(&u16::to_le_bytes(self.hero_id));
+        output.extend_from_slice(&u16::to_le_bytes(self.data.len() as u16));
+        output.extend_from_slice(&self.data);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture
+{
+    pub protocol_version : u16,
+    // ms since the epoch when the server started capturing.
+    pub start_time : u64,
+    pub records : Vec<CaptureRecord>,
+}
+
+impl Capture
+{
+    pub fn from_bytes(data : &[u8]) -> Result<Self, String>
+    {
+        if data.len() < CAPTURE_HEADER_SIZE || &data[0..4] != CAPTURE_MAGIC
+        {
+            return Err("not a packet capture".to_string());
+        }
+
+        let mut reader = CaptureReader { data, offset: 4 };
+        let version = reader.read_u16()?;
+        if version != CAPTURE_VERSION
+        {
+            return Err(format!("capture format {} is not supported, expected {}", version, CAPTURE_VERSION));
+        }
+
+        let protocol_version = reader.read_u16()?;
+        let start_time = reader.read_u64()?;
+
+        let mut records = Vec::new();
+        while reader.offset < data.len()
+        {
+            records.push(reader.read_record()?);
+        }
+
+        Ok(Capture { protocol_version, start_time, records })
+    }
+
+    pub fn load(path : &Path) -> Result<Self, String>
+    {
+        let data = std::fs::read(path).map_err(|error| format!("can't read {}: {}", path.display(), error))?;
+        Self::from_bytes(&data).map_err(|error| format!("invalid capture {}: {}", path.display(), error))
+    }
+}
+
+struct CaptureReader<'a>
+{
+    data : &'a [u8],
+    offset : usize,
+}
+
+impl<'a> CaptureReader<'a>
+{
+    fn read_bytes(&mut self, size : usize) -> Result<&'a [u8], String>
+    {
+        let end = self.offset + size;
+        if end > self.data.len()
+        {
+            return Err(format!("truncated at byte {}", self.offset));
+        }
+
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String>
+    {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, String>
+    {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, String>
+    {
+        Ok(u32::from_le_bytes(self.read_bytes(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, String>
+    {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_record(&mut self) -> Result<CaptureRecord, String>
+    {
+        let time = self.read_u32()?;
+        let kind = match self.read_u8()?
+        {
+            0 => TransportKind::Udp,
+            1 => TransportKind::WebSocket,
+            kind => return Err(format!("unknown transport {} at byte {}", kind, self.offset - 1)),
+        };
+
+        let ip = match self.read_u8()?
+        {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(self.read_bytes(4)?).unwrap())),
+            6 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(self.read_bytes(16)?).unwrap())),
+            ip_kind => return Err(format!("unknown ip kind {} at byte {}", ip_kind, self.offset - 1)),
+        };
+
+        let port = self.read_u16()?;
+        let session_id = self.read_u64()?;
+        let hero_id = self.read_u16()?;
+        let size = self.read_u16()? as usize;
+        let data = self.read_bytes(size)?.to_vec();
+
+        Ok(CaptureRecord
+        {
+            time,
+            connection: ConnectionId { kind, address: SocketAddr::new(ip, port) },
+            session_id,
+            hero_id,
+            data,
+        })
+    }
+}
+
+// records the packets route_packet accepts into a file, for replaying them later against a copy of the world.
+// the file is written from its own thread, capturing never waits for the disk.
+pub struct PacketCapture
+{
+    start_time : u64,
+    started : std::time::Instant,
+    tx_records : SyncSender<CaptureRecord>,
+    pub captured_packets : AtomicU64,
+    // the writer couldn't keep up or the packet was too big for a record, the capture has holes.
+    pub dropped_packets : AtomicU64,
+}
+
+impl PacketCapture
+{
+    pub fn start(path : &Path) -> std::io::Result<Self>
+    {
+        let start_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64;
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+
+        file.write_all(CAPTURE_MAGIC)?;
+        file.write_all(&u16::to_le_bytes(CAPTURE_VERSION))?;
+        file.write_all(&u16::to_le_bytes(protocol_version::PROTOCOL_VERSION))?;
+        file.write_all(&u64::to_le_bytes(start_time))?;
+        file.flush()?;
+
+        let (tx_records, rx_records) = std::sync::mpsc::sync_channel::<CaptureRecord>(CAPTURE_QUEUE_SIZE);
+        let display_path = path.display().to_string();
+        std::thread::Builder::new()
+            .name("gaia-packet_capture".to_string())
+            .spawn(move || write_records(file, rx_records, display_path))?;
+
+        cli_log::info!("capturing packets into {}", path.display());
+        Ok(PacketCapture
+        {
+            start_time,
+            started: std::time::Instant::now(),
+            tx_records,
+            captured_packets: AtomicU64::new(0),
+            dropped_packets: AtomicU64::new(0),
+        })
+    }
+
+    pub fn get_start_time(&self) -> u64
+    {
+        self.start_time
+    }
+
+    pub fn record(&self, connection : ConnectionId, session_id : u64, hero_id : u16, data : &[u8])
+    {
+        if data.len() > MAX_RECORD_DATA_SIZE
+        {
+            self.dropped_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            cli_log::error!("packet of {} bytes from {} is too big to capture", data.len(), connection);
+            return;
+        }
+
+        let record = CaptureRecord
+        {
+            time: self.started.elapsed().as_millis() as u32,
+            connection,
+            session_id,
+            hero_id,
+            data: data.to_vec(),
+        };
+
+        match self.tx_records.try_send(record)
+        {
+            Ok(()) => self.captured_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => self.dropped_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+        };
+    }
+}
+
+fn write_records(mut file : BufWriter<std::fs::File>, rx_records : Receiver<CaptureRecord>, path : String)
+{
+    let mut buffer = Vec::new();
+    // waits for one record, then writes everything that is already queued before flushing.
+    while let Ok(record) = rx_records.recv()
+    {
+        buffer.clear();
+        for record in std::iter::once(record).chain(rx_records.try_iter())
+        {
+            if let Err(error) = record.write_to(&mut buffer)
+            {
+                cli_log::error!("packet capture {} skipped a record: {}", path, error);
+            }
+        }
+
+        if let Err(error) = file.write_all(&buffer).and_then(|_| file.flush())
+        {
+            cli_log::error!("packet capture {} stopped: {}", path, error);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_reads_back_what_was_recorded()
+    {
+        let path = std::env::temp_dir().join(format!("gaia_capture_test_{}.gcap", std::process::id()));
+        let capture = PacketCapture::start(&path).unwrap();
+        let udp = ConnectionId::udp("10.0.0.1:4000".parse().unwrap());
+        let websocket = ConnectionId::websocket("[::1]:5000".parse().unwrap());
+        capture.record(udp, 7, 3, &[1, 2, 3]);
+        capture.record(websocket, 8, 4, &[]);
+        let start_time = capture.get_start_time();
+        drop(capture);
+
+        // the writer thread finishes on its own once the capture is dropped.
+        let mut loaded = Capture::load(&path).unwrap();
+        for _ in 0..100
+        {
+            if loaded.records.len() == 2
+            {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            loaded = Capture::load(&path).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let records = loaded.records;
+        assert_eq!(loaded.start_time, start_time);
+        assert_eq!(loaded.protocol_version, protocol_version::PROTOCOL_VERSION);
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].connection, records[0].session_id, records[0].hero_id, records[0].data.clone()), (udp, 7, 3, vec![1, 2, 3]));
+        assert_eq!((records[1].connection, records[1].session_id, records[1].hero_id, records[1].data.len()), (websocket, 8, 4, 0));
+
+        let mut truncated = Vec::new();
+        truncated.extend_from_slice(CAPTURE_MAGIC);
+        truncated.extend_from_slice(&u16::to_le_bytes(CAPTURE_VERSION));
+        truncated.extend_from_slice(&[0u8; 10]);
+        records[0].write_to(&mut truncated).unwrap();
+        truncated.pop();
+        assert!(Capture::from_bytes(&truncated).is_err());
+        assert!(Capture::from_bytes(b"nope").is_err());
+    }
+
+    #[test]
+    fn packets_too_big_for_a_record_are_skipped()
+    {
+        let path = std::env::temp_dir().join(format!("gaia_capture_big_test_{}.gcap", std::process::id()));
+        let capture = PacketCapture::start(&path).unwrap();
+        let udp = ConnectionId::udp("10.0.0.1:4000".parse().unwrap());
+        capture.record(udp, 7, 3, &vec![1; MAX_RECORD_DATA_SIZE + 1]);
+        capture.record(udp, 7, 3, &vec![2; MAX_RECORD_DATA_SIZE]);
+        assert_eq!(capture.dropped_packets.load(std::sync::atomic::Ordering::Relaxed), 1);
+        assert_eq!(capture.captured_packets.load(std::sync::atomic::Ordering::Relaxed), 1);
+        drop(capture);
+
+        let mut loaded = Capture::load(&path).unwrap();
+        for _ in 0..100
+        {
+            if !loaded.records.is_empty()
+            {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            loaded = Capture::load(&path).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        // the size would have wrapped around and broken every record after it.
+        assert_eq!(loaded.records.len(), 1);
+        assert_eq!(loaded.records[0].data, vec![2; MAX_RECORD_DATA_SIZE]);
+
+        let record = CaptureRecord { time: 0, connection: udp, session_id: 7, hero_id: 3, data: vec![0; MAX_RECORD_DATA_SIZE + 1] };
+        let mut output = Vec::new();
+        assert!(record.write_to(&mut output).is_err());
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+use crate::protocols::Protocol;
+use crate::ServerState;
+use super::connection::ConnectionId;
+use super::connection_stats::ConnectionStats;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketLimit
+{
+    // how many packets can arrive together
+    pub burst : f32,
+    pub per_second : f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig
+{
+    // indexed by the protocol byte, protocols without an entry use the default.
+    pub limits : HashMap<u8, BucketLimit>,
+    pub default_limit : BucketLimit,
+    // dropped packets inside the window before we kick the client.
+    pub max_violations : u32,
+    pub violations_window : Duration,
+}
+
+impl RateLimitConfig
+{
+    pub fn get_limit(&self, protocol : u8) -> BucketLimit
+    {
+        self.limits.get(&protocol).copied().unwrap_or(self.default_limit)
+    }
+}
+
+impl Default for RateLimitConfig
+{
+    fn default() -> Self
+    {
+        let limit = |burst, per_second| BucketLimit { burst, per_second };
+        let limits = HashMap::from([
+            (Protocol::Ping as u8, limit(5.0, 2.0)),
+            (Protocol::CharacterMovement as u8, limit(30.0, 20.0)),
+            (Protocol::AttackMob as u8, limit(20.0, 10.0)),
+            (Protocol::AttackTower as u8, limit(20.0, 10.0)),
+            (Protocol::AttackStructure as u8, limit(20.0, 10.0)),
+            (Protocol::CharacterAttacksCharacter as u8, limit(20.0, 10.0)),
+            (Protocol::SpawnMob as u8, limit(4.0, 2.0)),
+            (Protocol::ControlMob as u8, limit(4.0, 2.0)),
+            (Protocol::CastMobFromMob as u8, limit(4.0, 2.0)),
+            (Protocol::ChatMessage as u8, limit(5.0, 1.0)),
+            (Protocol::BuyItem as u8, limit(8.0, 4.0)),
+            (Protocol::SellItem as u8, limit(8.0, 4.0)),
+            (Protocol::UseItem as u8, limit(8.0, 4.0)),
+            (Protocol::EquipItem as u8, limit(8.0, 4.0)),
+            (Protocol::CraftCard as u8, limit(8.0, 4.0)),
+            (Protocol::MissingPackets as u8, limit(20.0, 10.0)),
+            (Protocol::EntityAck as u8, limit(60.0, 30.0)),
+            (Protocol::Spectate as u8, limit(5.0, 2.0)),
+        ]);
+
+        RateLimitConfig
+        {
+            limits,
+            default_limit: limit(40.0, 20.0),
+            max_violations: 50,
+            violations_window: Duration::from_secs(10),
+        }
+    }
+}
+
+pub struct TokenBucket
+{
+    limit : BucketLimit,
+    tokens : f32,
+    last_refill : Instant,
+}
+
+impl TokenBucket
+{
+    pub fn new(limit : BucketLimit, now : Instant) -> Self
+    {
+        TokenBucket { limit, tokens: limit.burst, last_refill: now }
+    }
+
+    pub fn try_take(&mut self, now : Instant) -> bool
+    {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f32();
+        self.tokens = f32::min(self.limit.burst, self.tokens + elapsed * self.limit.per_second);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0
+        {
+            self.tokens -= 1.0;
+            true
+        }
+        else
+        {
+            false
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitResult
+{
+    Allowed,
+    Dropped,
+    // too many drops, the connection should be closed.
+    Disconnect,
+}
+
+// one per connection, udp or websocket.
+pub struct RateLimiter
+{
+    config : Arc<RateLimitConfig>,
+    buckets : HashMap<u8, TokenBucket>,
+    violations : u32,
+    window_start : Instant,
+}
+
+impl RateLimiter
+{
+    pub fn new(config : Arc<RateLimitConfig>) -> Self
+    {
+        RateLimiter { config, buckets: HashMap::new(), violations: 0, window_start: Instant::now() }
+    }
+
+    pub fn check(&mut self, data : &[u8], now : Instant) -> RateLimitResult
+    {
+        // empty packets are dropped later by route_packet
+        let Some(protocol) = data.first().copied() else { return RateLimitResult::Allowed };
+
+        let config = &self.config;
+        let bucket = self.buckets
+            .entry(protocol)
+            .or_insert_with(|| TokenBucket::new(config.get_limit(protocol), now));
+
+        if bucket.try_take(now)
+        {
+            return RateLimitResult::Allowed;
+        }
+
+        if now.saturating_duration_since(self.window_start) > self.config.violations_window
+        {
+            self.window_start = now;
+            self.violations = 0;
+        }
+
+        self.violations += 1;
+        if self.violations > self.config.max_violations
+        {
+            RateLimitResult::Disconnect
+        }
+        else
+        {
+            RateLimitResult::Dropped
+        }
+    }
+}
+
+// shared by the udp and websocket loops, counts what we drop and kick.
+// only for packets we know come from the client, a spoofed one must not get the real client kicked.
+pub fn check_packet(limiter : &mut RateLimiter, data : &[u8], server_state : &ServerState, stats : Option<&ConnectionStats>, connection : ConnectionId) -> RateLimitResult
+{
+    let result = limiter.check(data, Instant::now());
+    if result != RateLimitResult::Allowed
+    {
+        if let Some(stats) = stats
+        {
+            stats.add_rate_limited();
+        }
+    }
+
+    match result
+    {
+        RateLimitResult::Allowed => {},
+        RateLimitResult::Dropped =>
+        {
+            server_state.rate_limited_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        },
+        RateLimitResult::Disconnect =>
+        {
+            server_state.rate_limited_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            server_state.rate_limit_disconnects.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            cli_log::error!("disconnecting {} for sending too many packets", connection);
+        },
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_mob_packet() -> [u8; 1]
+    {
+        [Protocol::SpawnMob as u8]
+    }
+
+    #[test]
+    fn bucket_refills_over_time()
+    {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(BucketLimit { burst: 2.0, per_second: 1.0 }, now);
+        assert!(bucket.try_take(now));
+        assert!(bucket.try_take(now));
+        assert!(!bucket.try_take(now));
+        assert!(bucket.try_take(now + Duration::from_secs(1)));
+        assert!(!bucket.try_take(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn repeat_offenders_get_disconnected()
+    {
+        let config = RateLimitConfig { max_violations: 3, ..Default::default() };
+        let burst = config.get_limit(Protocol::SpawnMob as u8).burst as usize;
+        let mut limiter = RateLimiter::new(Arc::new(config));
+        let now = Instant::now();
+
+        for _ in 0..burst
+        {
+            assert_eq!(limiter.check(&spawn_mob_packet(), now), RateLimitResult::Allowed);
+        }
+
+        // other protocols have their own bucket.
+        assert_eq!(limiter.check(&[Protocol::Ping as u8], now), RateLimitResult::Allowed);
+
+        for _ in 0..3
+        {
+            assert_eq!(limiter.check(&spawn_mob_packet(), now), RateLimitResult::Dropped);
+        }
+        assert_eq!(limiter.check(&spawn_mob_packet(), now), RateLimitResult::Disconnect);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+
+use tokio::sync::Mutex;
+
+use super::connection::ConnectionId;
+
+#[derive(Default)]
+struct Subscription
+{
+    // the hero this connection plays, its regions follow the hero position.
+    hero_id : Option<u16>,
+    // the hero a spectator camera follows, its regions also go in hero_regions.
+    camera_hero_id : Option<u16>,
+    hero_regions : Vec<u16>,
+    // regions asked for on top of the hero ones, spectators and admins only have these.
+    extra_regions : HashSet<u16>,
+}
+
+impl Subscription
+{
+    fn is_subscribed(&self, region : u16) -> bool
+    {
+        self.hero_regions.contains(&region) || self.extra_regions.contains(&region)
+    }
+}
+
+#[derive(Default)]
+struct Subscriptions
+{
+    by_region : HashMap<u16, HashSet<ConnectionId>>,
+    by_connection : HashMap<ConnectionId, Subscription>,
+    by_hero : HashMap<u16, ConnectionId>,
+    cameras : HashMap<u16, HashSet<ConnectionId>>,
+}
+
+impl Subscriptions
+{
+    fn add(&mut self, id : ConnectionId, region : u16)
+    {
+        self.by_region.entry(region).or_default().insert(id);
+    }
+
+    fn remove(&mut self, id : ConnectionId, region : u16)
+    {
+        if let Some(subscribers) = self.by_region.get_mut(&region)
+        {
+            subscribers.remove(&id);
+            if subscribers.is_empty()
+            {
+                self.by_region.remove(&region);
+            }
+        }
+    }
+
+    fn set_hero_regions(&mut self, id : ConnectionId, regions : &[u16])
+    {
+        let Some(subscription) = self.by_connection.get_mut(&id) else { return };
+        let old_regions = std::mem::replace(&mut subscription.hero_regions, regions.iter().copied().filter(|region| *region != 0).collect());
+        let added : Vec<u16> = subscription.hero_regions.iter().copied().filter(|region| !old_regions.contains(region)).collect();
+        let removed : Vec<u16> = old_regions.into_iter().filter(|region| !subscription.is_subscribed(*region)).collect();
+
+        for region in added
+        {
+            self.add(id, region);
+        }
+        for region in removed
+        {
+            self.remove(id, region);
+        }
+    }
+
+    fn remove_camera(&mut self, id : ConnectionId, hero_id : u16)
+    {
+        if let Some(cameras) = self.cameras.get_mut(&hero_id)
+        {
+            cameras.remove(&id);
+            if cameras.is_empty()
+            {
+                self.cameras.remove(&hero_id);
+            }
+        }
+    }
+}
+
+// who receives each region, region 0 is for everyone so nobody subscribes to it.
+// broadcasting a region only visits its subscribers instead of every connection.
+pub struct RegionSubscriptions
+{
+    subscriptions : Mutex<Subscriptions>,
+}
+
+impl RegionSubscriptions
+{
+    pub fn new() -> Self
+    {
+        RegionSubscriptions { subscriptions: Mutex::new(Subscriptions::default()) }
+    }
+
+    // the connection now follows the hero, the regions come from the hero position.
+    pub async fn add_hero_connection(&self, id : ConnectionId, hero_id : u16, regions : &[u16])
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        subscriptions.by_connection.entry(id).or_default().hero_id = Some(hero_id);
+        // a reconnection replaces the old connection, the old one keeps its extra regions until it is removed.
+        if let Some(old_id) = subscriptions.by_hero.insert(hero_id, id).filter(|old_id| *old_id != id)
+        {
+            subscriptions.set_hero_regions(old_id, &[]);
+            if let Some(old_subscription) = subscriptions.by_connection.get_mut(&old_id)
+            {
+                old_subscription.hero_id = None;
+            }
+        }
+        subscriptions.set_hero_regions(id, regions);
+    }
+
+    pub async fn update_hero_regions(&self, hero_id : u16, regions : &[u16])
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        if let Some(id) = subscriptions.by_hero.get(&hero_id).copied()
+        {
+            subscriptions.set_hero_regions(id, regions);
+        }
+
+        let cameras : Vec<ConnectionId> = subscriptions.cameras.get(&hero_id).into_iter().flatten().copied().collect();
+        for id in cameras
+        {
+            subscriptions.set_hero_regions(id, regions);
+        }
+    }
+
+    // a spectator camera gets the regions of the hero wherever it goes, None stops following.
+    pub async fn follow_hero(&self, id : ConnectionId, hero_id : Option<u16>, regions : &[u16])
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.by_connection.entry(id).or_default();
+        let old_hero_id = std::mem::replace(&mut subscription.camera_hero_id, hero_id);
+        if let Some(old_hero_id) = old_hero_id
+        {
+            subscriptions.remove_camera(id, old_hero_id);
+        }
+
+        if let Some(hero_id) = hero_id
+        {
+            subscriptions.cameras.entry(hero_id).or_default().insert(id);
+        }
+        subscriptions.set_hero_regions(id, if hero_id.is_some() { regions } else { &[] });
+    }
+
+    pub async fn subscribe(&self, id : ConnectionId, regions : &[u16])
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions.by_connection.entry(id).or_default();
+        let added : Vec<u16> = regions.iter().copied().filter(|region| *region != 0 && subscription.extra_regions.insert(*region)).collect();
+        for region in added
+        {
+            subscriptions.add(id, region);
+        }
+    }
+
+    pub async fn unsubscribe(&self, id : ConnectionId, regions : &[u16])
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let Some(subscription) = subscriptions.by_connection.get_mut(&id) else { return };
+        let removed : Vec<u16> = regions.iter()
+            .copied()
+            .filter(|region| subscription.extra_regions.remove(region) && !subscription.is_subscribed(*region))
+            .collect();
+        for region in removed
+        {
+            subscriptions.remove(id, region);
+        }
+    }
+
+    pub async fn remove_connection(&self, id : ConnectionId)
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let Some(subscription) = subscriptions.by_connection.remove(&id) else { return };
+        for region in subscription.hero_regions.iter().chain(subscription.extra_regions.iter())
+        {
+            subscriptions.remove(id, *region);
+        }
+
+        if let Some(hero_id) = subscription.hero_id
+        {
+            if subscriptions.by_hero.get(&hero_id) == Some(&id)
+            {
+                subscriptions.by_hero.remove(&hero_id);
+            }
+        }
+
+        if let Some(hero_id) = subscription.camera_hero_id
+        {
+            subscriptions.remove_camera(id, hero_id);
+        }
+    }
+
+    // everything the old connection had, hero regions and extra ones, now goes to the new one.
+    pub async fn move_connection(&self, old_id : ConnectionId, new_id : ConnectionId)
+    {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let Some(subscription) = subscriptions.by_connection.remove(&old_id) else { return };
+        for region in subscription.hero_regions.iter().chain(subscription.extra_regions.iter())
+        {
+            subscriptions.remove(old_id, *region);
+            subscriptions.add(new_id, *region);
+        }
+
+        if let Some(hero_id) = subscription.hero_id
+        {
+            if subscriptions.by_hero.get(&hero_id) == Some(&old_id)
+            {
+                subscriptions.by_hero.insert(hero_id, new_id);
+            }
+        }
+
+        if let Some(hero_id) = subscription.camera_hero_id
+        {
+            subscriptions.remove_camera(old_id, hero_id);
+            subscriptions.cameras.entry(hero_id).or_default().insert(new_id);
+        }
+        subscriptions.by_connection.insert(new_id, subscription);
+    }
+
+    pub async fn get_regions(&self, id : ConnectionId) -> HashSet<u16>
+    {
+        let subscriptions = self.subscriptions.lock().await;
+        subscriptions.by_connection
+            .get(&id)
+            .map(|subscription| subscription.hero_regions.iter().chain(subscription.extra_regions.iter()).copied().collect())
+            .unwrap_or_default()
+    }
+
+    // one lock for the whole batch of packets.
+    pub async fn get_subscribers(&self, regions : impl Iterator<Item = u16>) -> HashMap<u16, Vec<ConnectionId>>
+    {
+        let subscriptions = self.subscriptions.lock().await;
+        regions
+            .filter_map(|region| subscriptions.by_region.get(&region).map(|subscribers| (region, subscribers.iter().copied().collect())))
+            .collect()
+    }
+}
+
+impl Default for RegionSubscriptions
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(port : u16) -> ConnectionId
+    {
+        ConnectionId::udp(std::net::SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    #[tokio::test]
+    async fn hero_regions_follow_the_hero()
+    {
+        let subscriptions = RegionSubscriptions::new();
+        subscriptions.add_hero_connection(connection(1), 7, &[9, 1, 5, 13]).await;
+        subscriptions.subscribe(connection(1), &[9, 100]).await;
+
+        subscriptions.update_hero_regions(7, &[10, 2, 6, 14]).await;
+        // 9 is still an extra region.
+        assert_eq!(subscriptions.get_regions(connection(1)).await, HashSet::from([10, 2, 6, 14, 9, 100]));
+
+        let subscribers = subscriptions.get_subscribers([1, 9, 10].into_iter()).await;
+        assert!(!subscribers.contains_key(&1));
+        assert_eq!(subscribers[&9], vec![connection(1)]);
+        assert_eq!(subscribers[&10], vec![connection(1)]);
+
+        // reconnecting moves the hero regions to the new connection.
+        subscriptions.add_hero_connection(connection(2), 7, &[10]).await;
+        assert_eq!(subscriptions.get_regions(connection(1)).await, HashSet::from([9, 100]));
+
+        subscriptions.remove_connection(connection(1)).await;
+        subscriptions.update_hero_regions(7, &[11]).await;
+        let subscribers = subscriptions.get_subscribers([9, 10, 11].into_iter()).await;
+        assert_eq!(subscribers.len(), 1);
+        assert_eq!(subscribers[&11], vec![connection(2)]);
+
+        // a resumed session keeps following the hero from its new address.
+        subscriptions.subscribe(connection(2), &[50]).await;
+        subscriptions.move_connection(connection(2), connection(3)).await;
+        subscriptions.update_hero_regions(7, &[12]).await;
+        assert!(subscriptions.get_regions(connection(2)).await.is_empty());
+        assert_eq!(subscriptions.get_regions(connection(3)).await, HashSet::from([12, 50]));
+        assert_eq!(subscriptions.get_subscribers([11, 12].into_iter()).await[&12], vec![connection(3)]);
+    }
+
+    #[tokio::test]
+    async fn spectators_can_watch_any_number_of_regions()
+    {
+        let subscriptions = RegionSubscriptions::new();
+        let regions : Vec<u16> = (1..=320).collect();
+        subscriptions.subscribe(connection(3), &regions).await;
+        assert_eq!(subscriptions.get_subscribers(regions.iter().copied()).await.len(), 320);
+
+        subscriptions.unsubscribe(connection(3), &regions[..300]).await;
+        assert_eq!(subscriptions.get_regions(connection(3)).await.len(), 20);
+
+        subscriptions.remove_connection(connection(3)).await;
+        assert!(subscriptions.get_subscribers(regions.into_iter()).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cameras_follow_their_hero()
+    {
+        let subscriptions = RegionSubscriptions::new();
+        subscriptions.add_hero_connection(connection(1), 7, &[9]).await;
+        subscriptions.subscribe(connection(2), &[100]).await;
+        subscriptions.follow_hero(connection(2), Some(7), &[9]).await;
+        subscriptions.follow_hero(connection(3), Some(7), &[9]).await;
+
+        subscriptions.update_hero_regions(7, &[10, 11]).await;
+        assert_eq!(subscriptions.get_regions(connection(2)).await, HashSet::from([10, 11, 100]));
+        assert_eq!(subscriptions.get_subscribers([10].into_iter()).await[&10].len(), 3);
+
+        // the hero leaving doesn't stop the cameras, they keep the last regions.
+        subscriptions.remove_connection(connection(1)).await;
+        subscriptions.follow_hero(connection(3), None, &[]).await;
+        subscriptions.update_hero_regions(7, &[12]).await;
+        assert_eq!(subscriptions.get_regions(connection(2)).await, HashSet::from([12, 100]));
+        assert!(subscriptions.get_regions(connection(3)).await.is_empty());
+
+        subscriptions.remove_connection(connection(2)).await;
+        subscriptions.update_hero_regions(7, &[13]).await;
+        assert!(subscriptions.get_subscribers([12, 13, 100].into_iter()).await.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::protocols::Protocol;
+
+pub const INITIAL_RETRANSMIT : Duration = Duration::from_millis(200);
+pub const MAX_RETRANSMIT : Duration = Duration::from_secs(2);
+// a client that stops acking this many messages is not going to catch up.
+pub const MAX_PENDING : usize = 256;
+
+pub const RELIABLE_HEADER_SIZE : usize = 5;
+
+struct PendingMessage
+{
+    sequence : u32,
+    packet : Bytes,
+    next_send : Instant,
+    backoff : Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReliableError
+{
+    // too many messages waiting for an ack.
+    Full,
+}
+
+// server to client messages that can't be lost, like inventories or crafted cards.
+// protocol (1 byte), sequence (4 bytes) and then the original message. The client acks the
+// highest sequence it received in order and delivers them in that same order.
+// clients only get these after their first ReliableAck, before that messages go out as they are.
+pub struct ReliableChannel
+{
+    enabled : bool,
+    next_sequence : u32,
+    pending : VecDeque<PendingMessage>,
+}
+
+impl ReliableChannel
+{
+    pub fn new() -> Self
+    {
+        ReliableChannel { enabled: false, next_sequence: 1, pending: VecDeque::new() }
+    }
+
+    pub fn is_enabled(&self) -> bool
+    {
+        self.enabled
+    }
+
+    // the packet to send right away, it stays pending until it is acked.
+    pub fn push(&mut self, data : &[u8], now : Instant) -> Result<Bytes, ReliableError>
+    {
+        if self.pending.len() >= MAX_PENDING
+        {
+            return Err(ReliableError::Full);
+        }
+
+        let sequence = self.next_sequence;
+        self.next_sequence = self.next_sequence.wrapping_add(1);
+
+        let packet = encode(sequence, data);
+        self.pending.push_back(PendingMessage { sequence, packet: packet.clone(), next_send: now + INITIAL_RETRANSMIT, backoff: INITIAL_RETRANSMIT });
+        Ok(packet)
+    }
+
+    // acks are cumulative, everything up to the sequence arrived. The first one enables the channel.
+    pub fn ack(&mut self, sequence : u32) -> usize
+    {
+        self.enabled = true;
+        let mut acked = 0;
+        while let Some(message) = self.pending.front()
+        {
+            // sequences wrap, anything behind the ack counts as received.
+            if (sequence.wrapping_sub(message.sequence) as i32) < 0
+            {
+                break;
+            }
+            self.pending.pop_front();
+            acked += 1;
+        }
+        acked
+    }
+
+    // packets that waited too long for their ack, each one waits twice as long the next time.
+    pub fn get_due(&mut self, now : Instant) -> Vec<Bytes>
+    {
+        let mut due = Vec::new();
+        for message in self.pending.iter_mut().filter(|message| message.next_send <= now)
+        {
+            message.backoff = Duration::min(message.backoff * 2, MAX_RETRANSMIT);
+            message.next_send = now + message.backoff;
+            due.push(message.packet.clone());
+        }
+        due
+    }
+
+    pub fn pending_len(&self) -> usize
+    {
+        self.pending.len()
+    }
+}
+
+impl Default for ReliableChannel
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+pub fn encode(sequence : u32, data : &[u8]) -> Bytes
+{
+    let mut buffer = Vec::with_capacity(RELIABLE_HEADER_SIZE + data.len());
+    buffer.push(Protocol::Reliable as u8);
+    buffer.extend_from_slice(&u32::to_le_bytes(sequence));
+    buffer.extend_from_slice(data);
+    Bytes::from(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_resent_until_acked()
+    {
+        let start = Instant::now();
+        let mut channel = ReliableChannel::new();
+        assert!(!channel.is_enabled());
+        assert_eq!(channel.ack(0), 0);
+        assert!(channel.is_enabled());
+
+        let first = channel.push(&[5, 1, 2], start).unwrap();
+        assert_eq!(first[0], Protocol::Reliable as u8);
+        assert_eq!(u32::from_le_bytes([first[1], first[2], first[3], first[4]]), 1);
+        assert_eq!(&first[RELIABLE_HEADER_SIZE..], &[5, 1, 2]);
+        channel.push(&[30], start).unwrap();
+
+        assert!(channel.get_due(start).is_empty());
+        assert_eq!(channel.get_due(start + INITIAL_RETRANSMIT).len(), 2);
+        // backoff doubled, nothing to send yet.
+        assert!(channel.get_due(start + INITIAL_RETRANSMIT * 2).is_empty());
+        assert_eq!(channel.get_due(start + INITIAL_RETRANSMIT * 3).len(), 2);
+
+        assert_eq!(channel.ack(1), 1);
+        let due = channel.get_due(start + INITIAL_RETRANSMIT * 10);
+        assert_eq!(due.len(), 1);
+        assert_eq!(&due[0][RELIABLE_HEADER_SIZE..], &[30]);
+
+        // an old ack arriving late changes nothing.
+        assert_eq!(channel.ack(0), 0);
+        assert_eq!(channel.ack(2), 1);
+        assert_eq!(channel.pending_len(), 0);
+    }
+
+    #[test]
+    fn a_client_that_never_acks_fills_the_channel()
+    {
+        let now = Instant::now();
+        let mut channel = ReliableChannel::new();
+        for _ in 0..MAX_PENDING
+        {
+            channel.push(&[1], now).unwrap();
+        }
+        assert_eq!(channel.push(&[1], now), Err(ReliableError::Full));
+
+        channel.ack(MAX_PENDING as u32);
+        assert!(channel.push(&[1], now).is_ok());
+    }
+
+    #[test]
+    fn sequences_wrap()
+    {
+        let now = Instant::now();
+        let mut channel = ReliableChannel { enabled: true, next_sequence: u32::MAX, pending: VecDeque::new() };
+        channel.push(&[1], now).unwrap();
+        channel.push(&[2], now).unwrap();
+        assert_eq!(channel.ack(u32::MAX), 1);
+        assert_eq!(channel.ack(0), 1);
+    }
+}
@@ -1,28 +1,17 @@
 use axum::http::version;
-use tokio::{net::{TcpListener, TcpStream}, sync::{broadcast, mpsc::{self, Receiver, Sender}, watch, Mutex}, time};
+use tokio::{net::{TcpListener, TcpStream}, sync::{broadcast, mpsc::{self, Receiver, Sender}, watch}, time};
 use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
 use futures_util::{stream::ForEach, SinkExt, StreamExt}; // for reading/writing messages
-use std::{collections::{vec_deque, HashMap}, net::SocketAddr, sync::{atomic::{AtomicBool, AtomicU16}, Arc}, time::Duration};
+use std::{collections::vec_deque, net::SocketAddr, sync::Arc};
 use bytes::Bytes;
 
-use crate::{chat::ChatCommand, gaia_mpsc, gameplay_service::generic_command::GenericCommand, hero::hero_command::HeroCommand, kingdom::KingdomCommand, map::{map_entity::MapCommand, GameMap}, mob::mob_command::MobCommand, protocols, tower::TowerCommand, ServerState};
-
-pub struct WebSocketConnection
-{
-    pub session_id: u64,
-    pub hero_id : u16,
-    pub faction : u8,
-    pub address : SocketAddr,
-    pub link : tokio::sync::mpsc::Sender<Bytes>
-    // pub link : futures_util::stream::SplitSink<WebSocketStream<TcpStream>, Message>
-}
-
+use crate::{chat::ChatCommand, clients_service::{fragmentation::Reassembler, connection::{self, ConnectionId, ConnectionRegistry, ConnectionRole, Registration, WebSocketTransport, CONNECTION_TIMEOUT}, connection_stats::ConnectionStats, rate_limiter::{self, RateLimitConfig, RateLimitResult, RateLimiter}}, gaia_mpsc, gameplay_service::generic_command::GenericCommand, hero::hero_command::HeroCommand, kingdom::KingdomCommand, map::{map_entity::MapCommand, GameMap}, mob::mob_command::MobCommand, protocols::{self, packet_reader::{PacketReader, ProtocolError}, spectate_protocol, Protocol}, server_config::SpectatorConfig, tower::TowerCommand, ServerState};
 
 pub async fn run(
-    from_server:  tokio::sync::mpsc::Receiver<Vec<(u64, u8, u16, u32, Bytes)>>,
-    from_server_specific:  tokio::sync::mpsc::Receiver<(SocketAddr, Bytes)>,
+    addr : SocketAddr,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
+    connections : Arc<ConnectionRegistry>,
     tx_gc_clients_gameplay : gaia_mpsc::GaiaSender<GenericCommand>,
     // diconnected_channel_tx : mpsc::Sender<(std::net::SocketAddr, u64)>,
     tx_mc_clients_gameplay : gaia_mpsc::GaiaSender<MapCommand>,
@@ -31,28 +20,23 @@ pub async fn run(
     tx_tc_clients_gameplay : gaia_mpsc::GaiaSender<TowerCommand>,
     tx_kc_clients_gameplay : gaia_mpsc::GaiaSender<KingdomCommand>,
     tx_cc_clients_gameplay : gaia_mpsc::GaiaSender<ChatCommand>,
-    regions : Arc<HashMap<u16, [AtomicU16;3]>>)
+    rate_limit_config : Arc<RateLimitConfig>,
+    spectators : Arc<SpectatorConfig>)
 {
     // Bind to a local TCP socket
-    let addr = "0.0.0.0:11001";
     let listener = TcpListener::bind(&addr).await.expect("Can't bind");
     cli_log::info!("WebSocket server running at ws://{}", addr);
 
-    let clients = Arc::new(Mutex::new(HashMap::new()));
-
-    tokio::spawn(send_data_to_clients(from_server, clients.clone(), regions.clone()));
-    tokio::spawn(send_data_to_specific_client(from_server_specific, clients.clone()));
 
     // Accept incoming connections
     while let Ok((stream, socket_addr)) = listener.accept().await 
     {
-        let clients_for_adding= clients.clone();
         tokio::spawn(handle_connection(
             stream,
             socket_addr,
             map.clone(),
             server_state.clone(),
-            clients_for_adding,
+            connections.clone(),
             tx_gc_clients_gameplay.clone(),
             tx_mc_clients_gameplay.clone(),
             tx_moc_clients_gameplay.clone(),
@@ -60,7 +44,8 @@ pub async fn run(
             tx_tc_clients_gameplay.clone(),
             tx_kc_clients_gameplay.clone(),
             tx_cc_clients_gameplay.clone(),
-            regions.clone()
+            rate_limit_config.clone(),
+            spectators.clone()
         ));
     }
 }
@@ -73,7 +58,7 @@ async fn handle_connection(
     // faction:u8,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
-    clients: Arc<Mutex<HashMap<SocketAddr, WebSocketConnection>>>,
+    connections : Arc<ConnectionRegistry>,
     tx_gc_clients_gameplay : gaia_mpsc::GaiaSender<GenericCommand>,
     // diconnected_channel_tx : mpsc::Sender<(std::net::SocketAddr, u64)>,
     tx_mc_clients_gameplay : gaia_mpsc::GaiaSender<MapCommand>,
@@ -82,7 +67,8 @@ async fn handle_connection(
     tx_tc_clients_gameplay : gaia_mpsc::GaiaSender<TowerCommand>,
     tx_kc_clients_gameplay : gaia_mpsc::GaiaSender<KingdomCommand>,
     tx_cc_clients_gameplay : gaia_mpsc::GaiaSender<ChatCommand>,
-    regions : Arc<HashMap<u16, [AtomicU16;3]>>)
+    rate_limit_config : Arc<RateLimitConfig>,
+    spectators : Arc<SpectatorConfig>)
 
 {
     cli_log::info!("New connection from {}", addr);
@@ -100,18 +86,37 @@ async fn handle_connection(
         let (tx, rx) = tokio::sync::mpsc::channel::<Bytes>(100);
         tokio::spawn(send_data_to_client(rx, write, kill_tx));
 
-        server_state.online_players.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-        let created = AtomicBool::new(false);
+        let connection = ConnectionId::websocket(addr);
+        // session id and hero id bound to this connection by the first packet
+        let mut identity : Option<(u64, u16)> = None;
+        // of the registration made with that identity, see ConnectionRegistry::expire.
+        let mut generation : Option<u64> = None;
+        // changes once the registration is replaced or removed, like in the udp client handler.
+        let mut stopped : Option<watch::Receiver<bool>> = None;
+        let mut replaced = false;
+        // set instead of the identity when the first packet is a spectate request.
+        let mut spectator_role : Option<ConnectionRole> = None;
+        // taken from the registry once the connection is registered.
+        let mut stats : Option<Arc<ConnectionStats>> = None;
+        let mut rate_limiter = RateLimiter::new(rate_limit_config);
+        // websocket messages have no size problem, but a client can still send fragments.
+        let mut reassembler = Reassembler::new();
 
         'main_loop : loop
         {
-            let time_out = time::sleep(Duration::from_secs_f32(10.0)); 
+            let time_out = time::sleep(CONNECTION_TIMEOUT); 
             tokio::select! 
             {
                 _ = kill_rx.changed() => 
                 {
                     break 'main_loop;
                 },
+                _ = wait_until_stopped(&mut stopped) =>
+                {
+                    cli_log::info!("{} is not served by this task anymore", connection);
+                    replaced = true;
+                    break 'main_loop;
+                },
                 _ = time_out => 
                 {
                     cli_log::info!("we couldn't wait any longer sorry!");
@@ -126,48 +131,96 @@ async fn handle_connection(
                             if msg.is_binary() 
                             {
                                 let data = msg.into_data();
+                                stats.as_deref().inspect(|stats| stats.add_received(data.len()));
 
-                                if !created.load(std::sync::atomic::Ordering::Relaxed)
+                                // spectators only ever send spectate packets, never gameplay ones.
+                                if spectator_role.is_some() || (identity.is_none() && data.first() == Some(&(Protocol::Spectate as u8)))
                                 {
-                                    cli_log::info!("websocket:creating client");
-                                    created.store(true, std::sync::atomic::Ordering::Relaxed);
-
-                                    let start = 1;
-                                    let end = start + 8;
-                                    let player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-                                    let start = end;
-                                    let end = start + 2;
-                                    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
+                                    // gameplay packets are limited by route_packet, once they passed the identity check.
+                                    match rate_limiter::check_packet(&mut rate_limiter, &data, &server_state, stats.as_deref(), connection)
+                                    {
+                                        RateLimitResult::Allowed => {},
+                                        RateLimitResult::Dropped => continue 'main_loop,
+                                        RateLimitResult::Disconnect => break 'main_loop,
+                                    }
+
+                                    server_state.received_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                    server_state.received_bytes.fetch_add(data.len() as u64, std::sync::atomic::Ordering::Relaxed);
+                                    match process_spectate(connection, &data, spectator_role, &spectators, &map, &connections, &tx).await
+                                    {
+                                        Ok((role, registration)) =>
+                                        {
+                                            if let Some(registration) = registration
+                                            {
+                                                stopped = Some(registration.stopped);
+                                                stats = connections.get_stats(connection).await;
+                                                stats.as_deref().inspect(|stats| stats.add_received(data.len()));
+                                            }
+                                            spectator_role = Some(role);
+                                        },
+                                        Err(error) =>
+                                        {
+                                            cli_log::info!("websocket:rejected spectator packet from {} {}", addr, error);
+                                            server_state.rejected_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                            if spectator_role.is_none()
+                                            {
+                                                break 'main_loop;
+                                            }
+                                        }
+                                    }
+                                    continue 'main_loop;
+                                }
 
-                                    let start = end;
-                                    let _end = start + 1;
-                                    let faction = data[start];
+                                if identity.is_none()
+                                {
+                                    cli_log::info!("websocket:creating client");
+                                    let mut reader = PacketReader::new(&data);
+                                    let header = match reader.read_header()
+                                    {
+                                        Ok(header) => header,
+                                        Err(error) =>
+                                        {
+                                            cli_log::info!("websocket:rejected bad header from {} {}", addr, error);
+                                            continue 'main_loop;
+                                        }
+                                    };
+
+                                    let player_session_id = header.session_id;
+                                    let player_id = header.player_id;
+                                    let faction = header.faction;
+
+                                    if !connection::is_logged_in(&map, player_id, player_session_id)
+                                    {
+                                        cli_log::info!("websocket:rejected invalid session id for hero {player_id}");
+                                        server_state.rejected_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                                        break 'main_loop;
+                                    }
 
+                                    identity = Some((player_session_id, player_id));
                                     cli_log::info!("creating new websocket connection for {player_session_id} and hero id : {player_id}");
-
-                                    let mut clients_lock = clients.lock().await;
-                                    clients_lock.insert(addr, WebSocketConnection
-                                    {
-                                        session_id : player_session_id,
-                                        address: addr,
-                                        link: tx.clone(),
-                                        hero_id: player_id,
-                                        faction,
-                                    });
-                                    drop(clients_lock);
+                                    let transport = Box::new(WebSocketTransport { link: tx.clone() });
+                                    let registration = connections.register(connection, player_id, player_session_id, faction, transport).await;
+                                    generation = Some(registration.generation);
+                                    stopped = Some(registration.stopped);
+                                    stats = connections.get_stats(connection).await;
+                                    stats.as_deref().inspect(|stats| stats.add_received(data.len()));
                                 }
 
                                 // cli_log::info!("websocket:got data from client {}", data.len());
                                 // let _result = to_server.send(msg.into_data()).await;
-                                protocols::route_packet(
-                                    addr,
-                                    false,
+                                let (session_id, hero_id) = identity.unwrap_or_default();
+                                let rate_limit = protocols::route_packet(
+                                    connection,
+                                    session_id,
+                                    hero_id,
                                     &data,
                                     data.len(),
                                     &map,
                                     &server_state,
-                                    &regions,
+                                    &connections,
+                                    &mut reassembler,
+                                    &mut rate_limiter,
+                                    stats.as_deref(),
                                     &tx_gc_clients_gameplay,
                                     &tx_pc_clients_gameplay, 
                                     &tx_mc_clients_gameplay,
@@ -176,6 +229,11 @@ async fn handle_connection(
                                     &tx_kc_clients_gameplay,
                                     &tx_cc_clients_gameplay,
                                 ).await;
+
+                                if rate_limit == RateLimitResult::Disconnect
+                                {
+                                    break 'main_loop;
+                                }
                             }
                             else if msg.is_close() 
                             {
@@ -192,10 +250,17 @@ async fn handle_connection(
             }
         }
 
-        let mut clients_lock = clients.lock().await;
-        clients_lock.remove(&addr);
+        if let Some(generation) = generation
+        {
+            connections.expire(connection, generation, &tx_pc_clients_gameplay).await;
+        }
+
+        // the registration isn't ours anymore, removing it would drop whoever has it now.
+        if spectator_role.is_some() && !replaced
+        {
+            connections.remove_spectator(connection).await;
+        }
 
-        server_state.online_players.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
         cli_log::info!("Connection {} closed", addr);
     }
     else 
@@ -204,6 +269,53 @@ async fn handle_connection(
     }
 }
 
+// the first request registers the connection, the next ones change what it watches.
+// every request carries the token and it has to give the same role as the first one.
+async fn process_spectate(
+    connection : ConnectionId,
+    data : &[u8],
+    role : Option<ConnectionRole>,
+    spectators : &SpectatorConfig,
+    map : &Arc<GameMap>,
+    connections : &Arc<ConnectionRegistry>,
+    link : &Sender<Bytes>) -> Result<(ConnectionRole, Option<Registration>), ProtocolError>
+{
+    let protocol = data.first().copied().unwrap_or_default();
+    if protocol != Protocol::Spectate as u8
+    {
+        return Err(ProtocolError::UnknownProtocol(protocol));
+    }
+
+    let request = spectate_protocol::read(data)?;
+    let new_role = spectators.get_role(&request.token)
+        .filter(|new_role| role.is_none_or(|role| role == *new_role))
+        .ok_or(ProtocolError::InvalidField("token"))?;
+
+    let mut registration = None;
+    if role.is_none()
+    {
+        let transport = Box::new(WebSocketTransport { link: link.clone() });
+        registration = Some(connections.register_spectator(connection, new_role, transport).await);
+    }
+
+    spectate_protocol::process(connection, &request, map).await;
+    Ok((new_role, registration))
+}
+
+// never done before the connection is registered.
+async fn wait_until_stopped(stopped : &mut Option<watch::Receiver<bool>>)
+{
+    match stopped
+    {
+        Some(stopped) =>
+        {
+            // an error means the registration is gone, that stops the task too.
+            let _ = stopped.changed().await;
+        },
+        None => std::future::pending().await,
+    }
+}
+
 async fn send_data_to_client(
     mut from_server : tokio::sync::mpsc::Receiver<Bytes>,
     mut link : futures_util::stream::SplitSink<WebSocketStream<TcpStream>, Message>,
@@ -238,80 +350,3 @@ async fn send_data_to_client(
 
     let _ = kill_watch.send(0);
 }
-
-async fn send_data_to_clients(
-    mut from_server : tokio::sync::mpsc::Receiver<Vec<(u64, u8, u16, u32, Bytes)>>,
-    clients: Arc<Mutex<HashMap<SocketAddr, WebSocketConnection>>>,
-    regions : Arc<HashMap<u16, [AtomicU16;3]>>)
-{
-    loop 
-    {
-        if let Some(packet_list) = from_server.recv().await
-        {
-            let locked_clients = clients.lock().await;
-            for client in locked_clients.iter()
-            {
-                // cli_log::info!("sending data to clients {}" , client.0);
-                let client_regions = regions.get(&client.1.hero_id).unwrap();
-                let a = client_regions[0].load(std::sync::atomic::Ordering::Relaxed);
-                let b = client_regions[1].load(std::sync::atomic::Ordering::Relaxed);
-                let c = client_regions[2].load(std::sync::atomic::Ordering::Relaxed);
-
-                for (_packet_id, faction, region, game_packets, data) in packet_list.iter()
-                {
-                    let is_in_region = *region == a || *region == b || *region == c || a == 0 || *region == 0;
-                    if is_in_region && (client.1.faction == *faction || *faction == 0)
-                    {
-                        // sent_bytes += data.len() as u64;
-                        // sent_udp_packets += 1;
-                        // sent_game_packets += *game_packets as u64;
-                        // let result = send_udp_socket.try_send_to(data, client.0.clone());
-                        let result = client.1.link.send(data.clone()).await;
-                        match result 
-                        {
-                            Ok(_) => 
-                            {
-                                // cli_log::info!("data sent to client {}", packet.len());
-                            },
-                            Err(_) => cli_log::info!("error sending data to client queue"),
-                        }
-                    }
-                }
-            }
-        }
-        else
-        { 
-            cli_log::error!("error receiving data before sending to websocket");
-            break;
-        }
-    }
-}
-
-async fn send_data_to_specific_client(
-    mut from_server : tokio::sync::mpsc::Receiver<(SocketAddr, Bytes)>,
-    clients: Arc<Mutex<HashMap<SocketAddr, WebSocketConnection>>>
-) 
-{
-    'main : loop 
-    {
-        if let Some(message) = from_server.recv().await
-        {
-            cli_log::info!("websocket:sending data to specific client channel {}", message.0);
-            let locked_clients = clients.lock().await;
-
-            if let Some(client) = locked_clients.get(&message.0)
-            {
-                let _result = client.link.send(message.1).await;
-            }
-            else
-            {
-                cli_log::error!("client not found for direct message");
-            }
-        }
-        else
-        { 
-            cli_log::error!("error receiving data before sending to websocket");
-            break 'main;
-        }
-    }
-}
\ No newline at end of file
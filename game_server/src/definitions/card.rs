@@ -20,7 +20,7 @@ pub struct Card
     pub duration_time:f32,
     pub hits:u8,
     pub cooldown:f32,
-    pub hit_range:f32,
+    pub hit_range:f32, // in tiles at TILE_LOD (lod 9) center to center, the same tiles heroes walk on. 0.5 is melee, 2 is a couple of tiles.
     pub buff:String,
     pub effect_probability:f32,
 }
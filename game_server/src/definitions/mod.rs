@@ -1,3 +1,19 @@
+use std::collections::HashMap;
+
+use crate::{get_regions_by_code, get_regions_by_id};
+use buffs_data::BuffData;
+use card::Card;
+use character_progression::CharacterProgression;
+use definition_versions::DefinitionVersion;
+use definitions_container::{Definitions, DefinitionsData};
+use items::Item;
+use main_paths::MapPath;
+use mob_progression::MobProgression;
+use mobs_data::MobData;
+use props_data::PropData;
+use tower_difficulty::TowerDifficulty;
+use weapons::Weapon;
+
 pub mod character_progression;
 pub mod mob_progression;
 pub mod definition_versions;
@@ -18,4 +34,114 @@ pub trait Definition
     {
 
     }
-}
\ No newline at end of file
+}
+
+async fn load_definition_by_name<T>(file_name : String) -> (Vec<T>, Vec<u8>)
+where T: serde::de::DeserializeOwned + Definition
+{
+    let file_name = format!("definitions/{file_name}");
+    let mut data = Vec::<T>::new();
+    cli_log::info!("reading definition file {}", file_name);
+    let definition_versions_data = tokio::fs::read(file_name).await.unwrap();
+    let mut rdr = csv::Reader::from_reader(definition_versions_data.as_slice());
+    for result in rdr.deserialize() 
+    {
+        let record: T = result.unwrap();
+        data.push(record);
+    }
+    (data, definition_versions_data)
+}
+
+// reads every csv in the definitions folder, the parsed rows and the raw files the api serves.
+pub async fn load_definitions() -> (Definitions, DefinitionsData)
+{
+    let mut definition_versions = HashMap::new();
+
+    let file_name = format!("definition_versions.csv");
+    let definition_versions_result = load_definition_by_name::<DefinitionVersion>(file_name).await;
+
+    for entry in definition_versions_result.0
+    {
+        definition_versions.insert(entry.key.clone(), entry);
+    }
+
+    let file_name = format!("character_progression.csv");
+    let character_result = load_definition_by_name::<CharacterProgression>(file_name).await;
+
+    let file_name = format!("mob_progression.csv");
+    let mob_progression_result = load_definition_by_name::<MobProgression>(file_name).await;
+
+    let file_name = format!("props.csv");
+    let props_result = load_definition_by_name::<PropData>(file_name).await;
+
+    let file_name = format!("main_paths.csv");
+    let paths_result = load_definition_by_name::<MapPath>(file_name).await;
+
+    let file_name = format!("towers_difficulty.csv");
+    let towers_difficulty_result = load_definition_by_name::<TowerDifficulty>(file_name).await;
+
+    let file_name = format!("items.csv");
+    let items_result = load_definition_by_name::<Item>(file_name).await;
+
+    let file_name = format!("cards.csv");
+    let cards_result = load_definition_by_name::<Card>(file_name).await;
+
+    let file_name = format!("mobs.csv");
+    let mobs_result = load_definition_by_name::<MobData>(file_name).await;
+
+    let file_name = format!("buffs.csv");
+    let buffs_result = load_definition_by_name::<BuffData>(file_name).await;
+
+    let file_name = format!("weapons.csv");
+    let weapons_result = load_definition_by_name::<Weapon>(file_name).await;
+
+    let mut buffs_hash = HashMap::new();
+
+    for entry in &buffs_result.0
+    {
+        buffs_hash.insert(entry.id.clone(), entry.clone());
+    }
+
+    let mut mob_progression_by_mob = vec![Vec::new(); mobs_result.0.len()];
+
+    for item in mob_progression_result.0.iter().enumerate()
+    {
+        mob_progression_by_mob[item.1.mob as usize].push(item.1.clone());
+    }
+
+    let definitions = Definitions 
+    {
+        regions_by_id: get_regions_by_id(),
+        regions_by_code: get_regions_by_code(),
+        character_progression : character_result.0,
+        props : props_result.0,
+        mob_progression : mob_progression_result.0,
+        mob_progression_by_mob,
+        main_paths: paths_result.0,
+        towers_difficulty: towers_difficulty_result.0,
+        items: items_result.0,
+        cards :cards_result.0,
+        mobs: mobs_result.0,
+        buffs_by_code: buffs_result.0,
+        buffs : buffs_hash,
+        weapons : weapons_result.0,
+    };
+
+    let definitions_data = DefinitionsData
+    {
+        definition_versions,
+        character_progression_data : character_result.1,
+        mob_progression_data : mob_progression_result.1,
+        definition_versions_data : definition_versions_result.1,
+        props_data : props_result.1,
+        main_paths_data : paths_result.1,
+        towers_difficulty_data: towers_difficulty_result.1,
+        items_data :items_result.1,
+        cards_data: cards_result.1,
+        mobs_data: mobs_result.1,
+        buffs_data: buffs_result.1,
+        weapons_data: weapons_result.1,
+    };
+
+    (definitions, definitions_data)
+}
@@ -0,0 +1,75 @@
+use std::sync::atomic::AtomicU64;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// where the gameplay and chat loops get the time from, the processors only get what the loop read.
+// the replay runs them on simulated time and the tests on a manual clock.
+pub trait Clock : Send + Sync
+{
+    // ms since the epoch.
+    fn now_millis(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock
+{
+    fn now_millis(&self) -> u64
+    {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64
+    }
+}
+
+// starts at a given time and follows tokio's clock from there, so a paused runtime also stops it.
+pub struct SimulatedClock
+{
+    start_time : u64,
+    started : tokio::time::Instant,
+}
+
+impl SimulatedClock
+{
+    pub fn new(start_time : u64) -> Self
+    {
+        SimulatedClock { start_time, started: tokio::time::Instant::now() }
+    }
+}
+
+impl Clock for SimulatedClock
+{
+    fn now_millis(&self) -> u64
+    {
+        self.start_time + self.started.elapsed().as_millis() as u64
+    }
+}
+
+// only moves when told to, for testing the gameplay at exact times.
+pub struct ManualClock
+{
+    now : AtomicU64,
+}
+
+impl ManualClock
+{
+    pub fn new(start_time : u64) -> Self
+    {
+        ManualClock { now: AtomicU64::new(start_time) }
+    }
+
+    pub fn set(&self, time : u64)
+    {
+        self.now.store(time, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, millis : u64)
+    {
+        self.now.fetch_add(millis, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock
+{
+    fn now_millis(&self) -> u64
+    {
+        self.now.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
@@ -18,29 +18,42 @@ use bytes::Bytes;
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
 
-use super::PacketsData;
+use super::{delta_encoder, PacketsData};
 
 pub fn init_data_packet(packets_data : &mut PacketsData)
     -> usize
 {
     packets_data.packet_number += 1u64;
     // cli_log::info!("{packet_number} -A");
+    write_packet_header(&mut packets_data.buffer, crate::protocols::Protocol::GlobalState as u8, packets_data.packet_number, packets_data.time, packets_data.region)
+}
+
+pub const PACKET_HEADER_SIZE : usize = 19;
 
+// protocol (1 byte), packet number (8 bytes), server time in ms (8 bytes) and region (2 bytes).
+// the time is the one of the tick that made the packet, so it comes from the game clock like everything else.
+pub fn write_packet_header(buffer : &mut [u8;5000], protocol : u8, packet_number : u64, time : u64, region : u16) -> usize
+{
     let mut start: usize = 1;
-    packets_data.buffer[0] = crate::protocols::Protocol::GlobalState as u8;
+    buffer[0] = protocol;
 
-    let packet_number_bytes = u64::to_le_bytes(packets_data.packet_number); // 8 bytes
+    let packet_number_bytes = u64::to_le_bytes(packet_number); // 8 bytes
 
     let end: usize = start + 8;
-    packets_data.buffer[start..end].copy_from_slice(&packet_number_bytes);
+    buffer[start..end].copy_from_slice(&packet_number_bytes);
     start = end;
 
-    let result = std::time::SystemTime::now().duration_since(SystemTime::UNIX_EPOCH);
-    let current_time = result.ok().map(|d| d.as_secs() as u32);
-    let current_time_bytes = u32::to_le_bytes(current_time.unwrap()); // 4 bytes
- 
-    let end: usize = start + 4;
-    packets_data.buffer[start..end].copy_from_slice(&current_time_bytes);
+    // clients interpolate with this, seconds were not enough.
+    let current_time_bytes = u64::to_le_bytes(time); // 8 bytes
+
+    let end: usize = start + 8;
+    buffer[start..end].copy_from_slice(&current_time_bytes);
+    start = end;
+
+    // packet numbers are per region, the client needs the region to ask for missing packets.
+    let region_bytes = u16::to_le_bytes(region); // 2 bytes
+    let end: usize = start + 2;
+    buffer[start..end].copy_from_slice(&region_bytes);
     start = end;
 
     start
@@ -63,14 +76,56 @@ pub fn build_data_packet(
     {
         // this means we already have some data
         let encoded_data = encode_packet(&mut regions_packets_data.buffer, regions_packets_data.offset);
-        regions_packets_data.packets.push((regions_packets_data.packet_number, 0, 0, regions_packets_data.game_packets_count, Bytes::from(encoded_data)));
+        regions_packets_data.packets.push((regions_packets_data.packet_number, 0, regions_packets_data.region, regions_packets_data.game_packets_count, Bytes::from(encoded_data)));
         regions_packets_data.offset = init_data_packet(regions_packets_data);
         regions_packets_data.game_packets_count = 0;
     }
 
+    // clients with deltas build their own packets from these.
+    if delta_encoder::get_layout(data_type as u8).is_some()
+    {
+        regions_packets_data.snapshots.push((data_type as u8, Bytes::copy_from_slice(chunk)));
+    }
+
     add_to_data_packet(&mut regions_packets_data.buffer, &mut regions_packets_data.offset, &mut regions_packets_data.game_packets_count, data_type, chunk_size, &chunk);
 }
 
+// same as the region packets, but for a single client and with the chunks the delta encoder gave us.
+pub fn build_delta_packets(packet_number : &mut u64, region : u16, time : u64, chunks : &[(u8, Vec<u8>)]) -> Vec<Bytes>
+{
+    let mut packets = Vec::new();
+    let mut buffer = [0u8; 5000];
+    let mut offset = 0;
+
+    for (data_type, chunk) in chunks
+    {
+        if offset > 0 && offset + chunk.len() + 1 > 5000
+        {
+            packets.push(Bytes::from(encode_packet(&mut buffer, offset)));
+            offset = 0;
+        }
+
+        if offset == 0
+        {
+            *packet_number += 1;
+            offset = write_packet_header(&mut buffer, crate::protocols::Protocol::DeltaState as u8, *packet_number, time, region);
+        }
+
+        buffer[offset] = *data_type;
+        offset += 1;
+        let next = offset + chunk.len();
+        buffer[offset..next].copy_from_slice(chunk);
+        offset = next;
+    }
+
+    if offset > 0
+    {
+        packets.push(Bytes::from(encode_packet(&mut buffer, offset)));
+    }
+
+    packets
+}
+
 pub fn add_to_data_packet(
     buffer : &mut [u8;5000],
     offset: &mut usize ,
@@ -0,0 +1,329 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use bytes::Bytes;
+use tokio::sync::Mutex;
+
+use crate::clients_service::DataType;
+
+// size in bytes of every field of the entities, in the same order as their to_bytes.
+// a delta has one bit per field and only carries the fields that changed.
+pub const HERO_FIELDS : [usize; 23] = [2, 2, 1, 6, 6, 4, 1, 1, 1, 1, 4, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 5];
+pub const TILE_FIELDS : [usize; 19] = [2, 6, 2, 4, 4, 4, 1, 1, 4, 4, 4, 4, 4, 4, 4, 4, 2, 2, 2];
+pub const MOB_FIELDS : [usize; 11] = [4, 2, 1, 1, 2, 4, 6, 6, 4, 2, 5];
+// the last 10 fields are the damage records.
+pub const TOWER_FIELDS : [usize; 14] = [2, 6, 2, 1, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5];
+
+// after this many entities a client just keeps getting full snapshots for the new ones.
+pub const MAX_TRACKED_ENTITIES : usize = 4096;
+// versions we remember per entity while we wait for the client to ack one of them.
+pub const MAX_SENT_VERSIONS : usize = 4;
+
+pub struct EntityLayout
+{
+    pub full_type : u8,
+    pub delta_type : u8,
+    pub fields : &'static [usize],
+    // always sent, the client needs them to find the entity and to ack it.
+    pub key_field : usize,
+    pub version_field : usize,
+}
+
+pub const HERO_LAYOUT : EntityLayout = EntityLayout
+{
+    full_type: DataType::PlayerState as u8,
+    delta_type: DataType::PlayerStateDelta as u8,
+    fields: &HERO_FIELDS,
+    key_field: 0,
+    version_field: 1,
+};
+
+pub const TILE_LAYOUT : EntityLayout = EntityLayout
+{
+    full_type: DataType::TileState as u8,
+    delta_type: DataType::TileStateDelta as u8,
+    fields: &TILE_FIELDS,
+    key_field: 1,
+    version_field: 0,
+};
+
+pub const MOB_LAYOUT : EntityLayout = EntityLayout
+{
+    full_type: DataType::MobStatus as u8,
+    delta_type: DataType::MobStatusDelta as u8,
+    fields: &MOB_FIELDS,
+    key_field: 0,
+    version_field: 3,
+};
+
+pub const TOWER_LAYOUT : EntityLayout = EntityLayout
+{
+    full_type: DataType::TowerState as u8,
+    delta_type: DataType::TowerStateDelta as u8,
+    fields: &TOWER_FIELDS,
+    key_field: 1,
+    version_field: 0,
+};
+
+// data type of the full snapshot and the entity id, padded to 6 bytes.
+pub type EntityKey = (u8, [u8; 6]);
+
+pub fn get_layout(data_type : u8) -> Option<&'static EntityLayout>
+{
+    [&HERO_LAYOUT, &TILE_LAYOUT, &MOB_LAYOUT, &TOWER_LAYOUT]
+        .into_iter()
+        .find(|layout| layout.full_type == data_type)
+}
+
+fn field_range(layout : &EntityLayout, index : usize) -> std::ops::Range<usize>
+{
+    let start : usize = layout.fields[..index].iter().sum();
+    start..(start + layout.fields[index])
+}
+
+pub fn get_key(layout : &EntityLayout, chunk : &[u8]) -> EntityKey
+{
+    let mut key = [0u8; 6];
+    let id = &chunk[field_range(layout, layout.key_field)];
+    key[..id.len()].copy_from_slice(id);
+    (layout.full_type, key)
+}
+
+// mobs use a single byte for the version.
+pub fn get_version(layout : &EntityLayout, chunk : &[u8]) -> u16
+{
+    match &chunk[field_range(layout, layout.version_field)]
+    {
+        [low] => *low as u16,
+        [low, high] => u16::from_le_bytes([*low, *high]),
+        _ => 0,
+    }
+}
+
+// base version (2 bytes), field mask (4 bytes) and then the fields that are set in the mask.
+pub fn encode_delta(layout : &EntityLayout, base : &[u8], current : &[u8]) -> Vec<u8>
+{
+    let mut mask = 0u32;
+    let mut fields = Vec::new();
+    for index in 0..layout.fields.len()
+    {
+        let range = field_range(layout, index);
+        let always_sent = index == layout.key_field || index == layout.version_field;
+        if always_sent || base[range.clone()] != current[range.clone()]
+        {
+            mask |= 1 << index;
+            fields.extend_from_slice(&current[range]);
+        }
+    }
+
+    let mut delta = Vec::with_capacity(6 + fields.len());
+    delta.extend_from_slice(&u16::to_le_bytes(get_version(layout, base)));
+    delta.extend_from_slice(&u32::to_le_bytes(mask));
+    delta.extend_from_slice(&fields);
+    delta
+}
+
+// what the client does with a delta, returns None if the delta doesn't fit the layout.
+pub fn apply_delta(layout : &EntityLayout, base : &[u8], delta : &[u8]) -> Option<Vec<u8>>
+{
+    let mask = u32::from_le_bytes(delta.get(2..6)?.try_into().ok()?);
+    let mut current = base.to_vec();
+    let mut offset = 6;
+    for index in 0..layout.fields.len()
+    {
+        if mask & (1 << index) != 0
+        {
+            let range = field_range(layout, index);
+            let field = delta.get(offset..(offset + range.len()))?;
+            offset += range.len();
+            current.get_mut(range)?.copy_from_slice(field);
+        }
+    }
+
+    if offset != delta.len()
+    {
+        return None;
+    }
+    Some(current)
+}
+
+// what we know about a client that negotiated deltas.
+pub struct ClientDeltaState
+{
+    pub session_id : u64,
+    pub packet_number : u64,
+    acked : HashMap<EntityKey, (u16, Bytes)>,
+    sent : HashMap<EntityKey, VecDeque<(u16, Bytes)>>,
+}
+
+impl ClientDeltaState
+{
+    pub fn new(session_id : u64) -> Self
+    {
+        ClientDeltaState { session_id, packet_number: 0, acked: HashMap::new(), sent: HashMap::new() }
+    }
+
+    // the acked version becomes the base for the next deltas, older versions are not needed anymore.
+    pub fn ack(&mut self, key : EntityKey, version : u16) -> bool
+    {
+        let Some(sent_versions) = self.sent.get_mut(&key) else { return false };
+        let Some(position) = sent_versions.iter().position(|(sent_version, _)| *sent_version == version) else { return false };
+
+        let (_, data) = sent_versions.drain(..=position).last().unwrap();
+        if sent_versions.is_empty()
+        {
+            self.sent.remove(&key);
+        }
+
+        if self.acked.len() >= MAX_TRACKED_ENTITIES && !self.acked.contains_key(&key)
+        {
+            return false;
+        }
+        self.acked.insert(key, (version, data));
+        true
+    }
+
+    // returns the data type and the chunk to send, a delta if the client acked this entity before.
+    pub fn encode(&mut self, data_type : u8, chunk : &Bytes) -> (u8, Vec<u8>)
+    {
+        let Some(layout) = get_layout(data_type) else { return (data_type, chunk.to_vec()) };
+
+        let key = get_key(layout, chunk);
+        let version = get_version(layout, chunk);
+
+        if self.sent.len() < MAX_TRACKED_ENTITIES || self.sent.contains_key(&key)
+        {
+            let sent_versions = self.sent.entry(key).or_default();
+            sent_versions.retain(|(sent_version, _)| *sent_version != version);
+            if sent_versions.len() >= MAX_SENT_VERSIONS
+            {
+                sent_versions.pop_front();
+            }
+            sent_versions.push_back((version, chunk.clone()));
+        }
+
+        match self.acked.get(&key)
+        {
+            Some((_, base)) => (layout.delta_type, encode_delta(layout, base, chunk)),
+            None => (data_type, chunk.to_vec()),
+        }
+    }
+}
+
+// clients enable deltas by sending their first EntityAck, everybody else keeps getting full snapshots.
+pub struct DeltaTracker
+{
+    clients : Mutex<HashMap<u16, ClientDeltaState>>,
+}
+
+impl DeltaTracker
+{
+    pub fn new() -> Self
+    {
+        DeltaTracker { clients: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn ack(&self, hero_id : u16, session_id : u64, entries : &[(EntityKey, u16)])
+    {
+        let mut clients = self.clients.lock().await;
+        let client = clients.entry(hero_id).or_insert_with(|| ClientDeltaState::new(session_id));
+        if client.session_id != session_id
+        {
+            // new login, nothing we sent to the old session is valid.
+            *client = ClientDeltaState::new(session_id);
+        }
+
+        for (key, version) in entries
+        {
+            client.ack(*key, *version);
+        }
+    }
+
+    pub async fn remove(&self, hero_id : u16, session_id : u64)
+    {
+        let mut clients = self.clients.lock().await;
+        if clients.get(&hero_id).is_some_and(|client| client.session_id == session_id)
+        {
+            clients.remove(&hero_id);
+        }
+    }
+
+    // hero id and session id of the clients that negotiated deltas.
+    pub async fn get_clients(&self) -> HashSet<(u16, u64)>
+    {
+        let clients = self.clients.lock().await;
+        clients.iter().map(|(hero_id, client)| (*hero_id, client.session_id)).collect()
+    }
+
+    // encoded DeltaState packets for this client, None if the client didn't negotiate deltas.
+    pub async fn build_packets(&self, hero_id : u16, region : u16, time : u64, snapshots : &[(u8, Bytes)]) -> Option<Vec<Bytes>>
+    {
+        let mut clients = self.clients.lock().await;
+        let client = clients.get_mut(&hero_id)?;
+        let chunks : Vec<(u8, Vec<u8>)> = snapshots.iter()
+            .map(|(data_type, chunk)| client.encode(*data_type, chunk))
+            .collect();
+        Some(super::data_packer::build_delta_packets(&mut client.packet_number, region, time, &chunks))
+    }
+}
+
+impl Default for DeltaTracker
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::map_entity::MapEntity;
+
+    #[test]
+    fn layouts_match_entity_sizes()
+    {
+        assert_eq!(HERO_FIELDS.iter().sum::<usize>(), crate::hero::hero_entity::HERO_ENTITY_SIZE);
+        assert_eq!(TILE_FIELDS.iter().sum::<usize>(), crate::map::map_entity::MAP_ENTITY_SIZE);
+        assert_eq!(MOB_FIELDS.iter().sum::<usize>(), crate::mob::mob_entity::MOB_ENTITY_SIZE);
+        assert_eq!(TOWER_FIELDS.iter().sum::<usize>(), crate::tower::tower_entity::TOWER_ENTITY_SIZE);
+    }
+
+    #[test]
+    fn delta_only_carries_changed_fields()
+    {
+        let mut tile = MapEntity::new("a0123", 100);
+        let base = tile.to_bytes();
+
+        tile.version += 1;
+        tile.health = 77;
+        let current = tile.to_bytes();
+
+        let delta = encode_delta(&TILE_LAYOUT, &base, &current);
+        // header, version, id and health.
+        assert_eq!(delta.len(), 6 + 2 + 6 + 2);
+        assert_eq!(apply_delta(&TILE_LAYOUT, &base, &delta).unwrap(), current.to_vec());
+        assert!(apply_delta(&TILE_LAYOUT, &base, &delta[..delta.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn client_gets_deltas_only_after_acking()
+    {
+        let mut tile = MapEntity::new("a0123", 100);
+        let data_type = DataType::TileState as u8;
+        let mut client = ClientDeltaState::new(10);
+
+        let first = Bytes::copy_from_slice(&tile.to_bytes());
+        assert_eq!(client.encode(data_type, &first).0, data_type);
+
+        let key = get_key(&TILE_LAYOUT, &first);
+        assert!(!client.ack(key, tile.version + 5));
+        assert!(client.ack(key, tile.version));
+
+        tile.version += 1;
+        tile.mana = 3;
+        let second = Bytes::copy_from_slice(&tile.to_bytes());
+        let (delta_type, delta) = client.encode(data_type, &second);
+        assert_eq!(delta_type, DataType::TileStateDelta as u8);
+        assert_eq!(apply_delta(&TILE_LAYOUT, &first, &delta).unwrap(), second.to_vec());
+    }
+}
@@ -1,10 +1,13 @@
 use bytes::Bytes;
 
+use crate::clients_service::connection::ConnectionId;
+
 
 #[derive(Debug, Clone)]
 pub struct GenericCommand
 {
-    pub player_address : std::net::SocketAddr, 
-    pub is_udp : bool,
-    pub data : Bytes
+    pub connection : ConnectionId,
+    pub data : Bytes,
+    // resent until the client acks it, only for udp clients that use the reliable lane.
+    pub reliable : bool,
 }
\ No newline at end of file
@@ -2,6 +2,8 @@ use std::{sync::Arc, collections::HashMap};
 use tokio::{sync::{mpsc::Sender, Mutex}, time::error::Elapsed};
 use crate::{ability_user::{attack::Attack, attack_result::{AttackResult, BATTLE_CHAR_CHAR, BLOCKED_ATTACK_RESULT}, AbilityUser}, definitions::items::ItemUsage, gaia_mpsc::GaiaSender, gameplay_service::tile_commands_processor::attack_walker, hero::{hero_card_inventory::CardItem, hero_command::{self, HeroCommand, HeroCommandInfo, HeroMovement}, hero_entity::{self, HeroEntity, CHAT_FLAG, DASH_FLAG, INSIDE_TOWER_FLAG, TRYING_TO_ENTER_TOWER_FLAG}, hero_inventory::InventoryItem, hero_presentation::HeroPresentation, hero_reward::HeroReward, hero_weapon_inventory::WeaponItem}, map::{tetrahedron_id::{self, TetrahedronId}, GameMap}, tower::tower_entity::TowerEntity, ServerState};
 use crate::buffs::buff::BuffUser;
+use super::range_validation::{self, Action};
+use super::scheduler::{Caster, DelayedCommand, Scheduler};
 
 pub async fn process_hero_commands (
     map : Arc<GameMap>,
@@ -14,14 +16,14 @@ pub async fn process_hero_commands (
     attacks_summary : &mut  Vec<Attack>,
     attack_details_summary : &mut  Vec<AttackResult>,
     rewards_summary : &mut Vec<HeroReward>,
-    delayed_hero_commands_lock : Arc<Mutex<Vec<(u64, HeroCommand)>>>
-)
+    scheduler_lock : Arc<Mutex<Scheduler>>
+) -> usize
 {
     let mut hero_commands_data = hero_commands_processor_lock.lock().await;
 
     if hero_commands_data.len() == 0
     {
-        return;
+        return 0;
     }
 
     for hero_command in hero_commands_data.iter()
@@ -86,6 +88,12 @@ pub async fn process_hero_commands (
                     },
             hero_command::HeroCommandInfo::AttackCharacter(other_player_id, card_id, required_time, active_effect, missed) => 
                     {
+                        let Some(target_position) = range_validation::get_hero_position(&map, *other_player_id).await else { continue };
+                        if !range_validation::validate_hero_action(&map, &server_state, cloned_data.player_id, Action::AttackHero, *card_id, &target_position).await
+                        {
+                            continue;
+                        }
+
                         let end_time = current_time + *required_time as u64;
                         if *required_time == 0
                         {
@@ -105,10 +113,10 @@ pub async fn process_hero_commands (
                         else 
                         {
                             cli_log::info!("------------ required time for hero attack {required_time} current time: {current_time} {card_id}");
-                            let mut lock = delayed_hero_commands_lock.lock().await;
+                            let mut lock = scheduler_lock.lock().await;
                             let info = HeroCommandInfo::AttackCharacter(*other_player_id, *card_id, *required_time, *active_effect, *missed);
                             let character_action = HeroCommand { player_id: cloned_data.player_id, info };
-                            lock.push((end_time, character_action));
+                            lock.schedule(end_time, Caster::Hero(cloned_data.player_id), DelayedCommand::Hero(character_action));
                             drop(lock);
 
                             let attack = Attack
@@ -157,7 +165,9 @@ pub async fn process_hero_commands (
                     },
         }
     }
+    let processed = hero_commands_data.len();
     hero_commands_data.clear();
+    processed
 }
 
 
@@ -13,7 +13,7 @@ pub async fn process_kingdoms_commands (
     _kingdoms_summary : &mut Vec<KingdomEntity>,
     player_attacks_summary : &mut  Vec<Attack>,
     // delayed_tower_commands_lock : Arc<Mutex<Vec<(u64, TowerCommand)>>>
-)
+) -> usize
 {
     // process tower stuff.
     let mut kingdom_commands_data = kingdoms_commands_processor_lock.lock().await;
@@ -24,4 +24,8 @@ pub async fn process_kingdoms_commands (
         {
         }
     }
+    // nothing to do with them yet, but they shouldn't pile up either.
+    let processed = kingdom_commands_data.len();
+    kingdom_commands_data.clear();
+    processed
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
 use std::{collections::HashMap, sync::Arc, u16};
-use rand::rngs::StdRng;
 use tokio::sync::{mpsc::Sender, Mutex};
 use crate::{ServerState, ability_user::{attack::Attack, attack_result::{AttackResult, BATTLE_CHAR_MOB, BATTLE_MOB_CHAR, BATTLE_MOB_MOB}}, buffs::buff::BuffUser, definitions::definitions_container::Definitions, gaia_mpsc::GaiaSender, hero::{hero_entity::{INSIDE_TOWER_FLAG, TRYING_TO_ENTER_TOWER_FLAG}, hero_inventory::InventoryItem}, map::{GameMap, tetrahedron_id::{self, TetrahedronId}}, mob::{mob_command::{self, MobCommand}, mob_entity::MobEntity}};
 use crate::hero::{hero_entity::HeroEntity, hero_reward::HeroReward};
+use super::range_validation::{self, Action};
+use super::region_shards;
+use super::scheduler::{Caster, DelayedCommand, Scheduler};
 
 pub async fn process_mob_commands (
     map : Arc<GameMap>,
@@ -11,224 +13,177 @@ pub async fn process_mob_commands (
     tx_pe_gameplay_longterm : &GaiaSender<HeroEntity>,
     tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
     mobs_commands_processor_lock : Arc<Mutex<Vec<MobCommand>>>,
-    delayed_mob_commands_lock : Arc<Mutex<Vec<(u64, MobCommand)>>>,
+    scheduler_lock : Arc<Mutex<Scheduler>>,
     mobs_summary : &mut Vec<MobEntity>,
     characters_summary : &mut  Vec<HeroEntity>,
     attack_details_summary : &mut Vec<AttackResult>,
     rewards_summary : &mut  Vec<HeroReward>,
     attacks_summary : &mut  Vec<Attack>,
-)
+) -> usize
 {
-    let mut mobs_commands_data = mobs_commands_processor_lock.lock().await;
+    // taken out right away, the clients keep adding commands while the regions work.
+    let mob_commands = std::mem::take(&mut *mobs_commands_processor_lock.lock().await);
+    let processed = mob_commands.len();
 
-    // cli_log::info!("mobs commands len {}", mobs_commands_data.len());
-    if mobs_commands_data.len() > 0 
+    // cli_log::info!("mobs commands len {}", mob_commands.len());
+    let shards = region_shards::shard_by_region(mob_commands, get_mob_command_region);
+    let outputs = region_shards::run_shards(shards.regions, |mob_commands|
     {
-        for mobs_command in mobs_commands_data.iter()
+        let map = map.clone();
+        let server_state = server_state.clone();
+        let tx_pe_gameplay_longterm = tx_pe_gameplay_longterm.clone();
+        let tx_moe_gameplay_webservice = tx_moe_gameplay_webservice.clone();
+        let scheduler_lock = scheduler_lock.clone();
+        async move
         {
-            match mobs_command
+            let mut output = MobShardOutput::default();
+            for mobs_command in mob_commands.iter()
             {
-                mob_command::MobCommand::Touch(data) => 
-                {
-                    check_buffs(
-                        &map,
-                        current_time,
-                        &server_state,
-                        tx_moe_gameplay_webservice,
-                        mobs_summary, 
-                        data.mob_id,
-                        data.mob_tile_id.clone()
-                    ).await;
-                },
-                mob_command::MobCommand::CastFromMobToMob(data) => 
-                {
-                    let end_time = current_time + data.time as u64;
-                    if data.time == 0
-                    {
-                        cast_mob_from_mob(
-                            &map,
-                            current_time,
-                            &server_state,
-                            tx_moe_gameplay_webservice,
-                            mobs_summary,
-                            attack_details_summary,
-                            data.card_id,
-                            data.caster_mob_tile_id.clone(),
-                            data.caster_mob_id,
-                            data.target_mob_tile_id.clone(),
-                            data.target_mob_id,
-                            data.missed
-                        ).await;
-                    }
-                    else
-                    {
-                        // cli_log::info!("------------ required time for cast to mob {data.time} current time: {current_time} {card_id}");
-                        let mut lock = delayed_mob_commands_lock.lock().await;
-                        let delayed_command = mob_command::MobCommand::CastFromMobToMob(data.clone());
-                        // let mob_action = MobCommand { tile_id : mobs_command.tile_id.clone(), info };
-                        lock.push((end_time, delayed_command));
-                        drop(lock);
-
-                        // we only send attack messages if attack is delayed, for projectiles and other instances.
-                        let attack = Attack
-                        {
-                            id: (current_time % 10000) as u16,
-                            attacker_hero_id: 0,
-                            target_hero_id: 0,
-                            card_id: data.card_id,
-                            required_time: data.time,
-                            battle_type : BATTLE_MOB_MOB,
-                            attacker_mob_id: data.caster_mob_id,
-                            target_tile_id: TetrahedronId::default(),
-                            target_mob_id: data.target_mob_id,
-                        };
-
-                        cli_log::info!("--- cast {} ", attack.required_time);
-                        attacks_summary.push(attack);
-                    }
-
-                },
-                mob_command::MobCommand::CastFromHeroToMob(data) => 
-                {
-                    let end_time = current_time + data.time as u64;
-                    if data.time == 0
-                    {
-                        cast_mob_from_character(
-                            &map,
-                            current_time,
-                            &server_state,
-                            tx_moe_gameplay_webservice,
-                            tx_pe_gameplay_longterm,
-                            mobs_summary,
-                            characters_summary,
-                            attack_details_summary,
-                            rewards_summary,
-                            data.card_id,
-                            data.hero_id,
-                            data.target_mob_id,
-                            data.target_mob_tile_id.clone(),
-                            data.missed,
-                        ).await;
-                    }
-                    else 
-                    {
-                        // cli_log::info!("------------ required time for attack to mob {required_time} current time: {current_time} {card_id}");
-                        let mut lock = delayed_mob_commands_lock.lock().await;
-                        let delayed_command = mob_command::MobCommand::CastFromHeroToMob(data.clone());
-                        lock.push((end_time, delayed_command));
-                        drop(lock);
-
-                        // we only send attack messages if attack is delayed, for projectiles and other instances.
-                        let attack = Attack
-                        {
-                            id: (current_time % 10000) as u16,
-                            attacker_hero_id: data.hero_id,
-                            target_hero_id: 0,
-                            attacker_mob_id: 0,
-                            target_mob_id: data.target_mob_id,
-                            card_id: data.card_id,
-                            required_time: data.time,
-                            target_tile_id: TetrahedronId::default(),
-                            battle_type : BATTLE_CHAR_MOB,
-                        };
-
-                        cli_log::info!("--- attack {}", attack.required_time);
-                        attacks_summary.push(attack);
-                    }
-
-                },
-                mob_command::MobCommand::Spawn(data) => 
-                {
-                    spawn_mob(&map, &server_state, tx_moe_gameplay_webservice, mobs_summary, data.tile_id.clone(), current_time, data.hero_id, data.mob_definition_id, data.level).await;
-                },
-                mob_command::MobCommand::ControlMob(data) => 
-                {
-                    control_mob(&map, &server_state, tx_moe_gameplay_webservice, mobs_summary, data.mob_id, data.mob_tile_id.clone(), current_time, data.hero_id).await;
-                },
-                mob_command::MobCommand::MoveMob(data) => 
-                {
-                    move_mob(&map, &server_state, tx_moe_gameplay_webservice, mobs_summary, current_time, data.hero_id, data.mob_id, data.new_origin_tile_id.clone(), data.new_end_tile_id.clone(), data.path).await;
-                },
-                mob_command::MobCommand::AttackFromMobToHero(data) => 
-                {
-                    let end_time = current_time + data.time as u64;
-                    if data.time == 0
-                    {
-                        cast_hero_from_mob(
-                            &map,
-                            current_time,
-                            &server_state,
-                            tx_moe_gameplay_webservice,
-                            tx_pe_gameplay_longterm,
-                            mobs_summary,
-                            characters_summary,
-                            attack_details_summary,
-                            data.card_id,
-                            data.hero_id,
-                            data.attacker_mob_id,
-                            data.attacker_mob_tile_id.clone(),
-                            data.missed,
-                        ).await;
-                    }
-                    else 
-                    {
-                        // cli_log::info!("------------ required time for attack to character from mob {required_time} current time: {current_time} {card_id}");
-                        let mut lock = delayed_mob_commands_lock.lock().await;
-                        let delayed_command = mob_command::MobCommand::AttackFromMobToHero(data.clone());
-                        lock.push((end_time, delayed_command));
-                        drop(lock);
-
-                        // we only send attack messages if attack is delayed, for projectiles and other instances.
-                        let attack = Attack
-                        {
-                            id: (current_time % 10000) as u16,
-                            attacker_hero_id: 0,
-                            target_hero_id: data.hero_id,
-                            attacker_mob_id: data.attacker_mob_id,
-                            target_mob_id: 0,
-                            card_id: data.card_id,
-                            required_time: data.time,
-                            target_tile_id: TetrahedronId::default(),
-                            battle_type : BATTLE_MOB_CHAR,
-                        };
-
-                        cli_log::info!("--- attack {} ", attack.required_time);
-                        attacks_summary.push(attack);
-                    }
-                },
+                process_mob_command(
+                    &map,
+                    current_time,
+                    &server_state,
+                    &tx_pe_gameplay_longterm,
+                    &tx_moe_gameplay_webservice,
+                    &scheduler_lock,
+                    mobs_command,
+                    &mut output).await;
             }
+            output
         }
-        mobs_commands_data.clear();
+    }).await;
+
+    for output in outputs
+    {
+        output.merge_into(mobs_summary, characters_summary, attack_details_summary, rewards_summary, attacks_summary);
+    }
+
+    // these touch two regions, nothing else runs while they lock both.
+    for mobs_command in shards.crossing.iter()
+    {
+        let mut output = MobShardOutput::default();
+        process_mob_command(
+            &map,
+            current_time,
+            &server_state,
+            tx_pe_gameplay_longterm,
+            tx_moe_gameplay_webservice,
+            &scheduler_lock,
+            mobs_command,
+            &mut output).await;
+        output.merge_into(mobs_summary, characters_summary, attack_details_summary, rewards_summary, attacks_summary);
     }
+    processed
 }
 
-pub async fn process_delayed_mob_commands (
-    map : Arc<GameMap>,
+// where the mob is going, mobs stay in the map of the region of that tile.
+async fn get_mob_position(map : &Arc<GameMap>, mob_id : u32, mob_tile_id : &TetrahedronId) -> Option<TetrahedronId>
+{
+    if !mob_tile_id.is_valid_for_lod(9)
+    {
+        return None;
+    }
+
+    let mob_region = map.get_mob_region_from_child(mob_tile_id);
+    let mobs = mob_region.lock().await;
+    mobs.get(&mob_id).map(|mob| mob.end_position_id.clone())
+}
+
+// the region a mob command works on, None when it touches two regions or its tiles are not valid.
+pub fn get_mob_command_region(mob_command : &MobCommand) -> Option<TetrahedronId>
+{
+    let tiles = match mob_command
+    {
+        MobCommand::Touch(data) => vec![&data.mob_tile_id],
+        MobCommand::Spawn(data) => vec![&data.tile_id],
+        MobCommand::ControlMob(data) => vec![&data.mob_tile_id],
+        MobCommand::MoveMob(data) => vec![&data.new_origin_tile_id, &data.new_end_tile_id],
+        MobCommand::CastFromHeroToMob(data) => vec![&data.target_mob_tile_id],
+        MobCommand::CastFromMobToMob(data) => vec![&data.caster_mob_tile_id, &data.target_mob_tile_id],
+        MobCommand::AttackFromMobToHero(data) => vec![&data.attacker_mob_tile_id],
+    };
+
+    // the processors check the tiles, we only need them to not break get_parent.
+    if tiles.iter().any(|tile_id| !tile_id.is_valid_for_lod(9))
+    {
+        return None;
+    }
+
+    let region_id = tiles[0].get_parent(7);
+    if tiles[1..].iter().all(|tile_id| tile_id.get_parent(7) == region_id)
+    {
+        Some(region_id)
+    }
+    else
+    {
+        None
+    }
+}
+
+// what one region produced, merged into the tick summaries once every region is done.
+#[derive(Default)]
+struct MobShardOutput
+{
+    mobs_summary : Vec<MobEntity>,
+    characters_summary : Vec<HeroEntity>,
+    attack_details_summary : Vec<AttackResult>,
+    rewards_summary : Vec<HeroReward>,
+    attacks_summary : Vec<Attack>,
+}
+
+impl MobShardOutput
+{
+    fn merge_into(
+        self,
+        mobs_summary : &mut Vec<MobEntity>,
+        characters_summary : &mut Vec<HeroEntity>,
+        attack_details_summary : &mut Vec<AttackResult>,
+        rewards_summary : &mut Vec<HeroReward>,
+        attacks_summary : &mut Vec<Attack>)
+    {
+        mobs_summary.extend(self.mobs_summary);
+        characters_summary.extend(self.characters_summary);
+        attack_details_summary.extend(self.attack_details_summary);
+        rewards_summary.extend(self.rewards_summary);
+        attacks_summary.extend(self.attacks_summary);
+    }
+}
+
+async fn process_mob_command(
+    map : &Arc<GameMap>,
     current_time : u64,
-    server_state: Arc<ServerState>,
-    tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
+    server_state : &Arc<ServerState>,
     tx_pe_gameplay_longterm : &GaiaSender<HeroEntity>,
-    mobs_summary : &mut Vec<MobEntity>,
-    characters_summary : &mut Vec<HeroEntity>,
-    attack_details_summary : &mut Vec<AttackResult>,
-    rewards_summary : &mut Vec<HeroReward>,
-    delayed_mob_commands_to_execute : Vec<MobCommand>
+    tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
+    scheduler_lock : &Arc<Mutex<Scheduler>>,
+    mobs_command : &MobCommand,
+    output : &mut MobShardOutput,
 )
 {
-    for mobs_command in delayed_mob_commands_to_execute.iter()
+    let MobShardOutput { mobs_summary, characters_summary, attack_details_summary, rewards_summary, attacks_summary } = output;
+    match mobs_command
     {
-        match mobs_command
+        mob_command::MobCommand::Touch(data) => 
+        {
+            check_buffs(
+                map,
+                current_time,
+                server_state,
+                tx_moe_gameplay_webservice,
+                mobs_summary, 
+                data.mob_id,
+                data.mob_tile_id.clone()
+            ).await;
+        },
+        mob_command::MobCommand::CastFromMobToMob(data) => 
         {
-            mob_command::MobCommand::Touch(_) => todo!(),
-            mob_command::MobCommand::Spawn(_) => todo!(),
-            mob_command::MobCommand::ControlMob(_) => todo!(),
-            mob_command::MobCommand::MoveMob(_) => todo!(),
-            mob_command::MobCommand::CastFromMobToMob(data) => 
+            let end_time = current_time + data.time as u64;
+            if data.time == 0
             {
                 cast_mob_from_mob(
-                    &map,
+                    map,
                     current_time,
-                    &server_state,
+                    server_state,
                     tx_moe_gameplay_webservice,
                     mobs_summary,
                     attack_details_summary,
@@ -237,15 +192,57 @@ pub async fn process_delayed_mob_commands (
                     data.caster_mob_id,
                     data.target_mob_tile_id.clone(),
                     data.target_mob_id,
-                    data.missed,
+                    data.missed
                 ).await;
             }
-            mob_command::MobCommand::CastFromHeroToMob(data) => 
+            else
+            {
+                // cli_log::info!("------------ required time for cast to mob {data.time} current time: {current_time} {card_id}");
+                let mut lock = scheduler_lock.lock().await;
+                let delayed_command = mob_command::MobCommand::CastFromMobToMob(data.clone());
+                // let mob_action = MobCommand { tile_id : mobs_command.tile_id.clone(), info };
+                lock.schedule(end_time, Caster::Mob(data.caster_mob_id), DelayedCommand::Mob(delayed_command));
+                drop(lock);
+
+                // we only send attack messages if attack is delayed, for projectiles and other instances.
+                let attack = Attack
+                {
+                    id: (current_time % 10000) as u16,
+                    attacker_hero_id: 0,
+                    target_hero_id: 0,
+                    card_id: data.card_id,
+                    required_time: data.time,
+                    battle_type : BATTLE_MOB_MOB,
+                    attacker_mob_id: data.caster_mob_id,
+                    target_tile_id: TetrahedronId::default(),
+                    target_mob_id: data.target_mob_id,
+                };
+
+                cli_log::info!("--- cast {} ", attack.required_time);
+                attacks_summary.push(attack);
+            }
+
+        },
+        mob_command::MobCommand::CastFromHeroToMob(data) => 
+        {
+            // the client tells us where the mob is, we only trust our own copy.
+            let target_position = match get_mob_position(map, data.target_mob_id, &data.target_mob_tile_id).await
+            {
+                Some(position) => position,
+                None => return,
+            };
+            if !range_validation::validate_hero_action(map, server_state, data.hero_id, Action::CastToMob, data.card_id, &target_position).await
+            {
+                return;
+            }
+
+            let end_time = current_time + data.time as u64;
+            if data.time == 0
             {
                 cast_mob_from_character(
-                    &map,
+                    map,
                     current_time,
-                    &server_state,
+                    server_state,
                     tx_moe_gameplay_webservice,
                     tx_pe_gameplay_longterm,
                     mobs_summary,
@@ -258,13 +255,55 @@ pub async fn process_delayed_mob_commands (
                     data.target_mob_tile_id.clone(),
                     data.missed,
                 ).await;
-            },
-            mob_command::MobCommand::AttackFromMobToHero(data) => 
+            }
+            else 
+            {
+                // cli_log::info!("------------ required time for attack to mob {required_time} current time: {current_time} {card_id}");
+                let mut lock = scheduler_lock.lock().await;
+                let delayed_command = mob_command::MobCommand::CastFromHeroToMob(data.clone());
+                lock.schedule(end_time, Caster::Hero(data.hero_id), DelayedCommand::Mob(delayed_command));
+                drop(lock);
+
+                // we only send attack messages if attack is delayed, for projectiles and other instances.
+                let attack = Attack
+                {
+                    id: (current_time % 10000) as u16,
+                    attacker_hero_id: data.hero_id,
+                    target_hero_id: 0,
+                    attacker_mob_id: 0,
+                    target_mob_id: data.target_mob_id,
+                    card_id: data.card_id,
+                    required_time: data.time,
+                    target_tile_id: TetrahedronId::default(),
+                    battle_type : BATTLE_CHAR_MOB,
+                };
+
+                cli_log::info!("--- attack {}", attack.required_time);
+                attacks_summary.push(attack);
+            }
+
+        },
+        mob_command::MobCommand::Spawn(data) => 
+        {
+            spawn_mob(map, server_state, tx_moe_gameplay_webservice, mobs_summary, data.tile_id.clone(), current_time, data.hero_id, data.mob_definition_id, data.level).await;
+        },
+        mob_command::MobCommand::ControlMob(data) => 
+        {
+            control_mob(map, server_state, tx_moe_gameplay_webservice, mobs_summary, data.mob_id, data.mob_tile_id.clone(), current_time, data.hero_id).await;
+        },
+        mob_command::MobCommand::MoveMob(data) => 
+        {
+            move_mob(map, server_state, tx_moe_gameplay_webservice, mobs_summary, current_time, data.hero_id, data.mob_id, data.new_origin_tile_id.clone(), data.new_end_tile_id.clone(), data.path).await;
+        },
+        mob_command::MobCommand::AttackFromMobToHero(data) => 
+        {
+            let end_time = current_time + data.time as u64;
+            if data.time == 0
             {
                 cast_hero_from_mob(
-                    &map,
+                    map,
                     current_time,
-                    &server_state,
+                    server_state,
                     tx_moe_gameplay_webservice,
                     tx_pe_gameplay_longterm,
                     mobs_summary,
@@ -276,8 +315,166 @@ pub async fn process_delayed_mob_commands (
                     data.attacker_mob_tile_id.clone(),
                     data.missed,
                 ).await;
-            },
+            }
+            else 
+            {
+                // cli_log::info!("------------ required time for attack to character from mob {required_time} current time: {current_time} {card_id}");
+                let mut lock = scheduler_lock.lock().await;
+                let delayed_command = mob_command::MobCommand::AttackFromMobToHero(data.clone());
+                lock.schedule(end_time, Caster::Mob(data.attacker_mob_id), DelayedCommand::Mob(delayed_command));
+                drop(lock);
+
+                // we only send attack messages if attack is delayed, for projectiles and other instances.
+                let attack = Attack
+                {
+                    id: (current_time % 10000) as u16,
+                    attacker_hero_id: 0,
+                    target_hero_id: data.hero_id,
+                    attacker_mob_id: data.attacker_mob_id,
+                    target_mob_id: 0,
+                    card_id: data.card_id,
+                    required_time: data.time,
+                    target_tile_id: TetrahedronId::default(),
+                    battle_type : BATTLE_MOB_CHAR,
+                };
+
+                cli_log::info!("--- attack {} ", attack.required_time);
+                attacks_summary.push(attack);
+            }
+        },
+    }
+}
+
+pub async fn process_delayed_mob_commands (
+    map : Arc<GameMap>,
+    current_time : u64,
+    server_state: Arc<ServerState>,
+    tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
+    tx_pe_gameplay_longterm : &GaiaSender<HeroEntity>,
+    mobs_summary : &mut Vec<MobEntity>,
+    characters_summary : &mut Vec<HeroEntity>,
+    attack_details_summary : &mut Vec<AttackResult>,
+    rewards_summary : &mut Vec<HeroReward>,
+    delayed_mob_commands_to_execute : Vec<MobCommand>
+)
+{
+    let shards = region_shards::shard_by_region(delayed_mob_commands_to_execute, get_mob_command_region);
+    let outputs = region_shards::run_shards(shards.regions, |mob_commands|
+    {
+        let map = map.clone();
+        let server_state = server_state.clone();
+        let tx_moe_gameplay_webservice = tx_moe_gameplay_webservice.clone();
+        let tx_pe_gameplay_longterm = tx_pe_gameplay_longterm.clone();
+        async move
+        {
+            let mut output = MobShardOutput::default();
+            for mobs_command in mob_commands.iter()
+            {
+                process_delayed_mob_command(
+                    &map,
+                    current_time,
+                    &server_state,
+                    &tx_moe_gameplay_webservice,
+                    &tx_pe_gameplay_longterm,
+                    mobs_command,
+                    &mut output).await;
+            }
+            output
         }
+    }).await;
+
+    for output in outputs
+    {
+        output.merge_into(mobs_summary, characters_summary, attack_details_summary, rewards_summary, &mut Vec::new());
+    }
+
+    for mobs_command in shards.crossing.iter()
+    {
+        let mut output = MobShardOutput::default();
+        process_delayed_mob_command(
+            &map,
+            current_time,
+            &server_state,
+            tx_moe_gameplay_webservice,
+            tx_pe_gameplay_longterm,
+            mobs_command,
+            &mut output).await;
+        output.merge_into(mobs_summary, characters_summary, attack_details_summary, rewards_summary, &mut Vec::new());
+    }
+}
+
+async fn process_delayed_mob_command(
+    map : &Arc<GameMap>,
+    current_time : u64,
+    server_state : &Arc<ServerState>,
+    tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
+    tx_pe_gameplay_longterm : &GaiaSender<HeroEntity>,
+    mobs_command : &MobCommand,
+    output : &mut MobShardOutput,
+)
+{
+    let MobShardOutput { mobs_summary, characters_summary, attack_details_summary, rewards_summary, attacks_summary: _ } = output;
+    match mobs_command
+    {
+        // only casts and attacks are ever delayed.
+        mob_command::MobCommand::Touch(_) | mob_command::MobCommand::Spawn(_) | mob_command::MobCommand::ControlMob(_) | mob_command::MobCommand::MoveMob(_) =>
+        {
+            cli_log::error!("ignored delayed mob command that is never delayed {:?}", mobs_command);
+        },
+        mob_command::MobCommand::CastFromMobToMob(data) => 
+        {
+            cast_mob_from_mob(
+                map,
+                current_time,
+                server_state,
+                tx_moe_gameplay_webservice,
+                mobs_summary,
+                attack_details_summary,
+                data.card_id,
+                data.caster_mob_tile_id.clone(),
+                data.caster_mob_id,
+                data.target_mob_tile_id.clone(),
+                data.target_mob_id,
+                data.missed,
+            ).await;
+        }
+        mob_command::MobCommand::CastFromHeroToMob(data) => 
+        {
+            cast_mob_from_character(
+                map,
+                current_time,
+                server_state,
+                tx_moe_gameplay_webservice,
+                tx_pe_gameplay_longterm,
+                mobs_summary,
+                characters_summary,
+                attack_details_summary,
+                rewards_summary,
+                data.card_id,
+                data.hero_id,
+                data.target_mob_id,
+                data.target_mob_tile_id.clone(),
+                data.missed,
+            ).await;
+        },
+        mob_command::MobCommand::AttackFromMobToHero(data) => 
+        {
+            cast_hero_from_mob(
+                map,
+                current_time,
+                server_state,
+                tx_moe_gameplay_webservice,
+                tx_pe_gameplay_longterm,
+                mobs_summary,
+                characters_summary,
+                attack_details_summary,
+                data.card_id,
+                data.hero_id,
+                data.attacker_mob_id,
+                data.attacker_mob_tile_id.clone(),
+                data.missed,
+            ).await;
+        },
     }
 }
 
@@ -449,10 +646,24 @@ pub async fn move_mob(
         return;
     }
 
-    let mob_region = map.get_mob_region_from_child(&new_end_position_id);
     let previous_region_id = TetrahedronId::get_parent(&new_origin_position_id, 7);
     let new_region_id = TetrahedronId::get_parent(&new_end_position_id, 7);
+    if new_region_id != previous_region_id
+    {
+        move_mob_across_regions(
+            map,
+            tx_moe_gameplay_webservice,
+            mobs_summary,
+            current_time,
+            hero_id,
+            mob_id,
+            new_origin_position_id,
+            new_end_position_id,
+            path).await;
+        return;
+    }
 
+    let mob_region = map.get_mob_region_from_child(&new_end_position_id);
     let region_for_positions = map.get_mob_positions_region_from_child(&new_end_position_id);
 
     let mut mobs = mob_region.lock().await;
@@ -478,15 +689,6 @@ pub async fn move_mob(
             return;
         }
 
-        // somehow the compiler know that this lock is not the same as the above, there is not way for a deadlock to happen
-        if new_region_id != previous_region_id
-        {
-            let region_for_positions = map.get_mob_positions_region_from_child(&previous_region_id);
-            let mut mob_positions = region_for_positions.lock().await;
-            mob_positions.remove(&new_origin_position_id);
-            drop(mob_positions);
-        }
-
         mob_positions.remove(&new_origin_position_id);
         mob_positions.insert(new_end_position_id.clone());
 
@@ -509,6 +711,71 @@ pub async fn move_mob(
     }
 }
 
+// the mob leaves the map of its old region and shows up in the one of the new region.
+// it locks both regions, so it only runs in the crossing pass when no region worker is around.
+async fn move_mob_across_regions(
+    map : &Arc<GameMap>,
+    tx_moe_gameplay_webservice : &GaiaSender<MobEntity>,
+    mobs_summary : &mut Vec<MobEntity>,
+    current_time : u64,
+    hero_id: u16,
+    mob_id : u32,
+    new_origin_position_id: TetrahedronId,
+    new_end_position_id: TetrahedronId,
+    path: [u8;6],
+)
+{
+    let previous_mob_region = map.get_mob_region_from_child(&new_origin_position_id);
+    let mob_region = map.get_mob_region_from_child(&new_end_position_id);
+    let previous_region_for_positions = map.get_mob_positions_region_from_child(&new_origin_position_id);
+    let region_for_positions = map.get_mob_positions_region_from_child(&new_end_position_id);
+
+    let mut previous_mobs = previous_mob_region.lock().await;
+    let mut mobs = mob_region.lock().await;
+    let mut previous_mob_positions = previous_region_for_positions.lock().await;
+    let mut mob_positions = region_for_positions.lock().await;
+
+    if mob_positions.contains(&new_end_position_id)
+    {
+        return;
+    }
+
+    let Some(mob) = previous_mobs.get(&mob_id)
+    else
+    {
+        return;
+    };
+
+    let mut updated_mob = mob.clone();
+    if updated_mob.health == 0 || updated_mob.owner_id != hero_id || updated_mob.end_position_id != new_origin_position_id
+    {
+        drop(previous_mobs);
+        drop(mobs);
+        tx_moe_gameplay_webservice.send(updated_mob).await.unwrap();
+        return;
+    }
+
+    previous_mobs.remove(&mob_id);
+    previous_mob_positions.remove(&new_origin_position_id);
+    mob_positions.insert(new_end_position_id.clone());
+
+    updated_mob.version += 1;
+    updated_mob.start_position_id = new_origin_position_id;
+    updated_mob.end_position_id = new_end_position_id;
+    updated_mob.path = path;
+    updated_mob.time = (current_time / 1000) as u32;
+
+    mobs_summary.push(updated_mob.clone());
+    mobs.insert(mob_id, updated_mob.clone());
+
+    drop(previous_mobs);
+    drop(mobs);
+    drop(previous_mob_positions);
+    drop(mob_positions);
+
+    tx_moe_gameplay_webservice.send(updated_mob).await.unwrap();
+}
+
 pub async fn cast_mob_from_mob(
     map : &Arc<GameMap>,
     current_time : u64,
@@ -650,8 +917,7 @@ pub async fn cast_mob_from_character(
             attacker.add_inventory_item(reward);
 
 
-            let mut random_generator = <StdRng as rand::SeedableRng>::from_entropy();
-            let x =  rand::Rng::gen::<f32>(&mut random_generator);
+            let x = crate::gameplay_service::random::next_f32();
             let shard_id = (x * 15f32).floor() as u32;
 
             let shard_id_option = match shard_id 
@@ -877,4 +1143,48 @@ pub async fn check_buffs(
         mobs_summary.push(mob_copy.clone());
         tx_moe_gameplay_webservice.send(mob_copy).await.unwrap();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mob::mob_command::MoveMobData;
+    use crate::{gaia_mpsc, ServerChannels};
+
+    #[tokio::test]
+    async fn moves_to_tiles_off_the_planet_are_dropped()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        let server_state = Arc::new(ServerState::new(None));
+        let (tx_pe, _rx_pe) = gaia_mpsc::channel::<HeroEntity>(10, ServerChannels::TX_PE_GAMEPLAY_LONGTERM, server_state.clone());
+        let (tx_moe, _rx_moe) = gaia_mpsc::channel::<MobEntity>(10, ServerChannels::TX_MOE_GAMEPLAY_WEBSERVICE, server_state.clone());
+        let scheduler_lock = Arc::new(Mutex::new(Scheduler::new(server_state.clone())));
+
+        let origin = TetrahedronId::from_string("a012301230");
+        let move_to = |new_end_tile_id : TetrahedronId| MobCommand::MoveMob(MoveMobData { hero_id: 1, mob_id: 2, new_origin_tile_id: origin.clone(), new_end_tile_id, path: [0; 6] });
+        // lod 9, but there are only 20 areas and 4^9 tiles in each.
+        let off_the_planet = [TetrahedronId { area: 25, id: 0, lod: 9 }, TetrahedronId { area: 0, id: 4u32.pow(9), lod: 9 }];
+        for tile_id in off_the_planet.iter()
+        {
+            assert_eq!(get_mob_command_region(&move_to(tile_id.clone())), None);
+        }
+
+        // they used to be processed as region crossing moves, and the region lookup panicked.
+        let commands = off_the_planet.into_iter().map(move_to).collect();
+        let processed = process_mob_commands(
+            map,
+            0,
+            server_state,
+            &tx_pe,
+            &tx_moe,
+            Arc::new(Mutex::new(commands)),
+            scheduler_lock,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut Vec::new()).await;
+        assert_eq!(processed, 2);
+    }
+}
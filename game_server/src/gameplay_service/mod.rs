@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::vec;
 
@@ -23,9 +24,10 @@ use crate::tower::tower_entity::TowerEntity;
 use bytes::Bytes;
 use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::sync::Mutex;
-use utils::get_mob_commands_to_execute;
+use clock::Clock;
+use scheduler::{Caster, Scheduler};
+use tick_metrics::TickStage;
 
-use self::utils::{get_tile_commands_to_execute, get_player_commands_to_execute, get_tower_commands_to_execute};
 pub mod data_packer;
 pub mod utils;
 pub mod hero_commands_processor;
@@ -35,6 +37,17 @@ pub mod chat_commands_processor;
 pub mod mob_commands_processor;
 pub mod kingdoms_commands_processor;
 pub mod generic_command;
+pub mod packets_history;
+pub mod delta_encoder;
+pub mod clock;
+pub mod random;
+pub mod scheduler;
+pub mod tick_metrics;
+pub mod region_shards;
+pub mod range_validation;
+
+// the tick time and the raw entities packed for each region, the clients that use deltas get packets built from these.
+pub type TickSnapshots = (u64, Vec<(u16, Vec<(u8, Bytes)>)>);
 
 pub struct PacketsData
 {
@@ -45,6 +58,10 @@ pub struct PacketsData
     buffer: [u8;5000],
     game_packets_count : u32,
     offset : usize,
+    // raw entities that went into this region packets, for the clients that use deltas.
+    snapshots: Vec<(u8, Bytes)>,
+    // of the tick being packed, every packet header carries it.
+    time: u64,
 }
 
 pub fn start_service(
@@ -55,7 +72,10 @@ pub fn start_service(
     mut rx_kc_client_game : tokio::sync::mpsc::Receiver<KingdomCommand>,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
-    tx_bytes_game_socket: gaia_mpsc::GaiaSender<Vec<(u64, u8, u16, u32, Bytes)>>
+    tx_bytes_game_socket: gaia_mpsc::GaiaSender<Vec<(u64, u8, u16, u32, Bytes)>>,
+    tx_snapshots_game_socket: gaia_mpsc::GaiaSender<TickSnapshots>,
+    tick : std::time::Duration,
+    clock : Arc<dyn Clock>
 ) 
 -> (Receiver<MapEntity>, 
     Receiver<MapEntity>, 
@@ -112,27 +132,13 @@ pub fn start_service(
     let mob_commands_processor_lock = mob_commands_mutex.clone();
     let mob_commands_agregator_from_client_lock = mob_commands_mutex.clone();
 
-    //delayed commands for attacks so they struck a bit later.
-    let delayed_tile_commands = Vec::<(u64, MapCommand)>::new();
-    let delayed_tile_commands_mutex = Arc::new(Mutex::new(delayed_tile_commands));
-    let delayed_tile_commands_lock = delayed_tile_commands_mutex.clone();
+    //delayed commands for attacks so they struck a bit later, every kind goes into the same scheduler.
+    let scheduler = Scheduler::new(server_state.clone());
+    let scheduler_mutex = Arc::new(Mutex::new(scheduler));
+    let scheduler_lock = scheduler_mutex.clone();
 
-    //delayed commands for attacks so they struck a bit later.
-    let delayed_player_commands = Vec::<(u64, HeroCommand)>::new();
-    let delayed_player_commands_mutex = Arc::new(Mutex::new(delayed_player_commands));
-    let delayed_player_commands_lock = delayed_player_commands_mutex.clone();
-
-    //delayed commands for attacks so they struck a bit later.
-    let delayed_tower_commands = Vec::<(u64, TowerCommand)>::new();
-    let delayed_tower_commands_mutex = Arc::new(Mutex::new(delayed_tower_commands));
-    let delayed_tower_commands_lock = delayed_tower_commands_mutex.clone();
-
-    //delayed commands for attacks so they struck a bit later.
-    let delayed_mob_commands = Vec::<(u64, MobCommand)>::new();
-    let delayed_mob_commands_mutex = Arc::new(Mutex::new(delayed_mob_commands));
-    let delayed_mob_commands_lock = delayed_mob_commands_mutex.clone();
-
-    let mut interval = tokio::time::interval(std::time::Duration::from_millis(100));
+    let mut interval = tokio::time::interval(tick);
+    server_state.tick_metrics.set_tick(tick);
 
     //task that will handle receiving state changes from clients and updating the global statestate.
     tokio::spawn(async move 
@@ -244,6 +250,8 @@ pub fn start_service(
                 buffer: [0u8; 5000],
                 game_packets_count: 0,
                 offset: 0,
+                snapshots: Vec::new(),
+                time: 0,
             });
         }
         for region_id in map.definitions.regions_by_id.iter()
@@ -256,18 +264,18 @@ pub fn start_service(
         {
             // let mut packets = Vec::new();
             interval.tick().await;
+            // real time even when the gameplay runs on a simulated clock, this is what the tick costs.
+            let tick_started = std::time::Instant::now();
 
             server_status_deliver_count += 1;
 
-            // check for delayed_commands from player
-            let mut delayed_player_commands_guards = delayed_player_commands_lock.lock().await;
+            // check for delayed_commands, everything that is due this tick.
+            let current_time_in_millis = clock.now_millis();
 
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
-
-            let delayed_player_commands_to_execute = get_player_commands_to_execute(current_time_in_millis, &mut delayed_player_commands_guards);
-            drop(delayed_player_commands_guards);
+            let due_commands = scheduler_lock.lock().await.pop_due(current_time_in_millis);
 
+            let stage_started = std::time::Instant::now();
+            let delayed_commands = due_commands.heroes.len();
             hero_commands_processor::process_delayed_hero_commands(
                 map.clone(), 
                 current_time_in_millis,
@@ -276,13 +284,12 @@ pub fn start_service(
                 &mut heroes_summary, 
                 &mut attack_details_summary, 
                 &mut heroes_rewards_summary, 
-                delayed_player_commands_to_execute
+                due_commands.heroes
             ).await;
 
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
+            let current_time_in_millis = clock.now_millis();
 
-            hero_commands_processor::process_hero_commands(
+            let processed_commands = hero_commands_processor::process_hero_commands(
                 map.clone(), 
                 server_state.clone(),
                 current_time_in_millis,
@@ -293,18 +300,11 @@ pub fn start_service(
                 &mut attacks_summary, 
                 &mut attack_details_summary, 
                 &mut heroes_rewards_summary, 
-                delayed_player_commands_mutex.clone()).await;
-
-
-            let mut delayed_tile_commands_guard = delayed_tile_commands_lock.lock().await;
-
-            // check for delayed_commands
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
-
-            let delayed_tile_commands_to_execute = get_tile_commands_to_execute(current_time_in_millis, &mut delayed_tile_commands_guard);
-            drop(delayed_tile_commands_guard);
+                scheduler_mutex.clone()).await;
+            server_state.tick_metrics.record_stage(TickStage::Heroes, stage_started.elapsed(), delayed_commands + processed_commands);
 
+            let stage_started = std::time::Instant::now();
+            let delayed_commands = due_commands.tiles.len();
             tile_commands_processor::process_delayed_tile_commands(
                 map.clone(),
                 server_state.clone(),
@@ -314,14 +314,13 @@ pub fn start_service(
                 &mut tiles_summary,
                 &mut heroes_summary,
                 &mut heroes_rewards_summary,
-                delayed_tile_commands_to_execute).await;
+                due_commands.tiles).await;
 
             // drop(delayed_commands_lock);
 
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
+            let current_time_in_millis = clock.now_millis();
 
-            tile_commands_processor::process_tile_commands(
+            let processed_commands = tile_commands_processor::process_tile_commands(
                 map.clone(),
                 server_state.clone(),
                 current_time_in_millis,
@@ -333,12 +332,11 @@ pub fn start_service(
                 &mut heroes_summary,
                 &mut heroes_rewards_summary,
                 &mut attacks_summary,
-                delayed_tile_commands_lock.clone()).await;
-
-            let mut delayed_tower_commands_guard = delayed_tower_commands_lock.lock().await;
-            let delayed_tower_commands_to_execute = get_tower_commands_to_execute(current_time_in_millis, &mut delayed_tower_commands_guard);
-            drop(delayed_tower_commands_guard);
+                scheduler_lock.clone()).await;
+            server_state.tick_metrics.record_stage(TickStage::Tiles, stage_started.elapsed(), delayed_commands + processed_commands);
 
+            let stage_started = std::time::Instant::now();
+            let delayed_commands = due_commands.towers.len();
             tower_commands_processor::process_delayed_tower_commands(
                 map.clone(),
                 server_state.clone(),
@@ -350,20 +348,23 @@ pub fn start_service(
                 &mut heroes_summary,
                 &mut heroes_rewards_summary,
                 &mut attack_details_summary,
-                delayed_tower_commands_to_execute).await;
+                due_commands.towers).await;
 
-            tower_commands_processor::process_tower_commands(
+            let processed_commands = tower_commands_processor::process_tower_commands(
                 map.clone(),
                 server_state.clone(),
+                current_time_in_millis,
                 tower_commands_processor_lock.clone(),
                 &tx_te_gameplay_longterm,
                 &tx_te_gameplay_webservice,
                 // &tx_pe_gameplay_longterm,
                 &mut towers_summary,
                 &mut attacks_summary,
-                delayed_tower_commands_lock.clone()).await;
+                scheduler_lock.clone()).await;
+            server_state.tick_metrics.record_stage(TickStage::Towers, stage_started.elapsed(), delayed_commands + processed_commands);
 
-            kingdoms_commands_processor::process_kingdoms_commands(
+            let stage_started = std::time::Instant::now();
+            let processed_commands = kingdoms_commands_processor::process_kingdoms_commands(
                 map.clone(),
                 server_state.clone(),
                 kingdom_commands_processor_lock.clone(),
@@ -372,15 +373,12 @@ pub fn start_service(
                 // &tx_pe_gameplay_longterm,
                 &mut kingdoms_summary,
                 &mut attacks_summary).await;
+            server_state.tick_metrics.record_stage(TickStage::Kingdoms, stage_started.elapsed(), processed_commands);
 
-            let mut delayed_mob_commands_guard = delayed_mob_commands_lock.lock().await;
-
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
-
-            let delayed_mob_commands_to_execute = get_mob_commands_to_execute(current_time_in_millis, &mut delayed_mob_commands_guard);
-            drop(delayed_mob_commands_guard);
+            let current_time_in_millis = clock.now_millis();
 
+            let stage_started = std::time::Instant::now();
+            let delayed_commands = due_commands.mobs.len();
             mob_commands_processor::process_delayed_mob_commands(
                 map.clone(),
                 current_time_in_millis,
@@ -391,19 +389,18 @@ pub fn start_service(
                 &mut heroes_summary,
                 &mut attack_details_summary,
                 &mut heroes_rewards_summary,
-                delayed_mob_commands_to_execute).await;
+                due_commands.mobs).await;
 
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
+            let current_time_in_millis = clock.now_millis();
 
-            mob_commands_processor::process_mob_commands(
+            let processed_commands = mob_commands_processor::process_mob_commands(
                 map.clone(),
                 current_time_in_millis,
                 server_state.clone(),
                 &tx_he_gameplay_longterm,
                 &tx_moe_gameplay_webservice,
                 mob_commands_processor_lock.clone(),
-                delayed_mob_commands_lock.clone(),
+                scheduler_lock.clone(),
                 &mut mobs_summary,
                 &mut heroes_summary,
                 &mut attack_details_summary,
@@ -411,9 +408,14 @@ pub fn start_service(
                 &mut attacks_summary,
                 ).await;
 
+            // heroes and mobs that died this tick don't get to finish their attacks.
+            cancel_dead_casters(&mut *scheduler_lock.lock().await, &heroes_summary, &mobs_summary);
+            server_state.tick_metrics.record_stage(TickStage::Mobs, stage_started.elapsed(), delayed_commands + processed_commands);
 
-            let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-            let current_time_in_millis = current_time.as_millis() as u64;
+            let current_time_in_millis = clock.now_millis();
+            packets_data.iter_mut().for_each(|region_packets_data| region_packets_data.time = current_time_in_millis);
+
+            let stage_started = std::time::Instant::now();
 
             let game_packages= 
                 tiles_summary.len() +
@@ -498,6 +500,12 @@ pub fn start_service(
             {
                 cli_log::info!("--players {len}");
             }
+            // this is the authoritative position, it also decides what the hero receives.
+            for d in heroes_summary.iter()
+            {
+                map.update_interest_regions(d.hero_id, &d.position).await;
+            }
+
             heroes_summary.drain(..)
             .for_each(|d| 
             {
@@ -597,8 +605,16 @@ pub fn start_service(
 
 
             // cli_log::info!("---checking regions data packets");
+            let mut snapshots = Vec::new();
             for region_packets_data in packets_data.iter_mut()
             {
+                if !region_packets_data.snapshots.is_empty()
+                {
+                    let mut temp_vec = Vec::new();
+                    std::mem::swap(&mut region_packets_data.snapshots, &mut temp_vec);
+                    snapshots.push((region_packets_data.region, temp_vec));
+                }
+
                 if region_packets_data.offset > 0
                 {
                     let encoded_data = data_packer::encode_packet(&mut region_packets_data.buffer, region_packets_data.offset);
@@ -608,6 +624,12 @@ pub fn start_service(
                 if region_packets_data.packets.len() > 0 
                 {
                     // cli_log::info!("--found packets for region {} size: {} game_packets: {}", region_packets_data.region, region_packets_data.packets.iter().len(), region_packets_data.game_packets_count);
+                    // we keep a copy so clients can ask for the ones they lost.
+                    for (packet_number, faction, _region, _game_packets, data) in region_packets_data.packets.iter()
+                    {
+                        map.packets_history.store(region_packets_data.region, *packet_number, *faction, data.clone()).await;
+                    }
+
                     let mut temp_vec = Vec::new();
                     std::mem::swap(&mut region_packets_data.packets, &mut temp_vec);
                     tx_bytes_game_socket.send(temp_vec).await.unwrap();
@@ -616,6 +638,13 @@ pub fn start_service(
                 }
             }
 
+            if !snapshots.is_empty()
+            {
+                tx_snapshots_game_socket.send((current_time_in_millis, snapshots)).await.unwrap();
+            }
+
+            server_state.tick_metrics.record_stage(TickStage::Packing, stage_started.elapsed(), game_packages);
+            server_state.tick_metrics.record_tick(tick_started.elapsed());
 
         }
     });
@@ -631,4 +660,114 @@ pub fn start_service(
         rx_ke_gameplay_webservice,
         tx_mc_webservice_gameplay
     )
-}
\ No newline at end of file
+}
+// the summaries have every hero and mob that changed this tick, the last entry is how they ended it.
+fn cancel_dead_casters(scheduler : &mut Scheduler, heroes_summary : &[HeroEntity], mobs_summary : &[MobEntity])
+{
+    // a hero can be hit in several regions and they are merged after they finish, so the summary order is not the order
+    // of the hits, the highest version is. a mob only changes in its own region so its last entry is still the latest.
+    let mut last_health = HashMap::<Caster, (u16, u16)>::new();
+    for hero in heroes_summary
+    {
+        let entry = last_health.entry(Caster::Hero(hero.hero_id)).or_insert((hero.version, hero.health));
+        if hero.version >= entry.0
+        {
+            *entry = (hero.version, hero.health);
+        }
+    }
+
+    for mob in mobs_summary
+    {
+        last_health.insert(Caster::Mob(mob.mob_id), (0, mob.health));
+    }
+
+    for (caster, (_, health)) in last_health
+    {
+        if health == 0
+        {
+            scheduler.cancel_by_caster(caster);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use std::io::Read;
+
+    use crate::gameplay_service::clock::ManualClock;
+    use crate::map::tetrahedron_id::TetrahedronId;
+    use crate::tower::TowerCommandInfo;
+
+    // tokio's time is paused, the ticks run when the test sleeps and the game time only moves with the clock.
+    #[tokio::test(start_paused = true)]
+    async fn delayed_tower_attacks_land_at_their_time()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let tower_id = TetrahedronId::from_string("t312222222");
+        let tower = TowerEntity
+        {
+            object_id: None,
+            tetrahedron_id: tower_id.clone(),
+            version: 0,
+            event_id: 1,
+            faction: 1,
+            damage_received_in_event: Vec::new(),
+        };
+        let towers = HashMap::from([(tower_id.clone(), tower)]);
+        // the attacker has to be in range of the tower, one tile away.
+        let hero = HeroEntity::for_tests(5, 2, tower_id.get_parent(1).subdivide(3));
+        let map = Arc::new(GameMap::for_tests(definitions));
+        map.character.lock().await.insert(5, hero);
+        *map.towers.lock().await = towers;
+        let server_state = Arc::new(ServerState::new(None));
+
+        // towers sleep part of the time, we start on a second where faction 2 can attack this one.
+        let start_time = (0..360u64)
+            .map(|second| 1_700_000_000_000 + second * 1000)
+            .find(|time| TowerEntity::is_tower_active(&tower_id, 1, 2, (time / 1000) as u32))
+            .unwrap();
+        let clock = Arc::new(ManualClock::new(start_time));
+
+        let (_tx_hc, rx_hc) = gaia_mpsc::channel::<HeroCommand>(100, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (_tx_mc, rx_mc) = gaia_mpsc::channel::<MapCommand>(100, ServerChannels::TX_MC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (_tx_moc, rx_moc) = gaia_mpsc::channel::<MobCommand>(100, ServerChannels::TX_MOC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_tc, rx_tc) = gaia_mpsc::channel::<TowerCommand>(100, ServerChannels::TX_TC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (_tx_kc, rx_kc) = gaia_mpsc::channel::<KingdomCommand>(100, ServerChannels::TX_KC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_packets, mut rx_packets) = gaia_mpsc::channel::<Vec<(u64, u8, u16, u32, Bytes)>>(100, ServerChannels::TX_PACKETS_GAMEPLAY_CHAT_CLIENTS, server_state.clone());
+        let (tx_snapshots, _rx_snapshots) = gaia_mpsc::channel(100, ServerChannels::TX_SNAPSHOTS_GAMEPLAY_CLIENTS, server_state.clone());
+
+        let tick = Duration::from_millis(100);
+        let _receivers = start_service(rx_hc, rx_mc, rx_moc, rx_tc, rx_kc, map.clone(), server_state, tx_packets, tx_snapshots, tick, clock.clone());
+
+        let info = TowerCommandInfo::AttackTower(5, 1, 2, 1, 500);
+        tx_tc.send(TowerCommand { id: tower_id.clone(), info }).await.unwrap();
+
+        // the attack is on its way, but it needs 500 ms of game time.
+        tokio::time::sleep(tick * 3).await;
+        assert_eq!(map.towers.lock().await[&tower_id].get_damage_by_faction(2), 0);
+
+        clock.advance(499);
+        tokio::time::sleep(tick * 3).await;
+        assert_eq!(map.towers.lock().await[&tower_id].get_damage_by_faction(2), 0);
+
+        clock.advance(1);
+        tokio::time::sleep(tick * 3).await;
+        assert_eq!(map.towers.lock().await[&tower_id].get_damage_by_faction(2), 100);
+
+        // the tower update is stamped with the game clock, not with the time of this machine.
+        let mut times = Vec::new();
+        while let Ok(packets) = rx_packets.try_recv()
+        {
+            for (_, _, _, _, data) in packets
+            {
+                let mut decoded = Vec::new();
+                flate2::read::ZlibDecoder::new(&data[..]).read_to_end(&mut decoded).unwrap();
+                times.push(u64::from_le_bytes(decoded[9..17].try_into().unwrap()));
+            }
+        }
+        assert_eq!(times.last(), Some(&(start_time + 500)));
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::VecDeque;
+
+use bytes::Bytes;
+use tokio::sync::Mutex;
+
+// at 10 ticks per second this is a bit more than 6 seconds of history per region,
+// a client that lost more than that should just reload.
+pub const PACKETS_HISTORY_SIZE : usize = 64;
+pub const REGIONS_COUNT : usize = 321;
+
+// encoded GlobalState packets that were sent to each region, so clients can ask for the ones they lost.
+pub struct PacketsHistory
+{
+    regions : Vec<Mutex<VecDeque<(u64, u8, Bytes)>>>,
+}
+
+impl PacketsHistory
+{
+    pub fn new() -> Self
+    {
+        let mut regions = Vec::with_capacity(REGIONS_COUNT);
+        for _ in 0..REGIONS_COUNT
+        {
+            regions.push(Mutex::new(VecDeque::with_capacity(PACKETS_HISTORY_SIZE)));
+        }
+        PacketsHistory { regions }
+    }
+
+    pub async fn store(&self, region : u16, packet_number : u64, faction : u8, data : Bytes)
+    {
+        if let Some(region_history) = self.regions.get(region as usize)
+        {
+            let mut history = region_history.lock().await;
+            if history.len() >= PACKETS_HISTORY_SIZE
+            {
+                history.pop_front();
+            }
+            history.push_back((packet_number, faction, data));
+        }
+    }
+
+    // returns the faction the packet was meant for and the encoded packet
+    pub async fn get(&self, region : u16, packet_number : u64) -> Option<(u8, Bytes)>
+    {
+        let region_history = self.regions.get(region as usize)?;
+        let history = region_history.lock().await;
+        history.iter()
+            .find(|(stored_packet_number, _, _)| *stored_packet_number == packet_number)
+            .map(|(_, faction, data)| (*faction, data.clone()))
+    }
+}
+
+impl Default for PacketsHistory
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_only_the_latest_packets()
+    {
+        let history = PacketsHistory::new();
+        for packet_number in 1..=(PACKETS_HISTORY_SIZE as u64 + 10)
+        {
+            history.store(5, packet_number, 0, Bytes::from(vec![packet_number as u8])).await;
+        }
+
+        assert!(history.get(5, 1).await.is_none());
+        assert!(history.get(5, 10).await.is_none());
+        assert_eq!(history.get(5, 11).await, Some((0, Bytes::from(vec![11u8]))));
+        assert!(history.get(4, 11).await.is_none());
+        assert!(history.get(1000, 11).await.is_none());
+    }
+}
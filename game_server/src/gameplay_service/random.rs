@@ -0,0 +1,19 @@
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// every roll of the gameplay comes from here, the replay seeds it so a capture always plays out the same.
+static GENERATOR : Mutex<Option<StdRng>> = Mutex::new(None);
+
+pub fn seed(seed : u64)
+{
+    *GENERATOR.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
+}
+
+// between 0 and 1, from entropy until someone calls seed.
+pub fn next_f32() -> f32
+{
+    let mut generator = GENERATOR.lock().unwrap();
+    generator.get_or_insert_with(StdRng::from_entropy).gen::<f32>()
+}
@@ -0,0 +1,183 @@
+use serde::Serialize;
+
+use crate::definitions::definitions_container::Definitions;
+use crate::map::tetrahedron_id::TetrahedronId;
+use crate::map::{tile_geometry, GameMap};
+use crate::ServerState;
+
+// the hero position is the last tile it sent, the client can be a couple of tiles ahead of it.
+pub const RANGE_SLACK_TILES : f32 = 2.0;
+// resources don't use a card, you have to be standing next to them.
+pub const EXTRACT_RESOURCE_RANGE : f32 = 1.0;
+
+// the actions a client starts by naming its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action
+{
+    AttackHero,
+    CastToMob,
+    AttackTower,
+    ExtractResource,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RejectReason
+{
+    UnknownCard,
+    // passive cards only work on the one using them.
+    NotTargetable,
+    InvalidTile,
+    // the hero isn't on the map, so there is nowhere to measure from.
+    NotOnMap,
+    OutOfRange(f32),
+}
+
+// actions the server refused for one hero, a lot of them means a broken or modified client.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RejectedActions
+{
+    pub attack_hero : u32,
+    pub cast_to_mob : u32,
+    pub attack_tower : u32,
+    pub extract_resource : u32,
+    pub out_of_range : u32,
+    pub invalid_target : u32,
+}
+
+impl RejectedActions
+{
+    pub fn add(&mut self, action : Action, reason : RejectReason)
+    {
+        match action
+        {
+            Action::AttackHero => self.attack_hero += 1,
+            Action::CastToMob => self.cast_to_mob += 1,
+            Action::AttackTower => self.attack_tower += 1,
+            Action::ExtractResource => self.extract_resource += 1,
+        }
+
+        match reason
+        {
+            RejectReason::OutOfRange(_) => self.out_of_range += 1,
+            _ => self.invalid_target += 1,
+        }
+    }
+
+    pub fn get_total(&self) -> u32
+    {
+        self.attack_hero + self.cast_to_mob + self.attack_tower + self.extract_resource
+    }
+}
+
+// the range of the card, or the fixed one for resources. Both are in tiles at TILE_LOD like get_distance_in_tiles,
+// cards.csv is written in those: melee cards have 0.5, which only reaches your own tile, the slack gives the tiles around it.
+pub fn get_range(definitions : &Definitions, action : Action, card_id : u32) -> Result<f32, RejectReason>
+{
+    if action == Action::ExtractResource
+    {
+        return Ok(EXTRACT_RESOURCE_RANGE);
+    }
+
+    let card = definitions.cards.get(card_id as usize).ok_or(RejectReason::UnknownCard)?;
+    if card.target_type == "self"
+    {
+        return Err(RejectReason::NotTargetable);
+    }
+    Ok(card.hit_range)
+}
+
+pub fn check_action(definitions : &Definitions, action : Action, card_id : u32, from : &TetrahedronId, to : &TetrahedronId) -> Result<(), RejectReason>
+{
+    let range = get_range(definitions, action, card_id)?;
+    let distance = tile_geometry::get_distance_in_tiles(from, to).ok_or(RejectReason::InvalidTile)?;
+    if distance > range + RANGE_SLACK_TILES
+    {
+        return Err(RejectReason::OutOfRange(distance));
+    }
+    Ok(())
+}
+
+// true when the hero can do it, otherwise it gets logged and counted against the hero.
+pub fn validate_action(
+    server_state : &ServerState,
+    definitions : &Definitions,
+    hero_id : u16,
+    action : Action,
+    card_id : u32,
+    from : Option<&TetrahedronId>,
+    to : &TetrahedronId) -> bool
+{
+    let result = from.ok_or(RejectReason::NotOnMap).and_then(|from| check_action(definitions, action, card_id, from, to));
+    match result
+    {
+        Ok(()) => true,
+        Err(reason) =>
+        {
+            cli_log::info!("rejected {action:?} from hero {hero_id} with card {card_id} at {from:?} to {to}: {reason:?}");
+            let mut rejected_actions = server_state.rejected_actions.lock().unwrap();
+            rejected_actions.entry(hero_id).or_default().add(action, reason);
+            false
+        }
+    }
+}
+
+pub async fn get_hero_position(map : &GameMap, hero_id : u16) -> Option<TetrahedronId>
+{
+    map.character.lock().await.get(&hero_id).map(|hero| hero.position.clone())
+}
+
+// same as validate_action, from where the hero is standing.
+pub async fn validate_hero_action(
+    map : &GameMap,
+    server_state : &ServerState,
+    hero_id : u16,
+    action : Action,
+    card_id : u32,
+    target : &TetrahedronId) -> bool
+{
+    let position = get_hero_position(map, hero_id).await;
+    validate_action(server_state, &map.definitions, hero_id, action, card_id, position.as_ref(), target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn out_of_range_and_untargetable_actions_are_counted()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let server_state = ServerState::new(None);
+
+        let hero_tile = TetrahedronId::from_string("a012301230");
+        let next_tile = hero_tile.get_parent(1).subdivide(3);
+        let far_tile = TetrahedronId::from_string("a300000000");
+
+        // punch_1, short range.
+        assert!(validate_action(&server_state, &definitions, 1, Action::AttackHero, 1, Some(&hero_tile), &next_tile));
+        assert!(!validate_action(&server_state, &definitions, 1, Action::AttackHero, 1, Some(&hero_tile), &far_tile));
+        // water_shield only goes on yourself.
+        assert!(!validate_action(&server_state, &definitions, 1, Action::CastToMob, 6, Some(&hero_tile), &next_tile));
+        assert!(!validate_action(&server_state, &definitions, 1, Action::AttackTower, 999, Some(&hero_tile), &next_tile));
+        assert!(validate_action(&server_state, &definitions, 2, Action::ExtractResource, 0, Some(&hero_tile), &next_tile));
+        assert!(!validate_action(&server_state, &definitions, 2, Action::ExtractResource, 0, Some(&hero_tile), &far_tile));
+
+        // hit_range counts tiles, a tile three tiles away is too far for a punch and close enough for mana_burst.
+        let three_tiles_away = (0..4u8).flat_map(|first| (0..4u8).map(move |second| (first, second)))
+            .map(|(first, second)| hero_tile.get_parent(2).subdivide(first).subdivide(second))
+            .find(|tile| tile_geometry::get_distance_in_tiles(&hero_tile, tile).is_some_and(|distance| distance > 2.75 && distance < 3.5))
+            .unwrap();
+        assert!(check_action(&definitions, Action::AttackHero, 1, &hero_tile, &three_tiles_away).is_err());
+        assert!(check_action(&definitions, Action::AttackHero, 0, &hero_tile, &three_tiles_away).is_ok());
+
+        // a hero that isn't on the map is refused and counted like any other rejection.
+        assert!(!validate_action(&server_state, &definitions, 3, Action::AttackTower, 1, None, &next_tile));
+
+        let rejected_actions = server_state.rejected_actions.lock().unwrap();
+        let first_hero = &rejected_actions[&1];
+        assert_eq!((first_hero.attack_hero, first_hero.cast_to_mob, first_hero.attack_tower), (1, 1, 1));
+        assert_eq!((first_hero.out_of_range, first_hero.invalid_target, first_hero.get_total()), (1, 2, 3));
+        assert_eq!(rejected_actions[&2].extract_resource, 1);
+        assert_eq!((rejected_actions[&3].attack_tower, rejected_actions[&3].invalid_target), (1, 1));
+    }
+}
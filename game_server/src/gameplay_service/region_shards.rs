@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::future::Future;
+
+use crate::map::tetrahedron_id::TetrahedronId;
+
+// commands split by the region (lod 7 parent) they touch, so regions don't wait on each other.
+pub struct RegionShards<T>
+{
+    // in the order each region first showed up.
+    pub regions : Vec<(TetrahedronId, Vec<T>)>,
+    // commands that touch more than one region, they run on their own once every region is done.
+    pub crossing : Vec<T>,
+}
+
+// get_region returns None for the commands that cross regions.
+pub fn shard_by_region<T>(commands : Vec<T>, get_region : impl Fn(&T) -> Option<TetrahedronId>) -> RegionShards<T>
+{
+    let mut regions : Vec<(TetrahedronId, Vec<T>)> = Vec::new();
+    let mut region_indices = HashMap::<TetrahedronId, usize>::new();
+    let mut crossing = Vec::new();
+
+    for command in commands
+    {
+        match get_region(&command)
+        {
+            Some(region_id) =>
+            {
+                let index = *region_indices.entry(region_id.clone()).or_insert_with(||
+                {
+                    regions.push((region_id, Vec::new()));
+                    regions.len() - 1
+                });
+                regions[index].1.push(command);
+            },
+            None => crossing.push(command),
+        }
+    }
+
+    RegionShards { regions, crossing }
+}
+
+// every region gets its own task, the outputs come back in the same order as the regions so merging them is deterministic.
+// a region that panics only loses its own commands.
+pub async fn run_shards<T, O, F, Fut>(regions : Vec<(TetrahedronId, Vec<T>)>, worker : F) -> Vec<O>
+where
+    F : Fn(Vec<T>) -> Fut,
+    Fut : Future<Output = O> + Send + 'static,
+    O : Send + 'static,
+{
+    let handles : Vec<_> = regions.into_iter()
+        .map(|(region_id, commands)| (region_id, tokio::spawn(worker(commands))))
+        .collect();
+
+    let mut outputs = Vec::with_capacity(handles.len());
+    for (region_id, handle) in handles
+    {
+        match handle.await
+        {
+            Ok(output) => outputs.push(output),
+            Err(error) => cli_log::error!("region {} worker failed: {}", region_id, error),
+        }
+    }
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn regions_keep_their_order_and_crossing_commands_wait()
+    {
+        let a = TetrahedronId::from_string("a0123012");
+        let b = TetrahedronId::from_string("b0123012");
+        let commands = vec![(Some(b.clone()), 1), (Some(a.clone()), 2), (None, 3), (Some(b.clone()), 4)];
+
+        let shards = shard_by_region(commands, |(region, _)| region.clone());
+        assert_eq!(shards.regions.iter().map(|(region_id, _)| region_id.clone()).collect::<Vec<_>>(), vec![b, a]);
+        assert_eq!(shards.crossing.len(), 1);
+
+        let outputs = run_shards(shards.regions, |commands| async move
+        {
+            if commands.iter().any(|(_, value)| *value == 2)
+            {
+                panic!("this region fails");
+            }
+            commands.iter().map(|(_, value)| value).sum::<i32>()
+        }).await;
+        assert_eq!(outputs, vec![5]);
+    }
+}
@@ -0,0 +1,209 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use crate::hero::hero_command::HeroCommand;
+use crate::map::map_entity::MapCommand;
+use crate::mob::mob_command::MobCommand;
+use crate::tower::TowerCommand;
+use crate::ServerState;
+
+// attacks and casts that land a bit later, for projectiles and other instances.
+#[derive(Debug, Clone)]
+pub enum DelayedCommand
+{
+    Hero(HeroCommand),
+    Tile(MapCommand),
+    Tower(TowerCommand),
+    Mob(MobCommand),
+}
+
+// who started a delayed command, everything it has pending goes away when it dies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Caster
+{
+    Hero(u16),
+    Mob(u32),
+}
+
+// the commands that are due, split by kind so each processor gets its own.
+#[derive(Debug, Default)]
+pub struct DueCommands
+{
+    pub heroes : Vec<HeroCommand>,
+    pub tiles : Vec<MapCommand>,
+    pub towers : Vec<TowerCommand>,
+    pub mobs : Vec<MobCommand>,
+}
+
+// one min-heap for every delayed command, ordered by due time and then by the order they were scheduled.
+// cancelled commands leave their key in the heap, it gets skipped when it comes up.
+pub struct Scheduler
+{
+    due_times : BinaryHeap<Reverse<(u64, u64)>>,
+    pending : HashMap<u64, (Caster, DelayedCommand)>,
+    next_id : u64,
+    server_state : Arc<ServerState>,
+}
+
+impl Scheduler
+{
+    pub fn new(server_state : Arc<ServerState>) -> Self
+    {
+        Scheduler
+        {
+            due_times: BinaryHeap::new(),
+            pending: HashMap::new(),
+            next_id: 0,
+            server_state,
+        }
+    }
+
+    // the id is only needed to cancel this one command.
+    pub fn schedule(&mut self, due_time : u64, caster : Caster, command : DelayedCommand) -> u64
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.due_times.push(Reverse((due_time, id)));
+        self.pending.insert(id, (caster, command));
+        self.update_metrics();
+        id
+    }
+
+    pub fn cancel(&mut self, id : u64) -> bool
+    {
+        let cancelled = self.pending.remove(&id).is_some();
+        if cancelled
+        {
+            self.server_state.cancelled_delayed_commands.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            self.update_metrics();
+        }
+        cancelled
+    }
+
+    // a caster that died doesn't get to finish its attacks.
+    pub fn cancel_by_caster(&mut self, caster : Caster) -> usize
+    {
+        let previous_len = self.pending.len();
+        self.pending.retain(|_, (pending_caster, _)| *pending_caster != caster);
+        let cancelled = previous_len - self.pending.len();
+        if cancelled > 0
+        {
+            self.server_state.cancelled_delayed_commands.fetch_add(cancelled as u64, std::sync::atomic::Ordering::Relaxed);
+            self.update_metrics();
+        }
+        cancelled
+    }
+
+    pub fn pop_due(&mut self, current_time : u64) -> DueCommands
+    {
+        let mut due_commands = DueCommands::default();
+        let mut executed = 0;
+        while let Some(Reverse((due_time, id))) = self.due_times.peek().copied()
+        {
+            if due_time > current_time
+            {
+                break;
+            }
+
+            self.due_times.pop();
+            // nothing pending means it was cancelled.
+            if let Some((_, command)) = self.pending.remove(&id)
+            {
+                executed += 1;
+                match command
+                {
+                    DelayedCommand::Hero(command) => due_commands.heroes.push(command),
+                    DelayedCommand::Tile(command) => due_commands.tiles.push(command),
+                    DelayedCommand::Tower(command) => due_commands.towers.push(command),
+                    DelayedCommand::Mob(command) => due_commands.mobs.push(command),
+                }
+            }
+        }
+
+        if executed > 0
+        {
+            self.server_state.executed_delayed_commands.fetch_add(executed, std::sync::atomic::Ordering::Relaxed);
+            self.update_metrics();
+        }
+        due_commands
+    }
+
+    pub fn len(&self) -> usize
+    {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.pending.is_empty()
+    }
+
+    fn update_metrics(&self)
+    {
+        let depth = self.pending.len() as u32;
+        self.server_state.pending_delayed_commands.store(depth, std::sync::atomic::Ordering::Relaxed);
+        self.server_state.max_pending_delayed_commands.fetch_max(depth, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hero::hero_command::HeroCommandInfo;
+    use crate::map::tetrahedron_id::TetrahedronId;
+    use crate::tower::TowerCommandInfo;
+    use std::sync::atomic::Ordering;
+
+    fn attack(player_id : u16, card_id : u32) -> DelayedCommand
+    {
+        DelayedCommand::Hero(HeroCommand { player_id, info: HeroCommandInfo::AttackCharacter(9, card_id, 100, 0, 0) })
+    }
+
+    fn card_ids(commands : &[HeroCommand]) -> Vec<u32>
+    {
+        commands.iter().map(|command| match command.info
+        {
+            HeroCommandInfo::AttackCharacter(_, card_id, _, _, _) => card_id,
+            _ => 0,
+        }).collect()
+    }
+
+    #[test]
+    fn due_commands_pop_in_order_and_cancelled_ones_never_do()
+    {
+        let server_state = Arc::new(ServerState::new(None));
+        let mut scheduler = Scheduler::new(server_state.clone());
+
+        scheduler.schedule(300, Caster::Hero(1), attack(1, 3));
+        scheduler.schedule(100, Caster::Hero(1), attack(1, 1));
+        // same due time, the one scheduled first goes first.
+        scheduler.schedule(200, Caster::Hero(2), attack(2, 2));
+        let cancelled_id = scheduler.schedule(200, Caster::Hero(2), attack(2, 4));
+        scheduler.schedule(200, Caster::Hero(2), attack(2, 5));
+        scheduler.schedule(250, Caster::Mob(7), DelayedCommand::Tower(TowerCommand
+        {
+            id: TetrahedronId::from_string("a0"),
+            info: TowerCommandInfo::AttackTower(7, 1, 2, 1, 250),
+        }));
+        assert_eq!(server_state.pending_delayed_commands.load(Ordering::Relaxed), 6);
+
+        assert!(scheduler.cancel(cancelled_id));
+        assert!(!scheduler.cancel(cancelled_id));
+        assert!(scheduler.pop_due(99).heroes.is_empty());
+        assert_eq!(card_ids(&scheduler.pop_due(100).heroes), vec![1]);
+        assert_eq!(card_ids(&scheduler.pop_due(200).heroes), vec![2, 5]);
+
+        // the mob died before its attack landed.
+        assert_eq!(scheduler.cancel_by_caster(Caster::Mob(7)), 1);
+        let due_commands = scheduler.pop_due(1000);
+        assert!(due_commands.towers.is_empty());
+        assert_eq!(card_ids(&due_commands.heroes), vec![3]);
+
+        assert!(scheduler.is_empty());
+        assert_eq!(server_state.pending_delayed_commands.load(Ordering::Relaxed), 0);
+        assert_eq!(server_state.max_pending_delayed_commands.load(Ordering::Relaxed), 6);
+        assert_eq!(server_state.executed_delayed_commands.load(Ordering::Relaxed), 4);
+        assert_eq!(server_state.cancelled_delayed_commands.load(Ordering::Relaxed), 2);
+    }
+}
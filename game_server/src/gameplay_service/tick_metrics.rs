@@ -0,0 +1,279 @@
+use std::sync::atomic::AtomicU64;
+use std::time::Duration;
+
+use serde::Serialize;
+
+// upper bounds of the duration buckets in microseconds, the last bucket takes everything above them.
+pub const DURATION_BUCKETS_MICROS : [u64; 10] = [100, 250, 500, 1000, 2500, 5000, 10000, 25000, 50000, 100000];
+pub const DURATION_BUCKETS : usize = DURATION_BUCKETS_MICROS.len() + 1;
+
+// the parts of a gameplay tick, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickStage
+{
+    Heroes,
+    Tiles,
+    Towers,
+    Kingdoms,
+    Mobs,
+    // building the region packets and handing them to the clients.
+    Packing,
+}
+
+impl TickStage
+{
+    pub const ALL : [TickStage; 6] = [
+        TickStage::Heroes,
+        TickStage::Tiles,
+        TickStage::Towers,
+        TickStage::Kingdoms,
+        TickStage::Mobs,
+        TickStage::Packing,
+    ];
+
+    pub fn get_name(&self) -> &'static str
+    {
+        match self
+        {
+            TickStage::Heroes => "heroes",
+            TickStage::Tiles => "tiles",
+            TickStage::Towers => "towers",
+            TickStage::Kingdoms => "kingdoms",
+            TickStage::Mobs => "mobs",
+            TickStage::Packing => "packing",
+        }
+    }
+}
+
+pub struct DurationHistogram
+{
+    buckets : [AtomicU64; DURATION_BUCKETS],
+    count : AtomicU64,
+    total_micros : AtomicU64,
+    max_micros : AtomicU64,
+}
+
+impl DurationHistogram
+{
+    pub fn new() -> Self
+    {
+        DurationHistogram
+        {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, duration : Duration)
+    {
+        let micros = duration.as_micros() as u64;
+        let bucket = DURATION_BUCKETS_MICROS.iter().position(|bound| micros <= *bound).unwrap_or(DURATION_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, std::sync::atomic::Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_snapshot(&self) -> HistogramSnapshot
+    {
+        let order = std::sync::atomic::Ordering::Relaxed;
+        let buckets : Vec<u64> = self.buckets.iter().map(|bucket| bucket.load(order)).collect();
+        let count = self.count.load(order);
+        let max_micros = self.max_micros.load(order);
+        HistogramSnapshot
+        {
+            count,
+            average_micros: self.total_micros.load(order).checked_div(count).unwrap_or(0),
+            p50_micros: get_percentile_micros(&buckets, count, max_micros, 0.5),
+            p99_micros: get_percentile_micros(&buckets, count, max_micros, 0.99),
+            max_micros,
+            buckets,
+        }
+    }
+}
+
+impl Default for DurationHistogram
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+// the upper bound of the bucket the percentile falls in, the max when it falls past the last bound.
+fn get_percentile_micros(buckets : &[u64], count : u64, max_micros : u64, percentile : f64) -> u64
+{
+    if count == 0
+    {
+        return 0;
+    }
+
+    let target = ((count as f64 * percentile).ceil() as u64).max(1);
+    let mut seen = 0;
+    for (index, bucket) in buckets.iter().enumerate()
+    {
+        seen += bucket;
+        if seen >= target
+        {
+            return DURATION_BUCKETS_MICROS.get(index).map_or(max_micros, |bound| (*bound).min(max_micros));
+        }
+    }
+    max_micros
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramSnapshot
+{
+    pub count : u64,
+    pub average_micros : u64,
+    pub p50_micros : u64,
+    pub p99_micros : u64,
+    pub max_micros : u64,
+    // same order as DURATION_BUCKETS_MICROS, plus the one above the last bound.
+    pub buckets : Vec<u64>,
+}
+
+pub struct StageMetrics
+{
+    pub durations : DurationHistogram,
+    // commands for the processors, packed entities for the packing.
+    pub commands : AtomicU64,
+}
+
+// how long every tick and every stage of it takes, so a slow processor shows up before players notice the rubber-banding.
+pub struct TickMetrics
+{
+    tick_micros : AtomicU64,
+    pub ticks : DurationHistogram,
+    // ticks that took longer than the tick, the next one starts late.
+    pub overruns : AtomicU64,
+    stages : [StageMetrics; TickStage::ALL.len()],
+}
+
+impl TickMetrics
+{
+    pub fn new() -> Self
+    {
+        TickMetrics
+        {
+            tick_micros: AtomicU64::new(0),
+            ticks: DurationHistogram::new(),
+            overruns: AtomicU64::new(0),
+            stages: std::array::from_fn(|_| StageMetrics { durations: DurationHistogram::new(), commands: AtomicU64::new(0) }),
+        }
+    }
+
+    // the gameplay service sets it when it starts, overruns are counted against it.
+    pub fn set_tick(&self, tick : Duration)
+    {
+        self.tick_micros.store(tick.as_micros() as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn get_stage(&self, stage : TickStage) -> &StageMetrics
+    {
+        &self.stages[stage as usize]
+    }
+
+    pub fn record_stage(&self, stage : TickStage, duration : Duration, commands : usize)
+    {
+        let stage_metrics = self.get_stage(stage);
+        stage_metrics.durations.record(duration);
+        stage_metrics.commands.fetch_add(commands as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    pub fn record_tick(&self, duration : Duration)
+    {
+        self.ticks.record(duration);
+        let tick_micros = self.tick_micros.load(std::sync::atomic::Ordering::Relaxed);
+        if tick_micros > 0 && duration.as_micros() as u64 > tick_micros
+        {
+            self.overruns.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    pub fn get_snapshot(&self) -> TickMetricsSnapshot
+    {
+        let order = std::sync::atomic::Ordering::Relaxed;
+        TickMetricsSnapshot
+        {
+            tick_micros: self.tick_micros.load(order),
+            bucket_bounds_micros: DURATION_BUCKETS_MICROS.to_vec(),
+            overruns: self.overruns.load(order),
+            ticks: self.ticks.get_snapshot(),
+            stages: TickStage::ALL.iter().map(|stage|
+            {
+                let stage_metrics = self.get_stage(*stage);
+                StageSnapshot
+                {
+                    name: stage.get_name(),
+                    commands: stage_metrics.commands.load(order),
+                    durations: stage_metrics.durations.get_snapshot(),
+                }
+            }).collect(),
+        }
+    }
+}
+
+impl Default for TickMetrics
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageSnapshot
+{
+    pub name : &'static str,
+    pub commands : u64,
+    pub durations : HistogramSnapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TickMetricsSnapshot
+{
+    pub tick_micros : u64,
+    pub bucket_bounds_micros : Vec<u64>,
+    pub overruns : u64,
+    pub ticks : HistogramSnapshot,
+    pub stages : Vec<StageSnapshot>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stages_and_overruns_are_recorded()
+    {
+        let metrics = TickMetrics::new();
+        metrics.set_tick(Duration::from_millis(100));
+
+        for _ in 0..98
+        {
+            metrics.record_stage(TickStage::Mobs, Duration::from_micros(80), 2);
+        }
+        metrics.record_stage(TickStage::Mobs, Duration::from_millis(3), 1);
+        metrics.record_stage(TickStage::Mobs, Duration::from_millis(300), 1);
+        metrics.record_tick(Duration::from_millis(100));
+        metrics.record_tick(Duration::from_millis(101));
+
+        let snapshot = metrics.get_snapshot();
+        assert_eq!(snapshot.overruns, 1);
+        assert_eq!(snapshot.ticks.count, 2);
+
+        let mobs = &snapshot.stages[TickStage::Mobs as usize];
+        assert_eq!(mobs.name, "mobs");
+        assert_eq!(mobs.commands, 198);
+        assert_eq!(mobs.durations.count, 100);
+        assert_eq!(mobs.durations.p50_micros, 100);
+        assert_eq!(mobs.durations.p99_micros, 5000);
+        assert_eq!(mobs.durations.max_micros, 300000);
+        assert_eq!(mobs.durations.buckets[0], 98);
+        assert_eq!(mobs.durations.buckets[DURATION_BUCKETS - 1], 1);
+        assert_eq!(snapshot.stages[TickStage::Heroes as usize].durations, DurationHistogram::new().get_snapshot());
+    }
+}
@@ -2,6 +2,9 @@ use std::{sync::Arc, collections::HashMap};
 use tokio::sync::{mpsc::Sender, Mutex};
 use crate::{ability_user::attack::Attack, hero::{hero_entity::HeroEntity, hero_inventory::InventoryItem, hero_reward::HeroReward}, gaia_mpsc::GaiaSender, map::{map_entity::{MapCommand, MapCommandInfo, MapEntity}, tetrahedron_id::TetrahedronId, GameMap}, ServerState};
 use crate::buffs::buff::BuffUser;
+use super::range_validation::{self, Action};
+use super::region_shards;
+use super::scheduler::Scheduler;
 
 
 pub async fn process_tile_commands (
@@ -16,113 +19,190 @@ pub async fn process_tile_commands (
     players_summary : &mut Vec<HeroEntity>,
     players_rewards_summary : &mut Vec<HeroReward>,
     player_attacks_summary : &mut  Vec<Attack>,
-    delayed_tile_commands_lock : Arc<Mutex<Vec<(u64, MapCommand)>>>
-)
+    scheduler_lock : Arc<Mutex<Scheduler>>
+) -> usize
 {
-    let mut tile_commands_data = tile_commands_processor_lock.lock().await;
-    if tile_commands_data.len() == 0 
+    // taken out right away, the clients keep adding commands while the regions work.
+    let tile_commands = std::mem::take(&mut *tile_commands_processor_lock.lock().await);
+    if tile_commands.is_empty()
     {
-        return;
+        return 0;
     }
 
-    for tile_command in tile_commands_data.iter()
+    let processed = tile_commands.len();
+    // a tile command never leaves the region of its tile.
+    let shards = region_shards::shard_by_region(tile_commands, get_tile_command_region);
+    for tile_command in shards.crossing.iter()
+    {
+        cli_log::error!("dropped tile command for invalid tile {}", tile_command.id);
+    }
+    let outputs = region_shards::run_shards(shards.regions, |tile_commands|
     {
-        match &tile_command.info
+        let map = map.clone();
+        let server_state = server_state.clone();
+        let tx_me_gameplay_longterm = tx_me_gameplay_longterm.clone();
+        let tx_me_gameplay_webservice = tx_me_gameplay_webservice.clone();
+        let tx_pe_gameplay_longterm = tx_pe_gameplay_longterm.clone();
+        async move
         {
-            MapCommandInfo::Touch() => 
-            {
-                touch(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone()).await;
-            },
-            MapCommandInfo::ResourceExtraction(player_id, damage) => 
+            let mut output = TileShardOutput::default();
+            for tile_command in tile_commands.iter()
             {
-                extract_resource(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tx_pe_gameplay_longterm, tiles_summary, players_summary, players_rewards_summary, *player_id, tile_command.id.clone(), *damage).await;
-            }, // we need to deduct stuff from the player
-            MapCommandInfo::LayFoundation(player_id, prop,enemy_mob, _pathness_a, _pathness_b,_pathness_c) => 
-            {
-                lay_foundation(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, *player_id, tile_command.id.clone(), current_time, *prop).await;
-            },
-            MapCommandInfo::BuildStructure(_player_id, increment) => 
-            {
-                build_structure(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), *increment as u16).await;
-            },
-            // MapCommandInfo::AttackWalker(player_id, damage, required_time) => 
-            // {
-            //     announce_attack_walker(&map, tile_attacks_summary, tile_command.id.clone(), *player_id, current_time).await;
-            //     if *required_time > 0
-            //     {
-            //         let mut lock = delayed_tile_commands_lock.lock().await;
-            //         let info = MapCommandInfo::AttackWalker(*player_id, *damage, *required_time);
-            //         let map_action = MapCommand { id: tile_command.id.clone(), info };
-            //         lock.push((current_time + *required_time as u64, map_action));
-            //     }
-            //     else
-            //     {
-            //         attack_walker(&map, &server_state, tx_pe_gameplay_longterm, players_summary, *player_id).await;
-            //     }
-            // },
-            // MapCommandInfo::SpawnMob(player_id, mob_id, level) => 
-            // {
-            //     spawn_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), current_time, *player_id, *mob_id, *level as u8).await;
-            // },
-            // MapCommandInfo::MoveMob(player_id, mob_id, new_tile_id, _distance, required_time) => 
-            // {
-            //     move_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), new_tile_id.clone(), current_time, *required_time, *player_id, *mob_id).await;
-            // },
-            // MapCommandInfo::ControlMapEntity(player_id, mob_id) => 
-            // {
-            //     control_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), current_time, *player_id, *mob_id).await;
-            // },
-            // this is very similar to change health command, but here we need to send and arrow.
-            // MapCommandInfo::AttackMob(player_id, card_id, required_time, active_effect) => 
-            // {
-            //     let end_time = current_time + *required_time as u64;
-            //     if *required_time == 0
-            //     {
-            //         attack_mob(
-            //             &map,
-            //             &server_state,
-            //             tx_me_gameplay_longterm,
-            //             tx_me_gameplay_webservice,
-            //             tx_pe_gameplay_longterm,
-            //             tiles_summary,
-            //             players_summary,
-            //             players_rewards_summary,
-            //             *card_id,
-            //             *player_id,
-            //             tile_command.id.clone()).await;
-            //     }
-            //     else 
-            //     {
-            //         cli_log::info!("------------ required time for attack {required_time} current time: {current_time} {card_id}");
-            //         let mut lock = delayed_tile_commands_lock.lock().await;
-            //         let info = MapCommandInfo::AttackMob(*player_id, *card_id, *required_time, *active_effect);
-            //         let map_action = MapCommand { id: tile_command.id.clone(), info };
-            //         lock.push((end_time, map_action));
-            //         drop(lock);
-            //     }
-
-            //     let attack = CharacterAttack
-            //     {
-            //         id: (current_time % 10000) as u16,
-            //         player_id: *player_id,
-            //         target_player_id: 0,
-            //         card_id: *card_id,
-            //         target_tile_id: tile_command.id.clone(),
-            //         required_time: *required_time,
-            //         active_effect: *active_effect
-            //     };
-            //     cli_log::info!("--- attack {} effect {}", attack.required_time, attack.active_effect);
-            //     player_attacks_summary.push(attack);
-
-            // }
-            MapCommandInfo::LayWallFoundation(_player_id, faction, prop, endpoint_a, endpoint_b, wall_size) => 
-            {
-                lay_wall_foundation(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, *prop, *faction, tile_command.id.clone(), endpoint_a.clone(), endpoint_b.clone(), *wall_size as u16).await;
-            },
+                process_tile_command(
+                    &map,
+                    &server_state,
+                    current_time,
+                    tile_command,
+                    &tx_me_gameplay_longterm,
+                    &tx_me_gameplay_webservice,
+                    &tx_pe_gameplay_longterm,
+                    &mut output).await;
+            }
+            output
         }
+    }).await;
+
+    for output in outputs
+    {
+        tiles_summary.extend(output.tiles_summary);
+        players_summary.extend(output.players_summary);
+        players_rewards_summary.extend(output.players_rewards_summary);
+        player_attacks_summary.extend(output.player_attacks_summary);
     }
     // cli_log::info!("tiles summary {} ", tiles_summary.len());
-    tile_commands_data.clear();
+    processed
+}
+
+// the region a tile command works on, None when its tile is not valid.
+pub fn get_tile_command_region(tile_command : &MapCommand) -> Option<TetrahedronId>
+{
+    // get_parent(7) panics on tiles less than 7 lods deep, and the clients send whatever they want.
+    if !tile_command.id.is_valid_for_lod(9)
+    {
+        return None;
+    }
+    Some(tile_command.id.get_parent(7))
+}
+
+// what one region produced, merged into the tick summaries once every region is done.
+#[derive(Default)]
+struct TileShardOutput
+{
+    tiles_summary : Vec<MapEntity>,
+    players_summary : Vec<HeroEntity>,
+    players_rewards_summary : Vec<HeroReward>,
+    player_attacks_summary : Vec<Attack>,
+}
+
+async fn process_tile_command(
+    map : &Arc<GameMap>,
+    server_state : &Arc<ServerState>,
+    current_time : u64,
+    tile_command : &MapCommand,
+    tx_me_gameplay_longterm : &GaiaSender<MapEntity>,
+    tx_me_gameplay_webservice : &GaiaSender<MapEntity>,
+    tx_pe_gameplay_longterm : &GaiaSender<HeroEntity>,
+    output : &mut TileShardOutput,
+)
+{
+    let TileShardOutput { tiles_summary, players_summary, players_rewards_summary, player_attacks_summary: _ } = output;
+    match &tile_command.info
+    {
+        MapCommandInfo::Touch() => 
+        {
+            touch(map, server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone()).await;
+        },
+        MapCommandInfo::ResourceExtraction(player_id, damage) => 
+        {
+            if !range_validation::validate_hero_action(map, server_state, *player_id, Action::ExtractResource, 0, &tile_command.id).await
+            {
+                return;
+            }
+            extract_resource(map, server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tx_pe_gameplay_longterm, tiles_summary, players_summary, players_rewards_summary, *player_id, tile_command.id.clone(), *damage).await;
+        }, // we need to deduct stuff from the player
+        MapCommandInfo::LayFoundation(player_id, prop,enemy_mob, _pathness_a, _pathness_b,_pathness_c) => 
+        {
+            lay_foundation(map, server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, *player_id, tile_command.id.clone(), current_time, *prop).await;
+        },
+        MapCommandInfo::BuildStructure(_player_id, increment) => 
+        {
+            build_structure(map, server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), *increment as u16).await;
+        },
+        // MapCommandInfo::AttackWalker(player_id, damage, required_time) => 
+        // {
+        //     announce_attack_walker(&map, tile_attacks_summary, tile_command.id.clone(), *player_id, current_time).await;
+        //     if *required_time > 0
+        //     {
+        //         let mut lock = scheduler_lock.lock().await;
+        //         let info = MapCommandInfo::AttackWalker(*player_id, *damage, *required_time);
+        //         let map_action = MapCommand { id: tile_command.id.clone(), info };
+        //         lock.schedule(current_time + *required_time as u64, Caster::Hero(*player_id), DelayedCommand::Tile(map_action));
+        //     }
+        //     else
+        //     {
+        //         attack_walker(&map, &server_state, tx_pe_gameplay_longterm, players_summary, *player_id).await;
+        //     }
+        // },
+        // MapCommandInfo::SpawnMob(player_id, mob_id, level) => 
+        // {
+        //     spawn_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), current_time, *player_id, *mob_id, *level as u8).await;
+        // },
+        // MapCommandInfo::MoveMob(player_id, mob_id, new_tile_id, _distance, required_time) => 
+        // {
+        //     move_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), new_tile_id.clone(), current_time, *required_time, *player_id, *mob_id).await;
+        // },
+        // MapCommandInfo::ControlMapEntity(player_id, mob_id) => 
+        // {
+        //     control_mob(&map, &server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, tile_command.id.clone(), current_time, *player_id, *mob_id).await;
+        // },
+        // this is very similar to change health command, but here we need to send and arrow.
+        // MapCommandInfo::AttackMob(player_id, card_id, required_time, active_effect) => 
+        // {
+        //     let end_time = current_time + *required_time as u64;
+        //     if *required_time == 0
+        //     {
+        //         attack_mob(
+        //             &map,
+        //             &server_state,
+        //             tx_me_gameplay_longterm,
+        //             tx_me_gameplay_webservice,
+        //             tx_pe_gameplay_longterm,
+        //             tiles_summary,
+        //             players_summary,
+        //             players_rewards_summary,
+        //             *card_id,
+        //             *player_id,
+        //             tile_command.id.clone()).await;
+        //     }
+        //     else 
+        //     {
+        //         cli_log::info!("------------ required time for attack {required_time} current time: {current_time} {card_id}");
+        //         let mut lock = scheduler_lock.lock().await;
+        //         let info = MapCommandInfo::AttackMob(*player_id, *card_id, *required_time, *active_effect);
+        //         let map_action = MapCommand { id: tile_command.id.clone(), info };
+        //         lock.schedule(end_time, Caster::Hero(*player_id), DelayedCommand::Tile(map_action));
+        //         drop(lock);
+        //     }
+
+        //     let attack = CharacterAttack
+        //     {
+        //         id: (current_time % 10000) as u16,
+        //         player_id: *player_id,
+        //         target_player_id: 0,
+        //         card_id: *card_id,
+        //         target_tile_id: tile_command.id.clone(),
+        //         required_time: *required_time,
+        //         active_effect: *active_effect
+        //     };
+        //     cli_log::info!("--- attack {} effect {}", attack.required_time, attack.active_effect);
+        //     player_attacks_summary.push(attack);
+
+        // }
+        MapCommandInfo::LayWallFoundation(_player_id, faction, prop, endpoint_a, endpoint_b, wall_size) => 
+        {
+            lay_wall_foundation(map, server_state, tx_me_gameplay_longterm, tx_me_gameplay_webservice, tiles_summary, *prop, *faction, tile_command.id.clone(), endpoint_a.clone(), endpoint_b.clone(), *wall_size as u16).await;
+        },
+    }
 }
 
 
@@ -525,4 +605,20 @@ pub async fn lay_wall_foundation(
         }
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_on_invalid_tiles_have_no_region()
+    {
+        let command = MapCommand { id: TetrahedronId::from_string("a012301230"), info: MapCommandInfo::Touch() };
+        assert_eq!(get_tile_command_region(&command), Some(TetrahedronId::from_string("a01")));
+
+        // lod 4, get_parent(7) used to panic on it.
+        let command = MapCommand { id: TetrahedronId::from_string("a0123"), info: MapCommandInfo::Touch() };
+        assert_eq!(get_tile_command_region(&command), None);
+    }
+}
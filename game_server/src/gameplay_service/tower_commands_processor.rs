@@ -2,18 +2,21 @@ use std::{sync::Arc, collections::HashMap};
 use tokio::sync::{mpsc::Sender, Mutex};
 use crate::{ability_user::{attack::Attack, attack_result::{AttackResult, BATTLE_CHAR_TOWER, BATTLE_MOB_MOB, NORMAL_ATTACK_RESULT}}, gaia_mpsc::GaiaSender, map::{tetrahedron_id::TetrahedronId, GameMap}, tower::{tower_entity::TowerEntity, TowerCommand, TowerCommandInfo}, ServerState};
 use crate::hero::{hero_entity::HeroEntity, hero_reward::HeroReward};
+use super::range_validation::{self, Action};
+use super::scheduler::{Caster, DelayedCommand, Scheduler};
 
 
 pub async fn process_tower_commands (
     map : Arc<GameMap>,
-    _server_state: Arc<ServerState>,
+    server_state: Arc<ServerState>,
+    current_time : u64,
     tower_commands_processor_lock : Arc<Mutex<Vec<TowerCommand>>>,
     tx_te_gameplay_longterm : &GaiaSender<TowerEntity>,
     tx_te_gameplay_webservice : &GaiaSender<TowerEntity>,
     towers_summary : &mut Vec<TowerEntity>,
     player_attacks_summary : &mut  Vec<Attack>,
-    delayed_tower_commands_lock : Arc<Mutex<Vec<(u64, TowerCommand)>>>
-)
+    scheduler_lock : Arc<Mutex<Scheduler>>
+) -> usize
 {
     // process tower stuff.
     let mut tower_commands_data = tower_commands_processor_lock.lock().await;
@@ -23,6 +26,13 @@ pub async fn process_tower_commands (
         for tower_command in tower_commands_data.iter()
         {
             // let cloned_data = tower_command.to_owned();
+            // taken before locking the towers, nothing else locks the heroes inside the towers lock.
+            let attacker_position = match &tower_command.info
+            {
+                TowerCommandInfo::AttackTower(player_id, ..) => range_validation::get_hero_position(&map, *player_id).await,
+                _ => None,
+            };
+
             let mut towers = map.towers.lock().await;
             cli_log::info!("towers count {}", towers.len());
             let tower_option = towers.get_mut(&tower_command.id);
@@ -48,14 +58,17 @@ pub async fn process_tower_commands (
                     },
                     TowerCommandInfo::AttackTower(player_id,event_id, player_faction, card_id, required_time) => 
                     {
-                        let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-                        let current_time_in_seconds = current_time.as_secs() as u32;
-                        let current_time_in_milliseconds = current_time.as_millis() as u64;
+                        let current_time_in_seconds = (current_time / 1000) as u32;
+                        let current_time_in_milliseconds = current_time;
 
                         if tower.event_id != *event_id
                         {
                             cli_log::info!("tower event doesn't match");
                         }
+                        else if !range_validation::validate_action(&server_state, &map.definitions, *player_id, Action::AttackTower, *card_id, attacker_position.as_ref(), &tower_command.id)
+                        {
+                            cli_log::info!("tower out of reach");
+                        }
                         // tower might be sleeping or active
                         else if tower.is_active(*player_faction, current_time_in_seconds)
                         {
@@ -101,11 +114,11 @@ pub async fn process_tower_commands (
                             };
                             player_attacks_summary.push(attack);
 
-                            let mut lock = delayed_tower_commands_lock.lock().await;
+                            let mut lock = scheduler_lock.lock().await;
                             let info = TowerCommandInfo::AttackTower(*player_id, *event_id, *player_faction, *card_id, *required_time);
 
                             let tower_action = TowerCommand { id: tower_command.id.clone(), info };
-                            lock.push((current_time_in_milliseconds + *required_time as u64, tower_action));
+                            lock.schedule(current_time_in_milliseconds + *required_time as u64, Caster::Hero(*player_id), DelayedCommand::Tower(tower_action));
                             drop(lock);
 
                         // }
@@ -128,7 +141,9 @@ pub async fn process_tower_commands (
 
         }
     }
+    let processed = tower_commands_data.len();
     tower_commands_data.clear();
+    processed
 }
 
 pub async fn process_delayed_tower_commands (
@@ -1,9 +1,8 @@
 use std::sync::Arc;
 
-use rand::rngs::StdRng;
 use tokio::sync::mpsc::Sender;
 
-use crate::{ability_user::{attack_result::{BLOCKED_ATTACK_RESULT, MISSED_ATTACK_RESULT, NORMAL_ATTACK_RESULT}, AbilityUser}, buffs::buff::{BuffUser, BUFF_DEFENSE, BUFF_STRENGTH}, hero::{hero_command::HeroCommand, hero_entity::{HeroEntity}, hero_reward::HeroReward}, definitions::definitions_container::Definitions, map::map_entity::{MapCommand, MapEntity}, mob::mob_command::MobCommand, tower::{tower_entity::TowerEntity, TowerCommand}, web_service::heroes::PlayerCreationRequest, ServerState};
+use crate::{ability_user::{attack_result::{BLOCKED_ATTACK_RESULT, MISSED_ATTACK_RESULT, NORMAL_ATTACK_RESULT}, AbilityUser}, buffs::buff::{BuffUser, BUFF_DEFENSE, BUFF_STRENGTH}, hero::{hero_entity::{HeroEntity}, hero_reward::HeroReward}, definitions::definitions_container::Definitions, map::map_entity::MapEntity, tower::tower_entity::TowerEntity, web_service::heroes::PlayerCreationRequest, ServerState};
 
 
 pub fn attack<T:AbilityUser+BuffUser, S:AbilityUser+BuffUser>(
@@ -42,8 +41,7 @@ pub fn attack<T:AbilityUser+BuffUser, S:AbilityUser+BuffUser>(
     {
         if let Some(skill) = definitions.cards.get(card_id as usize)
         {
-            let mut random_generator = <StdRng as rand::SeedableRng>::from_entropy();
-            let x =  rand::Rng::gen::<f32>(&mut random_generator);
+            let x = crate::gameplay_service::random::next_f32();
             if x <= skill.effect_probability 
             {
                 if let Some(skill_def) = definitions.buffs.get(&skill.buff)
@@ -92,85 +90,3 @@ pub fn heal<T:AbilityUser+BuffUser, S:AbilityUser+BuffUser>(
 //     players_rewards_summary.push(reward);
 //     players_summary.push(player_entity.clone());
 // }
-
-
-pub fn get_tile_commands_to_execute(current_time : u64, delayed_tile_commands_guard : &mut Vec<(u64, MapCommand)>) -> Vec<MapCommand>
-{
-    let mut items_to_execute = Vec::<MapCommand>::new();
-    // let current_time = time.load(std::sync::atomic::Ordering::Relaxed);
-
-    delayed_tile_commands_guard.retain(|b| 
-    {
-        let should_execute = b.0 <= current_time;
-        // cli_log::info!("checking delayed action {} task_time {} current_time {current_time}", should_execute, b.0);
-        if should_execute
-        {
-            items_to_execute.push(b.1.clone());
-        }
-
-        !should_execute // we keep items that we didn't execute
-    });
-
-    items_to_execute
-}
-
-pub fn get_mob_commands_to_execute(current_time : u64, delayed_mob_commands_guard : &mut Vec<(u64, MobCommand)>) -> Vec<MobCommand>
-{
-    let mut items_to_execute = Vec::<MobCommand>::new();
-    // let current_time = time.load(std::sync::atomic::Ordering::Relaxed);
-
-    delayed_mob_commands_guard.retain(|b| 
-        {
-        let should_execute = b.0 <= current_time;
-        // cli_log::info!("checking delayed action {} task_time {} current_time {current_time}", should_execute, b.0);
-        if should_execute
-        {
-            items_to_execute.push(b.1.clone());
-        }
-
-        !should_execute // we keep items that we didn't execute
-    });
-
-    items_to_execute
-}
-
-pub fn get_tower_commands_to_execute(current_time : u64, delayed_tower_commands_guard : &mut Vec<(u64, TowerCommand)>) -> Vec<TowerCommand>
-{
-    let mut items_to_execute = Vec::<TowerCommand>::new();
-    // let current_time = time.load(std::sync::atomic::Ordering::Relaxed);
-
-    delayed_tower_commands_guard.retain(|b| 
-        {
-        let should_execute = b.0 <= current_time;
-        // cli_log::info!("checking delayed action {} task_time {} current_time {current_time}", should_execute, b.0);
-        if should_execute
-        {
-            items_to_execute.push(b.1.clone());
-        }
-
-        !should_execute // we keep items that we didn't execute
-    });
-
-    items_to_execute
-}
-
-
-pub fn get_player_commands_to_execute(current_time : u64, delayed_player_commands_guards : &mut Vec<(u64, HeroCommand)>) -> Vec<HeroCommand>
-{
-    let mut player_commands_to_execute = Vec::<HeroCommand>::new();
-
-    // cli_log::info!("checking delayed plaeyr commands {}" , delayed_commands_lock.len());
-    delayed_player_commands_guards.retain(|b| 
-    {
-        let should_execute = b.0 <= current_time;
-        // cli_log::info!("checking delayed player action {} task_time {} current_time {current_time}", should_execute, b.0);
-        if should_execute
-        {
-            player_commands_to_execute.push(b.1.clone());
-        }
-
-        !should_execute // we keep items that we didn't execute
-    });
-
-    player_commands_to_execute
-}
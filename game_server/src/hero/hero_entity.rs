@@ -246,6 +246,47 @@ impl HeroEntity
         HERO_ENTITY_SIZE
     }
 
+    // a level 1 hero standing on position with nothing else, tests change the fields they care about.
+    #[cfg(test)]
+    pub fn for_tests(hero_id : u16, faction : u8, position : TetrahedronId) -> HeroEntity
+    {
+        HeroEntity
+        {
+            object_id: None,
+            player_id: None,
+            version: 1,
+            hero_name: "a".to_owned(),
+            hero_id,
+            faction,
+            action: 0,
+            flags: 0,
+            position,
+            second_position: TetrahedronId::default(),
+            vertex_id: -1,
+            path: [0, 0, 0, 0, 0, 0],
+            time: 0,
+            inventory: Vec::new(),
+            card_inventory: Vec::new(),
+            weapon_inventory: Vec::new(),
+            inventory_version: 1,
+            health: 100,
+            level: 1,
+            experience: 0,
+            available_skill_points: 0,
+            weapon: 0,
+            base_strength: 0,
+            base_defense: 0,
+            base_intelligence: 0,
+            base_mana: 0,
+            strength_points: 0,
+            defense_points: 0,
+            intelligence_points: 0,
+            mana_points: 0,
+            buffs: Vec::new(),
+            buffs_summary: [0, 0, 0, 0, 0],
+            tower_progress: HeroTowerProgress::default(),
+        }
+    }
 }
 
 impl Hash for HeroEntity 
@@ -349,7 +390,7 @@ mod tests
     use std::num::Wrapping;
 
 
-    use crate::{hero::{hero_entity::HERO_ENTITY_SIZE, hero_inventory::HERO_INVENTORY_ITEM_SIZE, hero_tower_progress::HeroTowerProgress}, map::tetrahedron_id::TetrahedronId};
+    use crate::{hero::{hero_entity::HERO_ENTITY_SIZE, hero_inventory::HERO_INVENTORY_ITEM_SIZE}, map::tetrahedron_id::TetrahedronId};
 
     use super::HeroEntity;
 
@@ -398,42 +439,7 @@ mod tests
     #[test]
     fn test_add_inventory_item()
     {
-        let mut entity = HeroEntity
-        {
-            object_id: None,
-            player_id: None,
-            version:1,
-            hero_name: "a".to_owned(),
-            hero_id: 1234,
-            faction:0,
-            action: 0,
-            flags:0,
-            position: TetrahedronId::default(),
-            second_position: TetrahedronId::default(),
-            vertex_id:-1,
-            path:[0,0,0,0,0,0],
-            time:0,
-            inventory: Vec::new(),
-            card_inventory: Vec::new(),
-            weapon_inventory: Vec::new(),
-            inventory_version: 1,
-            health: 0,
-            level: 1,
-            experience: 0,
-            available_skill_points: 0,
-            weapon:0,
-            base_strength: 0,
-            base_defense: 0,
-            base_intelligence: 0,
-            base_mana: 0,
-            strength_points: 0,
-            defense_points: 0,
-            intelligence_points: 0,
-            mana_points: 0,
-            buffs: Vec::new(),
-            buffs_summary: [0,0,0,0,0],
-            tower_progress: HeroTowerProgress::default(),
-        };
+        let mut entity = HeroEntity::for_tests(1234, 0, TetrahedronId::default());
 
         entity.add_inventory_item(super::InventoryItem { item_id: 1, equipped: 0, amount: 1 });
         entity.add_inventory_item(super::InventoryItem { item_id: 1, equipped: 0, amount: 2 });
@@ -461,40 +467,18 @@ mod tests
     fn test_encode_character()
     {
 
-        let char = HeroEntity{
-            object_id: None,
-            player_id: None,
-            version: 1,
+        let char = HeroEntity
+        {
             hero_name: "Park".to_string(),
-            hero_id: 2,
-            faction: 0,
-            position: TetrahedronId::default(),
-            second_position: TetrahedronId::default(), 
-            vertex_id:-1,
-            path:[0,0,0,0,0,0],
-            time:0,
             action: 1,
-            flags:0,
-            inventory: Vec::new(),
-            card_inventory: Vec::new(),
-            weapon_inventory: Vec::new(),
             inventory_version: 10,
             level: 0,
-            experience: 0,
-            available_skill_points: 0,
-            weapon:0,
-            strength_points: 0,
-            defense_points: 0,
-            intelligence_points: 0,
-            mana_points: 0,
             base_strength: 23,
             base_defense: 10,
             base_intelligence: 3,
             base_mana: 3,
             health: 10,
-            buffs: Vec::new(),
-            buffs_summary: [0,0,0,0,0],
-            tower_progress: HeroTowerProgress::default(),
+            ..HeroEntity::for_tests(2, 0, TetrahedronId::default())
         };
         let buffer = char.to_bytes();
         cli_log::info!("{:?}", buffer);
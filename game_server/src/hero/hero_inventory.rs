@@ -1,4 +1,3 @@
-use rand::rngs::StdRng;
 
 use crate::{hero::hero_card_inventory::CardItem, definitions::{definitions_container::Definitions, Definition}};
 
@@ -157,8 +156,7 @@ impl HeroEntity
 
             let cards_count = definitions.cards.len();
 
-            let mut random_generator = <StdRng as rand::SeedableRng>::from_entropy();
-            let x =  rand::Rng::gen::<f32>(&mut random_generator);
+            let x = crate::gameplay_service::random::next_f32();
             let card_id = (x * cards_count as f32).floor() as u32;
 
             self.add_card(CardItem
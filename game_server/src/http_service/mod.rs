@@ -9,7 +9,7 @@ use tower_http::{
 
 // pub mod compression_layer;
 
-pub async fn run() 
+pub async fn run(addr : std::net::SocketAddr) 
 {
     // Serve static files from the `public` folder
     let file_service = ServeDir::new("public")
@@ -29,8 +29,8 @@ pub async fn run()
         .layer(cors);
         // .layer(map_response(set_header));
 
-    cli_log::info!("Server running at http://localhost:3030");
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3030").await.unwrap();
+    cli_log::info!("Server running at http://{}", addr);
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
     axum::serve(listener, app.into_make_service()).await.unwrap();
 }
 
@@ -5,6 +5,12 @@ use std::sync::atomic::AtomicU16;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
 
+use clients_service::clock_sync::ClientLatency;
+use clients_service::connection::ConnectionId;
+use clients_service::connection_stats::ConnectionStats;
+use clients_service::packet_capture::PacketCapture;
+use gameplay_service::range_validation::RejectedActions;
+use gameplay_service::tick_metrics::TickMetrics;
 use map::tetrahedron_id::TetrahedronId;
 use map::GameMap;
 use strum::IntoEnumIterator;
@@ -30,6 +36,7 @@ pub mod app;
 pub mod gaia_mpsc;
 pub mod http_service;
 pub mod kingdom;
+pub mod server_config;
 
 pub struct AppData
 {
@@ -51,8 +58,6 @@ pub enum ServerChannels
     TX_MOC_CLIENTS_GAMEPLAY,
     TX_MOE_GAMEPLAY_WEBSERVICE,
     TX_PACKETS_GAMEPLAY_CHAT_CLIENTS,
-    TX_PACKETS_GAMEPLAY_CHAT_WEBSOCKET_CLIENTS,
-    TX_PACKETS_GAMEPLAY_CHAT_WEBSOCKET_SPECIFIC_CLIENT,
     TX_MC_WEBSERVICE_GAMEPLAY,
     TX_ME_GAMEPLAY_LONGTERM,
     TX_ME_GAMEPLAY_WEBSERVICE,
@@ -65,6 +70,7 @@ pub enum ServerChannels
     TX_SAVED_LONGTERM_WEBSERVICE,
     TX_TE_SAVED_LONGTERM_WEBSERVICE,
     TX_KE_SAVED_LONGTERM_WEBSERVICE,
+    TX_SNAPSHOTS_GAMEPLAY_CLIENTS,
 }
 
 pub struct ServerState 
@@ -78,6 +84,29 @@ pub struct ServerState
     pub sent_game_packets:AtomicU64,
     pub sent_bytes:AtomicU64,
     pub total_players:AtomicU32,
+    // malformed or unknown packets, indexed by the protocol byte.
+    pub dropped_packets:[AtomicU64; 256],
+    // packets with a session or hero id that doesn't belong to the connection.
+    pub rejected_packets:AtomicU64,
+    // packets over the rate limit and connections closed because of them.
+    pub rate_limited_packets:AtomicU64,
+    pub rate_limit_disconnects:AtomicU64,
+    // round trip, jitter and clock offset of each logged in hero, measured from its pings.
+    // a std mutex so the tui can read it without being async.
+    pub client_latencies:std::sync::Mutex<HashMap<u16, ClientLatency>>,
+    // every registered connection, for the tui. The registry adds and removes them.
+    pub connection_stats:std::sync::Mutex<HashMap<ConnectionId, Arc<ConnectionStats>>>,
+    // only set when the server runs with a capture path, see packet_capture.
+    pub packet_capture:Option<PacketCapture>,
+    // attacks and casts waiting in the gameplay scheduler, and what happened to the ones that left it.
+    pub pending_delayed_commands:AtomicU32,
+    pub max_pending_delayed_commands:AtomicU32,
+    pub executed_delayed_commands:AtomicU64,
+    pub cancelled_delayed_commands:AtomicU64,
+    // how long the gameplay ticks and each of their stages take.
+    pub tick_metrics:TickMetrics,
+    // attacks and interactions refused by the range checks, by hero id.
+    pub rejected_actions:std::sync::Mutex<HashMap<u16, RejectedActions>>,
     // long term data.
     pub pending_regions_to_save:AtomicU32,
     pub saved_regions:AtomicU32,
@@ -96,6 +125,59 @@ pub struct ServerState
 
 impl ServerState 
 {
+    pub fn new(packet_capture : Option<PacketCapture>) -> Self
+    {
+        let mut channels_status = HashMap::new();
+        for channel in ServerChannels::iter() 
+        {
+            channels_status.insert(channel, AtomicU16::new(0));
+        }
+
+        ServerState
+        {
+            channels: channels_status,
+            mob_id_generator: AtomicU32::new(0),
+            online_players:AtomicU32::new(0),
+            total_players:AtomicU32::new(0),
+            received_packets: AtomicU64::new(0),
+            received_bytes: AtomicU64::new(0),
+            sent_udp_packets: AtomicU64::new(0),
+            sent_game_packets: AtomicU64::new(0),
+            sent_bytes: AtomicU64::new(0),
+            dropped_packets: std::array::from_fn(|_| AtomicU64::new(0)),
+            rejected_packets: AtomicU64::new(0),
+            rate_limited_packets: AtomicU64::new(0),
+            rate_limit_disconnects: AtomicU64::new(0),
+            client_latencies: std::sync::Mutex::new(HashMap::new()),
+            connection_stats: std::sync::Mutex::new(HashMap::new()),
+            packet_capture,
+            pending_delayed_commands: AtomicU32::new(0),
+            max_pending_delayed_commands: AtomicU32::new(0),
+            executed_delayed_commands: AtomicU64::new(0),
+            cancelled_delayed_commands: AtomicU64::new(0),
+            tick_metrics: TickMetrics::new(),
+            rejected_actions: std::sync::Mutex::new(HashMap::new()),
+
+            //char
+            pending_character_entities_to_save: AtomicU32::new(0),
+            saved_character_entities: AtomicU32::new(0),
+            last_character_entities_save_timestamp: AtomicU64::new(0),
+
+            //map
+            pending_regions_to_save: AtomicU32::new(0),
+            saved_regions: AtomicU32::new(0),
+            last_regions_save_timestamp: AtomicU64::new(0),
+
+            //towers
+            pending_tower_entities_to_save: AtomicU32::new(0),
+            saved_tower_entities: AtomicU32::new(0),
+            last_tower_entities_save_timestamp: AtomicU64::new(0),
+
+            pending_kingdome_entities_to_save: AtomicU32::new(0),
+            last_kingdome_entities_save_timestamp: AtomicU64::new(0),
+        }
+    }
+
     pub fn get_stats(&self) -> [u16; 10]
     {
         let order = std::sync::atomic::Ordering::Relaxed;
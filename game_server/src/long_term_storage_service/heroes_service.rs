@@ -129,7 +129,8 @@ pub fn start_server(
     mut rx_he_realtime_longterm : Receiver<HeroEntity>,
     map : Arc<GameMap>,
     server_state : Arc<ServerState>,
-    db_client : Client)
+    db_client : Client,
+    save_interval : std::time::Duration)
     {
 
     let modified_players = HashSet::<u16>::new();
@@ -183,7 +184,7 @@ pub fn start_server(
 
         loop 
         {
-            tokio::time::sleep(tokio::time::Duration::from_secs(100)).await;
+            tokio::time::sleep(save_interval).await;
             let mut modified_player_keys = modified_players_reader_lock.lock().await;
             let modified_heroes = modified_player_keys.len();
             let locked_players = map_reader.character.lock().await;
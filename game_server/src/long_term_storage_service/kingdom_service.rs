@@ -100,7 +100,8 @@ pub fn start_server(
     mut rx_ke_realtime_longterm : Receiver<KingdomEntity>,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
-    db_client : Client)
+    db_client : Client,
+    save_interval : std::time::Duration)
     -> Receiver<bool>
     {
 
@@ -159,7 +160,7 @@ pub fn start_server(
 
         loop 
         {
-            tokio::time::sleep(tokio::time::Duration::from_secs(100)).await;
+            tokio::time::sleep(save_interval).await;
             let mut modified_kingdome_keys = modified_kingdomes_reader_lock.lock().await;
             let modified_kingdomes= modified_kingdome_keys.len();
             let kingdomes_guard = map_reader.kingdomes.lock().await;
@@ -112,7 +112,8 @@ pub fn start_server(
     mut rx_te_realtime_longterm : Receiver<TowerEntity>,
     map : Arc<GameMap>,
     server_state: Arc<ServerState>,
-    db_client : Client)
+    db_client : Client,
+    save_interval : std::time::Duration)
     -> Receiver<bool>
     {
 
@@ -171,7 +172,7 @@ pub fn start_server(
 
         loop 
         {
-            tokio::time::sleep(tokio::time::Duration::from_secs(100)).await;
+            tokio::time::sleep(save_interval).await;
             let mut modified_tower_keys = modified_towers_reader_lock.lock().await;
             let modified_towers = modified_tower_keys.len();
             let towers_guard = map_reader.towers.lock().await;
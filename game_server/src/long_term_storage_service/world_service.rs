@@ -1,12 +1,12 @@
 
 use std::collections::{HashSet, HashMap};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::sync::Arc;
 use std::time::SystemTime;
 use crate::long_term_storage_service::db_region::StoredRegion;
 use crate::long_term_storage_service::db_world::StoredWorld;
 use crate::map::GameMap;
-use crate::map::map_entity::{MapEntity};
+use crate::map::map_entity::{MapEntity, MAP_ENTITY_SIZE};
 use crate::map::tetrahedron_id::TetrahedronId;
 use crate::{gaia_mpsc, ServerState};
 use bson::doc;
@@ -15,6 +15,7 @@ use mongodb::Client;
 use tokio::sync::Mutex;
 use tokio::sync::mpsc::{Receiver};
 use flate2::Compression;
+use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use futures_util::stream::StreamExt;
 
@@ -154,7 +155,8 @@ pub fn start_server(
     mut rx_me_realtime_longterm : Receiver<MapEntity>,
     map : Arc<GameMap>,
     server_state : Arc<ServerState>,
-    db_client : Client
+    db_client : Client,
+    save_interval : std::time::Duration
 ) 
 -> Receiver<u32>
 {
@@ -217,7 +219,7 @@ pub fn start_server(
 
         loop 
         {
-            tokio::time::sleep(tokio::time::Duration::from_secs(300)).await; // every 5 minutes
+            tokio::time::sleep(save_interval).await;
             let mut modified_regions = modified_regions_reader_lock.lock().await;
 
             let mut stored_regions_binary_data = Vec::<(TetrahedronId, Vec<u8>)>:: new();
@@ -311,3 +313,83 @@ pub fn start_server(
     });
     rx_me_tx_saved_longterm_webservice
 }
+
+// decompresses the regions stored in the db into tiles, also returns the compressed data of each region.
+pub fn load_regions_data_into_game_map(
+    regions_stored_data : &HashMap<TetrahedronId, StoredRegion>
+) 
+->  (
+        Vec<(TetrahedronId, HashMap<TetrahedronId, MapEntity>)>, 
+        Vec<(TetrahedronId, Vec<u8>)>
+    ) 
+{
+    let mut regions_data = Vec::<(TetrahedronId, HashMap<TetrahedronId, MapEntity>)>::new();
+    let mut regions_binary_data = Vec::<(TetrahedronId, Vec<u8>)>::new();
+
+    let mut count = 0;
+    let mut region_count = 0;
+    let region_total = regions_stored_data.len();
+
+    for region in regions_stored_data.iter()
+    {
+        region_count += 1;
+        // cli_log::info!("decoding region progress {region_count}/{region_total} tiles {count}");
+
+        let region_object_id = region.1.id.clone();
+        let binary_data: Vec<u8> = match region.1.compressed_data.clone() 
+        {
+            bson::Bson::Binary(binary) => binary.bytes,
+            _ => panic!("Expected Bson::Binary"),
+        };
+        let region_id = region.0;
+        let data : &[u8] = &binary_data;
+
+
+
+        let decoder = ZlibDecoder::new(data);
+
+        let decoded_data_result :  Result<Vec<u8>, _> = decoder.bytes().collect();
+        let decoded_data = decoded_data_result.unwrap();
+        let tiles : &[u8] = &decoded_data;
+        let size = tiles.len();
+
+        let mut buffer = [0u8;MAP_ENTITY_SIZE as usize];
+        let mut start = 0;
+        let mut end = MapEntity::get_size() as usize;
+
+        // cli_log::info!("initialy for region {} {}",region_id, all_tiles.len());
+
+        let mut region_tiles : HashMap<TetrahedronId, MapEntity> = HashMap::new();
+
+        loop {
+            buffer.copy_from_slice(&tiles[start..end]);
+            let mut map_entity = MapEntity::from_bytes(&buffer);
+            // all map entities will have the object id of the database region, this value is the same for all map entities in a region
+            map_entity.object_id = region_object_id;
+            
+            if map_entity.id.to_string() == "j202020303" {
+                cli_log::info!("Found saved entity  {:?} " , map_entity);
+            }
+            region_tiles.insert(map_entity.id.clone(), map_entity);
+
+
+            start = end;
+            end = end + MapEntity::get_size();
+
+            if end > size
+            {
+                break;
+            }
+            // counting mapentities
+            count += 1;
+
+        }
+
+        regions_binary_data.push((region_id.clone(), binary_data));
+        regions_data.push((region_id.clone(), region_tiles));
+    }
+
+    cli_log::info!("finished loading data, starting services. regions: {} with {} tiles",region_total, count);
+    (regions_data, regions_binary_data)
+    // GameMap::new(regions_data)
+}
@@ -1,45 +1,28 @@
 use std::collections::HashMap;
-use std::io::Read;
 use std::io::Write;
-use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use std::sync::atomic::AtomicU16;
-use std::sync::atomic::AtomicU32;
-use game_server::get_regions_by_code;
-use game_server::get_regions_by_id;
 use game_server::http_service;
 use strum;
 
 use cli_log::init_cli_log;
-use flate2::read::ZlibDecoder;
 use game_server::app;
-use game_server::definitions::buffs_data::BuffData;
-use game_server::definitions::card::Card;
-use game_server::definitions::character_progression::CharacterProgression;
-use game_server::definitions::definition_versions::DefinitionVersion;
-use game_server::definitions::definitions_container::Definitions;
-use game_server::definitions::definitions_container::DefinitionsData;
-use game_server::definitions::items::Item;
-use game_server::definitions::main_paths::MapPath;
-use game_server::definitions::mob_progression::MobProgression;
-use game_server::definitions::mobs_data::MobData;
-use game_server::definitions::props_data::PropData;
-use game_server::definitions::tower_difficulty::TowerDifficulty;
-use game_server::definitions::weapons::Weapon;
-use game_server::definitions::Definition;
+use game_server::definitions::load_definitions;
 use game_server::AppData;
-use game_server::ServerChannels;
 use game_server::ServerState;
 use game_server::chat_service;
 use game_server::gameplay_service;
+use game_server::gameplay_service::clock::{Clock, SystemClock};
 use game_server::long_term_storage_service;
-use game_server::long_term_storage_service::db_region::StoredRegion;
+use game_server::long_term_storage_service::world_service::load_regions_data_into_game_map;
 use game_server::map::GameMap;
 use game_server::map::map_entity::MAP_ENTITY_SIZE;
 use game_server::map::map_entity::MapEntity;
 use game_server::map::tetrahedron_id::TetrahedronId;
 use game_server::clients_service;
+use game_server::clients_service::packet_capture::PacketCapture;
 use game_server::web_service;
+use game_server::server_config::{Args, RunMode, ServerConfig};
+use clap::Parser;
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
 use hyper_static::serve;
@@ -48,7 +31,6 @@ use mongodb::Client;
 use mongodb::options::ClientOptions;
 use mongodb::options::ResolverConfig;
 
-use strum::IntoEnumIterator;
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::Receiver;
 use tokio::sync::oneshot::Sender;
@@ -61,9 +43,35 @@ fn main()
     init_cli_log!("gaia");
     // panic hook disabled
     // init_panic_hook();
+
+    let config = match ServerConfig::load(&Args::parse())
+    {
+        Ok(config) => config,
+        Err(error) =>
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    cli_log::info!("using config {:?}", config);
+
+    let web_tls_config = match (&config.network.web_tls_cert, &config.network.web_tls_key)
+    {
+        (Some(cert_path), Some(key_path)) => match web_service::tls::load_server_config(cert_path, key_path)
+        {
+            Ok(tls_config) => Some(tls_config),
+            Err(error) =>
+            {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+
     // build runtime
     let runtime = tokio::runtime::Builder::new_multi_thread()
-        .worker_threads(4)
+        .worker_threads(config.worker_threads)
         .thread_name("gaia-main_thread")
         .thread_stack_size(3 * 1024 * 1024)
         .enable_all()
@@ -72,18 +80,25 @@ fn main()
 
     // cli_log::info!("running http server");
     // println!("Running http server");
-    runtime.spawn(http_service::run()); 
+    runtime.spawn(http_service::run(config.network.http_address())); 
     // runtime.block_on(http_service::run()); 
 
     println!("Running main server");
     cli_log::info!("running main server");
     let (tx, rx) = oneshot::channel();
-    // // runtime.spawn(run_server(tx)); 
-    runtime.block_on(run_server(tx)); 
-
-
-    // cli_log::info!("running tui");
-    // runtime.block_on(run_tui(rx)); 
+    match config.run_mode
+    {
+        RunMode::Headless =>
+        {
+            runtime.block_on(run_server(tx, config, web_tls_config)); 
+        },
+        RunMode::Tui =>
+        {
+            runtime.spawn(run_server(tx, config, web_tls_config)); 
+            cli_log::info!("running tui");
+            runtime.block_on(run_tui(rx)); 
+        },
+    }
 }
 
 async fn run_tui(rx: Receiver<AppData>)
@@ -138,7 +153,7 @@ pub fn restore_tui() -> std::io::Result<()>
 
 // #[tokio::main(worker_threads = 1)]
 // #[tokio::main()]
-async fn run_server(tx: Sender<AppData>) 
+async fn run_server(tx: Sender<AppData>, config : ServerConfig, web_tls_config : Option<Arc<tokio_rustls::rustls::ServerConfig>>) 
 {
     cli_log::info!("running server");
     let mut main_loop = tokio::time::interval(std::time::Duration::from_millis(50000));
@@ -147,49 +162,24 @@ async fn run_server(tx: Sender<AppData>)
 
     cli_log::info!("definitions loaded");
     println!("definitions loaded");
-    let mut channels_status = HashMap::new();
-    for channel in ServerChannels::iter() 
-    {
-        channels_status.insert(channel, AtomicU16::new(0));
-    }
-
-    let server_state = Arc::new(ServerState
+    // a capture that can't be created is not worth stopping the server for.
+    let packet_capture = config.capture_path.as_ref().and_then(|path| match PacketCapture::start(path)
     {
-        channels: channels_status,
-        mob_id_generator: AtomicU32::new(0),
-        online_players:AtomicU32::new(0),
-        total_players:AtomicU32::new(0),
-        received_packets: AtomicU64::new(0),
-        received_bytes: AtomicU64::new(0),
-        sent_udp_packets: AtomicU64::new(0),
-        sent_game_packets: AtomicU64::new(0),
-        sent_bytes: AtomicU64::new(0),
-
-        //char
-        pending_character_entities_to_save: AtomicU32::new(0),
-        saved_character_entities: AtomicU32::new(0),
-        last_character_entities_save_timestamp: AtomicU64::new(0),
-
-        //map
-        pending_regions_to_save: AtomicU32::new(0),
-        saved_regions: AtomicU32::new(0),
-        last_regions_save_timestamp: AtomicU64::new(0),
-
-        //towers
-        pending_tower_entities_to_save: AtomicU32::new(0),
-        saved_tower_entities: AtomicU32::new(0),
-        last_tower_entities_save_timestamp: AtomicU64::new(0),
-
-        pending_kingdome_entities_to_save: AtomicU32::new(0),
-        last_kingdome_entities_save_timestamp: AtomicU64::new(0),
+        Ok(capture) => Some(capture),
+        Err(error) =>
+        {
+            cli_log::error!("can't capture packets into {}: {}", path.display(), error);
+            None
+        }
     });
+
+    let server_state = Arc::new(ServerState::new(packet_capture));
     // let (_tx, mut rx) = tokio::sync::watch::channel("hello");
 
-    let client_uri = "mongodb://localhost:27017/test?retryWrites=true&w=majority";
-    let options = ClientOptions::parse_with_resolver_config(&client_uri, ResolverConfig::cloudflare()).await.unwrap();
+    let options = ClientOptions::parse_with_resolver_config(&config.db_uri, ResolverConfig::cloudflare()).await.unwrap();
     let db_client = Client::with_options(options).unwrap();
 
-    let world_name = "world_088";
+    let world_name = config.world_name.as_str();
 
     let working_game_map: Option<GameMap>; // load_files_into_game_map(world_name).await;
     let storage_game_map: Option<GameMap>; // load_files_into_game_map(world_name).await;
@@ -295,6 +285,8 @@ async fn run_server(tx: Sender<AppData>)
             let working_game_map_reference= Arc::new(working_game_map);
             let storage_game_map_reference= Arc::new(storage_game_map);
             let tui_game_map_reference= storage_game_map_reference.clone();
+            // the gameplay and the chat share it, so their timestamps agree.
+            let clock : Arc<dyn Clock> = Arc::new(SystemClock);
 
             let (
                 rx_mc_client_gameplay,
@@ -304,9 +296,14 @@ async fn run_server(tx: Sender<AppData>)
                 rx_kc_client_gameplay ,
                 rx_cc_client_gameplay ,
                 tx_packets_gameplay_chat_clients,
+                tx_snapshots_gameplay_clients,
             ) =  clients_service::start_server(
                 working_game_map_reference.clone(), 
-                server_state.clone());
+                server_state.clone(),
+                config.network.udp_address(),
+                config.network.websocket_address(),
+                config.spectators.clone(),
+                config.rate_limit.get_config());
                 
 
             let (rx_me_gameplay_longterm,
@@ -326,27 +323,34 @@ async fn run_server(tx: Sender<AppData>)
                 rx_kc_client_gameplay,
                 working_game_map_reference.clone(), 
                 server_state.clone(),
-                tx_packets_gameplay_chat_clients.clone());
+                tx_packets_gameplay_chat_clients.clone(),
+                tx_snapshots_gameplay_clients,
+                config.gameplay_tick(),
+                clock.clone());
 
             let rx_ce_gameplay_webservice = chat_service::start_service(
                 rx_cc_client_gameplay,
                 working_game_map_reference.clone(), 
                 server_state.clone(),
-                tx_packets_gameplay_chat_clients);
+                tx_packets_gameplay_chat_clients,
+                config.chat_tick(),
+                clock);
 
             // realtime service sends the mapentity after updating the working copy, so it can be stored eventually
             let rx_me_saved_longterm_web= long_term_storage_service::world_service::start_server(
                 rx_me_gameplay_longterm,
                 storage_game_map_reference.clone(), 
                 server_state.clone(),
-                db_client.clone()
+                db_client.clone(),
+                std::time::Duration::from_secs(config.save.regions_secs)
             );
 
             long_term_storage_service::heroes_service::start_server(
                 rx_he_gameplay_longterm,
                 storage_game_map_reference.clone(), 
                 server_state.clone(),
-                db_client.clone()
+                db_client.clone(),
+                std::time::Duration::from_secs(config.save.heroes_secs)
             );
 
             // realtime service sends the mapentity after updating the working copy, so it can be stored eventually
@@ -354,7 +358,8 @@ async fn run_server(tx: Sender<AppData>)
                 rx_te_gameplay_longterm,
                 storage_game_map_reference.clone(), 
                 server_state.clone(),
-                db_client.clone()
+                db_client.clone(),
+                std::time::Duration::from_secs(config.save.towers_secs)
             );
 
             // realtime service sends the mapentity after updating the working copy, so it can be stored eventually
@@ -362,11 +367,15 @@ async fn run_server(tx: Sender<AppData>)
                 rx_ke_gameplay_longterm,
                 storage_game_map_reference.clone(), 
                 server_state.clone(),
-                db_client.clone()
+                db_client.clone(),
+                std::time::Duration::from_secs(config.save.kingdoms_secs)
             );
             
             web_service::start_server
             (
+                config.network.web_address(),
+                web_tls_config,
+                config.network.insecure_join,
                 presentation_data_cache,
                 working_game_map_reference, 
                 storage_game_map_reference, 
@@ -411,195 +420,6 @@ async fn run_server(tx: Sender<AppData>)
 }
 
 
-async fn load_definition_by_name<T>(file_name : String) -> (Vec<T>, Vec<u8>)
-where T: serde::de::DeserializeOwned + Definition
-{
-    let file_name = format!("definitions/{file_name}");
-    let mut data = Vec::<T>::new();
-    cli_log::info!("reading definition file {}", file_name);
-    let definition_versions_data = tokio::fs::read(file_name).await.unwrap();
-    let mut rdr = csv::Reader::from_reader(definition_versions_data.as_slice());
-    for result in rdr.deserialize() 
-    {
-        let record: T = result.unwrap();
-        data.push(record);
-    }
-    (data, definition_versions_data)
-}
-
-async fn load_definitions() -> (Definitions, DefinitionsData)
-{
-    let mut definition_versions = HashMap::new();
-
-    let file_name = format!("definition_versions.csv");
-    let definition_versions_result = load_definition_by_name::<DefinitionVersion>(file_name).await;
-
-    for entry in definition_versions_result.0
-    {
-        definition_versions.insert(entry.key.clone(), entry);
-    }
-
-    let file_name = format!("character_progression.csv");
-    let character_result = load_definition_by_name::<CharacterProgression>(file_name).await;
-
-    let file_name = format!("mob_progression.csv");
-    let mob_progression_result = load_definition_by_name::<MobProgression>(file_name).await;
-
-    let file_name = format!("props.csv");
-    let props_result = load_definition_by_name::<PropData>(file_name).await;
-
-    let file_name = format!("main_paths.csv");
-    let paths_result = load_definition_by_name::<MapPath>(file_name).await;
-
-    let file_name = format!("towers_difficulty.csv");
-    let towers_difficulty_result = load_definition_by_name::<TowerDifficulty>(file_name).await;
-
-    let file_name = format!("items.csv");
-    let items_result = load_definition_by_name::<Item>(file_name).await;
-
-    let file_name = format!("cards.csv");
-    let cards_result = load_definition_by_name::<Card>(file_name).await;
-
-    let file_name = format!("mobs.csv");
-    let mobs_result = load_definition_by_name::<MobData>(file_name).await;
-
-    let file_name = format!("buffs.csv");
-    let buffs_result = load_definition_by_name::<BuffData>(file_name).await;
-
-    let file_name = format!("weapons.csv");
-    let weapons_result = load_definition_by_name::<Weapon>(file_name).await;
-
-    let mut buffs_hash = HashMap::new();
-
-    for entry in &buffs_result.0
-    {
-        buffs_hash.insert(entry.id.clone(), entry.clone());
-    }
-
-    let mut mob_progression_by_mob = vec![Vec::new(); mobs_result.0.len()];
-
-    for item in mob_progression_result.0.iter().enumerate()
-    {
-        mob_progression_by_mob[item.1.mob as usize].push(item.1.clone());
-    }
-
-    let definitions = Definitions 
-    {
-        regions_by_id: get_regions_by_id(),
-        regions_by_code: get_regions_by_code(),
-        character_progression : character_result.0,
-        props : props_result.0,
-        mob_progression : mob_progression_result.0,
-        mob_progression_by_mob,
-        main_paths: paths_result.0,
-        towers_difficulty: towers_difficulty_result.0,
-        items: items_result.0,
-        cards :cards_result.0,
-        mobs: mobs_result.0,
-        buffs_by_code: buffs_result.0,
-        buffs : buffs_hash,
-        weapons : weapons_result.0,
-    };
-
-    let definitions_data = DefinitionsData
-    {
-        definition_versions,
-        character_progression_data : character_result.1,
-        mob_progression_data : mob_progression_result.1,
-        definition_versions_data : definition_versions_result.1,
-        props_data : props_result.1,
-        main_paths_data : paths_result.1,
-        towers_difficulty_data: towers_difficulty_result.1,
-        items_data :items_result.1,
-        cards_data: cards_result.1,
-        mobs_data: mobs_result.1,
-        buffs_data: buffs_result.1,
-        weapons_data: weapons_result.1,
-    };
-
-    (definitions, definitions_data)
-}
-
-
-fn load_regions_data_into_game_map(
-    regions_stored_data : &HashMap<TetrahedronId, StoredRegion>
-) 
-->  (
-        Vec<(TetrahedronId, HashMap<TetrahedronId, MapEntity>)>, 
-        Vec<(TetrahedronId, Vec<u8>)>
-    ) 
-{
-    let mut regions_data = Vec::<(TetrahedronId, HashMap<TetrahedronId, MapEntity>)>::new();
-    let mut regions_binary_data = Vec::<(TetrahedronId, Vec<u8>)>::new();
-
-    let mut count = 0;
-    let mut region_count = 0;
-    let region_total = regions_stored_data.len();
-
-    for region in regions_stored_data.iter()
-    {
-        region_count += 1;
-        // cli_log::info!("decoding region progress {region_count}/{region_total} tiles {count}");
-
-        let region_object_id = region.1.id.clone();
-        let binary_data: Vec<u8> = match region.1.compressed_data.clone() 
-        {
-            bson::Bson::Binary(binary) => binary.bytes,
-            _ => panic!("Expected Bson::Binary"),
-        };
-        let region_id = region.0;
-        let data : &[u8] = &binary_data;
-
-
-
-        let decoder = ZlibDecoder::new(data);
-
-        let decoded_data_result :  Result<Vec<u8>, _> = decoder.bytes().collect();
-        let decoded_data = decoded_data_result.unwrap();
-        let tiles : &[u8] = &decoded_data;
-        let size = tiles.len();
-
-        let mut buffer = [0u8;MAP_ENTITY_SIZE as usize];
-        let mut start = 0;
-        let mut end = MapEntity::get_size() as usize;
-
-        // cli_log::info!("initialy for region {} {}",region_id, all_tiles.len());
-
-        let mut region_tiles : HashMap<TetrahedronId, MapEntity> = HashMap::new();
-
-        loop {
-            buffer.copy_from_slice(&tiles[start..end]);
-            let mut map_entity = MapEntity::from_bytes(&buffer);
-            // all map entities will have the object id of the database region, this value is the same for all map entities in a region
-            map_entity.object_id = region_object_id;
-            
-            if map_entity.id.to_string() == "j202020303" {
-                cli_log::info!("Found saved entity  {:?} " , map_entity);
-            }
-            region_tiles.insert(map_entity.id.clone(), map_entity);
-
-
-            start = end;
-            end = end + MapEntity::get_size();
-
-            if end > size
-            {
-                break;
-            }
-            // counting mapentities
-            count += 1;
-
-        }
-
-        regions_binary_data.push((region_id.clone(), binary_data));
-        regions_data.push((region_id.clone(), region_tiles));
-    }
-
-    cli_log::info!("finished loading data, starting services. regions: {} with {} tiles",region_total, count);
-    (regions_data, regions_binary_data)
-    // GameMap::new(regions_data)
-}
-
 async fn get_compressed_tiles_data_from_file(world_id : &str, region_id : String) -> (Vec<TetrahedronId>, Vec<u8>)
 {
     let file_name = format!("../../map_initial_data/{}_{}_props.bytes",world_id, region_id);
@@ -661,7 +481,7 @@ mod tests {
     use mongodb::bson::doc;
     use flate2::{write::ZlibEncoder, Compression};
 
-    use crate::load_regions_data_into_game_map;
+    use game_server::long_term_storage_service::world_service::load_regions_data_into_game_map;
 
     #[tokio::test]
     async fn test_insert() {
@@ -3,12 +3,13 @@ use std::{sync::{Arc, atomic::{AtomicU64, AtomicU16}}, collections::{HashMap, Ha
 use bson::oid::ObjectId;
 use tokio::sync::Mutex;
 
-use crate::{definitions::definitions_container::Definitions, hero::hero_entity::HeroEntity, kingdom::kingdom_entity::KingdomEntity, long_term_storage_service::db_region::StoredRegion, mob::mob_entity::MobEntity, tower::tower_entity::TowerEntity};
+use crate::{clients_service::{connection::ConnectionId, region_subscriptions::RegionSubscriptions}, definitions::definitions_container::Definitions, gameplay_service::{delta_encoder::DeltaTracker, packets_history::PacketsHistory}, hero::hero_entity::HeroEntity, kingdom::kingdom_entity::KingdomEntity, long_term_storage_service::db_region::StoredRegion, protocols::packet_auth::SessionKeys, mob::mob_entity::MobEntity, tower::tower_entity::TowerEntity};
 
 use self::{map_entity::MapEntity, tetrahedron_id::TetrahedronId};
 
 pub mod map_entity;
 pub mod tetrahedron_id;
+pub mod tile_geometry;
 
 
 pub struct GameMap
@@ -26,6 +27,11 @@ pub struct GameMap
     pub character : Arc<Mutex<HashMap<u16, HeroEntity>>>,
     pub towers : Arc<Mutex<HashMap<TetrahedronId, TowerEntity>>>,
     pub kingdomes : Arc<Mutex<HashMap<TetrahedronId, KingdomEntity>>>,
+    pub packets_history : PacketsHistory,
+    pub delta_tracker : DeltaTracker,
+    pub session_keys : SessionKeys,
+    // regions each connection receives, for heroes they come from the hero position, never from what the client says.
+    pub region_subscriptions : RegionSubscriptions,
 }
 
 impl GameMap 
@@ -100,9 +106,72 @@ impl GameMap
             towers : Arc::new(Mutex::new(towers)),
             kingdomes : Arc::new(Mutex::new(kingdomes)),
             stored_regions: arc_stored_regions,
+            packets_history: PacketsHistory::new(),
+            delta_tracker: DeltaTracker::new(),
+            session_keys: SessionKeys::new(),
+            region_subscriptions: RegionSubscriptions::new(),
         }
     }
 
+    // an empty world with the real definitions, heroes and towers go straight into character and towers.
+    #[cfg(test)]
+    pub fn for_tests(definitions : Definitions) -> GameMap
+    {
+        GameMap::new(None, "test_world".to_string(), definitions, Vec::new(), Vec::new(), HashMap::new(), HashMap::new(), HashMap::new())
+    }
+
+    // the region of the tile and the three regions that share an edge with it.
+    pub fn get_interest_regions(&self, position : &TetrahedronId) -> [u16;4]
+    {
+        let mut interest_regions = [0u16;4];
+        // positions are lod 9 tiles, anything else can't be in a region.
+        if position.lod < 9
+        {
+            return interest_regions;
+        }
+
+        let region = self.get_parent(position);
+        let Some(region_code) = self.definitions.regions_by_id.get(&region) else { return interest_regions };
+        interest_regions[0] = *region_code;
+
+        let neighbours = tile_geometry::get_edge_neighbours(&region)
+            .into_iter()
+            .flatten()
+            .filter_map(|neighbour| self.definitions.regions_by_id.get(&neighbour).copied());
+
+        for (slot, neighbour_code) in interest_regions.iter_mut().skip(1).zip(neighbours)
+        {
+            *slot = neighbour_code;
+        }
+        interest_regions
+    }
+
+    pub async fn update_interest_regions(&self, hero_id : u16, position : &TetrahedronId)
+    {
+        let interest_regions = self.get_interest_regions(position);
+        self.region_subscriptions.update_hero_regions(hero_id, &interest_regions).await;
+    }
+
+    // used when a connection is created, before the hero moves for the first time.
+    pub async fn add_hero_connection(&self, id : ConnectionId, hero_id : u16)
+    {
+        let position = self.character.lock().await.get(&hero_id).map(|hero| hero.position.clone());
+        let interest_regions = position.map(|position| self.get_interest_regions(&position)).unwrap_or_default();
+        self.region_subscriptions.add_hero_connection(id, hero_id, &interest_regions).await;
+    }
+
+    // spectator cameras start where the hero is, then move with it like the hero connection.
+    pub async fn follow_hero(&self, id : ConnectionId, hero_id : Option<u16>)
+    {
+        let position = match hero_id
+        {
+            Some(hero_id) => self.character.lock().await.get(&hero_id).map(|hero| hero.position.clone()),
+            None => None,
+        };
+        let interest_regions = position.map(|position| self.get_interest_regions(&position)).unwrap_or_default();
+        self.region_subscriptions.follow_hero(id, hero_id, &interest_regions).await;
+    }
+
     fn get_parent(&self, tetrahedron_id : &TetrahedronId) -> TetrahedronId
     {
         tetrahedron_id.get_parent(7)
@@ -166,3 +235,44 @@ pub fn get_regions(initial : TetrahedronId, target_lod : u8, regions : &mut Vec<
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn interest_regions_cross_the_parent_boundary()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = GameMap::for_tests(definitions);
+        let code = |id : &TetrahedronId| map.definitions.regions_by_id[id];
+
+        // a corner region of a corner region, only the middle sibling shares an edge with it.
+        let region = TetrahedronId::from_string("a00");
+        let interest_regions = map.get_interest_regions(&TetrahedronId::from_string("a000000000"));
+        assert_eq!(interest_regions[0], code(&region));
+        let parent = region.get_parent(1);
+        assert!(interest_regions.contains(&code(&parent.subdivide(2))));
+        assert!(!interest_regions.contains(&code(&parent.subdivide(1))));
+        assert!(!interest_regions.contains(&code(&parent.subdivide(3))));
+
+        // the roads in main_paths.csv mostly join the middles of lod 3 tiles, when two of those are next
+        // to each other in different regions the regions share an edge.
+        let mut crossings = 0;
+        for path in &map.definitions.main_paths
+        {
+            let origin = TetrahedronId::from_string(&path.origin);
+            let destination = TetrahedronId::from_string(&path.destination);
+            let next_to_each_other = tile_geometry::get_distance_in_tiles(&origin, &destination).is_some_and(|distance| distance < 48.0);
+            if !path.origin.ends_with("222222") || !path.destination.ends_with("222222") || !next_to_each_other || map.get_parent(&origin) == map.get_parent(&destination)
+            {
+                continue;
+            }
+            crossings += 1;
+            assert!(map.get_interest_regions(&origin).contains(&code(&map.get_parent(&destination))), "{} {}", path.origin, path.destination);
+        }
+        assert!(crossings > 100, "{crossings}");
+
+        assert_eq!(map.get_interest_regions(&region), [0u16; 4]);
+    }
+}
@@ -43,9 +43,11 @@ impl TetrahedronId {
         }
     }
 
+    // ids from clients can name areas or children the planet doesn't have, get_parent takes them anyway.
     pub fn is_valid_for_lod(&self, lod : u8) -> bool
     {
-        self.lod == lod
+        let children = 4u32.checked_pow(lod as u32);
+        self.lod == lod && self.area < 20 && children.is_some_and(|children| self.id < children)
     }
 
     pub fn subdivide(&self, child_index : u8) -> TetrahedronId
@@ -0,0 +1,248 @@
+use super::tetrahedron_id::TetrahedronId;
+
+// the planet is an icosahedron pushed out to a sphere of radius 1, the areas are its 20 faces.
+// areas a to e go around the top vertex, f to o make the band in the middle, alternating between a face
+// under the top ring and one over the bottom ring, p to t go around the bottom vertex.
+// every lod splits a triangle in four, children 0, 1 and 3 keep the first, second and third corner and 2 is the one in the middle.
+// the client source isn't in this repo, the layout is the one main_paths.csv agrees with: roads only cross
+// between areas that share an edge and the road ends ending in 222222 sit in the middle of their lod 3 tile.
+// the tests at the bottom check both against the file.
+
+const ICOSAHEDRON_VERTICES : [[f64; 3]; 12] = [
+    [0.0, 0.0, 1.0],
+    // the top ring.
+    [0.894_427_190_999_915_9, 0.0, 0.447_213_595_499_957_9],
+    [0.276_393_202_250_021_1, 0.850_650_808_352_039_9, 0.447_213_595_499_957_9],
+    [-0.723_606_797_749_978_8, 0.525_731_112_119_133_7, 0.447_213_595_499_957_9],
+    [-0.723_606_797_749_979, -0.525_731_112_119_133_5, 0.447_213_595_499_957_9],
+    [0.276_393_202_250_020_8, -0.850_650_808_352_04, 0.447_213_595_499_957_9],
+    // the bottom ring, turned a tenth of a turn from the top one.
+    [0.723_606_797_749_978_9, 0.525_731_112_119_133_6, -0.447_213_595_499_957_9],
+    [-0.276_393_202_250_021, 0.850_650_808_352_04, -0.447_213_595_499_957_9],
+    [-0.894_427_190_999_915_9, 0.0, -0.447_213_595_499_957_9],
+    [-0.276_393_202_250_021_1, -0.850_650_808_352_039_9, -0.447_213_595_499_957_9],
+    [0.723_606_797_749_978_8, -0.525_731_112_119_133_8, -0.447_213_595_499_957_9],
+    [0.0, 0.0, -1.0],
+];
+
+const ICOSAHEDRON_FACES : [[usize; 3]; 20] = [
+    [0, 1, 2], [0, 2, 3], [0, 3, 4], [0, 4, 5], [0, 5, 1],
+    [1, 2, 6], [2, 6, 7], [2, 3, 7], [3, 7, 8], [3, 4, 8],
+    [4, 8, 9], [4, 5, 9], [5, 9, 10], [5, 1, 10], [1, 10, 6],
+    [11, 6, 7], [11, 7, 8], [11, 8, 9], [11, 9, 10], [11, 10, 6],
+];
+
+// the lod of the tiles heroes and mobs stand on, distances are measured in tiles of this lod.
+pub const TILE_LOD : u8 = 9;
+
+// angle between two corners of an area, atan(2).
+const AREA_EDGE_ANGLE : f64 = 1.107_148_717_794_090_4;
+
+type Point = [f64; 3];
+
+fn normalize(point : Point) -> Point
+{
+    let length = (point[0] * point[0] + point[1] * point[1] + point[2] * point[2]).sqrt();
+    [point[0] / length, point[1] / length, point[2] / length]
+}
+
+fn get_midpoint(a : &Point, b : &Point) -> Point
+{
+    normalize([a[0] + b[0], a[1] + b[1], a[2] + b[2]])
+}
+
+fn get_area_corners(area : usize) -> [Point; 3]
+{
+    ICOSAHEDRON_FACES[area].map(|vertex| normalize(ICOSAHEDRON_VERTICES[vertex]))
+}
+
+// the corners of the four children, by child index.
+fn split(corners : &[Point; 3]) -> [[Point; 3]; 4]
+{
+    let [a, b, c] = *corners;
+    let ab = get_midpoint(&a, &b);
+    let bc = get_midpoint(&b, &c);
+    let ca = get_midpoint(&c, &a);
+    [[a, ab, ca], [ab, b, bc], [bc, ca, ab], [ca, bc, c]]
+}
+
+// positive when the point is inside, the lowest of the three sides so the best match wins on the edges.
+fn get_containment(corners : &[Point; 3], point : &Point) -> f64
+{
+    let side = |a : &Point, b : &Point, point : &Point| {
+        let normal = [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]];
+        normal[0] * point[0] + normal[1] * point[1] + normal[2] * point[2]
+    };
+    let [a, b, c] = corners;
+    // the areas don't all go round the same way, turn every side to face the inside first.
+    let orientation = side(a, b, c).signum();
+    f64::min(side(a, b, point) * orientation, f64::min(side(b, c, point) * orientation, side(c, a, point) * orientation))
+}
+
+// None for ids that can't be on the planet.
+pub fn get_tile_corners(tile_id : &TetrahedronId) -> Option<[Point; 3]>
+{
+    if tile_id.area as usize >= ICOSAHEDRON_FACES.len()
+    {
+        return None;
+    }
+    let mut corners = get_area_corners(tile_id.area as usize);
+
+    // the first child index is the lowest base 4 digit of the id.
+    let mut remaining = tile_id.id;
+    for _ in 0..tile_id.lod
+    {
+        corners = split(&corners)[(remaining % 4) as usize];
+        remaining /= 4;
+    }
+    Some(corners)
+}
+
+// the tile of that lod with the point inside, points right on an edge get one of the two sides.
+pub fn get_tile_at(point : &Point, lod : u8) -> TetrahedronId
+{
+    let best = |candidates : &[[Point; 3]]| (0..candidates.len())
+        .max_by(|a, b| get_containment(&candidates[*a], point).total_cmp(&get_containment(&candidates[*b], point)))
+        .unwrap_or_default();
+
+    let areas : Vec<[Point; 3]> = (0..ICOSAHEDRON_FACES.len()).map(get_area_corners).collect();
+    let area = best(&areas);
+    let mut tile = TetrahedronId { area: area as u8, id: 0, lod: 0 };
+    let mut corners = areas[area];
+    for _ in 0..lod
+    {
+        let children = split(&corners);
+        let child_index = best(&children);
+        tile = tile.subdivide(child_index as u8);
+        corners = children[child_index];
+    }
+    tile
+}
+
+// the three tiles of the same lod that share an edge with this one, they can be in another parent or area.
+pub fn get_edge_neighbours(tile_id : &TetrahedronId) -> Option<[TetrahedronId; 3]>
+{
+    let corners = get_tile_corners(tile_id)?;
+    let center = get_tile_center(tile_id)?;
+    Some([0, 1, 2].map(|edge| {
+        let middle = get_midpoint(&corners[edge], &corners[(edge + 1) % 3]);
+        // as far past the edge as the center is before it, that is well inside the tile on the other side.
+        let across = normalize([2.0 * middle[0] - center[0], 2.0 * middle[1] - center[1], 2.0 * middle[2] - center[2]]);
+        get_tile_at(&across, tile_id.lod)
+    }))
+}
+
+pub fn get_tile_center(tile_id : &TetrahedronId) -> Option<Point>
+{
+    let [a, b, c] = get_tile_corners(tile_id)?;
+    Some(normalize([a[0] + b[0] + c[0], a[1] + b[1] + c[1], a[2] + b[2] + c[2]]))
+}
+
+// distance along the surface between the centers of two tiles, one unit is the side of a tile at TILE_LOD.
+pub fn get_distance_in_tiles(from : &TetrahedronId, to : &TetrahedronId) -> Option<f32>
+{
+    let from = get_tile_center(from)?;
+    let to = get_tile_center(to)?;
+    // acos loses too much for tiles this small, atan2 keeps it.
+    let cross = [
+        from[1] * to[2] - from[2] * to[1],
+        from[2] * to[0] - from[0] * to[2],
+        from[0] * to[1] - from[1] * to[0],
+    ];
+    let sine = (cross[0] * cross[0] + cross[1] * cross[1] + cross[2] * cross[2]).sqrt();
+    let cosine = from[0] * to[0] + from[1] * to[1] + from[2] * to[2];
+    let tile_angle = AREA_EDGE_ANGLE / 2f64.powi(TILE_LOD as i32);
+    Some((sine.atan2(cosine) / tile_angle) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbour_tiles_are_about_one_tile_away()
+    {
+        let tile = TetrahedronId::from_string("a012301230");
+        assert_eq!(get_distance_in_tiles(&tile, &tile), Some(0.0));
+
+        // the middle child touches the other three.
+        let parent = tile.get_parent(1);
+        let middle = parent.subdivide(2);
+        for corner in [0, 1, 3]
+        {
+            let distance = get_distance_in_tiles(&middle, &parent.subdivide(corner)).unwrap();
+            assert!(distance > 0.4 && distance < 0.8, "{distance}");
+        }
+
+        // the corner tiles of two areas that share the top vertex.
+        let distance = get_distance_in_tiles(&TetrahedronId::from_string("a000000000"), &TetrahedronId::from_string("b000000000")).unwrap();
+        assert!(distance < 1.5, "{distance}");
+
+        // the top and the bottom vertex.
+        let distance = get_distance_in_tiles(&TetrahedronId::from_string("a000000000"), &TetrahedronId::from_string("t000000000")).unwrap();
+        assert!(distance > 1400.0, "{distance}");
+
+        assert_eq!(get_tile_center(&TetrahedronId { area: 20, id: 0, lod: 9 }), None);
+    }
+
+    #[test]
+    fn edge_neighbours_go_both_ways()
+    {
+        let tile = TetrahedronId::from_string("a012301230");
+        assert_eq!(get_tile_at(&get_tile_center(&tile).unwrap(), tile.lod), tile);
+
+        // the middle child touches its three siblings and nothing else.
+        let parent = tile.get_parent(1);
+        let mut neighbours = get_edge_neighbours(&parent.subdivide(2)).unwrap().to_vec();
+        neighbours.sort_by_key(|neighbour| neighbour.id);
+        assert_eq!(neighbours, vec![parent.subdivide(0), parent.subdivide(1), parent.subdivide(3)]);
+
+        for area in 0..20
+        {
+            for id in 0..16
+            {
+                let tile = TetrahedronId { area, id, lod: 2 };
+                for neighbour in get_edge_neighbours(&tile).unwrap()
+                {
+                    assert_ne!(neighbour, tile);
+                    assert!(get_edge_neighbours(&neighbour).unwrap().contains(&tile), "{tile} {neighbour}");
+                    let distance = get_distance_in_tiles(&tile, &neighbour).unwrap() / 2f32.powi(TILE_LOD as i32 - 2);
+                    assert!(distance > 0.4 && distance < 0.8, "{tile} {neighbour} {distance}");
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn roads_only_cross_between_areas_that_share_an_edge()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let mut crossings = 0;
+        for path in &definitions.main_paths
+        {
+            let origin = TetrahedronId::from_string(&path.origin).get_parent(TILE_LOD as usize);
+            let destination = TetrahedronId::from_string(&path.destination).get_parent(TILE_LOD as usize);
+            if origin != destination
+            {
+                crossings += 1;
+                assert!(get_edge_neighbours(&origin).unwrap().contains(&destination), "{} {}", path.origin, path.destination);
+            }
+        }
+        assert!(crossings > 200, "{crossings}");
+    }
+
+    #[tokio::test]
+    async fn road_ends_sit_in_the_middle_of_their_cells()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let mut lengths : Vec<f32> = definitions.main_paths.iter()
+            .map(|path| get_distance_in_tiles(&TetrahedronId::from_string(&path.origin), &TetrahedronId::from_string(&path.destination)).unwrap())
+            .collect();
+        lengths.sort_by(f32::total_cmp);
+
+        // most roads join the middles of two lod 3 tiles next to each other, that is about 37 tiles,
+        // with another middle child or corners in the wrong place the typical road is twice as long.
+        let median = lengths[lengths.len() / 2];
+        assert!(median > 30.0 && median < 45.0, "{median}");
+    }
+}
@@ -1,31 +1,21 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
 use crate::{hero::hero_command::{HeroCommand, HeroCommandInfo, HeroMovement}, gaia_mpsc::GaiaSender};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_tx : &GaiaSender<HeroCommand>)
+    channel_tx : &GaiaSender<HeroCommand>) -> Result<(), ProtocolError>
 {
     //1 - protocolo 1 bytes
     //2 - id 8 bytes
     // the rest depends on the code.
-    let mut start = 1;
-    let mut end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let player_id = header.player_id;
 
-    start = end;
-    end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-    start = end;
-    end = start + 1;
-    let _faction = data[start];
-
-    start = end;
-    end = start + 1;
-    let action = data[start];
-    start = end;
+    let action = reader.read_u8()?;
 
     let character_command = HeroCommand
     {
@@ -34,4 +24,5 @@ pub async fn process(
     };
 
     channel_tx.send(character_command).await.unwrap();
+    Ok(())
 }
\ No newline at end of file
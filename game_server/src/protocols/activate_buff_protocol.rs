@@ -2,25 +2,16 @@ use tokio::net::UdpSocket;
 use tokio::sync::mpsc::Sender;
 
 use crate::{hero::hero_command::{HeroCommand, HeroCommandInfo}, gaia_mpsc::GaiaSender};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
-pub async fn process(data : &[u8],  channel_player_tx : &GaiaSender<HeroCommand>)
+pub async fn process(data : &[u8],  channel_player_tx : &GaiaSender<HeroCommand>) -> Result<(), ProtocolError>
 {
-    let mut start = 1;
-    let mut end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let player_id = header.player_id;
 
-    start = end;
-    end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-    start = end;
-    end = start + 1;
-    let _faction = data[start];
-
-    start = end;
-    end = start + 4;
-    let card_id = u32::from_le_bytes(data[start..end].try_into().unwrap()); // 4 bytes
+    let card_id = reader.read_u32()?; // 4 bytes
 
     let command = HeroCommand
     {
@@ -31,4 +22,5 @@ pub async fn process(data : &[u8],  channel_player_tx : &GaiaSender<HeroCommand>
     cli_log::info!("got a command {:?}", command);
 
     channel_player_tx.send(command).await.unwrap();
+    Ok(())
 }
\ No newline at end of file
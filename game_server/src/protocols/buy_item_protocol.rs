@@ -1,37 +1,23 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
 use crate::{hero::hero_command::{HeroCommand, HeroCommandInfo}, gaia_mpsc::GaiaSender};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 // we cant do the same is inventory request, because selling modifies the faction inventory and we need to propagate those changes.
 
 pub async fn process(
      data : &[u8],
-    channel_player_tx : &GaiaSender<HeroCommand>)
+    channel_player_tx : &GaiaSender<HeroCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
+        let faction = header.faction;
 
-        start = end;
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-        start = end;
-        end = start + 1;
-        let faction = data[start];
-
-        start = end;
-        end = start + 4;
-        let item_id = u32::from_le_bytes(data[start..end].try_into().unwrap()); 
-
-        start = end;
-        end = start + 1;
-        let item_type = data[start]; 
-
-        start = end;
-        end = start + 2;
-        let amount = u16::from_le_bytes(data[start..end].try_into().unwrap()); 
+        let item_id = reader.read_u32()?;
+        let item_type = reader.read_u8()?;
+        let amount = reader.read_u16()?;
 
         let command = HeroCommand{
             player_id,
@@ -41,4 +27,5 @@ pub async fn process(
         cli_log::info!("got a command {:?}", command);
 
         channel_player_tx.send(command).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -1,34 +1,19 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{gaia_mpsc::GaiaSender, map::{map_entity::{MapCommand, MapCommandInfo}, tetrahedron_id::TetrahedronId}, mob::mob_command::{ControlMobData, MobCommand}};
+use crate::{gaia_mpsc::GaiaSender, map::map_entity::{MapCommand, MapCommandInfo}, mob::mob_command::{ControlMobData, MobCommand}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_mob_tx : &GaiaSender<MobCommand>)
+    channel_mob_tx : &GaiaSender<MobCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
 
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 1;
-        let _faction = data[start];
-        start = end;
-
-        end = start + 4;
-        let mob_id = u32::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-        // start = end;
+        let mob_id = reader.read_u32()?;
+        let tile_id = reader.read_tetrahedron_id()?;
 
         let mob_action = MobCommand::ControlMob(ControlMobData
         {
@@ -40,4 +25,5 @@ pub async fn process(
         // cli_log::info!("got a {:?}", map_action);
 
         channel_mob_tx.send(mob_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -7,26 +7,19 @@ use crate::gameplay_service::generic_command::GenericCommand;
 use crate::map::GameMap;
 use crate::hero::hero_command::{HeroCommand, HeroMovement};
 use crate::protocols::inventory_request_protocol::pack_inventory;
+use super::packet_reader::{PacketReader, ProtocolError};
+use crate::clients_service::connection::ConnectionId;
 
 pub async fn process_request(
-    player_address : std::net::SocketAddr, 
-    is_udp: bool,
+    connection : ConnectionId,
     tx_gc_clients_gameplay : &GaiaSender<GenericCommand>,
     data : &[u8],
-    map : &Arc<GameMap>)
+    map : &Arc<GameMap>) -> Result<(), ProtocolError>
 {
     cli_log::info!("---- card crafting request");
-    let start = 1;
-    let end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 1;
-    let _faction = data[start];
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let player_id = header.player_id;
 
     let mut player_entities = map.character.lock().await;
     let player_option = player_entities.get_mut(&player_id);
@@ -44,11 +37,12 @@ pub async fn process_request(
             drop(player_entities); // we drop the lock asap, we can do what we want later.
 
             let compressed_bytes = pack_inventory(inventory, card_inventory, weapon_inventory, version);
-            tx_gc_clients_gameplay.send(GenericCommand{player_address, is_udp, data : Bytes::from(compressed_bytes)}).await.unwrap();
+            tx_gc_clients_gameplay.send(GenericCommand{connection, data : Bytes::from(compressed_bytes), reliable : true}).await.unwrap();
         }
     }
     else 
     {
         cli_log::info!("Inventory Request - player not found {}" , player_id);
     };
+    Ok(())
 }
\ No newline at end of file
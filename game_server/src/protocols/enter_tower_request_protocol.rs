@@ -2,40 +2,29 @@
 use std::sync::Arc;
 
 use crate::gaia_mpsc::GaiaSender;
-use crate::map::tetrahedron_id::TetrahedronId;
 use crate::map::GameMap;
 use crate::hero::hero_command::HeroCommand;
+use super::packet_reader::{PacketReader, ProtocolError};
+use crate::clients_service::connection::ConnectionId;
 
 pub async fn process_request(
-    player_address : std::net::SocketAddr, 
+    connection : ConnectionId,
     hero_channel_tx : &GaiaSender<HeroCommand>,
     data : &[u8],
-    map : &Arc<GameMap>)
+    map : &Arc<GameMap>) -> Result<(), ProtocolError>
 {
     cli_log::info!("---- enter or exit tower");
-    let start = 1;
-    let end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-    let start = end;
-
-    let end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-    let start = end;
-
-
-    let end = start + 6;
-    let mut buffer = [0u8;6];
-    buffer.copy_from_slice(&data[start..end]);
-    let tile_id = TetrahedronId::from_bytes(&buffer);
-    let start = end;
-
-    let end = start + 1;
-    let faction = data[start];
-    let start = end;
+    // this packet has the tile before the faction, so we can't use read_header
+    let mut reader = PacketReader::new(data);
+    let _player_session_id = reader.read_u64()?;
+    let player_id = reader.read_u16()?;
+    let tile_id = reader.read_tetrahedron_id()?;
+    let faction = reader.read_u8()?;
 
     hero_channel_tx.send(HeroCommand 
         {
             player_id,
             info: crate::hero::hero_command::HeroCommandInfo::EnterTower(tile_id, faction) 
         }).await.unwrap();
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use crate::gameplay_service::delta_encoder;
+use crate::map::GameMap;
+use super::packet_reader::{PacketReader, ProtocolError};
+
+pub const MAX_ACKS_PER_REQUEST : u8 = 64;
+pub const ACK_ENTRY_SIZE : usize = 9;
+
+// count (1 byte) and then count entries of data type (1 byte), entity id (6 bytes, padded) and version (2 bytes).
+// the first ack also switches the client to deltas, an empty ack is enough for that.
+pub async fn process(data : &[u8], map : &Arc<GameMap>) -> Result<(), ProtocolError>
+{
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let count = reader.read_u8()?;
+
+    if count > MAX_ACKS_PER_REQUEST
+    {
+        return Err(ProtocolError::InvalidField("count"));
+    }
+
+    reader.ensure(count as usize * ACK_ENTRY_SIZE)?;
+
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count
+    {
+        let data_type = reader.read_u8()?;
+        if delta_encoder::get_layout(data_type).is_none()
+        {
+            return Err(ProtocolError::InvalidField("data_type"));
+        }
+        let id = reader.read_bytes::<6>()?;
+        let version = reader.read_u16()?;
+        entries.push(((data_type, id), version));
+    }
+
+    map.delta_tracker.ack(header.player_id, header.session_id, &entries).await;
+    Ok(())
+}
@@ -1,37 +1,22 @@
 
 use crate::gaia_mpsc::GaiaSender;
-use crate::map::tetrahedron_id::TetrahedronId;
 use crate::hero::hero_command::HeroCommand;
 use crate::tower::{TowerCommand, TowerCommandInfo};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 pub async fn process_request(
     hero_channel_tx : &GaiaSender<HeroCommand>,
     tower_channel_tx : &GaiaSender<TowerCommand>,
-    data : &[u8])
+    data : &[u8]) -> Result<(), ProtocolError>
 {
     cli_log::info!("---- enter or exit tower");
-    let start = 1;
-    let end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-    let start = end;
-
-    let end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-    let start = end;
-
-    let end = start + 6;
-    let mut buffer = [0u8;6];
-    buffer.copy_from_slice(&data[start..end]);
-    let tile_id = TetrahedronId::from_bytes(&buffer);
-    let start = end;
-
-    let end = start + 1;
-    let faction = data[start];
-    let start = end;
-
-    let end = start + 1;
-    let points = data[start];
-    let start = end;
+    // same layout as enter tower, the tile goes before the faction
+    let mut reader = PacketReader::new(data);
+    let _player_session_id = reader.read_u64()?;
+    let player_id = reader.read_u16()?;
+    let tile_id = reader.read_tetrahedron_id()?;
+    let faction = reader.read_u8()?;
+    let points = reader.read_u8()?;
 
     hero_channel_tx.send(HeroCommand 
         {
@@ -48,4 +33,5 @@ pub async fn process_request(
     cli_log::info!("got a {:?}", tower_action);
 
     tower_channel_tx.send(tower_action).await.unwrap();
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,21 @@
+use crate::clients_service::fragmentation::{Reassembler, MAX_CLIENT_FRAGMENT_PAYLOAD};
+use super::packet_reader::{PacketReader, ProtocolError};
+
+// message id (2 bytes), index (1 byte), count (1 byte) and then a piece of the message.
+// the message itself is a whole packet, protocol and header included, without the auth trailer.
+pub fn process(data : &[u8], reassembler : &mut Reassembler) -> Result<Option<Vec<u8>>, ProtocolError>
+{
+    let mut reader = PacketReader::new(data);
+    let _header = reader.read_header()?;
+    let message_id = reader.read_u16()?;
+    let index = reader.read_u8()?;
+    let count = reader.read_u8()?;
+    let chunk = &data[reader.offset()..];
+    // a bigger one wouldn't fit in a datagram once signed, only a broken client sends it.
+    if chunk.len() > MAX_CLIENT_FRAGMENT_PAYLOAD
+    {
+        return Err(ProtocolError::InvalidField("fragment_size"));
+    }
+
+    reassembler.push(message_id, index, count, chunk, std::time::Instant::now())
+}
@@ -1,26 +1,37 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{hero::hero_command::{HeroCommand, HeroCommandInfo, HeroMovement}, gaia_mpsc::GaiaSender};
+use std::sync::Arc;
+
+use crate::{hero::hero_command::{HeroCommand, HeroCommandInfo}, gaia_mpsc::GaiaSender};
+use super::packet_reader::{PacketReader, ProtocolError};
+use super::protocol_version::{self, RejectReason};
+use crate::clients_service::connection::{ConnectionId, ConnectionRegistry};
 
 
 pub async fn process(
-     data : &[u8],
-    channel_tx : &GaiaSender<HeroCommand>)
+    connection : ConnectionId,
+    data : &[u8],
+    connections : &Arc<ConnectionRegistry>,
+    channel_tx : &GaiaSender<HeroCommand>) -> Result<(), ProtocolError>
 {
     //1 - protocolo 1 bytes
     //2 - id 8 bytes
     // the rest depends on the code.
-    let mut start = 1;
-    let mut end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-    start = end;
-    end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let player_id = header.player_id;
 
-    start = end;
-    end = start + 1;
-    let _faction = data[start];
+    // greet is the first thing a client sends, so this is where we check it understands our packets.
+    let version = reader.read_u16()?;
+    if !protocol_version::is_supported(version)
+    {
+        // sent right away, the connection is gone once the disconnect is done.
+        let rejection = protocol_version::build_rejection(RejectReason::UnsupportedProtocolVersion);
+        connections.send_to(connection, rejection, false).await;
+        connections.disconnect(connection, header.session_id, channel_tx).await;
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    connections.set_protocol_version(connection, version).await;
 
     let character_command = HeroCommand
     {
@@ -29,4 +40,48 @@ pub async fn process(
     };
 
     channel_tx.send(character_command).await.unwrap();
-}
\ No newline at end of file
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::clients_service::connection::RecordingTransport;
+    use crate::map::GameMap;
+    use crate::protocols::Protocol;
+    use crate::{gaia_mpsc, ServerChannels, ServerState};
+
+    fn greet(version : u16) -> Vec<u8>
+    {
+        let mut data = vec![Protocol::Greet as u8];
+        data.extend_from_slice(&9u64.to_le_bytes());
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(&version.to_le_bytes());
+        data
+    }
+
+    #[tokio::test]
+    async fn unsupported_clients_lose_their_session()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        let server_state = Arc::new(ServerState::new(None));
+        let (tx_hc, mut rx_hc) = gaia_mpsc::channel::<HeroCommand>(10, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let connections = Arc::new(ConnectionRegistry::new(map.clone(), server_state.clone()));
+        let connection = ConnectionId::websocket("127.0.0.1:5000".parse().unwrap());
+        let transport = RecordingTransport::default();
+        map.logged_in_players[3].store(9, std::sync::atomic::Ordering::Relaxed);
+        connections.register(connection, 3, 9, 1, Box::new(transport.clone())).await;
+
+        process(connection, &greet(protocol_version::PROTOCOL_VERSION), &connections, &tx_hc).await.unwrap();
+        assert_eq!(connections.get_protocol_version(connection).await, Some(protocol_version::PROTOCOL_VERSION));
+        assert!(matches!(rx_hc.try_recv().unwrap().info, HeroCommandInfo::Greet()));
+
+        assert!(matches!(process(connection, &greet(0), &connections, &tx_hc).await, Err(ProtocolError::UnsupportedVersion(0))));
+        assert_eq!(transport.sent.lock().unwrap()[0][0], Protocol::Rejected as u8);
+        assert!(!connections.contains(connection).await);
+        assert_eq!(map.logged_in_players[3].load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+}
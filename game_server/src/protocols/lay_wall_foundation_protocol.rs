@@ -1,53 +1,28 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{gaia_mpsc::GaiaSender, map::{map_entity::{MapCommand, MapCommandInfo}, tetrahedron_id::TetrahedronId}};
+use crate::{gaia_mpsc::GaiaSender, map::map_entity::{MapCommand, MapCommandInfo}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process_construction(
      data : &[u8],
-    channel_map_tx : &GaiaSender<MapCommand>)
+    channel_map_tx : &GaiaSender<MapCommand>) -> Result<(), ProtocolError>
 {
 
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-        start = end;
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-        start = end;
-        end = start + 1;
-        let faction = data[start];
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
+        let faction = header.faction;
 
         // id
-        start = end;
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-
+        let tile_id = reader.read_tetrahedron_id()?;
         // endpointA
-        start = end;
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let endpoint_a = TetrahedronId::from_bytes(&buffer);
-
+        let endpoint_a = reader.read_tetrahedron_id()?;
         // endpointB
-        start = end;
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let endpoint_b = TetrahedronId::from_bytes(&buffer);
-
-        start = end;
-        end = start + 1;
-        let wall_size = data[start]; 
-
-        start = end;
-        end = start + 4;
-        let prop = u32::from_le_bytes(data[start..end].try_into().unwrap()); 
+        let endpoint_b = reader.read_tetrahedron_id()?;
+
+        let wall_size = reader.read_u8()?; 
+        let prop = reader.read_u32()?; 
         // cli_log::info!("construction protocol count {}", count);
 
         let map_action = MapCommand{
@@ -58,4 +33,5 @@ pub async fn process_construction(
         cli_log::info!("got a {:?}", map_action);
 
         channel_map_tx.send(map_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -1,60 +1,36 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{gaia_mpsc::GaiaSender, map::{map_entity::{MapCommand, MapCommandInfo}, tetrahedron_id::TetrahedronId}};
+use crate::{gaia_mpsc::GaiaSender, map::map_entity::{MapCommand, MapCommandInfo}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
+// tile id (6) + prop (4) + three pathness values (4 each)
+pub const FOUNDATION_ENTRY_SIZE : usize = 22;
 
 pub async fn process_construction(
      data : &[u8],
-    channel_map_tx : &GaiaSender<MapCommand>)
+    channel_map_tx : &GaiaSender<MapCommand>) -> Result<(), ProtocolError>
 {
 
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
 
-        start = end;
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-        start = end;
-        end = start + 1;
-        let _faction = data[start];
-
-        start = end;
-        end = start + 1;
-        let full_health = data[start];
-
-        start = end;
-        end = start + 2;
-        let count = u16::from_le_bytes(data[start..end].try_into().unwrap()); 
+        let full_health = reader.read_u8()?;
+        let count = reader.read_u16()?; 
 
 
         cli_log::info!("construction protocol count {}", count);
 
-        start = end;
-        for _ in 0..count {
-
-            end = start + 6;
-            let mut buffer = [0u8;6];
-            buffer.copy_from_slice(&data[start..end]);
-            let tile_id = TetrahedronId::from_bytes(&buffer);
-            start = end;
-
-            end = start + 4;
-            let prop = u32::from_le_bytes(data[start..end].try_into().unwrap()); 
-            start = end;
+        // we check the whole list first, we don't want to lay half of the foundations.
+        reader.ensure(count as usize * FOUNDATION_ENTRY_SIZE)?;
 
-            end = start + 4;
-            let pathness_a = f32::from_le_bytes(data[start..end].try_into().unwrap()); 
-            start = end;
-
-            end = start + 4;
-            let pathness_b = f32::from_le_bytes(data[start..end].try_into().unwrap()); 
-            start = end;
+        for _ in 0..count {
 
-            end = start + 4;
-            let pathness_c = f32::from_le_bytes(data[start..end].try_into().unwrap()); 
-            start = end;
+            let tile_id = reader.read_tetrahedron_id()?;
+            let prop = reader.read_u32()?; 
+            let pathness_a = reader.read_f32()?; 
+            let pathness_b = reader.read_f32()?; 
+            let pathness_c = reader.read_f32()?; 
 
             let map_action = MapCommand{
                 id: tile_id,
@@ -65,4 +41,5 @@ pub async fn process_construction(
 
             channel_map_tx.send(map_action).await.unwrap();
         }
+        Ok(())
 }
\ No newline at end of file
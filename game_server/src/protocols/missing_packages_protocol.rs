@@ -1,36 +1,110 @@
 
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::atomic::AtomicU64;
 
-pub fn process_request(
-    _player_id: u16,
-    data : &[u8; 508],
-    missing_packages : Arc<HashMap<u16, [AtomicU64;10]>>)
+use crate::gaia_mpsc::GaiaSender;
+use crate::gameplay_service::generic_command::GenericCommand;
+use crate::map::GameMap;
+use super::packet_reader::{PacketReader, ProtocolError};
+use crate::clients_service::connection::ConnectionId;
+
+pub const MAX_MISSING_PACKETS_PER_REQUEST : u8 = 32;
+
+// region (2 bytes), count (1 byte) and then count packet numbers (8 bytes each).
+// we resend the same encoded GlobalState packets, over the transport the request came from.
+pub async fn process_request(
+    connection : ConnectionId,
+    generic_channel_tx : &GaiaSender<GenericCommand>,
+    data : &[u8],
+    map : &Arc<GameMap>) -> Result<(), ProtocolError>
 {
-    let mut start = 1;
-    let mut end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+
+    let region = reader.read_u16()?;
+    let count = reader.read_u8()?;
+
+    if count > MAX_MISSING_PACKETS_PER_REQUEST
+    {
+        return Err(ProtocolError::InvalidField("count"));
+    }
 
-    start = end;
-    end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
+    reader.ensure(count as usize * 8)?;
 
-    start = end;
-    end = start + 1;
-    let _faction = data[start];
+    // region 0 goes to everyone, the rest only to the connections that get that region anyway.
+    if region != 0 && !map.region_subscriptions.get_regions(connection).await.contains(&region)
+    {
+        cli_log::info!("{} asked for packets of region {} without being subscribed", connection, region);
+        return Ok(());
+    }
 
-    // cli_log::info!("set missing packages for character {player_id}");
-    start = end;
-    if let Some(group) = missing_packages.get(&player_id)
+    for _ in 0..count
     {
-        for index in 0..10 
-        { 
-            end = start + 8;
-            let missing_packet = u64::from_le_bytes(data[start..end].try_into().unwrap());
-            start = end;
-            // cli_log::info!("set missing {index} packet {missing_packet}");
-            group[index].store(missing_packet, std::sync::atomic::Ordering::Relaxed);
+        let missing_packet = reader.read_u64()?;
+        if let Some((faction, packet)) = map.packets_history.get(region, missing_packet).await
+        {
+            if faction == 0 || faction == header.faction
+            {
+                generic_channel_tx.send(GenericCommand { connection, data: packet, reliable: false }).await.unwrap();
+            }
+        }
+        else
+        {
+            cli_log::info!("missing packet {} for region {} is not in the history anymore", missing_packet, region);
         }
     }
-}
\ No newline at end of file
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use crate::ServerChannels;
+    use crate::ServerState;
+    use crate::gaia_mpsc;
+    use crate::protocols::Protocol;
+
+    fn request(region : u16, faction : u8, packets : &[u64]) -> Vec<u8>
+    {
+        let mut data = vec![Protocol::MissingPackets as u8];
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.push(faction);
+        data.extend_from_slice(&region.to_le_bytes());
+        data.push(packets.len() as u8);
+        for packet in packets
+        {
+            data.extend_from_slice(&packet.to_le_bytes());
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn only_subscribed_regions_are_resent()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        let server_state = Arc::new(ServerState::new(None));
+        let (tx_gc, mut rx_gc) = gaia_mpsc::channel::<GenericCommand>(10, ServerChannels::TX_GC_ClIENTS_GAMEPLAY, server_state.clone());
+        let connection = ConnectionId::websocket("127.0.0.1:5000".parse().unwrap());
+        map.region_subscriptions.subscribe(connection, &[5]).await;
+
+        map.packets_history.store(5, 1, 0, Bytes::from_static(&[1])).await;
+        map.packets_history.store(5, 2, 2, Bytes::from_static(&[2])).await;
+        map.packets_history.store(6, 1, 0, Bytes::from_static(&[3])).await;
+        map.packets_history.store(0, 1, 0, Bytes::from_static(&[4])).await;
+
+        process_request(connection, &tx_gc, &request(5, 1, &[1, 2]), &map).await.unwrap();
+        assert_eq!(&rx_gc.try_recv().unwrap().data[..], &[1]);
+        // the other faction's packet stays with them.
+        assert!(rx_gc.try_recv().is_err());
+
+        process_request(connection, &tx_gc, &request(6, 1, &[1]), &map).await.unwrap();
+        assert!(rx_gc.try_recv().is_err());
+
+        process_request(connection, &tx_gc, &request(0, 1, &[1]), &map).await.unwrap();
+        assert_eq!(&rx_gc.try_recv().unwrap().data[..], &[4]);
+    }
+}
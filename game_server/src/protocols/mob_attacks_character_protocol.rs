@@ -1,44 +1,20 @@
-use crate::{gaia_mpsc::GaiaSender, map::tetrahedron_id::TetrahedronId, mob::mob_command::{MobCommand, MobToHeroData}};
+use crate::{gaia_mpsc::GaiaSender, mob::mob_command::{MobCommand, MobToHeroData}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_mob_tx : &GaiaSender<MobCommand>)
+    channel_mob_tx : &GaiaSender<MobCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
 
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 1;
-        let _faction = data[start];
-        start = end; // ignoring first byte
-
-        end = start + 4;
-        let attacker_mob_id = u32::from_le_bytes(data[start..end].try_into().unwrap()); // 4 bytes
-        start = end;
-
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-        start = end;
-
-        end = start + 4;
-        let card_id = u32::from_le_bytes(data[start..end].try_into().unwrap()); // 4 bytes
-        start = end;
-
-        end = start + 4;
-        let required_time = u32::from_le_bytes(data[start..end].try_into().unwrap()); // 4 bytes
-        start = end;
-
-        end = start + 1;
-        let missed = data[start]; // 1 bytes
-        // start = end;
+        let attacker_mob_id = reader.read_u32()?; // 4 bytes
+        let tile_id = reader.read_tetrahedron_id()?;
+        let card_id = reader.read_u32()?; // 4 bytes
+        let required_time = reader.read_u32()?; // 4 bytes
+        let missed = reader.read_u8()?; // 1 bytes
 
         let mob_action = MobCommand::AttackFromMobToHero(MobToHeroData
         {
@@ -53,4 +29,5 @@ pub async fn process(
         cli_log::info!("got a {:?}", mob_action);
 
         channel_mob_tx.send(mob_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
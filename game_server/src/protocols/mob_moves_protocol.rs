@@ -1,49 +1,25 @@
-use crate::{gaia_mpsc::GaiaSender, map::tetrahedron_id::TetrahedronId, mob::mob_command::{MobCommand, MoveMobData}};
+use crate::{gaia_mpsc::GaiaSender, mob::mob_command::{MobCommand, MoveMobData}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_mob_tx : &GaiaSender<MobCommand>)
+    channel_mob_tx : &GaiaSender<MobCommand>) -> Result<(), ProtocolError>
 {
-        let mut buffer = [0u8;6];
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
 
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 1;
-        let _faction = data[start];
-        start = end;
-
-
-        end = start + 4;
-        let mob_id = u32::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mob_id = reader.read_u32()?;
 
         // new tile, must be empty and not water.
-        end = start + 6;
-        buffer.copy_from_slice(&data[start..end]);
-        let origin_position = TetrahedronId::from_bytes(&buffer);
-        start = end;
+        let origin_position = reader.read_tetrahedron_id()?;
 
         // new tile, must be empty and not water.
-        end = start + 6;
-        buffer.copy_from_slice(&data[start..end]);
-        let end_position = TetrahedronId::from_bytes(&buffer);
-        start = end;
+        let end_position = reader.read_tetrahedron_id()?;
 
         // path should point to the end position for consistency
-        let mut path : [u8;6] = [0,0,0,0,0,0];
-        for i in 0..6
-        {
-            end = start + 1;
-            path[i] = data[start];
-            start = end;
-        }
+        let path : [u8;6] = reader.read_bytes()?;
 
         let map_action = MobCommand::MoveMob(MoveMobData
         {
@@ -57,4 +33,5 @@ pub async fn process(
         cli_log::info!("-------------------------- got a {:?}", map_action);
 
         channel_mob_tx.send(map_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -30,12 +30,22 @@ pub mod craft_card_protocol;
 pub mod try_enter_tower_request_protocol;
 pub mod enter_tower_request_protocol;
 pub mod exit_tower_request_protocol;
+pub mod entity_ack_protocol;
+pub mod reliable_ack_protocol;
+pub mod fragment_protocol;
+pub mod spectate_protocol;
+pub mod protocol_version;
+pub mod packet_auth;
+pub mod packet_reader;
+pub mod schema;
 
-use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::atomic::AtomicU16;
 
 use crate::gaia_mpsc::GaiaSender;
+use crate::clients_service::connection::{ConnectionId, ConnectionRegistry, TransportKind};
+use crate::clients_service::connection_stats::ConnectionStats;
+use crate::clients_service::fragmentation::Reassembler;
+use crate::clients_service::rate_limiter::{self, RateLimitResult, RateLimiter};
 use crate::gameplay_service::generic_command::GenericCommand;
 use crate::kingdom::KingdomCommand;
 use crate::mob::mob_command::MobCommand;
@@ -45,6 +55,7 @@ use crate::map::GameMap;
 use crate::map::map_entity::MapCommand;
 use crate::hero::hero_command::HeroCommand;
 use crate::tower::TowerCommand;
+use packet_reader::ProtocolError;
 
 
 pub enum Protocol
@@ -83,16 +94,34 @@ pub enum Protocol
     EnterTower = 32,
     HeroData = 33,
     ExitTower = 34,
+    EntityAck = 35,
+    // server to client, same header as GlobalState but numbered per client.
+    DeltaState = 36,
+    // server to client, see protocol_version::RejectReason.
+    Rejected = 37,
+    // the client moved to a new udp address, handled by clients_service before any routing.
+    Resume = 38,
+    // server to client, a message that is resent until the client acks it, see reliable_channel.
+    Reliable = 39,
+    ReliableAck = 40,
+    // both ways, a piece of a message bigger than a datagram, see fragmentation.
+    Fragment = 41,
+    // websocket only, a connection that watches regions without a hero, see spectate_protocol.
+    Spectate = 42,
 }
     
 pub async fn route_packet(
-    player_address : std::net::SocketAddr, 
-    is_udp: bool,
+    connection : ConnectionId,
+    session_id : u64,
+    hero_id : u16,
     data : &[u8],
     packet_size: usize,
     map : &Arc<GameMap>,
     server_state: &Arc<ServerState>,
-    regions: &Arc<HashMap<u16, [AtomicU16;3]>>,
+    connections : &Arc<ConnectionRegistry>,
+    reassembler : &mut Reassembler,
+    rate_limiter : &mut RateLimiter,
+    stats : Option<&ConnectionStats>,
     tx_gc_clients_gameplay: &GaiaSender<GenericCommand>,
     tx_hc_clients_gameplay: &GaiaSender<HeroCommand>,
     tx_mc_clients_gameplay: &GaiaSender<MapCommand>,
@@ -100,157 +129,535 @@ pub async fn route_packet(
     tx_tc_clients_gameplay: &GaiaSender<TowerCommand>,
     tx_kc_clients_gameplay: &GaiaSender<KingdomCommand>,
     tx_cc_clients_gameplay: &GaiaSender<ChatCommand>
-){
+) -> RateLimitResult
+{
 
     server_state.received_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     server_state.received_bytes.fetch_add(packet_size as u64, std::sync::atomic::Ordering::Relaxed);
 
-    match data.get(0) 
+    // udp buffers are bigger than the packet, we never read past what the client sent.
+    let data = &data[..usize::min(packet_size, data.len())];
+
+    // every packet has to come from the hero bound to this socket, with the session that is still logged in.
+    if let Err(error) = validate_identity(data, session_id, hero_id, map)
+    {
+        if let ProtocolError::IdentityMismatch = error
+        {
+            server_state.rejected_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            cli_log::error!("rejected packet from {} bound to hero {}", connection, hero_id);
+        }
+        else
+        {
+            drop_packet(server_state, connection, data, error);
+        }
+        return RateLimitResult::Allowed;
+    }
+
+    // udp packets end with a nonce and a tag made with the session secret, websockets don't need it.
+    let data = if connection.kind == TransportKind::Udp
+    {
+        match map.session_keys.authenticate(hero_id, session_id, data).await
+        {
+            Ok(payload_size) => &data[..payload_size],
+            Err(error) =>
+            {
+                server_state.rejected_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                cli_log::error!("rejected udp packet from {} bound to hero {}: {}", connection, hero_id, error);
+                return RateLimitResult::Allowed;
+            }
+        }
+    }
+    else
+    {
+        data
+    };
+
+    // only now we know the client sent it, packets anyone could have sent don't count against it.
+    let rate_limit = rate_limiter::check_packet(rate_limiter, data, server_state, stats, connection);
+    if rate_limit != RateLimitResult::Allowed
+    {
+        return rate_limit;
+    }
+
+    // big messages come in fragments, each one signed on its own. Once complete the message
+    // is routed like any other packet, a fragment inside it is just an unknown protocol.
+    let reassembled;
+    let data = if data.first() == Some(&(Protocol::Fragment as u8))
+    {
+        match fragment_protocol::process(data, reassembler)
+        {
+            Ok(Some(message)) =>
+            {
+                if let Err(error) = validate_identity(&message, session_id, hero_id, map)
+                {
+                    server_state.rejected_packets.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                    cli_log::error!("rejected fragmented packet from {} bound to hero {}: {}", connection, hero_id, error);
+                    return RateLimitResult::Allowed;
+                }
+
+                // the fragments went through the limiter already, the message inside has its own bucket.
+                let rate_limit = rate_limiter::check_packet(rate_limiter, &message, server_state, stats, connection);
+                if rate_limit != RateLimitResult::Allowed
+                {
+                    return rate_limit;
+                }
+                reassembled = message;
+                &reassembled[..]
+            },
+            Ok(None) => return RateLimitResult::Allowed,
+            Err(error) =>
+            {
+                drop_packet(server_state, connection, data, error);
+                return RateLimitResult::Allowed;
+            }
+        }
+    }
+    else
+    {
+        data
+    };
+
+    // after the checks, so a replay gets exactly what reached the game.
+    if let Some(capture) = &server_state.packet_capture
+    {
+        capture.record(connection, session_id, hero_id, data);
+    }
+
+    dispatch_packet(connection, data, map, server_state, connections, tx_gc_clients_gameplay, tx_hc_clients_gameplay, tx_mc_clients_gameplay, tx_moc_clients_gameplay, tx_tc_clients_gameplay, tx_kc_clients_gameplay, tx_cc_clients_gameplay).await;
+    RateLimitResult::Allowed
+}
+
+// turns a packet that already passed the identity and signature checks into commands, the replay uses it directly.
+pub async fn dispatch_packet(
+    connection : ConnectionId,
+    data : &[u8],
+    map : &Arc<GameMap>,
+    server_state: &Arc<ServerState>,
+    connections : &Arc<ConnectionRegistry>,
+    tx_gc_clients_gameplay: &GaiaSender<GenericCommand>,
+    tx_hc_clients_gameplay: &GaiaSender<HeroCommand>,
+    tx_mc_clients_gameplay: &GaiaSender<MapCommand>,
+    tx_moc_clients_gameplay: &GaiaSender<MobCommand>,
+    tx_tc_clients_gameplay: &GaiaSender<TowerCommand>,
+    tx_kc_clients_gameplay: &GaiaSender<KingdomCommand>,
+    tx_cc_clients_gameplay: &GaiaSender<ChatCommand>
+){
+    let result = match data.get(0) 
     {
         Some(protocol) if *protocol == Protocol::Ping as u8 => 
         {
-            ping_protocol::process_ping(player_address, is_udp, tx_gc_clients_gameplay, data).await;
+            ping_protocol::process_ping(connection, tx_gc_clients_gameplay, data, connections).await
         },
         Some(protocol) if *protocol == Protocol::SellItem as u8 => 
         {
-            sell_item_protocol::process(data, tx_hc_clients_gameplay).await;
+            sell_item_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::BuyItem as u8 => 
         {
-            buy_item_protocol::process(data, tx_hc_clients_gameplay).await;
+            buy_item_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::UseItem as u8 => 
         {
-            use_item_protocol::process(data, tx_hc_clients_gameplay).await;
+            use_item_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::EquipItem as u8 => 
         {
-            equip_item_protocol::process(data, tx_hc_clients_gameplay).await;
+            equip_item_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::InventoryRequest as u8 => 
         {
-            inventory_request_protocol::process_request(player_address, is_udp, tx_gc_clients_gameplay, data, map).await;
+            inventory_request_protocol::process_request(connection, tx_gc_clients_gameplay, data, map).await
         },
         Some(protocol) if *protocol == Protocol::LayFoundation as u8 => 
         {
-            layfoundation_protocol::process_construction(data, tx_mc_clients_gameplay).await;
+            layfoundation_protocol::process_construction(data, tx_mc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::CharacterMovement as u8 => 
         {
-            movement_protocol::process_movement(data, regions, tx_hc_clients_gameplay).await;
+            movement_protocol::process_movement(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::ResourceExtraction as u8 => 
         {
-            resource_extraction_protocol::process(data, tx_mc_clients_gameplay).await;
+            resource_extraction_protocol::process(data, tx_mc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::Build as u8 => 
         {
-            build_protocol::process(data, tx_mc_clients_gameplay).await;
+            build_protocol::process(data, tx_mc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::MobAttacksWalker as u8 => 
         { // used by mobs and towers.
-            mob_attacks_character_protocol::process(data, tx_moc_clients_gameplay).await;
+            mob_attacks_character_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::SpawnMob as u8 => 
         {
-            spawn_mob_protocol::process(data, tx_moc_clients_gameplay).await;
+            spawn_mob_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::MobMoves as u8 => 
         {
-            mob_moves_protocol::process(data, tx_moc_clients_gameplay).await;
+            mob_moves_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::ControlMob as u8 => 
         {
-            claim_mob_ownership::process(data, tx_moc_clients_gameplay).await;
+            claim_mob_ownership::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::AttackMob as u8 => 
         {
-            cast_mob_from_character_protocol::process(data, tx_moc_clients_gameplay).await;
+            cast_mob_from_character_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::MissingPackets as u8 => 
         {
-            // let capacity = tx_mc_clients_gameplay.capacity();
-            // server_state.tx_mc_clients_gameplay.store(capacity as f32 as u16, std::sync::atomic::Ordering::Relaxed);
-            // missing_packages_protocol::process_request(player_id, data, missing_packets);
+            missing_packages_protocol::process_request(connection, tx_gc_clients_gameplay, data, map).await
+        },
+        Some(protocol) if *protocol == Protocol::EntityAck as u8 => 
+        {
+            entity_ack_protocol::process(data, map).await
+        },
+        Some(protocol) if *protocol == Protocol::ReliableAck as u8 => 
+        {
+            reliable_ack_protocol::process(connection, data, connections).await
+        },
+        Some(protocol) if *protocol == Protocol::Resume as u8 => 
+        {
+            // a resume sent again after the session already moved to this address, the client only needs the answer.
+            let answer = GenericCommand { connection, data: bytes::Bytes::from_static(&[Protocol::Resume as u8]), reliable: false };
+            tx_gc_clients_gameplay.send(answer).await.unwrap();
+            Ok(())
         },
         Some(protocol) if *protocol == Protocol::AttackTower as u8 => 
         {
-            attack_tower_protocol::process(data, tx_tc_clients_gameplay).await;
+            attack_tower_protocol::process(data, tx_tc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::RepairTower as u8 => 
         {
-            repair_tower_protocol::process(data, tx_tc_clients_gameplay).await;
+            repair_tower_protocol::process(data, tx_tc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::RepairTower as u8 => 
         {
-            repair_tower_protocol::process(data, tx_tc_clients_gameplay).await;
+            repair_tower_protocol::process(data, tx_tc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::ChatMessage as u8 => 
         {
-            chat_message_protocol::process(data, tx_cc_clients_gameplay).await;
+            chat_message_protocol::process(data, tx_cc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::BuildWall as u8 => 
         {
-            lay_wall_foundation_protocol::process_construction(data, tx_mc_clients_gameplay).await;
+            lay_wall_foundation_protocol::process_construction(data, tx_mc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::Respawn as u8 => 
         {
             cli_log::info!("--------------------- process respawn");
-            respawn_protocol::process_respawn(data, regions, tx_hc_clients_gameplay).await;
+            respawn_protocol::process_respawn(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::CharacterAction as u8 => 
         {
             cli_log::info!("--------------------- process character action");
-            action_protocol::process(data, tx_hc_clients_gameplay).await;
+            action_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::Greet as u8 => 
         {
             cli_log::info!("--------------------- process greet");
-            greet_protocol::process(data, tx_hc_clients_gameplay).await;
+            greet_protocol::process(connection, data, connections, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::ActivateBuff as u8 => 
         {
             cli_log::info!("--------------------- process buff");
-            activate_buff_protocol::process(data, tx_hc_clients_gameplay).await;
+            activate_buff_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::CharacterAttacksCharacter as u8 => 
         {
             cli_log::info!("--------------------- process character attack");
-            character_attacks_character_protocol::process(data, tx_hc_clients_gameplay).await;
+            character_attacks_character_protocol::process(data, tx_hc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::TouchMob as u8 => 
         {
             cli_log::info!("--------------------- process touch mob");
-            touch_mob_protocol::process(data, tx_moc_clients_gameplay).await;
+            touch_mob_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::CastMobFromMob as u8 => 
         {
             cli_log::info!("--------------------- process touch mob");
-            cast_mob_from_mob_protocol::process(data, tx_moc_clients_gameplay).await;
+            cast_mob_from_mob_protocol::process(data, tx_moc_clients_gameplay).await
         },
         Some(protocol) if *protocol == Protocol::CraftCard as u8 => 
         {
             cli_log::info!("--------------------- process craft card");
-            craft_card_protocol::process_request(player_address, is_udp, tx_gc_clients_gameplay, data, map).await;
+            craft_card_protocol::process_request(connection, tx_gc_clients_gameplay, data, map).await
         },
         Some(protocol) if *protocol == Protocol::TryEnterTower as u8 => 
         {
             cli_log::info!("--------------------- process try enter tower");
-            try_enter_tower_request_protocol::process_request(player_address, is_udp, tx_gc_clients_gameplay, data, map).await;
+            try_enter_tower_request_protocol::process_request(connection, tx_gc_clients_gameplay, data, map).await
         },
         Some(protocol) if *protocol == Protocol::EnterTower as u8 => 
         {
             cli_log::info!("--------------------- process enter tower");
-            enter_tower_request_protocol::process_request(player_address, tx_hc_clients_gameplay, data, map).await;
+            enter_tower_request_protocol::process_request(connection, tx_hc_clients_gameplay, data, map).await
         },
         Some(protocol) if *protocol == Protocol::ExitTower as u8 => 
         {
             cli_log::info!("--------------------- process exit tower");
-            exit_tower_request_protocol::process_request(tx_hc_clients_gameplay, tx_tc_clients_gameplay, data).await;
+            exit_tower_request_protocol::process_request(tx_hc_clients_gameplay, tx_tc_clients_gameplay, data).await
         },
-        unknown_protocol => 
+        Some(unknown_protocol) => 
+        {
+            Err(ProtocolError::UnknownProtocol(*unknown_protocol))
+        }
+        None => 
+        {
+            Err(ProtocolError::Truncated { offset: 0, needed: 1, packet_size: data.len() })
+        }
+    };
+
+    if let Err(error) = result
+    {
+        drop_packet(server_state, connection, data, error);
+    }
+}
+
+fn drop_packet(server_state: &Arc<ServerState>, connection : ConnectionId, data : &[u8], error : ProtocolError)
+{
+    let protocol = data.first().copied().unwrap_or(0);
+    server_state.dropped_packets[protocol as usize].fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    cli_log::error!("dropped packet with protocol {} from {}: {}", protocol, connection, error);
+}
+
+pub fn validate_identity(data : &[u8], session_id : u64, hero_id : u16, map : &Arc<GameMap>) -> Result<(), ProtocolError>
+{
+    let mut reader = packet_reader::PacketReader::new(data);
+    let packet_session_id = reader.read_u64()?;
+    let packet_hero_id = reader.read_u16()?;
+
+    if packet_session_id != session_id || packet_hero_id != hero_id
+    {
+        return Err(ProtocolError::IdentityMismatch);
+    }
+
+    // the hero could have logged in again from somewhere else.
+    let logged_in_session_id = map.logged_in_players
+        .get(hero_id as usize)
+        .map(|stored_session_id| stored_session_id.load(std::sync::atomic::Ordering::Relaxed))
+        .unwrap_or(0);
+
+    if logged_in_session_id != session_id || session_id == 0
+    {
+        return Err(ProtocolError::IdentityMismatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::Receiver;
+
+    use crate::ServerChannels;
+    use crate::gaia_mpsc;
+    use crate::clients_service::fragmentation;
+    use crate::clients_service::rate_limiter::{BucketLimit, RateLimitConfig};
+    use crate::map::tetrahedron_id::TetrahedronId;
+
+    const HERO_ID : u16 = 7;
+    const SESSION_ID : u64 = 1234;
+
+    async fn create_map() -> Arc<GameMap>
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        map.logged_in_players[HERO_ID as usize].store(SESSION_ID, std::sync::atomic::Ordering::Relaxed);
+        map
+    }
+
+    fn respawn_packet(session_id : u64, hero_id : u16) -> Vec<u8>
+    {
+        let mut data = vec![Protocol::Respawn as u8];
+        data.extend_from_slice(&session_id.to_le_bytes());
+        data.extend_from_slice(&hero_id.to_le_bytes());
+        data.push(1);
+        data.extend_from_slice(&[0u8; 6]);
+        data.extend_from_slice(&TetrahedronId::from_string("a012301230").to_bytes());
+        data
+    }
+
+    // websockets don't sign their packets, so only the identity checks are in the way.
+    async fn route(map : &Arc<GameMap>, server_state : &Arc<ServerState>, data : &[u8]) -> Receiver<HeroCommand>
+    {
+        let connection = ConnectionId::websocket("127.0.0.1:5000".parse().unwrap());
+        let mut rate_limiter = RateLimiter::new(Arc::new(RateLimitConfig::default()));
+        route_from(map, server_state, connection, &mut Reassembler::new(), &mut rate_limiter, data).await.1
+    }
+
+    async fn route_from(
+        map : &Arc<GameMap>,
+        server_state : &Arc<ServerState>,
+        connection : ConnectionId,
+        reassembler : &mut Reassembler,
+        rate_limiter : &mut RateLimiter,
+        data : &[u8]) -> (RateLimitResult, Receiver<HeroCommand>)
+    {
+        let (tx_gc, _rx_gc) = gaia_mpsc::channel::<GenericCommand>(10, ServerChannels::TX_GC_ClIENTS_GAMEPLAY, server_state.clone());
+        let (tx_hc, rx_hc) = gaia_mpsc::channel::<HeroCommand>(10, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_mc, _rx_mc) = gaia_mpsc::channel::<MapCommand>(10, ServerChannels::TX_MC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_moc, _rx_moc) = gaia_mpsc::channel::<MobCommand>(10, ServerChannels::TX_MOC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_tc, _rx_tc) = gaia_mpsc::channel::<TowerCommand>(10, ServerChannels::TX_TC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_kc, _rx_kc) = gaia_mpsc::channel::<KingdomCommand>(10, ServerChannels::TX_KC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_cc, _rx_cc) = gaia_mpsc::channel::<ChatCommand>(10, ServerChannels::TX_CC_CLIENTS_GAMEPLAY, server_state.clone());
+        let connections = Arc::new(ConnectionRegistry::new(map.clone(), server_state.clone()));
+
+        let result = route_packet(connection, SESSION_ID, HERO_ID, data, data.len(), map, server_state, &connections, reassembler, rate_limiter, None,
+            &tx_gc, &tx_hc, &tx_mc, &tx_moc, &tx_tc, &tx_kc, &tx_cc).await;
+        (result, rx_hc)
+    }
+
+    #[tokio::test]
+    async fn packets_from_someone_else_are_rejected()
+    {
+        let map = create_map().await;
+
+        assert!(validate_identity(&respawn_packet(SESSION_ID, HERO_ID), SESSION_ID, HERO_ID, &map).is_ok());
+        assert!(matches!(validate_identity(&respawn_packet(SESSION_ID + 1, HERO_ID), SESSION_ID, HERO_ID, &map), Err(ProtocolError::IdentityMismatch)));
+        assert!(matches!(validate_identity(&respawn_packet(SESSION_ID, HERO_ID + 1), SESSION_ID, HERO_ID, &map), Err(ProtocolError::IdentityMismatch)));
+
+        // the hero logged in again somewhere else, the old session is done.
+        map.logged_in_players[HERO_ID as usize].store(SESSION_ID + 5, std::sync::atomic::Ordering::Relaxed);
+        assert!(matches!(validate_identity(&respawn_packet(SESSION_ID, HERO_ID), SESSION_ID, HERO_ID, &map), Err(ProtocolError::IdentityMismatch)));
+        map.logged_in_players[HERO_ID as usize].store(0, std::sync::atomic::Ordering::Relaxed);
+        assert!(matches!(validate_identity(&respawn_packet(0, HERO_ID), 0, HERO_ID, &map), Err(ProtocolError::IdentityMismatch)));
+    }
+
+    #[tokio::test]
+    async fn route_packet_drops_packets_from_someone_else()
+    {
+        let map = create_map().await;
+        let server_state = Arc::new(ServerState::new(None));
+
+        let mut rx_hc = route(&map, &server_state, &respawn_packet(SESSION_ID, HERO_ID)).await;
+        assert_eq!(rx_hc.try_recv().unwrap().player_id, HERO_ID);
+
+        for data in [respawn_packet(SESSION_ID + 1, HERO_ID), respawn_packet(SESSION_ID, HERO_ID + 1)]
+        {
+            let mut rx_hc = route(&map, &server_state, &data).await;
+            assert!(rx_hc.try_recv().is_err());
+        }
+
+        map.logged_in_players[HERO_ID as usize].store(0, std::sync::atomic::Ordering::Relaxed);
+        let mut rx_hc = route(&map, &server_state, &respawn_packet(SESSION_ID, HERO_ID)).await;
+        assert!(rx_hc.try_recv().is_err());
+
+        assert_eq!(server_state.rejected_packets.load(std::sync::atomic::Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn only_signed_packets_count_against_the_rate_limit()
+    {
+        let map = create_map().await;
+        let server_state = Arc::new(ServerState::new(None));
+        let secret = packet_auth::generate_secret();
+        map.session_keys.insert(HERO_ID, SESSION_ID, secret).await;
+        let connection = ConnectionId::udp("127.0.0.1:5000".parse().unwrap());
+        let config = RateLimitConfig { max_violations: 1, ..Default::default() };
+        let burst = config.get_limit(Protocol::Respawn as u8).burst as u64;
+        let mut rate_limiter = RateLimiter::new(Arc::new(config));
+
+        // someone sending from the client address without the secret.
+        for _ in 0..burst * 2
+        {
+            let (result, _) = route_from(&map, &server_state, connection, &mut Reassembler::new(), &mut rate_limiter, &respawn_packet(SESSION_ID, HERO_ID)).await;
+            assert_eq!(result, RateLimitResult::Allowed);
+        }
+        assert_eq!(server_state.rate_limited_packets.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        // the real client still has all of its burst.
+        for nonce in 1..=burst
+        {
+            let data = packet_auth::sign(&secret, nonce, &respawn_packet(SESSION_ID, HERO_ID));
+            let (result, mut rx_hc) = route_from(&map, &server_state, connection, &mut Reassembler::new(), &mut rate_limiter, &data).await;
+            assert_eq!(result, RateLimitResult::Allowed);
+            assert!(rx_hc.try_recv().is_ok());
+        }
+
+        let data = packet_auth::sign(&secret, burst + 1, &respawn_packet(SESSION_ID, HERO_ID));
+        assert_eq!(route_from(&map, &server_state, connection, &mut Reassembler::new(), &mut rate_limiter, &data).await.0, RateLimitResult::Dropped);
+        let data = packet_auth::sign(&secret, burst + 2, &respawn_packet(SESSION_ID, HERO_ID));
+        assert_eq!(route_from(&map, &server_state, connection, &mut Reassembler::new(), &mut rate_limiter, &data).await.0, RateLimitResult::Disconnect);
+    }
+
+    // what a udp client sends for a message that doesn't fit in one datagram.
+    fn signed_fragments(secret : &[u8; packet_auth::SESSION_SECRET_SIZE], first_nonce : u64, message : &[u8]) -> Vec<Vec<u8>>
+    {
+        let chunks : Vec<&[u8]> = message.chunks(fragmentation::MAX_CLIENT_FRAGMENT_PAYLOAD).collect();
+        chunks.iter().enumerate().map(|(index, chunk)|
+        {
+            let mut fragment = vec![Protocol::Fragment as u8];
+            fragment.extend_from_slice(&SESSION_ID.to_le_bytes());
+            fragment.extend_from_slice(&HERO_ID.to_le_bytes());
+            fragment.push(1);
+            fragment.extend_from_slice(&9u16.to_le_bytes());
+            fragment.push(index as u8);
+            fragment.push(chunks.len() as u8);
+            fragment.extend_from_slice(chunk);
+            packet_auth::sign(secret, first_nonce + index as u64, &fragment)
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn full_signed_fragments_fit_in_a_datagram()
+    {
+        let map = create_map().await;
+        let server_state = Arc::new(ServerState::new(None));
+        let secret = packet_auth::generate_secret();
+        map.session_keys.insert(HERO_ID, SESSION_ID, secret).await;
+        let connection = ConnectionId::udp("127.0.0.1:5000".parse().unwrap());
+        let mut rate_limiter = RateLimiter::new(Arc::new(RateLimitConfig::default()));
+
+        // the respawn doesn't read past its fields, the rest only makes the message need two fragments.
+        let mut message = respawn_packet(SESSION_ID, HERO_ID);
+        message.resize(fragmentation::MAX_CLIENT_FRAGMENT_PAYLOAD + 10, 0);
+        let fragments = signed_fragments(&secret, 1, &message);
+        assert_eq!(fragments[0].len(), fragmentation::MAX_DATAGRAM_SIZE);
+
+        let mut reassembler = Reassembler::new();
+        let mut received = Vec::new();
+        for fragment in fragments
         {
-            cli_log::error!("unknown protocol {:?}", unknown_protocol);
+            assert!(fragment.len() <= fragmentation::MAX_DATAGRAM_SIZE);
+            let (_, mut rx_hc) = route_from(&map, &server_state, connection, &mut reassembler, &mut rate_limiter, &fragment).await;
+            received.extend(std::iter::from_fn(|| rx_hc.try_recv().ok()));
+        }
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].player_id, HERO_ID);
+        assert_eq!(server_state.rejected_packets.load(std::sync::atomic::Ordering::Relaxed), 0);
+
+        // one byte more doesn't fit anymore.
+        let mut fragment = signed_fragments(&secret, 10, &message).remove(0);
+        fragment.insert(20, 0);
+        assert!(fragment_protocol::process(&fragment[..fragment.len() - packet_auth::AUTH_TRAILER_SIZE], &mut Reassembler::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn fragmented_messages_use_the_bucket_of_what_they_carry()
+    {
+        let map = create_map().await;
+        let server_state = Arc::new(ServerState::new(None));
+        let connection = ConnectionId::websocket("127.0.0.1:5000".parse().unwrap());
+        let mut config = RateLimitConfig::default();
+        config.limits.insert(Protocol::Respawn as u8, BucketLimit { burst: 2.0, per_second: 0.1 });
+        let mut rate_limiter = RateLimiter::new(Arc::new(config));
+
+        let mut results = Vec::new();
+        for message_id in 0..3u16
+        {
+            let mut fragment = vec![Protocol::Fragment as u8];
+            fragment.extend_from_slice(&SESSION_ID.to_le_bytes());
+            fragment.extend_from_slice(&HERO_ID.to_le_bytes());
+            fragment.push(1);
+            fragment.extend_from_slice(&message_id.to_le_bytes());
+            fragment.extend_from_slice(&[0, 1]);
+            fragment.extend_from_slice(&respawn_packet(SESSION_ID, HERO_ID));
+
+            let (result, mut rx_hc) = route_from(&map, &server_state, connection, &mut Reassembler::new(), &mut rate_limiter, &fragment).await;
+            results.push((result, rx_hc.try_recv().is_ok()));
         }
+        assert_eq!(results, vec![(RateLimitResult::Allowed, true), (RateLimitResult::Allowed, true), (RateLimitResult::Dropped, false)]);
     }
 }
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use tokio::sync::Mutex;
+
+use super::packet_reader::ProtocolError;
+
+pub const SESSION_SECRET_SIZE : usize = 32;
+pub const NONCE_SIZE : usize = 8;
+pub const TAG_SIZE : usize = 16;
+// every udp packet ends with the nonce and then the tag, the tag covers everything before it.
+pub const AUTH_TRAILER_SIZE : usize = NONCE_SIZE + TAG_SIZE;
+// how far back a nonce can arrive out of order, udp doesn't keep the order.
+const REPLAY_WINDOW_SIZE : u64 = 64;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub fn generate_secret() -> [u8; SESSION_SECRET_SIZE]
+{
+    let mut secret = [0u8; SESSION_SECRET_SIZE];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+pub fn compute_tag(secret : &[u8; SESSION_SECRET_SIZE], data : &[u8]) -> [u8; TAG_SIZE]
+{
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(data);
+    let result = mac.finalize().into_bytes();
+    let mut tag = [0u8; TAG_SIZE];
+    tag.copy_from_slice(&result[..TAG_SIZE]);
+    tag
+}
+
+// what the client does before sending a packet.
+pub fn sign(secret : &[u8; SESSION_SECRET_SIZE], nonce : u64, data : &[u8]) -> Vec<u8>
+{
+    let mut signed = Vec::with_capacity(data.len() + AUTH_TRAILER_SIZE);
+    signed.extend_from_slice(data);
+    signed.extend_from_slice(&u64::to_le_bytes(nonce));
+    let tag = compute_tag(secret, &signed);
+    signed.extend_from_slice(&tag);
+    signed
+}
+
+// returns the size of the packet without the trailer and the nonce.
+fn verify_tag(secret : &[u8; SESSION_SECRET_SIZE], data : &[u8]) -> Result<(usize, u64), ProtocolError>
+{
+    if data.len() < AUTH_TRAILER_SIZE + 1
+    {
+        return Err(ProtocolError::Truncated { offset: 0, needed: AUTH_TRAILER_SIZE + 1, packet_size: data.len() });
+    }
+
+    let tag_start = data.len() - TAG_SIZE;
+    let nonce_start = tag_start - NONCE_SIZE;
+
+    let mut mac = HmacSha256::new_from_slice(secret).unwrap();
+    mac.update(&data[..tag_start]);
+    mac.verify_truncated_left(&data[tag_start..]).map_err(|_| ProtocolError::InvalidTag)?;
+
+    let nonce = u64::from_le_bytes(data[nonce_start..tag_start].try_into().unwrap());
+    Ok((nonce_start, nonce))
+}
+
+// the highest nonce we got and a bit for each of the previous ones.
+pub struct ReplayWindow
+{
+    highest : u64,
+    seen : u64,
+}
+
+impl ReplayWindow
+{
+    pub fn new() -> Self
+    {
+        // nonce 0 counts as used, clients start at 1.
+        ReplayWindow { highest: 0, seen: 1 }
+    }
+
+    // same as accept without using the nonce.
+    pub fn is_fresh(&self, nonce : u64) -> bool
+    {
+        if nonce > self.highest
+        {
+            return true;
+        }
+
+        let offset = self.highest - nonce;
+        offset < REPLAY_WINDOW_SIZE && self.seen & (1 << offset) == 0
+    }
+
+    pub fn accept(&mut self, nonce : u64) -> bool
+    {
+        if nonce > self.highest
+        {
+            let shift = nonce - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW_SIZE { 0 } else { self.seen << shift };
+            self.seen |= 1;
+            self.highest = nonce;
+            return true;
+        }
+
+        let offset = self.highest - nonce;
+        if offset >= REPLAY_WINDOW_SIZE || self.seen & (1 << offset) != 0
+        {
+            return false;
+        }
+        self.seen |= 1 << offset;
+        true
+    }
+}
+
+impl Default for ReplayWindow
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+struct SessionAuth
+{
+    session_id : u64,
+    secret : [u8; SESSION_SECRET_SIZE],
+    replay_window : ReplayWindow,
+}
+
+// secrets handed out on login, by hero id.
+pub struct SessionKeys
+{
+    sessions : Mutex<HashMap<u16, SessionAuth>>,
+}
+
+impl SessionKeys
+{
+    pub fn new() -> Self
+    {
+        SessionKeys { sessions: Mutex::new(HashMap::new()) }
+    }
+
+    pub async fn insert(&self, hero_id : u16, session_id : u64, secret : [u8; SESSION_SECRET_SIZE])
+    {
+        let mut sessions = self.sessions.lock().await;
+        sessions.insert(hero_id, SessionAuth { session_id, secret, replay_window: ReplayWindow::new() });
+    }
+
+    // doesn't use the nonce, used before creating a udp connection so the packet can still be routed after.
+    // a packet that was already routed can't be replayed from another address to open a connection.
+    pub async fn check(&self, hero_id : u16, session_id : u64, data : &[u8]) -> Result<usize, ProtocolError>
+    {
+        let sessions = self.sessions.lock().await;
+        let session = sessions.get(&hero_id)
+            .filter(|session| session.session_id == session_id)
+            .ok_or(ProtocolError::IdentityMismatch)?;
+        let (payload_size, nonce) = verify_tag(&session.secret, data)?;
+        if !session.replay_window.is_fresh(nonce)
+        {
+            return Err(ProtocolError::Replayed(nonce));
+        }
+        Ok(payload_size)
+    }
+
+    // checks the tag and uses the nonce, returns the size of the packet without the trailer.
+    pub async fn authenticate(&self, hero_id : u16, session_id : u64, data : &[u8]) -> Result<usize, ProtocolError>
+    {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.get_mut(&hero_id)
+            .filter(|session| session.session_id == session_id)
+            .ok_or(ProtocolError::IdentityMismatch)?;
+
+        let (payload_size, nonce) = verify_tag(&session.secret, data)?;
+        if !session.replay_window.accept(nonce)
+        {
+            return Err(ProtocolError::Replayed(nonce));
+        }
+        Ok(payload_size)
+    }
+}
+
+impl Default for SessionKeys
+{
+    fn default() -> Self
+    {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_window_rejects_repeated_and_old_nonces()
+    {
+        let mut window = ReplayWindow::new();
+        assert!(!window.accept(0));
+        assert!(window.accept(1));
+        assert!(window.accept(3));
+        assert!(window.accept(2));
+        assert!(!window.accept(2));
+        assert!(window.accept(100));
+        assert!(!window.accept(3));
+        assert!(window.accept(99));
+    }
+
+    #[tokio::test]
+    async fn signed_packets_are_accepted_once()
+    {
+        let keys = SessionKeys::new();
+        let secret = generate_secret();
+        keys.insert(7, 1234, secret).await;
+
+        let packet = [1u8, 2, 3, 4];
+        let signed = sign(&secret, 1, &packet);
+
+        assert_eq!(keys.check(7, 1234, &signed).await, Ok(packet.len()));
+        assert_eq!(keys.authenticate(7, 1234, &signed).await, Ok(packet.len()));
+        assert_eq!(keys.authenticate(7, 1234, &signed).await, Err(ProtocolError::Replayed(1)));
+        assert_eq!(keys.check(7, 1234, &signed).await, Err(ProtocolError::Replayed(1)));
+        assert_eq!(keys.authenticate(7, 999, &signed).await, Err(ProtocolError::IdentityMismatch));
+
+        let mut tampered = sign(&secret, 2, &packet);
+        tampered[0] = 9;
+        assert_eq!(keys.authenticate(7, 1234, &tampered).await, Err(ProtocolError::InvalidTag));
+
+        let other_secret = generate_secret();
+        let forged = sign(&other_secret, 3, &packet);
+        assert_eq!(keys.authenticate(7, 1234, &forged).await, Err(ProtocolError::InvalidTag));
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::map::tetrahedron_id::TetrahedronId;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError
+{
+    // the packet ended before the field we wanted to read
+    Truncated { offset : usize, needed : usize, packet_size : usize },
+    // the field was there but the value makes no sense
+    InvalidField(&'static str),
+    UnknownProtocol(u8),
+    // session id or hero id don't match the ones bound to the connection
+    IdentityMismatch,
+    // the client speaks a protocol version we can't encode for anymore
+    UnsupportedVersion(u16),
+    // udp packet not signed with the session secret
+    InvalidTag,
+    // udp packet with a nonce we already got
+    Replayed(u64),
+}
+
+impl fmt::Display for ProtocolError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ProtocolError::Truncated { offset, needed, packet_size } =>
+                write!(f, "truncated packet, needed {} bytes at offset {} but packet size is {}", needed, offset, packet_size),
+            ProtocolError::InvalidField(field) => write!(f, "invalid field {}", field),
+            ProtocolError::UnknownProtocol(protocol) => write!(f, "unknown protocol {}", protocol),
+            ProtocolError::IdentityMismatch => write!(f, "session or hero id mismatch"),
+            ProtocolError::UnsupportedVersion(version) => write!(f, "unsupported protocol version {}", version),
+            ProtocolError::InvalidTag => write!(f, "invalid packet tag"),
+            ProtocolError::Replayed(nonce) => write!(f, "replayed nonce {}", nonce),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketHeader
+{
+    pub session_id : u64,
+    pub player_id : u16,
+    pub faction : u8,
+}
+
+pub struct PacketReader<'a>
+{
+    data : &'a [u8],
+    offset : usize,
+}
+
+impl<'a> PacketReader<'a>
+{
+    // byte 0 is the protocol, route_packet already used it to pick the handler.
+    pub fn new(data : &'a [u8]) -> Self
+    {
+        PacketReader { data, offset: 1 }
+    }
+
+    pub fn offset(&self) -> usize
+    {
+        self.offset
+    }
+
+    pub fn remaining(&self) -> usize
+    {
+        self.data.len().saturating_sub(self.offset)
+    }
+
+    // useful before loops, so we don't process half a packet and then fail.
+    pub fn ensure(&self, size : usize) -> Result<(), ProtocolError>
+    {
+        if self.remaining() < size
+        {
+            return Err(ProtocolError::Truncated { offset: self.offset, needed: size, packet_size: self.data.len() });
+        }
+        Ok(())
+    }
+
+    pub fn read_bytes<const N: usize>(&mut self) -> Result<[u8; N], ProtocolError>
+    {
+        self.ensure(N)?;
+        let start = self.offset;
+        let end = start + N;
+        let mut buffer = [0u8; N];
+        buffer.copy_from_slice(&self.data[start..end]);
+        self.offset = end;
+        Ok(buffer)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, ProtocolError>
+    {
+        Ok(self.read_bytes::<1>()?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, ProtocolError>
+    {
+        Ok(u16::from_le_bytes(self.read_bytes()?))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, ProtocolError>
+    {
+        Ok(u32::from_le_bytes(self.read_bytes()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, ProtocolError>
+    {
+        Ok(u64::from_le_bytes(self.read_bytes()?))
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, ProtocolError>
+    {
+        Ok(i32::from_le_bytes(self.read_bytes()?))
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, ProtocolError>
+    {
+        let value = f32::from_le_bytes(self.read_bytes()?);
+        if !value.is_finite()
+        {
+            return Err(ProtocolError::InvalidField("f32"));
+        }
+        Ok(value)
+    }
+
+    pub fn read_tetrahedron_id(&mut self) -> Result<TetrahedronId, ProtocolError>
+    {
+        let buffer = self.read_bytes::<6>()?;
+        Ok(TetrahedronId::from_bytes(&buffer))
+    }
+
+    // session id (8 bytes), player id (2 bytes) and faction (1 byte), shared by almost every protocol.
+    pub fn read_header(&mut self) -> Result<PacketHeader, ProtocolError>
+    {
+        let session_id = self.read_u64()?;
+        let player_id = self.read_u16()?;
+        let faction = self.read_u8()?;
+        Ok(PacketHeader { session_id, player_id, faction })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_header_and_fields()
+    {
+        let mut data = vec![2u8];
+        data.extend_from_slice(&u64::to_le_bytes(123456));
+        data.extend_from_slice(&u16::to_le_bytes(42));
+        data.push(3);
+        data.extend_from_slice(&TetrahedronId::from_string("a0123").to_bytes());
+        data.extend_from_slice(&f32::to_le_bytes(0.5));
+
+        let mut reader = PacketReader::new(&data);
+        let header = reader.read_header().unwrap();
+        assert_eq!(header, PacketHeader { session_id: 123456, player_id: 42, faction: 3 });
+        assert_eq!(reader.read_tetrahedron_id().unwrap(), TetrahedronId::from_string("a0123"));
+        assert_eq!(reader.read_f32().unwrap(), 0.5);
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn truncated_packet_returns_error()
+    {
+        let data = [2u8, 1, 2, 3];
+        let mut reader = PacketReader::new(&data);
+        let result = reader.read_header();
+        assert_eq!(result, Err(ProtocolError::Truncated { offset: 1, needed: 8, packet_size: 4 }));
+
+        let empty : [u8; 0] = [];
+        let mut reader = PacketReader::new(&empty);
+        assert!(reader.read_u8().is_err());
+    }
+
+    #[test]
+    fn invalid_float_returns_error()
+    {
+        let mut data = vec![6u8];
+        data.extend_from_slice(&f32::to_le_bytes(f32::NAN));
+        let mut reader = PacketReader::new(&data);
+        assert_eq!(reader.read_f32(), Err(ProtocolError::InvalidField("f32")));
+    }
+}
@@ -1,55 +1,61 @@
+use std::sync::Arc;
+
 use bytes::Bytes;
-use tokio::sync::mpsc::Sender;
 use crate::gaia_mpsc::GaiaSender;
 use crate::gameplay_service::generic_command::GenericCommand;
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
+use super::packet_reader::{PacketReader, ProtocolError};
+use crate::clients_service::connection::{ConnectionId, ConnectionRegistry};
+
+pub const PONG_SIZE : usize = 27;
 
+// id (2 bytes), client time (8 bytes), the server time of the last pong the client got (8 bytes, 0 for none)
+// and how long the client held that pong before sending this ping (4 bytes). Times in ms.
+// the round trip is measured from when we sent that pong, the echoed time only has to match it.
+// the pong has the id, the client time back, and when we got the ping and sent the pong, so the
+// client can work out its own offset and round trip like ntp does.
 pub async fn process_ping(
-    player_address : std::net::SocketAddr, 
-    is_udp: bool,
+    connection : ConnectionId,
     generic_channel_tx : &GaiaSender<GenericCommand>,
-    data : &[u8])
+    data : &[u8],
+    connections : &Arc<ConnectionRegistry>) -> Result<(), ProtocolError>
 {
-    let start = 1;
-    let end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 2;
-    let _player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 1;
-    let _faction = data[start];
-
-    let start = end;
-    let end = start + 2;
-    let id = u16::from_le_bytes(data[start..end].try_into().unwrap()); 
-
-    let mut buffer = [0u8; 11];
-
-    let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
-    let current_time_in_millis = current_time.as_millis() as u64;
-
-    let time_bytes = u64::to_le_bytes(current_time_in_millis);
-
-    let id_bytes = u16::to_le_bytes(id);
-
-    let mut encoder = ZlibEncoder::new(Vec::new(),Compression::new(9));        
-    buffer[0] = crate::protocols::Protocol::Ping as u8;
-
-    let mut start = 1;
-    let mut end = start + 2; 
-    buffer[start..end].copy_from_slice(&id_bytes);
-    start = end;
-
-    end = start + 8;
-    buffer[start..end].copy_from_slice(&time_bytes);
-    //start = end;
-
+    let received_time = get_time_in_millis();
+    let mut reader = PacketReader::new(data);
+    let _header = reader.read_header()?;
+    let id = reader.read_u16()?;
+    let client_time = reader.read_u64()?;
+    let echoed_server_time = reader.read_u64()?;
+    let hold_ms = reader.read_u32()?;
+
+    connections.add_ping(connection, received_time, client_time, echoed_server_time, hold_ms).await;
+
+    let sent_time = get_time_in_millis();
+    connections.pong_sent(connection, sent_time).await;
+    let buffer = build_pong(id, client_time, received_time, sent_time);
+    let mut encoder = ZlibEncoder::new(Vec::new(),Compression::new(9));
     std::io::Write::write_all(&mut encoder, &buffer).unwrap();
     let compressed_bytes = encoder.reset(Vec::new()).unwrap();
 
-    generic_channel_tx.send(GenericCommand { player_address, is_udp, data: Bytes::from(compressed_bytes)}).await.unwrap();
-}
\ No newline at end of file
+    generic_channel_tx.send(GenericCommand { connection, data: Bytes::from(compressed_bytes), reliable: false }).await.unwrap();
+    Ok(())
+}
+
+// protocol (1 byte), id (2 bytes), client time (8 bytes), received time (8 bytes) and sent time (8 bytes).
+pub fn build_pong(id : u16, client_time : u64, received_time : u64, sent_time : u64) -> [u8; PONG_SIZE]
+{
+    let mut buffer = [0u8; PONG_SIZE];
+    buffer[0] = crate::protocols::Protocol::Ping as u8;
+    buffer[1..3].copy_from_slice(&u16::to_le_bytes(id));
+    buffer[3..11].copy_from_slice(&u64::to_le_bytes(client_time));
+    buffer[11..19].copy_from_slice(&u64::to_le_bytes(received_time));
+    buffer[19..27].copy_from_slice(&u64::to_le_bytes(sent_time));
+    buffer
+}
+
+fn get_time_in_millis() -> u64
+{
+    let current_time = std::time::SystemTime::now().duration_since(std::time::SystemTime::UNIX_EPOCH).unwrap();
+    current_time.as_millis() as u64
+}
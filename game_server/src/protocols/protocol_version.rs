@@ -0,0 +1,60 @@
+use bytes::Bytes;
+
+use super::Protocol;
+
+// bump this every time a packet or entity layout changes.
+// 1 - GlobalState header without region.
+// 2 - GlobalState header with region, delta states and entity acks.
+// 3 - session secret in the join response, udp packets signed with it.
+// 4 - Resume to move a udp session to a new address.
+// 5 - Reliable lane for one-off messages, acked with ReliableAck.
+// 6 - Fragment packets for messages bigger than a datagram.
+// 7 - GlobalState and DeltaState time in ms (8 bytes), ping with clock sync times.
+pub const PROTOCOL_VERSION : u16 = 7;
+// oldest version the encoders can still talk to, keep it at PROTOCOL_VERSION - 1
+// when the change allows it so clients that didn't update yet can keep playing.
+// the packet header changed size, older clients can't read any state packet.
+pub const MIN_PROTOCOL_VERSION : u16 = 7;
+
+// sent in a Rejected packet so the client can show something better than a timeout.
+pub enum RejectReason
+{
+    UnsupportedProtocolVersion = 1,
+    // the session is gone, the client has to join again.
+    SessionExpired = 2,
+}
+
+pub fn is_supported(version : u16) -> bool
+{
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+// protocol (1 byte), reason (1 byte), server version (2 bytes) and min version (2 bytes).
+pub fn build_rejection(reason : RejectReason) -> Bytes
+{
+    let mut buffer = Vec::with_capacity(6);
+    buffer.push(Protocol::Rejected as u8);
+    buffer.push(reason as u8);
+    buffer.extend_from_slice(&u16::to_le_bytes(PROTOCOL_VERSION));
+    buffer.extend_from_slice(&u16::to_le_bytes(MIN_PROTOCOL_VERSION));
+    Bytes::from(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_supported_versions_are_accepted()
+    {
+        assert!(is_supported(PROTOCOL_VERSION));
+        assert!(is_supported(MIN_PROTOCOL_VERSION));
+        assert!(!is_supported(0));
+        assert!(!is_supported(PROTOCOL_VERSION + 1));
+
+        let rejection = build_rejection(RejectReason::UnsupportedProtocolVersion);
+        assert_eq!(rejection[0], Protocol::Rejected as u8);
+        assert_eq!(rejection[1], RejectReason::UnsupportedProtocolVersion as u8);
+        assert_eq!(u16::from_le_bytes([rejection[2], rejection[3]]), PROTOCOL_VERSION);
+    }
+}
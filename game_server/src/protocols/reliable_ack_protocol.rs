@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use crate::clients_service::connection::{ConnectionId, ConnectionRegistry};
+use super::packet_reader::{PacketReader, ProtocolError};
+
+// the highest reliable sequence (4 bytes) the client received in order, 0 if none yet.
+// the first one also switches the client to the reliable lane.
+pub async fn process(connection : ConnectionId, data : &[u8], connections : &Arc<ConnectionRegistry>) -> Result<(), ProtocolError>
+{
+    let mut reader = PacketReader::new(data);
+    let _header = reader.read_header()?;
+    let sequence = reader.read_u32()?;
+
+    connections.ack_reliable(connection, sequence).await;
+    Ok(())
+}
@@ -1,34 +1,20 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{gaia_mpsc::GaiaSender, map::tetrahedron_id::TetrahedronId, tower::{TowerCommand, TowerCommandInfo}};
+use crate::{gaia_mpsc::GaiaSender, tower::{TowerCommand, TowerCommandInfo}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_tower_tx : &GaiaSender<TowerCommand>)
+    channel_tower_tx : &GaiaSender<TowerCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
+        let faction = header.faction;
 
-        start = end;
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-        start = end;
-        end = start + 1;
-        let faction = data[start];
-        start = end;
-
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-
-        start = end;
-        end = start + 2;
-        let repair_amount = u16::from_le_bytes(data[start..end].try_into().unwrap()); 
-        // start = end;
+        let tile_id = reader.read_tetrahedron_id()?;
+        let repair_amount = reader.read_u16()?; 
 
         let tower_action = TowerCommand{
             id: tile_id,
@@ -38,4 +24,5 @@ pub async fn process(
         cli_log::info!("got a {:?}", tower_action);
 
         channel_tower_tx.send(tower_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,1317 @@
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::clients_service::DataType;
+use crate::clients_service::fragmentation;
+use crate::map::tetrahedron_id::TetrahedronId;
+use super::entity_ack_protocol::MAX_ACKS_PER_REQUEST;
+use super::missing_packages_protocol::MAX_MISSING_PACKETS_PER_REQUEST;
+use super::packet_auth::AUTH_TRAILER_SIZE;
+use super::packet_reader::ProtocolError;
+use super::protocol_version::{MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use super::spectate_protocol::{MAX_SPECTATED_REGIONS, MAX_TOKEN_SIZE};
+use super::Protocol;
+
+// everything is little endian, a tetrahedron id is area (1 byte), id (4 bytes) and lod (1 byte).
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FieldType
+{
+    U8,
+    U16,
+    U32,
+    U64,
+    I32,
+    F32,
+    TetrahedronId,
+    // a few fields that repeat together, like the entries of a list.
+    Group { fields : &'static [Field] },
+}
+
+impl FieldType
+{
+    pub fn size(&self) -> usize
+    {
+        match self
+        {
+            FieldType::U8 => 1,
+            FieldType::U16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::U64 => 8,
+            FieldType::TetrahedronId => 6,
+            FieldType::Group { fields } => fields.iter().map(|field| field.max_size()).sum(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Count
+{
+    One,
+    Fixed(usize),
+    // as many as an earlier field says. Padded lists are always `padded_to` long on the wire,
+    // the rest are exactly `count` long and can't have more than `max`.
+    CountedBy { field : &'static str, max : Option<usize>, padded_to : Option<usize> },
+    // only there when the bit is set in an earlier field, deltas use it for the fields that changed.
+    // a list of `items` when it has one, a single value when it doesn't.
+    Masked { field : &'static str, bit : u8, items : Option<usize> },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Field
+{
+    pub name : &'static str,
+    #[serde(flatten)]
+    pub field_type : FieldType,
+    pub count : Count,
+}
+
+impl Field
+{
+    // the most this field can take on the wire, None for lists without a limit.
+    fn max_size(&self) -> usize
+    {
+        let items = match self.count
+        {
+            Count::One | Count::Masked { items: None, .. } => 1,
+            Count::Fixed(count) | Count::Masked { items: Some(count), .. } => count,
+            Count::CountedBy { max, padded_to, .. } => padded_to.or(max).unwrap_or(0),
+        };
+        items * self.field_type.size()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageSchema
+{
+    pub name : &'static str,
+    // the protocol or the data type, entities that only go through the web service have none.
+    pub code : Option<u8>,
+    // the whole packet, protocol byte included, goes through zlib.
+    pub compressed : bool,
+    pub fields : &'static [Field],
+}
+
+impl MessageSchema
+{
+    // None when the size depends on a list or on a mask.
+    pub fn fixed_size(&self) -> Option<usize>
+    {
+        let mut size = 0;
+        for field in self.fields
+        {
+            match field.count
+            {
+                Count::CountedBy { padded_to: None, .. } | Count::Masked { .. } => return None,
+                _ => size += field.max_size(),
+            }
+        }
+        Some(size)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProtocolSchema
+{
+    pub protocol_version : u16,
+    pub min_protocol_version : u16,
+    // udp requests end with a nonce and a tag, see packet_auth.
+    pub auth_trailer_size : usize,
+    pub max_datagram_size : usize,
+    // the most a client can put in one fragment, after the fragment fields and before the auth trailer.
+    pub max_fragment_payload : usize,
+    // fields after the protocol byte of GlobalState and DeltaState packets, the entities follow.
+    // both are compressed with zlib, protocol byte included.
+    pub state_header : &'static [Field],
+    // fields after the protocol byte.
+    pub requests : &'static [MessageSchema],
+    // what the server sends outside of the state packets, fields after the protocol byte.
+    pub responses : &'static [MessageSchema],
+    // fields after the data type byte in GlobalState and DeltaState packets, or as they are in other responses.
+    pub entities : &'static [MessageSchema],
+}
+
+const fn one(name : &'static str, field_type : FieldType) -> Field
+{
+    Field { name, field_type, count: Count::One }
+}
+
+const fn fixed(name : &'static str, field_type : FieldType, count : usize) -> Field
+{
+    Field { name, field_type, count: Count::Fixed(count) }
+}
+
+const fn counted(name : &'static str, field_type : FieldType, count_field : &'static str, max : Option<usize>) -> Field
+{
+    Field { name, field_type, count: Count::CountedBy { field: count_field, max, padded_to: None } }
+}
+
+const fn padded(name : &'static str, field_type : FieldType, count_field : &'static str, padded_to : usize) -> Field
+{
+    Field { name, field_type, count: Count::CountedBy { field: count_field, max: Some(padded_to), padded_to: Some(padded_to) } }
+}
+
+const fn masked(name : &'static str, field_type : FieldType, bit : u8) -> Field
+{
+    Field { name, field_type, count: Count::Masked { field: "mask", bit, items: None } }
+}
+
+const fn masked_list(name : &'static str, field_type : FieldType, count : usize, bit : u8) -> Field
+{
+    Field { name, field_type, count: Count::Masked { field: "mask", bit, items: Some(count) } }
+}
+
+const SESSION_ID : Field = one("session_id", FieldType::U64);
+const HERO_ID : Field = one("hero_id", FieldType::U16);
+const FACTION : Field = one("faction", FieldType::U8);
+
+const fn request(name : &'static str, protocol : Protocol, fields : &'static [Field]) -> MessageSchema
+{
+    MessageSchema { name, code: Some(protocol as u8), compressed: false, fields }
+}
+
+const fn response(name : &'static str, protocol : Protocol, compressed : bool, fields : &'static [Field]) -> MessageSchema
+{
+    MessageSchema { name, code: Some(protocol as u8), compressed, fields }
+}
+
+const fn entity(name : &'static str, data_type : Option<u8>, fields : &'static [Field]) -> MessageSchema
+{
+    MessageSchema { name, code: data_type, compressed: false, fields }
+}
+
+pub const STATE_HEADER : &[Field] = &[
+    one("packet_number", FieldType::U64),
+    // ms since the unix epoch.
+    one("server_time", FieldType::U64),
+    one("region", FieldType::U16),
+];
+
+pub const REQUESTS : &[MessageSchema] = &[
+    request("ping", Protocol::Ping, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("id", FieldType::U16),
+        one("client_time", FieldType::U64),
+        // from the last pong, 0 if there wasn't one.
+        one("echoed_server_time", FieldType::U64),
+        one("hold_ms", FieldType::U32),
+    ]),
+    request("character_movement", Protocol::CharacterMovement, &[
+        SESSION_ID, HERO_ID, FACTION,
+        // ignored, the server works out the regions from the position.
+        fixed("regions", FieldType::U8, 6),
+        one("position", FieldType::TetrahedronId),
+        one("second_position", FieldType::TetrahedronId),
+        one("vertex_id", FieldType::I32),
+        fixed("path", FieldType::U8, 6),
+    ]),
+    request("resource_extraction", Protocol::ResourceExtraction, &[SESSION_ID, HERO_ID, FACTION, one("tile_id", FieldType::TetrahedronId), one("damage", FieldType::U16)]),
+    request("inventory_request", Protocol::InventoryRequest, &[SESSION_ID, HERO_ID, FACTION, one("page", FieldType::U8)]),
+    request("lay_foundation", Protocol::LayFoundation, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("full_health", FieldType::U8),
+        one("count", FieldType::U16),
+        counted("foundations", FieldType::Group { fields: &[
+            one("tile_id", FieldType::TetrahedronId),
+            one("prop", FieldType::U32),
+            fixed("pathness", FieldType::F32, 3),
+        ]}, "count", None),
+    ]),
+    request("build", Protocol::Build, &[SESSION_ID, HERO_ID, FACTION, one("tile_id", FieldType::TetrahedronId), one("increment", FieldType::U32)]),
+    request("mob_attacks_walker", Protocol::MobAttacksWalker, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("attacker_mob_id", FieldType::U32),
+        one("tile_id", FieldType::TetrahedronId),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+        one("missed", FieldType::U8),
+    ]),
+    request("spawn_mob", Protocol::SpawnMob, &[SESSION_ID, HERO_ID, FACTION, one("tile_id", FieldType::TetrahedronId), one("mob_definition_id", FieldType::U32), one("level", FieldType::U8)]),
+    request("mob_moves", Protocol::MobMoves, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("mob_id", FieldType::U32),
+        one("origin_position", FieldType::TetrahedronId),
+        one("end_position", FieldType::TetrahedronId),
+        fixed("path", FieldType::U8, 6),
+    ]),
+    request("control_mob", Protocol::ControlMob, &[SESSION_ID, HERO_ID, FACTION, one("mob_id", FieldType::U32), one("tile_id", FieldType::TetrahedronId)]),
+    request("attack_mob", Protocol::AttackMob, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("mob_id", FieldType::U32),
+        one("tile_id", FieldType::TetrahedronId),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+        one("missed", FieldType::U8),
+    ]),
+    request("missing_packets", Protocol::MissingPackets, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("region", FieldType::U16),
+        one("count", FieldType::U8),
+        counted("packet_numbers", FieldType::U64, "count", Some(MAX_MISSING_PACKETS_PER_REQUEST as usize)),
+    ]),
+    request("attack_tower", Protocol::AttackTower, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("tile_id", FieldType::TetrahedronId),
+        one("event_id", FieldType::U16),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+    ]),
+    request("repair_tower", Protocol::RepairTower, &[SESSION_ID, HERO_ID, FACTION, one("tile_id", FieldType::TetrahedronId), one("repair_amount", FieldType::U16)]),
+    request("chat_message", Protocol::ChatMessage, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("tile_id", FieldType::TetrahedronId),
+        one("message_length", FieldType::U8),
+        // one character per u32.
+        counted("message", FieldType::U32, "message_length", Some(100)),
+    ]),
+    request("build_wall", Protocol::BuildWall, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("tile_id", FieldType::TetrahedronId),
+        one("endpoint_a", FieldType::TetrahedronId),
+        one("endpoint_b", FieldType::TetrahedronId),
+        one("wall_size", FieldType::U8),
+        one("prop", FieldType::U32),
+    ]),
+    request("sell_item", Protocol::SellItem, &[SESSION_ID, HERO_ID, FACTION, one("item_id", FieldType::U32), one("inventory_type", FieldType::U8), one("amount", FieldType::U16)]),
+    request("buy_item", Protocol::BuyItem, &[SESSION_ID, HERO_ID, FACTION, one("item_id", FieldType::U32), one("item_type", FieldType::U8), one("amount", FieldType::U16)]),
+    request("use_item", Protocol::UseItem, &[SESSION_ID, HERO_ID, FACTION, one("item_id", FieldType::U32), one("amount", FieldType::U16)]),
+    request("equip_item", Protocol::EquipItem, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("item_id", FieldType::U32),
+        one("inventory_type", FieldType::U8),
+        one("current_slot", FieldType::U8),
+        one("new_slot", FieldType::U8),
+    ]),
+    request("respawn", Protocol::Respawn, &[SESSION_ID, HERO_ID, FACTION, fixed("regions", FieldType::U8, 6), one("tile_id", FieldType::TetrahedronId)]),
+    request("character_action", Protocol::CharacterAction, &[SESSION_ID, HERO_ID, FACTION, one("action", FieldType::U8)]),
+    request("greet", Protocol::Greet, &[SESSION_ID, HERO_ID, FACTION, one("protocol_version", FieldType::U16)]),
+    request("activate_buff", Protocol::ActivateBuff, &[SESSION_ID, HERO_ID, FACTION, one("card_id", FieldType::U32)]),
+    request("character_attacks_character", Protocol::CharacterAttacksCharacter, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("other_hero_id", FieldType::U16),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+        one("active_effect", FieldType::U8),
+        one("missed", FieldType::U8),
+    ]),
+    request("touch_mob", Protocol::TouchMob, &[SESSION_ID, HERO_ID, FACTION, one("mob_id", FieldType::U32), one("tile_id", FieldType::TetrahedronId)]),
+    request("cast_mob_from_mob", Protocol::CastMobFromMob, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("caster_mob_id", FieldType::U32),
+        one("caster_tile_id", FieldType::TetrahedronId),
+        one("target_mob_id", FieldType::U32),
+        one("target_tile_id", FieldType::TetrahedronId),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+    ]),
+    request("craft_card", Protocol::CraftCard, &[SESSION_ID, HERO_ID, FACTION]),
+    request("try_enter_tower", Protocol::TryEnterTower, &[SESSION_ID, HERO_ID, FACTION]),
+    // these two have the tile before the faction.
+    request("enter_tower", Protocol::EnterTower, &[SESSION_ID, HERO_ID, one("tile_id", FieldType::TetrahedronId), FACTION]),
+    request("exit_tower", Protocol::ExitTower, &[SESSION_ID, HERO_ID, one("tile_id", FieldType::TetrahedronId), FACTION, one("points", FieldType::U8)]),
+    request("entity_ack", Protocol::EntityAck, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("count", FieldType::U8),
+        counted("entities", FieldType::Group { fields: &[
+            one("data_type", FieldType::U8),
+            // padded with zeros for the entities with shorter ids.
+            fixed("id", FieldType::U8, 6),
+            one("version", FieldType::U16),
+        ]}, "count", Some(MAX_ACKS_PER_REQUEST as usize)),
+    ]),
+    request("resume", Protocol::Resume, &[SESSION_ID, HERO_ID, FACTION]),
+    request("reliable_ack", Protocol::ReliableAck, &[SESSION_ID, HERO_ID, FACTION, one("sequence", FieldType::U32)]),
+    request("fragment", Protocol::Fragment, &[
+        SESSION_ID, HERO_ID, FACTION,
+        one("message_id", FieldType::U16),
+        one("index", FieldType::U8),
+        one("count", FieldType::U8),
+        // the rest of the datagram, a piece of the whole packet.
+    ]),
+    // no hero header, spectators don't have one.
+    request("spectate", Protocol::Spectate, &[
+        one("token_length", FieldType::U8),
+        counted("token", FieldType::U8, "token_length", Some(MAX_TOKEN_SIZE)),
+        // 0 to not follow anyone.
+        one("follow_hero_id", FieldType::U16),
+        one("count", FieldType::U8),
+        counted("regions", FieldType::U16, "count", Some(MAX_SPECTATED_REGIONS as usize)),
+    ]),
+];
+
+pub const RESPONSES : &[MessageSchema] = &[
+    // the answer to a ping, the times are in ms.
+    response("pong", Protocol::Ping, true, &[
+        one("id", FieldType::U16),
+        one("client_time", FieldType::U64),
+        one("received_time", FieldType::U64),
+        one("sent_time", FieldType::U64),
+    ]),
+    response("rejected", Protocol::Rejected, false, &[
+        // 1 unsupported protocol version, 2 session expired.
+        one("reason", FieldType::U8),
+        one("protocol_version", FieldType::U16),
+        one("min_protocol_version", FieldType::U16),
+    ]),
+    response("reliable", Protocol::Reliable, false, &[
+        // ack it with a ReliableAck, the rest of the packet is the message as it would be sent on its own.
+        one("sequence", FieldType::U32),
+    ]),
+    response("hero_data", Protocol::HeroData, true, &[one("hero", FieldType::Group { fields: HERO })]),
+];
+
+const DAMAGE_RECORD : &[Field] = &[one("event_id", FieldType::U16), one("faction", FieldType::U8), one("amount", FieldType::U16)];
+const HERO : &[Field] = &[
+    one("hero_id", FieldType::U16),
+    one("version", FieldType::U16),
+    one("faction", FieldType::U8),
+    one("position", FieldType::TetrahedronId),
+    fixed("path", FieldType::U8, 6),
+    one("time", FieldType::U32),
+    one("action", FieldType::U8),
+    one("flags", FieldType::U8),
+    one("inventory_version", FieldType::U8),
+    one("level", FieldType::U8),
+    one("experience", FieldType::U32),
+    one("available_skill_points", FieldType::U8),
+    one("weapon", FieldType::U8),
+    one("strength_points", FieldType::U8),
+    one("defense_points", FieldType::U8),
+    one("intelligence_points", FieldType::U8),
+    one("mana_points", FieldType::U8),
+    one("base_strength", FieldType::U16),
+    one("base_defense", FieldType::U16),
+    one("base_intelligence", FieldType::U16),
+    one("base_mana", FieldType::U16),
+    one("health", FieldType::U16),
+    fixed("buffs_summary", FieldType::U8, 5),
+];
+// the version of the base the delta applies to, and one bit per field of the entity.
+const DELTA_BASE_VERSION : Field = one("base_version", FieldType::U16);
+const DELTA_MASK : Field = one("mask", FieldType::U32);
+const INVENTORY_ITEM : &[Field] = &[one("item_id", FieldType::U32), one("equipped", FieldType::U8), one("amount", FieldType::U16)];
+
+pub const ENTITIES : &[MessageSchema] = &[
+    entity("hero", Some(DataType::PlayerState as u8), HERO),
+    entity("tile", Some(DataType::TileState as u8), &[
+        one("version", FieldType::U16),
+        one("id", FieldType::TetrahedronId),
+        one("owner_id", FieldType::U16),
+        one("ownership_time", FieldType::U32),
+        one("time", FieldType::U32),
+        one("prop", FieldType::U32),
+        one("faction", FieldType::U8),
+        one("level", FieldType::U8),
+        one("temperature", FieldType::F32),
+        one("moisture", FieldType::F32),
+        fixed("heights", FieldType::F32, 3),
+        fixed("pathness", FieldType::F32, 3),
+        one("health", FieldType::U16),
+        one("constitution", FieldType::U16),
+        one("mana", FieldType::U16),
+    ]),
+    entity("hero_presentation", Some(DataType::PlayerPresentation as u8), &[
+        one("hero_id", FieldType::U16),
+        // one character per u32.
+        fixed("name", FieldType::U32, 5),
+    ]),
+    entity("attack", Some(DataType::Attack as u8), &[
+        one("id", FieldType::U16),
+        one("attacker_hero_id", FieldType::U16),
+        one("attacker_mob_id", FieldType::U32),
+        one("target_hero_id", FieldType::U16),
+        one("target_mob_id", FieldType::U32),
+        one("target_tile_id", FieldType::TetrahedronId),
+        one("card_id", FieldType::U32),
+        one("required_time", FieldType::U32),
+        one("battle_type", FieldType::U8),
+    ]),
+    entity("hero_reward", Some(DataType::PlayerReward as u8), &[
+        one("hero_id", FieldType::U16),
+        one("item_id", FieldType::U32),
+        one("amount", FieldType::U16),
+        one("inventory_hash", FieldType::U8),
+        fixed("unused", FieldType::U8, 3),
+    ]),
+    entity("tower", Some(DataType::TowerState as u8), &[
+        one("version", FieldType::U16),
+        one("id", FieldType::TetrahedronId),
+        one("event_id", FieldType::U16),
+        one("faction", FieldType::U8),
+        // the records of the current event, zeros after them.
+        fixed("damage_records", FieldType::Group { fields: DAMAGE_RECORD }, 10),
+    ]),
+    entity("chat_message", Some(DataType::ChatMessage as u8), &[
+        one("tile_id", FieldType::TetrahedronId),
+        one("timestamp", FieldType::U32),
+        one("hero_id", FieldType::U16),
+        one("faction", FieldType::U8),
+        one("message_length", FieldType::U8),
+        padded("message", FieldType::U32, "message_length", 100),
+    ]),
+    // what changed since the version the client acked, the fields are there when their bit is set in the mask.
+    // the key and the version are always set, see delta_encoder.
+    entity("hero_delta", Some(DataType::PlayerStateDelta as u8), &[
+        DELTA_BASE_VERSION, DELTA_MASK,
+        masked("hero_id", FieldType::U16, 0),
+        masked("version", FieldType::U16, 1),
+        masked("faction", FieldType::U8, 2),
+        masked("position", FieldType::TetrahedronId, 3),
+        masked_list("path", FieldType::U8, 6, 4),
+        masked("time", FieldType::U32, 5),
+        masked("action", FieldType::U8, 6),
+        masked("flags", FieldType::U8, 7),
+        masked("inventory_version", FieldType::U8, 8),
+        masked("level", FieldType::U8, 9),
+        masked("experience", FieldType::U32, 10),
+        masked("available_skill_points", FieldType::U8, 11),
+        masked("weapon", FieldType::U8, 12),
+        masked("strength_points", FieldType::U8, 13),
+        masked("defense_points", FieldType::U8, 14),
+        masked("intelligence_points", FieldType::U8, 15),
+        masked("mana_points", FieldType::U8, 16),
+        masked("base_strength", FieldType::U16, 17),
+        masked("base_defense", FieldType::U16, 18),
+        masked("base_intelligence", FieldType::U16, 19),
+        masked("base_mana", FieldType::U16, 20),
+        masked("health", FieldType::U16, 21),
+        masked_list("buffs_summary", FieldType::U8, 5, 22),
+    ]),
+    // each height and pathness has its own bit.
+    entity("tile_delta", Some(DataType::TileStateDelta as u8), &[
+        DELTA_BASE_VERSION, DELTA_MASK,
+        masked("version", FieldType::U16, 0),
+        masked("id", FieldType::TetrahedronId, 1),
+        masked("owner_id", FieldType::U16, 2),
+        masked("ownership_time", FieldType::U32, 3),
+        masked("time", FieldType::U32, 4),
+        masked("prop", FieldType::U32, 5),
+        masked("faction", FieldType::U8, 6),
+        masked("level", FieldType::U8, 7),
+        masked("temperature", FieldType::F32, 8),
+        masked("moisture", FieldType::F32, 9),
+        masked("height_0", FieldType::F32, 10),
+        masked("height_1", FieldType::F32, 11),
+        masked("height_2", FieldType::F32, 12),
+        masked("pathness_0", FieldType::F32, 13),
+        masked("pathness_1", FieldType::F32, 14),
+        masked("pathness_2", FieldType::F32, 15),
+        masked("health", FieldType::U16, 16),
+        masked("constitution", FieldType::U16, 17),
+        masked("mana", FieldType::U16, 18),
+    ]),
+    entity("mob_delta", Some(DataType::MobStatusDelta as u8), &[
+        DELTA_BASE_VERSION, DELTA_MASK,
+        masked("mob_id", FieldType::U32, 0),
+        masked("mob_definition_id", FieldType::U16, 1),
+        masked("level", FieldType::U8, 2),
+        masked("version", FieldType::U8, 3),
+        masked("owner_id", FieldType::U16, 4),
+        masked("ownership_time", FieldType::U32, 5),
+        masked("start_position", FieldType::TetrahedronId, 6),
+        masked_list("path", FieldType::U8, 6, 7),
+        masked("time", FieldType::U32, 8),
+        masked("health", FieldType::U16, 9),
+        masked_list("buffs_summary", FieldType::U8, 5, 10),
+    ]),
+    // each damage record has its own bit.
+    entity("tower_delta", Some(DataType::TowerStateDelta as u8), &[
+        DELTA_BASE_VERSION, DELTA_MASK,
+        masked("version", FieldType::U16, 0),
+        masked("id", FieldType::TetrahedronId, 1),
+        masked("event_id", FieldType::U16, 2),
+        masked("faction", FieldType::U8, 3),
+        masked("damage_record_0", FieldType::Group { fields: DAMAGE_RECORD }, 4),
+        masked("damage_record_1", FieldType::Group { fields: DAMAGE_RECORD }, 5),
+        masked("damage_record_2", FieldType::Group { fields: DAMAGE_RECORD }, 6),
+        masked("damage_record_3", FieldType::Group { fields: DAMAGE_RECORD }, 7),
+        masked("damage_record_4", FieldType::Group { fields: DAMAGE_RECORD }, 8),
+        masked("damage_record_5", FieldType::Group { fields: DAMAGE_RECORD }, 9),
+        masked("damage_record_6", FieldType::Group { fields: DAMAGE_RECORD }, 10),
+        masked("damage_record_7", FieldType::Group { fields: DAMAGE_RECORD }, 11),
+        masked("damage_record_8", FieldType::Group { fields: DAMAGE_RECORD }, 12),
+        masked("damage_record_9", FieldType::Group { fields: DAMAGE_RECORD }, 13),
+    ]),
+    entity("server_status", Some(DataType::ServerStatus as u8), &[fixed("stats", FieldType::U16, 10)]),
+    entity("mob", Some(DataType::MobStatus as u8), &[
+        one("mob_id", FieldType::U32),
+        one("mob_definition_id", FieldType::U16),
+        one("level", FieldType::U8),
+        one("version", FieldType::U8),
+        one("owner_id", FieldType::U16),
+        one("ownership_time", FieldType::U32),
+        one("start_position", FieldType::TetrahedronId),
+        fixed("path", FieldType::U8, 6),
+        one("time", FieldType::U32),
+        one("health", FieldType::U16),
+        fixed("buffs_summary", FieldType::U8, 5),
+    ]),
+    entity("attack_details", Some(DataType::AttackDetails as u8), &[
+        one("id", FieldType::U16),
+        one("card_id", FieldType::U32),
+        one("attacker_hero_id", FieldType::U16),
+        one("attacker_mob_id", FieldType::U32),
+        one("target_hero_id", FieldType::U16),
+        one("target_mob_id", FieldType::U32),
+        one("target_tile_id", FieldType::TetrahedronId),
+        one("battle_type", FieldType::U8),
+        one("result", FieldType::U8),
+    ]),
+    entity("kingdom", None, &[one("version", FieldType::U16), one("id", FieldType::TetrahedronId), one("faction", FieldType::U8)]),
+    entity("inventory_item", None, INVENTORY_ITEM),
+    entity("card_item", None, &[one("card_id", FieldType::U32), one("equipped", FieldType::U8), one("amount", FieldType::U16)]),
+    entity("weapon_item", None, &[one("weapon_id", FieldType::U32), one("equipped", FieldType::U8), one("amount", FieldType::U16)]),
+];
+
+pub fn get_schema() -> ProtocolSchema
+{
+    ProtocolSchema
+    {
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        auth_trailer_size: AUTH_TRAILER_SIZE,
+        max_datagram_size: fragmentation::MAX_DATAGRAM_SIZE,
+        max_fragment_payload: fragmentation::MAX_CLIENT_FRAGMENT_PAYLOAD,
+        state_header: STATE_HEADER,
+        requests: REQUESTS,
+        responses: RESPONSES,
+        entities: ENTITIES,
+    }
+}
+
+pub fn get_request(protocol : u8) -> Option<&'static MessageSchema>
+{
+    REQUESTS.iter().find(|schema| schema.code == Some(protocol))
+}
+
+pub fn get_request_by_name(name : &str) -> Option<&'static MessageSchema>
+{
+    REQUESTS.iter().find(|schema| schema.name == name)
+}
+
+pub fn get_response(name : &str) -> Option<&'static MessageSchema>
+{
+    RESPONSES.iter().find(|schema| schema.name == name)
+}
+
+pub fn get_entity(name : &str) -> Option<&'static MessageSchema>
+{
+    ENTITIES.iter().find(|schema| schema.name == name)
+}
+
+// reads the fields into json, for tools and for checking the descriptors against the encoders.
+// returns the fields and how many bytes they took.
+pub fn decode(fields : &[Field], data : &[u8]) -> Result<(Map<String, Value>, usize), ProtocolError>
+{
+    let mut values = Map::new();
+    let mut offset = 0;
+    for field in fields
+    {
+        let (items, padded_to) = match field.count
+        {
+            Count::One => (None, None),
+            Count::Fixed(count) => (Some(count), None),
+            Count::CountedBy { field: count_field, max, padded_to } =>
+            {
+                let count = values.get(count_field).and_then(|value| value.as_u64()).ok_or(ProtocolError::InvalidField(count_field))? as usize;
+                if max.is_some_and(|max| count > max)
+                {
+                    return Err(ProtocolError::InvalidField(count_field));
+                }
+                (Some(count), padded_to)
+            },
+            Count::Masked { field: mask_field, bit, items } =>
+            {
+                let mask = values.get(mask_field).and_then(|value| value.as_u64()).ok_or(ProtocolError::InvalidField(mask_field))?;
+                if mask & (1 << bit) == 0
+                {
+                    continue;
+                }
+                (items, None)
+            },
+        };
+
+        let value = match items
+        {
+            None => decode_value(&field.field_type, data, &mut offset)?,
+            Some(count) =>
+            {
+                let list = (0..count)
+                    .map(|_| decode_value(&field.field_type, data, &mut offset))
+                    .collect::<Result<Vec<Value>, ProtocolError>>()?;
+                Value::Array(list)
+            },
+        };
+
+        if let (Some(padded_to), Some(count)) = (padded_to, items)
+        {
+            offset += (padded_to - count) * field.field_type.size();
+        }
+        values.insert(field.name.to_string(), value);
+    }
+
+    if offset > data.len()
+    {
+        return Err(ProtocolError::Truncated { offset: data.len(), needed: offset - data.len(), packet_size: data.len() });
+    }
+    Ok((values, offset))
+}
+
+fn take<'a>(data : &'a [u8], offset : &mut usize, size : usize) -> Result<&'a [u8], ProtocolError>
+{
+    let end = *offset + size;
+    let bytes = data.get(*offset..end).ok_or(ProtocolError::Truncated { offset: *offset, needed: size, packet_size: data.len() })?;
+    *offset = end;
+    Ok(bytes)
+}
+
+fn decode_value(field_type : &FieldType, data : &[u8], offset : &mut usize) -> Result<Value, ProtocolError>
+{
+    let bytes = match field_type
+    {
+        FieldType::Group { fields } =>
+        {
+            let (values, size) = decode(fields, &data[usize::min(*offset, data.len())..])?;
+            *offset += size;
+            return Ok(Value::Object(values));
+        },
+        _ => take(data, offset, field_type.size())?,
+    };
+
+    let value = match field_type
+    {
+        FieldType::U8 => json!(bytes[0]),
+        FieldType::U16 => json!(u16::from_le_bytes(bytes.try_into().unwrap())),
+        FieldType::U32 => json!(u32::from_le_bytes(bytes.try_into().unwrap())),
+        FieldType::U64 => json!(u64::from_le_bytes(bytes.try_into().unwrap())),
+        FieldType::I32 => json!(i32::from_le_bytes(bytes.try_into().unwrap())),
+        FieldType::F32 => json!(f32::from_le_bytes(bytes.try_into().unwrap())),
+        FieldType::TetrahedronId => json!(TetrahedronId::from_bytes(bytes.try_into().unwrap()).to_string()),
+        FieldType::Group { .. } => unreachable!(),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ability_user::attack::{Attack, ATTACK_SIZE};
+    use crate::ability_user::attack_result::{AttackResult, ATTACK_RESULT_SIZE};
+    use crate::chat::chat_entry::{ChatEntry, CHAT_ENTRY_SIZE};
+    use crate::hero::hero_card_inventory::CardItem;
+    use crate::hero::hero_entity::{HeroEntity, HERO_ENTITY_SIZE};
+    use crate::hero::hero_inventory::InventoryItem;
+    use crate::hero::hero_presentation::{HeroPresentation, HERO_PRESENTATION_SIZE};
+    use crate::hero::hero_reward::{HeroReward, HERO_REWARD_SIZE};
+    use crate::hero::hero_weapon_inventory::WeaponItem;
+    use crate::kingdom::kingdom_entity::{KingdomEntity, KINGDOM_ENTITY_SIZE};
+    use crate::map::map_entity::{MapEntity, MAP_ENTITY_SIZE};
+    use crate::mob::mob_entity::{MobEntity, MOB_ENTITY_SIZE};
+    use crate::tower::tower_entity::{DamageByFaction, TowerEntity, TOWER_ENTITY_SIZE};
+    use crate::{ServerState, SERVER_STATE_SIZE};
+    use crate::protocols::entity_ack_protocol::ACK_ENTRY_SIZE;
+    use crate::gameplay_service::data_packer::{write_packet_header, PACKET_HEADER_SIZE};
+    use crate::protocols::layfoundation_protocol::FOUNDATION_ENTRY_SIZE;
+    use crate::protocols::ping_protocol::{build_pong, PONG_SIZE};
+    use crate::protocols::protocol_version::{build_rejection, RejectReason};
+    use crate::protocols::try_enter_tower_request_protocol::pack_hero_data;
+    use crate::gameplay_service::delta_encoder::{encode_delta, EntityLayout, HERO_LAYOUT, MOB_LAYOUT, TILE_LAYOUT, TOWER_LAYOUT};
+    use crate::gameplay_service::generic_command::GenericCommand;
+    use crate::clients_service::reliable_channel;
+    use crate::clients_service::connection::{ConnectionId, ConnectionRegistry};
+    use crate::chat::ChatCommand;
+    use crate::hero::hero_command::HeroCommand;
+    use crate::kingdom::KingdomCommand;
+    use crate::map::GameMap;
+    use crate::map::map_entity::MapCommand;
+    use crate::mob::mob_command::MobCommand;
+    use crate::tower::TowerCommand;
+    use crate::{gaia_mpsc, ServerChannels};
+    use std::sync::Arc;
+
+    #[test]
+    fn state_header_matches_the_packer()
+    {
+        let mut buffer = [0u8; 5000];
+        let size = write_packet_header(&mut buffer, Protocol::GlobalState as u8, 42, 1_700_000_000_123, 7);
+        assert_eq!(size, PACKET_HEADER_SIZE);
+
+        let (values, decoded_size) = decode(STATE_HEADER, &buffer[1..size]).unwrap();
+        assert_eq!(decoded_size + 1, size);
+        assert_eq!(values["packet_number"], json!(42));
+        assert_eq!(values["region"], json!(7));
+        // the tick time in ms, not in seconds.
+        assert_eq!(values["server_time"], json!(1_700_000_000_123u64));
+    }
+
+    fn entry_size(schema : &MessageSchema, name : &str) -> usize
+    {
+        schema.fields.iter().find(|field| field.name == name).unwrap().field_type.size()
+    }
+
+    fn decode_entity(name : &str, data : &[u8]) -> Map<String, Value>
+    {
+        let schema = get_entity(name).unwrap();
+        assert_eq!(schema.fixed_size(), Some(data.len()), "{} size", name);
+        let (values, size) = decode(schema.fields, data).unwrap();
+        assert_eq!(size, data.len(), "{} decoded size", name);
+        values
+    }
+
+    fn tile(id : &str) -> TetrahedronId
+    {
+        TetrahedronId::from_string(id)
+    }
+
+    fn create_hero() -> HeroEntity
+    {
+        // every field different, so a field read from the wrong offset shows up.
+        HeroEntity
+        {
+            version: 7,
+            action: 3,
+            flags: 4,
+            second_position: tile("b0123"),
+            path: [1, 2, 3, 4, 5, 6],
+            time: 100_000,
+            inventory_version: 5,
+            health: 900,
+            level: 6,
+            experience: 70_000,
+            available_skill_points: 8,
+            weapon: 9,
+            base_strength: 11,
+            base_defense: 12,
+            base_intelligence: 13,
+            base_mana: 14,
+            strength_points: 15,
+            defense_points: 16,
+            intelligence_points: 17,
+            mana_points: 18,
+            buffs_summary: [21, 22, 23, 24, 25],
+            ..HeroEntity::for_tests(1234, 2, tile("a0123"))
+        }
+    }
+
+    #[test]
+    fn hero_and_tile_match_their_encoders()
+    {
+        let hero = create_hero();
+        assert_eq!(HERO_ENTITY_SIZE, get_entity("hero").unwrap().fixed_size().unwrap());
+        let values = decode_entity("hero", &hero.to_bytes());
+        assert_eq!(values["hero_id"], json!(1234));
+        assert_eq!(values["version"], json!(7));
+        assert_eq!(values["faction"], json!(2));
+        assert_eq!(values["position"], json!("a0123"));
+        assert_eq!(values["path"], json!([1, 2, 3, 4, 5, 6]));
+        assert_eq!(values["time"], json!(100_000));
+        assert_eq!(values["action"], json!(3));
+        assert_eq!(values["flags"], json!(4));
+        assert_eq!(values["inventory_version"], json!(5));
+        assert_eq!(values["level"], json!(6));
+        assert_eq!(values["experience"], json!(70_000));
+        assert_eq!(values["available_skill_points"], json!(8));
+        assert_eq!(values["weapon"], json!(9));
+        assert_eq!(values["strength_points"], json!(15));
+        assert_eq!(values["defense_points"], json!(16));
+        assert_eq!(values["intelligence_points"], json!(17));
+        assert_eq!(values["mana_points"], json!(18));
+        assert_eq!(values["base_strength"], json!(11));
+        assert_eq!(values["base_defense"], json!(12));
+        assert_eq!(values["base_intelligence"], json!(13));
+        assert_eq!(values["base_mana"], json!(14));
+        assert_eq!(values["health"], json!(900));
+        assert_eq!(values["buffs_summary"], json!([21, 22, 23, 24, 25]));
+
+        let mut map_tile = MapEntity::new("a0123", 100);
+        map_tile.version = 3;
+        map_tile.owner_id = 4;
+        map_tile.ownership_time = 5;
+        map_tile.time = 6;
+        map_tile.prop = 7;
+        map_tile.faction = 8;
+        map_tile.level = 9;
+        map_tile.temperature = 0.5;
+        map_tile.moisture = 0.25;
+        map_tile.heights = [1.0, 2.0, 3.0];
+        map_tile.pathness = [4.0, 5.0, 6.0];
+        map_tile.constitution = 11;
+        map_tile.mana = 12;
+        assert_eq!(MAP_ENTITY_SIZE, get_entity("tile").unwrap().fixed_size().unwrap());
+        let values = decode_entity("tile", &map_tile.to_bytes());
+        assert_eq!(values["version"], json!(3));
+        assert_eq!(values["id"], json!("a0123"));
+        assert_eq!(values["owner_id"], json!(4));
+        assert_eq!(values["ownership_time"], json!(5));
+        assert_eq!(values["time"], json!(6));
+        assert_eq!(values["prop"], json!(7));
+        assert_eq!(values["faction"], json!(8));
+        assert_eq!(values["level"], json!(9));
+        assert_eq!(values["temperature"], json!(0.5));
+        assert_eq!(values["moisture"], json!(0.25));
+        assert_eq!(values["heights"], json!([1.0, 2.0, 3.0]));
+        assert_eq!(values["pathness"], json!([4.0, 5.0, 6.0]));
+        assert_eq!(values["health"], json!(100));
+        assert_eq!(values["constitution"], json!(11));
+        assert_eq!(values["mana"], json!(12));
+    }
+
+    #[test]
+    fn mob_tower_and_kingdom_match_their_encoders()
+    {
+        let mob = MobEntity
+        {
+            mob_id: 100_000,
+            mob_definition_id: 2,
+            level: 3,
+            version: 4,
+            owner_id: 5,
+            ownership_time: 6,
+            start_position_id: tile("c0123"),
+            end_position_id: tile("d0123"),
+            path: [1, 2, 3, 4, 5, 6],
+            time: 7,
+            health: 8,
+            buffs: Vec::new(),
+            buffs_summary: [9, 10, 11, 12, 13],
+        };
+        assert_eq!(MOB_ENTITY_SIZE, get_entity("mob").unwrap().fixed_size().unwrap());
+        let values = decode_entity("mob", &mob.to_bytes());
+        assert_eq!(values["mob_id"], json!(100_000));
+        assert_eq!(values["mob_definition_id"], json!(2));
+        assert_eq!(values["level"], json!(3));
+        assert_eq!(values["version"], json!(4));
+        assert_eq!(values["owner_id"], json!(5));
+        assert_eq!(values["ownership_time"], json!(6));
+        assert_eq!(values["start_position"], json!("c0123"));
+        assert_eq!(values["path"], json!([1, 2, 3, 4, 5, 6]));
+        assert_eq!(values["time"], json!(7));
+        assert_eq!(values["health"], json!(8));
+        assert_eq!(values["buffs_summary"], json!([9, 10, 11, 12, 13]));
+
+        let tower = TowerEntity
+        {
+            object_id: None,
+            version: 2,
+            tetrahedron_id: tile("e0123"),
+            event_id: 3,
+            faction: 4,
+            damage_received_in_event: vec![
+                DamageByFaction { event_id: 3, faction: 1, amount: 500 },
+                DamageByFaction { event_id: 2, faction: 2, amount: 600 },
+            ],
+        };
+        assert_eq!(TOWER_ENTITY_SIZE, get_entity("tower").unwrap().fixed_size().unwrap());
+        let values = decode_entity("tower", &tower.to_bytes());
+        assert_eq!(values["version"], json!(2));
+        assert_eq!(values["id"], json!("e0123"));
+        assert_eq!(values["event_id"], json!(3));
+        assert_eq!(values["faction"], json!(4));
+        assert_eq!(values["damage_records"][0], json!({ "event_id": 3, "faction": 1, "amount": 500 }));
+        // old events are not sent.
+        assert_eq!(values["damage_records"][1], json!({ "event_id": 0, "faction": 0, "amount": 0 }));
+
+        let kingdom = KingdomEntity { version: 5, tetrahedron_id: tile("f0123"), faction: 6, object_id: None };
+        assert_eq!(KINGDOM_ENTITY_SIZE, get_entity("kingdom").unwrap().fixed_size().unwrap());
+        let values = decode_entity("kingdom", &kingdom.to_bytes());
+        assert_eq!(values["version"], json!(5));
+        assert_eq!(values["id"], json!("f0123"));
+        assert_eq!(values["faction"], json!(6));
+    }
+
+    #[test]
+    fn small_entities_match_their_encoders()
+    {
+        let presentation = HeroPresentation { player_id: 3, character_name: [65, 66, 67, 68, 69] };
+        assert_eq!(HERO_PRESENTATION_SIZE, get_entity("hero_presentation").unwrap().fixed_size().unwrap());
+        let values = decode_entity("hero_presentation", &presentation.to_bytes());
+        assert_eq!(values["hero_id"], json!(3));
+        assert_eq!(values["name"], json!([65, 66, 67, 68, 69]));
+
+        let reward = HeroReward { player_id: 4, item_id: 100_000, amount: 6, inventory_hash: 7 };
+        assert_eq!(HERO_REWARD_SIZE, get_entity("hero_reward").unwrap().fixed_size().unwrap());
+        let values = decode_entity("hero_reward", &reward.to_bytes());
+        assert_eq!(values["hero_id"], json!(4));
+        assert_eq!(values["item_id"], json!(100_000));
+        assert_eq!(values["amount"], json!(6));
+        assert_eq!(values["inventory_hash"], json!(7));
+
+        let attack = Attack
+        {
+            id: 1,
+            attacker_hero_id: 2,
+            attacker_mob_id: 3,
+            target_hero_id: 4,
+            target_mob_id: 5,
+            target_tile_id: tile("a0123"),
+            card_id: 6,
+            required_time: 7,
+            battle_type: 8,
+        };
+        assert_eq!(ATTACK_SIZE, get_entity("attack").unwrap().fixed_size().unwrap());
+        let values = decode_entity("attack", &attack.to_bytes());
+        assert_eq!(values["id"], json!(1));
+        assert_eq!(values["attacker_hero_id"], json!(2));
+        assert_eq!(values["attacker_mob_id"], json!(3));
+        assert_eq!(values["target_hero_id"], json!(4));
+        assert_eq!(values["target_mob_id"], json!(5));
+        assert_eq!(values["target_tile_id"], json!("a0123"));
+        assert_eq!(values["card_id"], json!(6));
+        assert_eq!(values["required_time"], json!(7));
+        assert_eq!(values["battle_type"], json!(8));
+
+        let result = AttackResult
+        {
+            id: 1,
+            card_id: 2,
+            attacker_character_id: 3,
+            attacker_mob_id: 4,
+            target_character_id: 5,
+            target_mob_id: 6,
+            target_tile_id: tile("b0123"),
+            battle_type: 7,
+            result: 8,
+        };
+        assert_eq!(ATTACK_RESULT_SIZE, get_entity("attack_details").unwrap().fixed_size().unwrap());
+        let values = decode_entity("attack_details", &result.to_bytes());
+        assert_eq!(values["id"], json!(1));
+        assert_eq!(values["card_id"], json!(2));
+        assert_eq!(values["attacker_hero_id"], json!(3));
+        assert_eq!(values["attacker_mob_id"], json!(4));
+        assert_eq!(values["target_hero_id"], json!(5));
+        assert_eq!(values["target_mob_id"], json!(6));
+        assert_eq!(values["target_tile_id"], json!("b0123"));
+        assert_eq!(values["battle_type"], json!(7));
+        assert_eq!(values["result"], json!(8));
+
+        let mut message = [0u32; 100];
+        message[..3].copy_from_slice(&[72, 73, 74]);
+        let chat = ChatEntry { tetrahedron_id: tile("c0123"), timestamp: 9, player_id: 10, faction: 11, message_length: 3, message };
+        assert_eq!(CHAT_ENTRY_SIZE, get_entity("chat_message").unwrap().fixed_size().unwrap());
+        let values = decode_entity("chat_message", &chat.to_bytes());
+        assert_eq!(values["tile_id"], json!("c0123"));
+        assert_eq!(values["timestamp"], json!(9));
+        assert_eq!(values["hero_id"], json!(10));
+        assert_eq!(values["faction"], json!(11));
+        assert_eq!(values["message"], json!([72, 73, 74]));
+
+        let stats = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+        assert_eq!(SERVER_STATE_SIZE, get_entity("server_status").unwrap().fixed_size().unwrap());
+        let values = decode_entity("server_status", &ServerState::stats_to_bytes(&stats));
+        assert_eq!(values["stats"], json!(stats));
+
+        let item = InventoryItem { item_id: 100_000, equipped: 1, amount: 2 };
+        assert_eq!(decode_entity("inventory_item", &item.to_bytes())["item_id"], json!(100_000));
+        let card = CardItem { card_id: 3, equipped: 1, amount: 4 };
+        assert_eq!(decode_entity("card_item", &card.to_bytes())["amount"], json!(4));
+        let weapon = WeaponItem { weapon_id: 5, equipped: 1, amount: 6 };
+        assert_eq!(decode_entity("weapon_item", &weapon.to_bytes())["weapon_id"], json!(5));
+    }
+
+    #[test]
+    fn requests_are_described_once()
+    {
+        let mut codes = std::collections::HashSet::new();
+        for schema in REQUESTS
+        {
+            assert!(codes.insert(schema.code), "{} is described twice", schema.name);
+            assert_eq!(get_request(schema.code.unwrap()).unwrap().name, schema.name);
+        }
+
+        // the same bytes the test client builds for a movement.
+        let mut packet = vec![Protocol::CharacterMovement as u8];
+        packet.extend_from_slice(&u64::to_le_bytes(99));
+        packet.extend_from_slice(&u16::to_le_bytes(7));
+        packet.push(1);
+        packet.extend_from_slice(&[0; 6]);
+        packet.extend_from_slice(&tile("a0123").to_bytes());
+        packet.extend_from_slice(&tile("a0122").to_bytes());
+        packet.extend_from_slice(&i32::to_le_bytes(-1));
+        packet.extend_from_slice(&[1, 2, 3, 0, 0, 0]);
+
+        let schema = get_request(Protocol::CharacterMovement as u8).unwrap();
+        assert_eq!(schema.fixed_size(), Some(packet.len() - 1));
+        let (values, _) = decode(schema.fields, &packet[1..]).unwrap();
+        assert_eq!(values["session_id"], json!(99));
+        assert_eq!(values["second_position"], json!("a0122"));
+        assert_eq!(values["vertex_id"], json!(-1));
+
+        // the parsers check the whole list is there before reading it.
+        assert_eq!(entry_size(get_request(Protocol::EntityAck as u8).unwrap(), "entities"), ACK_ENTRY_SIZE);
+        assert_eq!(entry_size(get_request(Protocol::LayFoundation as u8).unwrap(), "foundations"), FOUNDATION_ENTRY_SIZE);
+
+        // lists can't go over their limits.
+        let chat = get_request(Protocol::ChatMessage as u8).unwrap();
+        assert_eq!(chat.fixed_size(), None);
+        let mut packet = vec![0u8; 17];
+        packet.push(101);
+        assert!(decode(chat.fields, &packet).is_err());
+
+        let json = serde_json::to_value(get_schema()).unwrap();
+        assert_eq!(json["protocol_version"], json!(PROTOCOL_VERSION));
+        assert_eq!(json["requests"][0]["fields"][0], json!({ "name": "session_id", "type": "u64", "count": "one" }));
+    }
+
+    fn decode_delta(layout : &EntityLayout, base : &[u8], current : &[u8]) -> Map<String, Value>
+    {
+        let delta = encode_delta(layout, base, current);
+        let schema = ENTITIES.iter().find(|schema| schema.code == Some(layout.delta_type)).unwrap();
+        let (values, size) = decode(schema.fields, &delta).unwrap();
+        assert_eq!(size, delta.len(), "{} decoded size", schema.name);
+        values
+    }
+
+    #[test]
+    fn deltas_match_the_encoder()
+    {
+        // one masked field per field of the layout, in the same order and with the same size.
+        for layout in [&HERO_LAYOUT, &TILE_LAYOUT, &MOB_LAYOUT, &TOWER_LAYOUT]
+        {
+            let schema = ENTITIES.iter().find(|schema| schema.code == Some(layout.delta_type)).unwrap();
+            assert_eq!(schema.fields[0].name, "base_version");
+            assert_eq!(schema.fields[1].name, "mask");
+            assert_eq!(schema.fields.len(), layout.fields.len() + 2, "{} fields", schema.name);
+            for (index, field) in schema.fields[2..].iter().enumerate()
+            {
+                assert!(matches!(field.count, Count::Masked { bit, .. } if bit as usize == index), "{} bit", field.name);
+                assert_eq!(field.max_size(), layout.fields[index], "{} size", field.name);
+            }
+        }
+
+        let base = MapEntity::new("a0123", 100);
+        let mut current = base.clone();
+        current.version = base.version + 1;
+        current.heights[1] = 2.5;
+        current.health = 80;
+        let values = decode_delta(&TILE_LAYOUT, &base.to_bytes(), &current.to_bytes());
+        let names : Vec<&str> = values.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, ["base_version", "mask", "version", "id", "height_1", "health"]);
+        assert_eq!(values["base_version"], json!(base.version));
+        assert_eq!(values["version"], json!(current.version));
+        assert_eq!(values["id"], json!("a0123"));
+        assert_eq!(values["height_1"], json!(2.5));
+        assert_eq!(values["health"], json!(80));
+
+        let mut hero = create_hero();
+        let base = hero.to_bytes();
+        hero.version += 1;
+        hero.path = [6, 5, 4, 3, 2, 1];
+        hero.buffs_summary = [1, 0, 0, 0, 0];
+        let values = decode_delta(&HERO_LAYOUT, &base, &hero.to_bytes());
+        assert_eq!(values.len(), 6);
+        assert_eq!(values["hero_id"], json!(1234));
+        assert_eq!(values["version"], json!(8));
+        assert_eq!(values["path"], json!([6, 5, 4, 3, 2, 1]));
+        assert_eq!(values["buffs_summary"], json!([1, 0, 0, 0, 0]));
+
+        let mob = MobEntity
+        {
+            mob_id: 100_000,
+            mob_definition_id: 2,
+            level: 3,
+            version: 4,
+            owner_id: 5,
+            ownership_time: 6,
+            start_position_id: tile("c0123"),
+            end_position_id: tile("d0123"),
+            path: [1, 2, 3, 4, 5, 6],
+            time: 7,
+            health: 8,
+            buffs: Vec::new(),
+            buffs_summary: [0; 5],
+        };
+        let mut moved = mob.clone();
+        moved.version = 5;
+        moved.start_position_id = tile("c0122");
+        let values = decode_delta(&MOB_LAYOUT, &mob.to_bytes(), &moved.to_bytes());
+        assert_eq!(values.len(), 5);
+        assert_eq!(values["base_version"], json!(4));
+        assert_eq!(values["mob_id"], json!(100_000));
+        assert_eq!(values["version"], json!(5));
+        assert_eq!(values["start_position"], json!("c0122"));
+
+        let mut tower = TowerEntity
+        {
+            object_id: None,
+            version: 2,
+            tetrahedron_id: tile("e0123"),
+            event_id: 3,
+            faction: 4,
+            damage_received_in_event: vec![DamageByFaction { event_id: 3, faction: 1, amount: 500 }],
+        };
+        let base = tower.to_bytes();
+        tower.version = 3;
+        tower.damage_received_in_event.push(DamageByFaction { event_id: 3, faction: 2, amount: 600 });
+        let values = decode_delta(&TOWER_LAYOUT, &base, &tower.to_bytes());
+        assert_eq!(values.len(), 5);
+        assert_eq!(values["id"], json!("e0123"));
+        assert_eq!(values["damage_record_1"], json!({ "event_id": 3, "faction": 2, "amount": 600 }));
+    }
+
+    fn decompress(data : &[u8]) -> Vec<u8>
+    {
+        let mut output = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(data), &mut output).unwrap();
+        output
+    }
+
+    fn decode_response(name : &str, data : &[u8]) -> (Map<String, Value>, usize)
+    {
+        let schema = get_response(name).unwrap();
+        let data = if schema.compressed { decompress(data) } else { data.to_vec() };
+        assert_eq!(schema.code, Some(data[0]), "{} protocol", name);
+        decode(schema.fields, &data[1..]).unwrap()
+    }
+
+    #[test]
+    fn responses_match_their_builders()
+    {
+        let rejection = build_rejection(RejectReason::SessionExpired);
+        let (values, size) = decode_response("rejected", &rejection);
+        assert_eq!(size + 1, rejection.len());
+        assert_eq!(values["reason"], json!(RejectReason::SessionExpired as u8));
+        assert_eq!(values["protocol_version"], json!(PROTOCOL_VERSION));
+        assert_eq!(values["min_protocol_version"], json!(MIN_PROTOCOL_VERSION));
+
+        // the message follows the fields.
+        let reliable = reliable_channel::encode(42, &[Protocol::Resume as u8]);
+        let (values, size) = decode_response("reliable", &reliable);
+        assert_eq!(values["sequence"], json!(42));
+        assert_eq!(&reliable[(size + 1)..], &[Protocol::Resume as u8]);
+
+        let hero_data = pack_hero_data(&create_hero());
+        let (values, size) = decode_response("hero_data", &hero_data);
+        assert_eq!(size, HERO_ENTITY_SIZE);
+        assert_eq!(values["hero"]["hero_id"], json!(1234));
+        assert_eq!(values["hero"]["buffs_summary"], json!([21, 22, 23, 24, 25]));
+
+        let schema = get_response("pong").unwrap();
+        assert_eq!(schema.fixed_size(), Some(PONG_SIZE - 1));
+        let (values, _) = decode(schema.fields, &build_pong(5, 100, 200, 300)[1..]).unwrap();
+        assert_eq!(values["id"], json!(5));
+        assert_eq!(values["client_time"], json!(100));
+        assert_eq!(values["received_time"], json!(200));
+        assert_eq!(values["sent_time"], json!(300));
+    }
+
+    // a value for every field, lists get a single entry. The parsers check a few of them.
+    fn sample_values(fields : &[Field]) -> Map<String, Value>
+    {
+        let mut values = Map::new();
+        for field in fields
+        {
+            let is_count = fields.iter().any(|other| matches!(other.count, Count::CountedBy { field: count_field, .. } if count_field == field.name));
+            let value = match field.count
+            {
+                _ if is_count => json!(1),
+                Count::One | Count::Masked { items: None, .. } => sample_value(&field.field_type),
+                Count::Fixed(count) | Count::Masked { items: Some(count), .. } => json!(vec![sample_value(&field.field_type); count]),
+                Count::CountedBy { .. } => json!([sample_value(&field.field_type)]),
+            };
+            values.insert(field.name.to_string(), value);
+        }
+        values
+    }
+
+    fn sample_value(field_type : &FieldType) -> Value
+    {
+        match field_type
+        {
+            FieldType::F32 => json!(1.0),
+            FieldType::TetrahedronId => json!("a012301230"),
+            FieldType::Group { fields } => Value::Object(sample_values(fields)),
+            _ => json!(1),
+        }
+    }
+
+    // the other way around from decode, so we can build packets from the descriptors.
+    fn encode(fields : &[Field], values : &Map<String, Value>, output : &mut Vec<u8>)
+    {
+        for field in fields
+        {
+            let value = &values[field.name];
+            match field.count
+            {
+                Count::One | Count::Masked { items: None, .. } => encode_value(&field.field_type, value, output),
+                _ =>
+                {
+                    let list = value.as_array().unwrap();
+                    list.iter().for_each(|item| encode_value(&field.field_type, item, output));
+                    if let Count::CountedBy { padded_to: Some(padded_to), .. } = field.count
+                    {
+                        output.resize(output.len() + (padded_to - list.len()) * field.field_type.size(), 0);
+                    }
+                },
+            }
+        }
+    }
+
+    fn encode_value(field_type : &FieldType, value : &Value, output : &mut Vec<u8>)
+    {
+        match field_type
+        {
+            FieldType::U8 => output.push(value.as_u64().unwrap() as u8),
+            FieldType::U16 => output.extend_from_slice(&u16::to_le_bytes(value.as_u64().unwrap() as u16)),
+            FieldType::U32 => output.extend_from_slice(&u32::to_le_bytes(value.as_u64().unwrap() as u32)),
+            FieldType::U64 => output.extend_from_slice(&u64::to_le_bytes(value.as_u64().unwrap())),
+            FieldType::I32 => output.extend_from_slice(&i32::to_le_bytes(value.as_i64().unwrap() as i32)),
+            FieldType::F32 => output.extend_from_slice(&f32::to_le_bytes(value.as_f64().unwrap() as f32)),
+            FieldType::TetrahedronId => output.extend_from_slice(&TetrahedronId::from_string(value.as_str().unwrap()).to_bytes()),
+            FieldType::Group { fields } => encode(fields, value.as_object().unwrap(), output),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_go_through_the_parsers()
+    {
+        let (definitions, _) = crate::definitions::load_definitions().await;
+        let map = Arc::new(GameMap::for_tests(definitions));
+        let server_state = Arc::new(ServerState::new(None));
+        let connections = Arc::new(ConnectionRegistry::new(map.clone(), server_state.clone()));
+        let connection = ConnectionId::websocket("127.0.0.1:5000".parse().unwrap());
+        let (tx_gc, _rx_gc) = gaia_mpsc::channel::<GenericCommand>(100, ServerChannels::TX_GC_ClIENTS_GAMEPLAY, server_state.clone());
+        let (tx_hc, _rx_hc) = gaia_mpsc::channel::<HeroCommand>(100, ServerChannels::TX_PC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_mc, _rx_mc) = gaia_mpsc::channel::<MapCommand>(100, ServerChannels::TX_MC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_moc, _rx_moc) = gaia_mpsc::channel::<MobCommand>(100, ServerChannels::TX_MOC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_tc, _rx_tc) = gaia_mpsc::channel::<TowerCommand>(100, ServerChannels::TX_TC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_kc, _rx_kc) = gaia_mpsc::channel::<KingdomCommand>(100, ServerChannels::TX_KC_CLIENTS_GAMEPLAY, server_state.clone());
+        let (tx_cc, _rx_cc) = gaia_mpsc::channel::<ChatCommand>(100, ServerChannels::TX_CC_CLIENTS_GAMEPLAY, server_state.clone());
+
+        for schema in REQUESTS
+        {
+            // fragments are put together before the parsers see them, spectators go through the web service.
+            if schema.name == "fragment" || schema.name == "spectate"
+            {
+                continue;
+            }
+
+            let mut values = sample_values(schema.fields);
+            match schema.name
+            {
+                "greet" => values["protocol_version"] = json!(PROTOCOL_VERSION),
+                "entity_ack" => values["entities"][0]["data_type"] = json!(DataType::TileState as u8),
+                _ => {},
+            }
+
+            let code = schema.code.unwrap();
+            let mut packet = vec![code];
+            encode(schema.fields, &values, &mut packet);
+            assert_eq!(decode(schema.fields, &packet[1..]).unwrap(), (values, packet.len() - 1), "{} round trip", schema.name);
+
+            let dropped = || server_state.dropped_packets[code as usize].load(std::sync::atomic::Ordering::Relaxed);
+            crate::protocols::dispatch_packet(connection, &packet, &map, &server_state, &connections, &tx_gc, &tx_hc, &tx_mc, &tx_moc, &tx_tc, &tx_kc, &tx_cc).await;
+            assert_eq!(dropped(), 0, "{} was dropped", schema.name);
+
+            // a resume is answered without reading it, route_packet already checked the identity in it.
+            if schema.name == "resume"
+            {
+                continue;
+            }
+
+            // the parser needs every byte the descriptor has.
+            crate::protocols::dispatch_packet(connection, &packet[..(packet.len() - 1)], &map, &server_state, &connections, &tx_gc, &tx_hc, &tx_mc, &tx_moc, &tx_tc, &tx_kc, &tx_cc).await;
+            assert_eq!(dropped(), 1, "{} without its last byte", schema.name);
+        }
+    }
+}
@@ -1,36 +1,18 @@
-use crate::{gaia_mpsc::GaiaSender, map::tetrahedron_id::TetrahedronId, mob::mob_command::{MobCommand, SpawnMobData}};
+use crate::{gaia_mpsc::GaiaSender, mob::mob_command::{MobCommand, SpawnMobData}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_map_tx : &GaiaSender<MobCommand>)
+    channel_map_tx : &GaiaSender<MobCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mut reader = PacketReader::new(data);
+        let header = reader.read_header()?;
+        let player_id = header.player_id;
 
-        end = start + 2;
-        let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 1;
-        let _faction = data[start];
-        start = end;
-
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-        start = end;
-
-        end = start + 4;
-        let mob_definition_id = u32::from_le_bytes(data[start..end].try_into().unwrap()); 
-        start = end;
-
-        end = start + 1;
-        let level = data[start]; 
-        start = end;
+        let tile_id = reader.read_tetrahedron_id()?;
+        let mob_definition_id = reader.read_u32()?; 
+        let level = reader.read_u8()?; 
 
         let map_action = MobCommand::Spawn(SpawnMobData
         {
@@ -41,4 +23,5 @@ pub async fn process(
         });
 
         channel_map_tx.send(map_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use crate::clients_service::connection::ConnectionId;
+use crate::map::GameMap;
+use super::packet_reader::{PacketReader, ProtocolError};
+
+pub const MAX_TOKEN_SIZE : usize = 64;
+pub const MAX_SPECTATED_REGIONS : u8 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpectateRequest
+{
+    pub token : String,
+    pub follow_hero_id : Option<u16>,
+    pub regions : Vec<u16>,
+}
+
+// websocket only, there is no hero header. Token length (1 byte), token, the hero the camera
+// follows (2 bytes, 0 for none), region count (1 byte) and the regions (2 bytes each).
+// the first one authenticates the connection, the next ones replace what it watches and keep it alive.
+pub fn read(data : &[u8]) -> Result<SpectateRequest, ProtocolError>
+{
+    let mut reader = PacketReader::new(data);
+    let token_length = reader.read_u8()? as usize;
+    if token_length == 0 || token_length > MAX_TOKEN_SIZE
+    {
+        return Err(ProtocolError::InvalidField("token_length"));
+    }
+
+    reader.ensure(token_length)?;
+    let token_bytes = (0..token_length).map(|_| reader.read_u8()).collect::<Result<Vec<u8>, ProtocolError>>()?;
+    let token = String::from_utf8(token_bytes).map_err(|_| ProtocolError::InvalidField("token"))?;
+
+    let follow_hero_id = Some(reader.read_u16()?).filter(|hero_id| *hero_id != 0);
+    let count = reader.read_u8()?;
+    if count > MAX_SPECTATED_REGIONS
+    {
+        return Err(ProtocolError::InvalidField("count"));
+    }
+
+    reader.ensure(count as usize * 2)?;
+    let regions = (0..count).map(|_| reader.read_u16()).collect::<Result<Vec<u16>, ProtocolError>>()?;
+    Ok(SpectateRequest { token, follow_hero_id, regions })
+}
+
+pub async fn process(connection : ConnectionId, request : &SpectateRequest, map : &Arc<GameMap>)
+{
+    let subscriptions = &map.region_subscriptions;
+    let old_regions : Vec<u16> = subscriptions.get_regions(connection).await.into_iter().collect();
+    subscriptions.unsubscribe(connection, &old_regions).await;
+    subscriptions.subscribe(connection, &request.regions).await;
+    map.follow_hero(connection, request.follow_hero_id).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::Protocol;
+
+    fn packet(token : &[u8], follow_hero_id : u16, regions : &[u16]) -> Vec<u8>
+    {
+        let mut data = vec![Protocol::Spectate as u8, token.len() as u8];
+        data.extend_from_slice(token);
+        data.extend_from_slice(&u16::to_le_bytes(follow_hero_id));
+        data.push(regions.len() as u8);
+        for region in regions
+        {
+            data.extend_from_slice(&u16::to_le_bytes(*region));
+        }
+        data
+    }
+
+    #[test]
+    fn read_spectate_requests()
+    {
+        let request = read(&packet(b"watch", 7, &[3, 4])).unwrap();
+        assert_eq!(request, SpectateRequest { token: "watch".to_string(), follow_hero_id: Some(7), regions: vec![3, 4] });
+        assert_eq!(read(&packet(b"watch", 0, &[])).unwrap().follow_hero_id, None);
+
+        assert_eq!(read(&packet(b"", 0, &[])), Err(ProtocolError::InvalidField("token_length")));
+        assert_eq!(read(&packet(&[0xff], 0, &[])), Err(ProtocolError::InvalidField("token")));
+        let regions : Vec<u16> = (0..=MAX_SPECTATED_REGIONS as u16).collect();
+        assert_eq!(read(&packet(b"watch", 0, &regions)), Err(ProtocolError::InvalidField("count")));
+
+        let data = packet(b"watch", 0, &[3, 4]);
+        assert!(matches!(read(&data[..data.len() - 1]), Err(ProtocolError::Truncated { .. })));
+    }
+}
@@ -1,34 +1,18 @@
 use tokio::{sync::mpsc::Sender, net::UdpSocket};
 
-use crate::{gaia_mpsc::GaiaSender, map::{map_entity::{MapCommand, MapCommandInfo}, tetrahedron_id::TetrahedronId}, mob::mob_command::{MobCommand, TouchMobData}};
+use crate::{gaia_mpsc::GaiaSender, map::map_entity::{MapCommand, MapCommandInfo}, mob::mob_command::{MobCommand, TouchMobData}};
+use super::packet_reader::{PacketReader, ProtocolError};
 
 
 pub async fn process(
      data : &[u8],
-    channel_map_tx : &GaiaSender<MobCommand>)
+    channel_map_tx : &GaiaSender<MobCommand>) -> Result<(), ProtocolError>
 {
-        let mut start = 1;
-        let mut end = start + 8;
-        let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
+        let mut reader = PacketReader::new(data);
+        let _header = reader.read_header()?;
 
-        end = start + 2;
-        let _player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 1;
-        let _faction = data[start];
-        start = end;
-
-        end = start + 4;
-        let mob_id = u32::from_le_bytes(data[start..end].try_into().unwrap());
-        start = end;
-
-        end = start + 6;
-        let mut buffer = [0u8;6];
-        buffer.copy_from_slice(&data[start..end]);
-        let tile_id = TetrahedronId::from_bytes(&buffer);
-        start = end;
+        let mob_id = reader.read_u32()?;
+        let tile_id = reader.read_tetrahedron_id()?;
 
         let map_action = MobCommand::Touch(TouchMobData
         {
@@ -37,4 +21,5 @@ pub async fn process(
         });
 
         channel_map_tx.send(map_action).await.unwrap();
+        Ok(())
 }
\ No newline at end of file
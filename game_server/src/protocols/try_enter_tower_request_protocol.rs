@@ -14,28 +14,19 @@ use crate::map::GameMap;
 use crate::hero::hero_command::{HeroCommand, HeroMovement};
 use flate2::Compression;
 use flate2::write::ZlibEncoder;
+use super::packet_reader::{PacketReader, ProtocolError};
+use crate::clients_service::connection::ConnectionId;
 
 pub async fn process_request(
-    player_address : std::net::SocketAddr, 
-    is_udp : bool,
+    connection : ConnectionId,
     generic_channel_tx : &GaiaSender<GenericCommand>,
     data : &[u8],
-    map : &Arc<GameMap>)
+    map : &Arc<GameMap>) -> Result<(), ProtocolError>
 {
     cli_log::info!("---- inventory request");
-    let start = 1;
-    let end = start + 8;
-    let _player_session_id = u64::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 2;
-    let player_id = u16::from_le_bytes(data[start..end].try_into().unwrap());
-
-    let start = end;
-    let end = start + 1;
-    let _faction = data[start];
-
-    let start = end;
+    let mut reader = PacketReader::new(data);
+    let header = reader.read_header()?;
+    let player_id = header.player_id;
 
     let mut player_entities = map.character.lock().await;
     let player_option = player_entities.get_mut(&player_id);
@@ -58,8 +49,9 @@ pub async fn process_request(
 
     if let Some(data) = result 
     {
-        generic_channel_tx.send(GenericCommand{player_address, is_udp, data : Bytes::from(data.to_vec())}).await.unwrap();
+        generic_channel_tx.send(GenericCommand{connection, data : Bytes::from(data.to_vec()), reliable : true}).await.unwrap();
     }
+    Ok(())
 }
 
 pub fn pack_hero_data(hero_data: &HeroEntity)
@@ -1,4 +1,4 @@
-use std::{cmp, time::SystemTime};
+use std::cmp;
 
 use bson::oid::ObjectId;
 
@@ -287,4 +287,24 @@ mod tests {
 
         cli_log::info!("{:?}", entity.damage_received_in_event);
     }
+
+    #[test]
+    fn towers_sleep_one_minute_every_six()
+    {
+        // a0 has no offset, the cycle starts at second 0.
+        let tile_id = TetrahedronId::from_string("a0");
+        assert!(!TowerEntity::is_tower_active(&tile_id, 1, 2, 0));
+        assert!(!TowerEntity::is_tower_active(&tile_id, 1, 2, 60));
+        assert!(TowerEntity::is_tower_active(&tile_id, 1, 2, 61));
+        assert!(TowerEntity::is_tower_active(&tile_id, 1, 2, 359));
+        assert!(!TowerEntity::is_tower_active(&tile_id, 1, 2, 360));
+        // the owners can't attack their own tower.
+        assert!(!TowerEntity::is_tower_active(&tile_id, 1, 1, 61));
+
+        // other towers are shifted, so they don't all sleep at once.
+        let shifted_id = TetrahedronId::from_string("b0");
+        assert!(!TowerEntity::is_tower_active(&tile_id, 1, 2, 55));
+        assert!(TowerEntity::is_tower_active(&shifted_id, 1, 2, 55));
+        assert!(!TowerEntity::is_tower_active(&shifted_id, 1, 2, 350));
+    }
 }
\ No newline at end of file
@@ -4,7 +4,7 @@ use futures_util::StreamExt;
 use hyper::{body, http::Error, Body, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
 
-use crate::{hero::{hero_card_inventory::CardItem, hero_entity::HeroEntity, hero_inventory::InventoryItem, hero_presentation::HeroPresentation, hero_tower_progress::HeroTowerProgress, hero_weapon_inventory::WeaponItem}, long_term_storage_service::{db_hero::StoredHero, db_player::StoredPlayer, db_world::StoredWorld}, map::tetrahedron_id::TetrahedronId, web_service::create_response_builder};
+use crate::{hero::{hero_card_inventory::CardItem, hero_entity::HeroEntity, hero_inventory::InventoryItem, hero_presentation::HeroPresentation, hero_tower_progress::HeroTowerProgress, hero_weapon_inventory::WeaponItem}, long_term_storage_service::{db_hero::StoredHero, db_player::StoredPlayer, db_world::StoredWorld}, map::tetrahedron_id::TetrahedronId, protocols::{packet_auth, protocol_version}, web_service::create_response_builder};
 
 use super::AppContext;
 
@@ -63,6 +63,9 @@ pub struct JoinWithHeroRequest
 {
     pub player_token: String,
     pub hero_id:u16,
+    // old clients don't send it, they get rejected.
+    #[serde(default)]
+    pub protocol_version:u16,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -407,6 +410,12 @@ pub async fn handle_create_hero(context: AppContext, mut req: Request<Body>) ->R
 
 pub async fn handle_login_with_hero(context: AppContext, mut req: Request<Body>) ->Result<Body, String> 
 {
+    // anyone reading the response could sign packets as this hero, so it doesn't go out over plain http.
+    if !context.join_allowed
+    {
+        return Err("join_needs_tls".to_owned());
+    }
+
     let body = req.body_mut();
     let data = body::to_bytes(body).await.unwrap();
     let data: JoinWithHeroRequest = serde_json::from_slice(&data).unwrap();
@@ -437,6 +446,12 @@ pub async fn handle_login_with_hero(context: AppContext, mut req: Request<Body>)
         return Err("player_token_not_valid".to_owned())
     }
 
+    if !protocol_version::is_supported(data.protocol_version)
+    {
+        cli_log::info!("rejected join for {} with protocol version {}", data.hero_id, data.protocol_version);
+        return Err("protocol_version_not_supported".to_owned())
+    }
+
     let players = context.working_game_map.character.lock().await;
 
     if let Some(player) = players.get(&data.hero_id) 
@@ -449,8 +464,16 @@ pub async fn handle_login_with_hero(context: AppContext, mut req: Request<Body>)
 
         let mut output = Vec::<u8>::with_capacity(100);
 
+        // first the version we are going to speak, then the rest depends on it.
+        let version_bytes = u16::to_le_bytes(protocol_version::PROTOCOL_VERSION); // 2 bytes
+        output.extend_from_slice(&version_bytes);
+
         let session_bytes = u64::to_le_bytes(session_id); // 8 bytes
         output.extend_from_slice(&session_bytes);
+
+        // udp packets have to be signed with this, the session id alone travels in clear text.
+        let session_secret = packet_auth::generate_secret(); // 32 bytes
+        output.extend_from_slice(&session_secret);
         let encoded_player_data = player.to_bytes();
         output.extend_from_slice(&encoded_player_data);
         pack_inventory(&mut output, &player.inventory, &player.card_inventory, &player.weapon_inventory, player.inventory_version);
@@ -458,6 +481,7 @@ pub async fn handle_login_with_hero(context: AppContext, mut req: Request<Body>)
         drop(players);
 
         cli_log::info!("creating session id {} for {}", session_id, data.hero_id);
+        context.working_game_map.session_keys.insert(data.hero_id, session_id, session_secret).await;
         let session = &context.working_game_map.logged_in_players[data.hero_id as usize];
         session.store(session_id, std::sync::atomic::Ordering::Relaxed);
 
@@ -30,6 +30,7 @@ pub mod map;
 pub mod towers;
 pub mod kingdoms;
 pub mod chat;
+pub mod tls;
 
 pub const CHAT_STORAGE_SIZE: usize = 100;
 
@@ -89,7 +90,9 @@ pub struct AppContext
     temp_mobs_regions : Arc::<HashMap::<TetrahedronId, Arc<Mutex<TempMobBuffer>>>>,
     temp_towers : Arc::<Mutex::<(usize,[u8;100000])>>,
     temp_kingdoms : Arc::<Mutex::<(usize,[u8;10000])>>,
-    old_messages : Arc::<Mutex::<HashMap<u8, ChatStorage>>>//index, offset, count, 20 messages
+    old_messages : Arc::<Mutex::<HashMap<u8, ChatStorage>>>,//index, offset, count, 20 messages
+    // false when the api is plain http and nobody allowed joining over it, the join response carries the session secret.
+    join_allowed : bool,
 }
 
 pub fn create_response_builder() -> hyper::http::response::Builder
@@ -189,6 +192,29 @@ async fn handle_check_version(_context: AppContext, mut req: Request<Body>) ->Re
     Ok(Body::from(data))
 }
 
+// the layout of every request and entity, so clients and tools don't have to copy it from the code.
+async fn handle_protocol_schema_request() ->Result<Body, String> 
+{
+    let schema = crate::protocols::schema::get_schema();
+    let data = serde_json::to_vec(&schema).map_err(|error| error.to_string())?;
+    Ok(Body::from(data))
+}
+
+// stage timings, overruns and commands of the gameplay ticks, for dashboards and alerts.
+async fn handle_tick_metrics_request(context: AppContext) ->Result<Body, String> 
+{
+    let metrics = context.server_state.tick_metrics.get_snapshot();
+    let data = serde_json::to_vec(&metrics).map_err(|error| error.to_string())?;
+    Ok(Body::from(data))
+}
+
+// by hero id, only the heroes that had something refused.
+async fn handle_rejected_actions_request(context: AppContext) ->Result<Body, String> 
+{
+    let rejected_actions = context.server_state.rejected_actions.lock().unwrap().clone();
+    let data = serde_json::to_vec(&rejected_actions).map_err(|error| error.to_string())?;
+    Ok(Body::from(data))
+}
 
 async fn handle_definition_request(context: AppContext, mut req: Request<Body>) ->Result<Body, String> 
 {
@@ -307,6 +333,9 @@ async fn route(context: AppContext, req: Request<Body>) -> Result<Response<Body>
             "chat_record" => chat::handle_chat_record_request(context, rest).await,
             "exchange_skill_points" => heroes::exchange_skill_points(context, req).await,
             "check_version" => handle_check_version(context, req).await,
+            "protocol_schema" => handle_protocol_schema_request().await,
+            "tick_metrics" => handle_tick_metrics_request(context).await,
+            "rejected_actions" => handle_rejected_actions_request(context).await,
             _ => 
             {
                 cli_log::warn!("route not found: {route}");
@@ -373,7 +402,49 @@ impl TempMobBuffer
 }
 
 
+// same routes as the plain server, every connection does the tls handshake first.
+async fn serve_tls(addr : SocketAddr, tls_config : Arc<tokio_rustls::rustls::ServerConfig>, context : AppContext)
+{
+    let listener = match tokio::net::TcpListener::bind(addr).await
+    {
+        Ok(listener) => listener,
+        Err(error) =>
+        {
+            cli_log::error!("server error: {}", error);
+            return;
+        }
+    };
+    let acceptor = tokio_rustls::TlsAcceptor::from(tls_config);
+
+    loop
+    {
+        let Ok((stream, _addr)) = listener.accept().await else { continue };
+        let acceptor = acceptor.clone();
+        let context = context.clone();
+        tokio::spawn(async move
+        {
+            let stream = match acceptor.accept(stream).await
+            {
+                Ok(stream) => stream,
+                Err(error) =>
+                {
+                    cli_log::info!("tls handshake failed: {}", error);
+                    return;
+                }
+            };
+            let service = service_fn(move |req| route(context.clone(), req));
+            if let Err(error) = hyper::server::conn::Http::new().serve_connection(stream, service).await
+            {
+                cli_log::info!("server error: {}", error);
+            }
+        });
+    }
+}
+
 pub fn start_server(
+    addr : SocketAddr,
+    tls_config : Option<Arc<tokio_rustls::rustls::ServerConfig>>,
+    insecure_join : bool,
     presentation_cache : Vec<u8>,
     working_map: Arc<GameMap>,
     storage_map: Arc<GameMap>,
@@ -450,12 +521,22 @@ pub fn start_server(
         temp_towers : towers_reader_reference,
         temp_kingdoms : kingdoms_reader_reference,
         old_messages : chat_reader_reference,
+        join_allowed : tls_config.is_some() || insecure_join,
     };
 
+    if !context.join_allowed
+    {
+        cli_log::warn!("the web api is plain http, join_with_hero is refused until web_tls_cert and web_tls_key or insecure_join are set");
+    }
 
     tokio::spawn(async move 
     {
-        let addr = SocketAddr::from(([0, 0, 0, 0], 3031));
+        if let Some(tls_config) = tls_config
+        {
+            serve_tls(addr, tls_config, context).await;
+            return;
+        }
+
         let make_service = make_service_fn(move |conn: &AddrStream| 
         {
             let context = context.clone();
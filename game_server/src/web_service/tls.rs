@@ -0,0 +1,38 @@
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+// the web api only goes over https when there is a certificate, join_with_hero needs it because it answers with the session secret.
+pub fn load_server_config(cert_path : &Path, key_path : &Path) -> Result<Arc<ServerConfig>, String>
+{
+    let cert_file = std::fs::File::open(cert_path).map_err(|error| format!("can't read {}: {}", cert_path.display(), error))?;
+    let certs : Vec<Certificate> = rustls_pemfile::certs(&mut BufReader::new(cert_file))
+        .map_err(|error| format!("invalid certificate {}: {}", cert_path.display(), error))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certs.is_empty()
+    {
+        return Err(format!("no certificate in {}", cert_path.display()));
+    }
+
+    let key_file = std::fs::File::open(key_path).map_err(|error| format!("can't read {}: {}", key_path.display(), error))?;
+    let key = rustls_pemfile::read_all(&mut BufReader::new(key_file))
+        .map_err(|error| format!("invalid key {}: {}", key_path.display(), error))?
+        .into_iter()
+        .find_map(|item| match item
+        {
+            rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", key_path.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|error| format!("invalid certificate or key: {}", error))?;
+    Ok(Arc::new(config))
+}